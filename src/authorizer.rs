@@ -0,0 +1,900 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::net::SocketAddr;
+use std::sync::{Mutex, OnceLock};
+
+use ring::rand::{SecureRandom, SystemRandom};
+
+use crate::config::BrokerConfig;
+use crate::connections::ClientConnection;
+use crate::metadata_log::{self, MetadataRecord};
+use crate::{
+    frame_response, read_array_length, read_compact_string, read_i8, read_nullable_string, read_string,
+    skip_request_header, skip_tagged_fields, write_array_length, write_compact_string, write_nullable_string,
+    write_string, RequestContext,
+};
+
+const ACCESS_CONTROL_ENTRY_RECORD: u32 = 6;
+const REMOVE_ACCESS_CONTROL_ENTRY_RECORD: u32 = 7;
+
+// The authorizer.class.name of the ACL authorizer below, as Kafka names its KRaft authorizer
+pub const STANDARD_AUTHORIZER: &str = "org.apache.kafka.metadata.authorizer.StandardAuthorizer";
+
+// The one CLUSTER resource
+pub const CLUSTER_NAME: &str = "kafka-cluster";
+const WILDCARD_RESOURCE: &str = "*";
+const WILDCARD_PRINCIPAL: &str = "User:*";
+const WILDCARD_HOST: &str = "*";
+
+// Kafka's wire codes; codes this broker doesn't know decode as Unknown, which requests can't use
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ResourceType {
+    Unknown = 0,
+    Any = 1,
+    Topic = 2,
+    Group = 3,
+    Cluster = 4,
+    TransactionalId = 5,
+    DelegationToken = 6,
+    User = 7,
+}
+
+impl ResourceType {
+    fn from_code(code: i8) -> ResourceType {
+        match code {
+            1 => ResourceType::Any,
+            2 => ResourceType::Topic,
+            3 => ResourceType::Group,
+            4 => ResourceType::Cluster,
+            5 => ResourceType::TransactionalId,
+            6 => ResourceType::DelegationToken,
+            7 => ResourceType::User,
+            _ => ResourceType::Unknown,
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            ResourceType::Unknown => "Unknown",
+            ResourceType::Any => "Any",
+            ResourceType::Topic => "Topic",
+            ResourceType::Group => "Group",
+            ResourceType::Cluster => "Cluster",
+            ResourceType::TransactionalId => "TransactionalId",
+            ResourceType::DelegationToken => "DelegationToken",
+            ResourceType::User => "User",
+        }
+    }
+}
+
+// MATCH and ANY only appear in filters: MATCH selects the ACLs that apply to a named resource
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum PatternType {
+    Unknown = 0,
+    Any = 1,
+    Match = 2,
+    Literal = 3,
+    Prefixed = 4,
+}
+
+impl PatternType {
+    fn from_code(code: i8) -> PatternType {
+        match code {
+            1 => PatternType::Any,
+            2 => PatternType::Match,
+            3 => PatternType::Literal,
+            4 => PatternType::Prefixed,
+            _ => PatternType::Unknown,
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            PatternType::Unknown => "UNKNOWN",
+            PatternType::Any => "ANY",
+            PatternType::Match => "MATCH",
+            PatternType::Literal => "LITERAL",
+            PatternType::Prefixed => "PREFIXED",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum AclOperation {
+    Unknown = 0,
+    Any = 1,
+    All = 2,
+    Read = 3,
+    Write = 4,
+    Create = 5,
+    Delete = 6,
+    Alter = 7,
+    Describe = 8,
+    ClusterAction = 9,
+    DescribeConfigs = 10,
+    AlterConfigs = 11,
+    IdempotentWrite = 12,
+    CreateTokens = 13,
+    DescribeTokens = 14,
+}
+
+impl AclOperation {
+    fn from_code(code: i8) -> AclOperation {
+        match code {
+            1 => AclOperation::Any,
+            2 => AclOperation::All,
+            3 => AclOperation::Read,
+            4 => AclOperation::Write,
+            5 => AclOperation::Create,
+            6 => AclOperation::Delete,
+            7 => AclOperation::Alter,
+            8 => AclOperation::Describe,
+            9 => AclOperation::ClusterAction,
+            10 => AclOperation::DescribeConfigs,
+            11 => AclOperation::AlterConfigs,
+            12 => AclOperation::IdempotentWrite,
+            13 => AclOperation::CreateTokens,
+            14 => AclOperation::DescribeTokens,
+            _ => AclOperation::Unknown,
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            AclOperation::Unknown => "UNKNOWN",
+            AclOperation::Any => "ANY",
+            AclOperation::All => "ALL",
+            AclOperation::Read => "READ",
+            AclOperation::Write => "WRITE",
+            AclOperation::Create => "CREATE",
+            AclOperation::Delete => "DELETE",
+            AclOperation::Alter => "ALTER",
+            AclOperation::Describe => "DESCRIBE",
+            AclOperation::ClusterAction => "CLUSTER_ACTION",
+            AclOperation::DescribeConfigs => "DESCRIBE_CONFIGS",
+            AclOperation::AlterConfigs => "ALTER_CONFIGS",
+            AclOperation::IdempotentWrite => "IDEMPOTENT_WRITE",
+            AclOperation::CreateTokens => "CREATE_TOKENS",
+            AclOperation::DescribeTokens => "DESCRIBE_TOKENS",
+        }
+    }
+
+    // Whether an ALLOW ACL for this operation allows the requested one: ALL allows everything,
+    // anything that reads or changes a resource allows describing it, and ALTER_CONFIGS allows
+    // DESCRIBE_CONFIGS
+    fn allows(&self, requested: AclOperation) -> bool {
+        match (self, requested) {
+            (AclOperation::All, _) => true,
+            (AclOperation::Read | AclOperation::Write | AclOperation::Delete | AclOperation::Alter, AclOperation::Describe) => true,
+            (AclOperation::AlterConfigs, AclOperation::DescribeConfigs) => true,
+            (operation, requested) => *operation == requested,
+        }
+    }
+}
+
+// Operations reported in topic_authorized_operations and cluster_authorized_operations
+pub const TOPIC_OPERATIONS: &[AclOperation] = &[
+    AclOperation::Read, AclOperation::Write, AclOperation::Create, AclOperation::Delete, AclOperation::Alter,
+    AclOperation::Describe, AclOperation::DescribeConfigs, AclOperation::AlterConfigs,
+];
+pub const CLUSTER_OPERATIONS: &[AclOperation] = &[
+    AclOperation::Create, AclOperation::Alter, AclOperation::Describe, AclOperation::ClusterAction,
+    AclOperation::DescribeConfigs, AclOperation::AlterConfigs, AclOperation::IdempotentWrite,
+];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum AclPermissionType {
+    Unknown = 0,
+    Any = 1,
+    Deny = 2,
+    Allow = 3,
+}
+
+impl AclPermissionType {
+    fn from_code(code: i8) -> AclPermissionType {
+        match code {
+            1 => AclPermissionType::Any,
+            2 => AclPermissionType::Deny,
+            3 => AclPermissionType::Allow,
+            _ => AclPermissionType::Unknown,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct ResourcePattern {
+    pub resource_type: ResourceType,
+    pub name: String,
+    pub pattern_type: PatternType,
+}
+
+impl ResourcePattern {
+    // Whether ACLs on this pattern apply to the named resource
+    fn applies_to(&self, resource_type: ResourceType, resource_name: &str) -> bool {
+        self.resource_type == resource_type
+            && match self.pattern_type {
+                PatternType::Literal => self.name == resource_name || self.name == WILDCARD_RESOURCE,
+                PatternType::Prefixed => resource_name.starts_with(&self.name),
+                _ => false,
+            }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct AccessControlEntry {
+    // Type:name, e.g. User:alice, or User:* for every user
+    pub principal: String,
+    pub host: String,
+    pub operation: AclOperation,
+    pub permission_type: AclPermissionType,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct AclBinding {
+    pub pattern: ResourcePattern,
+    pub entry: AccessControlEntry,
+}
+
+// Selects ACLs for DescribeAcls and DeleteAcls; None fields and ANY codes match everything
+pub struct AclBindingFilter {
+    pub resource_type: ResourceType,
+    pub resource_name: Option<String>,
+    pub pattern_type: PatternType,
+    pub principal: Option<String>,
+    pub host: Option<String>,
+    pub operation: AclOperation,
+    pub permission_type: AclPermissionType,
+}
+
+impl AclBindingFilter {
+    pub fn matches(&self, binding: &AclBinding) -> bool {
+        let pattern = &binding.pattern;
+        let entry = &binding.entry;
+        let pattern_matches = (self.resource_type == ResourceType::Any || self.resource_type == pattern.resource_type)
+            && match (self.pattern_type, &self.resource_name) {
+                (PatternType::Any | PatternType::Match, None) => true,
+                (pattern_type, None) => pattern_type == pattern.pattern_type,
+                (PatternType::Any, Some(name)) => *name == pattern.name,
+                (PatternType::Match, Some(name)) => pattern.applies_to(pattern.resource_type, name),
+                (pattern_type, Some(name)) => pattern_type == pattern.pattern_type && *name == pattern.name,
+            };
+        pattern_matches
+            && self.principal.as_ref().map_or(true, |principal| *principal == entry.principal)
+            && self.host.as_ref().map_or(true, |host| *host == entry.host)
+            && (self.operation == AclOperation::Any || self.operation == entry.operation)
+            && (self.permission_type == AclPermissionType::Any || self.permission_type == entry.permission_type)
+    }
+
+    fn has_unknown_elements(&self) -> bool {
+        self.resource_type == ResourceType::Unknown
+            || self.pattern_type == PatternType::Unknown
+            || self.operation == AclOperation::Unknown
+            || self.permission_type == AclPermissionType::Unknown
+    }
+}
+
+// Who is asking: the principal as Type:name and the client's IP address
+pub struct AuthorizationContext {
+    pub principal: String,
+    pub host: String,
+}
+
+impl AuthorizationContext {
+    pub fn of(connection: &ClientConnection) -> AuthorizationContext {
+        let host = connection
+            .peer
+            .parse::<SocketAddr>()
+            .map(|address| address.ip().to_string())
+            .unwrap_or_else(|_| connection.peer.clone());
+        AuthorizationContext { principal: format!("User:{}", connection.principal()), host }
+    }
+}
+
+pub struct Action<'a> {
+    pub operation: AclOperation,
+    pub resource_type: ResourceType,
+    pub resource_name: &'a str,
+    // Authorized-operations lookups probe every operation, so their denials aren't worth logging
+    pub log_if_denied: bool,
+}
+
+// Decides which requests a principal may make and manages the ACLs behind those decisions. Another
+// authorizer can be installed with set_authorizer before the broker starts serving; otherwise
+// authorizer.class.name picks one.
+pub trait Authorizer: Send + Sync {
+    fn authorize(&self, context: &AuthorizationContext, action: &Action) -> bool;
+    fn acls(&self, filter: &AclBindingFilter) -> Vec<AclBinding>;
+    // One result per binding, in order. Errors are (error_code, error_message).
+    fn create_acls(&self, bindings: Vec<AclBinding>) -> Vec<Result<(), (i16, String)>>;
+    // One result per filter, in order: the ACLs the filter deleted
+    fn delete_acls(&self, filters: &[AclBindingFilter]) -> Vec<Result<Vec<AclBinding>, (i16, String)>>;
+}
+
+static AUTHORIZER: OnceLock<Box<dyn Authorizer>> = OnceLock::new();
+
+pub fn set_authorizer(authorizer: Box<dyn Authorizer>) {
+    if AUTHORIZER.set(authorizer).is_err() {
+        println!("authorizer was already initialized");
+    }
+}
+
+// Installs the authorizer authorizer.class.name names unless another one was installed; called once at startup
+pub fn init_authorizer(config: &BrokerConfig) {
+    if AUTHORIZER.get().is_some() || config.authorizer_class_name.as_deref() != Some(STANDARD_AUTHORIZER) {
+        return;
+    }
+    set_authorizer(Box::new(StandardAuthorizer {
+        super_users: config.super_users.clone(),
+        allow_everyone_if_no_acl_found: config.allow_everyone_if_no_acl_found,
+    }));
+}
+
+pub fn authorizer() -> Option<&'static dyn Authorizer> {
+    AUTHORIZER.get().map(|authorizer| authorizer.as_ref())
+}
+
+// Without an authorizer every request is allowed, as in Kafka
+pub fn authorize(connection: &ClientConnection, operation: AclOperation, resource_type: ResourceType, resource_name: &str) -> bool {
+    let Some(authorizer) = authorizer() else {
+        return true;
+    };
+    let action = Action { operation, resource_type, resource_name, log_if_denied: true };
+    authorizer.authorize(&AuthorizationContext::of(connection), &action)
+}
+
+// The bit field of the operations the connection may perform on a resource, bit n for operation code n
+pub fn authorized_operations(connection: &ClientConnection, resource_type: ResourceType, resource_name: &str,
+                             operations: &[AclOperation]) -> i32 {
+    let context = AuthorizationContext::of(connection);
+    operations
+        .iter()
+        .filter(|operation| {
+            authorizer().map_or(true, |authorizer| {
+                let action = Action { operation: **operation, resource_type, resource_name, log_if_denied: false };
+                authorizer.authorize(&context, &action)
+            })
+        })
+        .fold(0, |operations, operation| operations | 1 << *operation as i32)
+}
+
+// ACLs by ID, as replayed from the metadata log
+static ACLS: Mutex<BTreeMap<[u8; 16], AclBinding>> = Mutex::new(BTreeMap::new());
+
+static ACL_UPDATE_LOCK: Mutex<()> = Mutex::new(());
+
+enum AclChange {
+    Add([u8; 16], AclBinding),
+    Remove([u8; 16]),
+}
+
+// Applies an AccessControlEntryRecord or RemoveAccessControlEntryRecord read back from the metadata log
+pub fn replay(record: &MetadataRecord) {
+    let change = match record.record_type {
+        ACCESS_CONTROL_ENTRY_RECORD => decode_access_control_entry_record(&record.data),
+        REMOVE_ACCESS_CONTROL_ENTRY_RECORD => read_uuid(&record.data, 0).map(|(id, _)| AclChange::Remove(id)),
+        _ => return,
+    };
+    match change {
+        Ok(change) => apply_acl_change(change),
+        Err(e) => println!("error decoding ACL record at offset {}: {}", record.offset, e),
+    }
+}
+
+fn apply_acl_change(change: AclChange) {
+    let mut acls = ACLS.lock().unwrap();
+    match change {
+        AclChange::Add(id, binding) => {
+            println!("added ACL {}", describe_binding(&binding));
+            acls.insert(id, binding);
+        }
+        AclChange::Remove(id) => {
+            if let Some(binding) = acls.remove(&id) {
+                println!("removed ACL {}", describe_binding(&binding));
+            }
+        }
+    }
+}
+
+// Persists the changes to the metadata log, then applies them
+fn commit_acl_changes(changes: Vec<AclChange>) -> Result<(), String> {
+    let records: Vec<(u32, u32, Vec<u8>)> = changes.iter().map(encode_acl_change).collect();
+    metadata_log::append(&records).map_err(|e| format!("Unable to persist ACLs: {}", e))?;
+    for change in changes {
+        apply_acl_change(change);
+    }
+    Ok(())
+}
+
+fn describe_binding(binding: &AclBinding) -> String {
+    let (pattern, entry) = (&binding.pattern, &binding.entry);
+    let permission = if entry.permission_type == AclPermissionType::Deny { "Deny" } else { "Allow" };
+    format!("{} {} {} from host {} on {}:{}:{}", permission, entry.principal, entry.operation.name(), entry.host,
+            pattern.resource_type.name(), pattern.pattern_type.name(), pattern.name)
+}
+
+fn encode_acl_change(change: &AclChange) -> (u32, u32, Vec<u8>) {
+    let mut data = Vec::new();
+    match change {
+        AclChange::Add(id, binding) => {
+            data.extend_from_slice(id);                                          // id (16 bytes)
+            data.push(binding.pattern.resource_type as u8);                      // resource_type (1 byte)
+            write_compact_string(&mut data, &binding.pattern.name);              // resource_name
+            data.push(binding.pattern.pattern_type as u8);                       // pattern_type (1 byte)
+            write_compact_string(&mut data, &binding.entry.principal);           // principal
+            write_compact_string(&mut data, &binding.entry.host);                // host
+            data.push(binding.entry.operation as u8);                            // operation (1 byte)
+            data.push(binding.entry.permission_type as u8);                      // permission_type (1 byte)
+            data.push(0);                                                        // tag buffer (1 byte)
+            (ACCESS_CONTROL_ENTRY_RECORD, 0, data)
+        }
+        AclChange::Remove(id) => {
+            data.extend_from_slice(id); // id (16 bytes)
+            data.push(0);               // tag buffer (1 byte)
+            (REMOVE_ACCESS_CONTROL_ENTRY_RECORD, 0, data)
+        }
+    }
+}
+
+fn read_uuid(data: &[u8], offset: usize) -> Result<([u8; 16], usize), &'static str> {
+    let bytes = data.get(offset..offset + 16).ok_or("Buffer too short for uuid")?;
+    Ok((bytes.try_into().unwrap(), offset + 16))
+}
+
+fn decode_access_control_entry_record(data: &[u8]) -> Result<AclChange, &'static str> {
+    let (id, offset) = read_uuid(data, 0)?;
+    let resource_type = ResourceType::from_code(read_i8(data, offset)?);
+    let (name, offset) = read_compact_string(data, offset + 1)?;
+    let pattern_type = PatternType::from_code(read_i8(data, offset)?);
+    let (principal, offset) = read_compact_string(data, offset + 1)?;
+    let (host, offset) = read_compact_string(data, offset)?;
+    let operation = AclOperation::from_code(read_i8(data, offset)?);
+    let permission_type = AclPermissionType::from_code(read_i8(data, offset + 1)?);
+    let binding = AclBinding {
+        pattern: ResourcePattern { resource_type, name, pattern_type },
+        entry: AccessControlEntry { principal, host, operation, permission_type },
+    };
+    validate_new_acl(&binding).map_err(|_| "invalid ACL")?;
+    Ok(AclChange::Add(id, binding))
+}
+
+// The checks Kafka's controller makes before storing an ACL
+fn validate_new_acl(binding: &AclBinding) -> Result<(), String> {
+    let (pattern, entry) = (&binding.pattern, &binding.entry);
+    if matches!(pattern.resource_type, ResourceType::Unknown | ResourceType::Any) {
+        return Err(format!("Invalid resourceType {}", pattern.resource_type.name()));
+    }
+    if !matches!(pattern.pattern_type, PatternType::Literal | PatternType::Prefixed) {
+        return Err(format!("Invalid patternType {}", pattern.pattern_type.name()));
+    }
+    if matches!(entry.operation, AclOperation::Unknown | AclOperation::Any) {
+        return Err(format!("Invalid operation {}", entry.operation.name()));
+    }
+    if !matches!(entry.permission_type, AclPermissionType::Deny | AclPermissionType::Allow) {
+        return Err("Invalid permissionType".to_string());
+    }
+    if pattern.name.is_empty() {
+        return Err("Resource name should not be empty".to_string());
+    }
+    if pattern.resource_type == ResourceType::Cluster && pattern.name != CLUSTER_NAME {
+        return Err(format!("The only valid name for the CLUSTER resource is {}", CLUSTER_NAME));
+    }
+    if !entry.principal.contains(':') {
+        return Err(format!("Could not parse principal from `{}` (no colon is present separating the principal type from the principal name)",
+                           entry.principal));
+    }
+    Ok(())
+}
+
+// The ACL authorizer: super users may do anything; otherwise a DENY ACL that matches wins over
+// any ALLOW ACL, and a resource without any ACL falls back to allow.everyone.if.no.acl.found
+pub struct StandardAuthorizer {
+    super_users: Vec<String>,
+    allow_everyone_if_no_acl_found: bool,
+}
+
+impl StandardAuthorizer {
+    fn acls_allow(&self, context: &AuthorizationContext, action: &Action) -> bool {
+        let acls = ACLS.lock().unwrap();
+        let resource_acls: Vec<&AccessControlEntry> = acls
+            .values()
+            .filter(|binding| binding.pattern.applies_to(action.resource_type, action.resource_name))
+            .map(|binding| &binding.entry)
+            .collect();
+        if resource_acls.is_empty() {
+            return self.allow_everyone_if_no_acl_found;
+        }
+
+        let matching: Vec<&&AccessControlEntry> = resource_acls
+            .iter()
+            .filter(|entry| entry.principal == context.principal || entry.principal == WILDCARD_PRINCIPAL)
+            .filter(|entry| entry.host == context.host || entry.host == WILDCARD_HOST)
+            .collect();
+        let denied = matching.iter().any(|entry| {
+            entry.permission_type == AclPermissionType::Deny
+                && (entry.operation == AclOperation::All || entry.operation == action.operation)
+        });
+        !denied && matching.iter().any(|entry| entry.permission_type == AclPermissionType::Allow && entry.operation.allows(action.operation))
+    }
+}
+
+impl Authorizer for StandardAuthorizer {
+    fn authorize(&self, context: &AuthorizationContext, action: &Action) -> bool {
+        let allowed = self.super_users.contains(&context.principal) || self.acls_allow(context, action);
+        if !allowed && action.log_if_denied {
+            println!("Principal = {} is Denied operation = {} from host = {} on resource = {}:{}",
+                     context.principal, action.operation.name(), context.host, action.resource_type.name(), action.resource_name);
+        }
+        allowed
+    }
+
+    fn acls(&self, filter: &AclBindingFilter) -> Vec<AclBinding> {
+        ACLS.lock().unwrap().values().filter(|binding| filter.matches(binding)).cloned().collect()
+    }
+
+    fn create_acls(&self, bindings: Vec<AclBinding>) -> Vec<Result<(), (i16, String)>> {
+        let mut results: Vec<Result<(), (i16, String)>> = bindings
+            .iter()
+            .map(|binding| validate_new_acl(binding).map_err(|message| (42, message))) // INVALID_REQUEST
+            .collect();
+
+        // Requests are handled concurrently; the existence check and apply must not interleave with another update
+        let _update_guard = ACL_UPDATE_LOCK.lock().unwrap();
+        let mut existing: BTreeSet<AclBinding> = ACLS.lock().unwrap().values().cloned().collect();
+        let random = SystemRandom::new();
+        let mut changes = Vec::new();
+        let mut created = Vec::new();
+        for (index, binding) in bindings.into_iter().enumerate() {
+            // Creating an ACL that already exists succeeds without a new record
+            if results[index].is_err() || existing.contains(&binding) {
+                continue;
+            }
+            let mut id = [0u8; 16];
+            if random.fill(&mut id).is_err() {
+                results[index] = Err((-1, "Unable to generate an ACL id".to_string())); // UNKNOWN_SERVER_ERROR
+                continue;
+            }
+            existing.insert(binding.clone());
+            changes.push(AclChange::Add(id, binding));
+            created.push(index);
+        }
+
+        if let Err(e) = commit_acl_changes(changes) {
+            println!("error creating ACLs: {}", e);
+            for index in created {
+                results[index] = Err((-1, e.clone())); // UNKNOWN_SERVER_ERROR
+            }
+        }
+        results
+    }
+
+    fn delete_acls(&self, filters: &[AclBindingFilter]) -> Vec<Result<Vec<AclBinding>, (i16, String)>> {
+        let _update_guard = ACL_UPDATE_LOCK.lock().unwrap();
+        let acls = ACLS.lock().unwrap().clone();
+
+        // An ACL several filters match is removed once but reported under each of them
+        let mut removed = BTreeSet::new();
+        let mut results = Vec::new();
+        for filter in filters {
+            if filter.has_unknown_elements() {
+                results.push(Err((42, "The filter contains UNKNOWN elements".to_string()))); // INVALID_REQUEST
+                continue;
+            }
+            let matching: Vec<(&[u8; 16], &AclBinding)> = acls.iter().filter(|(_, binding)| filter.matches(binding)).collect();
+            removed.extend(matching.iter().map(|(id, _)| **id));
+            results.push(Ok(matching.into_iter().map(|(_, binding)| binding.clone()).collect()));
+        }
+
+        let changes: Vec<AclChange> = removed.into_iter().map(AclChange::Remove).collect();
+        if let Err(e) = commit_acl_changes(changes) {
+            println!("error deleting ACLs: {}", e);
+            return filters.iter().map(|_| Err((-1, e.clone()))).collect(); // UNKNOWN_SERVER_ERROR
+        }
+        results
+    }
+}
+
+// Reads the filter fields DescribeAcls and DeleteAcls share; v0 predates prefixed ACLs and only matches literal ones
+fn read_acl_binding_filter(buffer: &[u8], offset: usize, api_version: i16, flexible: bool) -> Result<(AclBindingFilter, usize), &'static str> {
+    let resource_type = ResourceType::from_code(read_i8(buffer, offset)?);
+    let (resource_name, mut offset) = read_nullable_string(buffer, offset + 1, flexible)?;
+    let mut pattern_type = PatternType::Literal;
+    if api_version >= 1 {
+        pattern_type = PatternType::from_code(read_i8(buffer, offset)?);
+        offset += 1;
+    }
+    let (principal, offset) = read_nullable_string(buffer, offset, flexible)?;
+    let (host, offset) = read_nullable_string(buffer, offset, flexible)?;
+    let operation = AclOperation::from_code(read_i8(buffer, offset)?);
+    let permission_type = AclPermissionType::from_code(read_i8(buffer, offset + 1)?);
+    let filter = AclBindingFilter { resource_type, resource_name, pattern_type, principal, host, operation, permission_type };
+    Ok((filter, offset + 2))
+}
+
+fn write_acl_entry(buffer: &mut Vec<u8>, entry: &AccessControlEntry, flexible: bool) {
+    write_string(buffer, &entry.principal, flexible);
+    write_string(buffer, &entry.host, flexible);
+    buffer.push(entry.operation as u8);       // operation (1 byte)
+    buffer.push(entry.permission_type as u8); // permission_type (1 byte)
+}
+
+fn parse_describe_acls_request(buffer: &[u8], api_version: i16) -> Result<AclBindingFilter, &'static str> {
+    // v2+ is flexible
+    let flexible = api_version >= 2;
+    let offset = skip_request_header(buffer, flexible)?;
+    let (filter, offset) = read_acl_binding_filter(buffer, offset, api_version, flexible)?;
+    if flexible {
+        skip_tagged_fields(buffer, offset)?;
+    }
+    Ok(filter)
+}
+
+fn create_describe_acls_response(context: &RequestContext, error_code: i16, error_message: Option<&str>, acls: &[AclBinding]) -> Vec<u8> {
+    let api_version = context.api_version;
+    let flexible = api_version >= 2;
+    let mut response_body = Vec::new();
+
+    // Response header
+    response_body.extend_from_slice(&context.correlation_id.to_be_bytes()); // correlation_id (4 bytes)
+    if flexible {
+        response_body.push(0); // tag buffer (1 byte)
+    }
+
+    // Response body
+    response_body.extend_from_slice(&context.throttle_time_ms.to_be_bytes()); // throttle_time_ms (4 bytes)
+    response_body.extend_from_slice(&error_code.to_be_bytes());              // error_code (2 bytes)
+    write_nullable_string(&mut response_body, error_message, flexible);
+
+    // ACLs are grouped by the resource pattern they're on
+    let mut resources: BTreeMap<&ResourcePattern, Vec<&AccessControlEntry>> = BTreeMap::new();
+    for binding in acls {
+        resources.entry(&binding.pattern).or_default().push(&binding.entry);
+    }
+    write_array_length(&mut response_body, resources.len(), flexible);
+    for (pattern, entries) in resources {
+        response_body.push(pattern.resource_type as u8); // resource_type (1 byte)
+        write_string(&mut response_body, &pattern.name, flexible);
+        if api_version >= 1 {
+            response_body.push(pattern.pattern_type as u8); // pattern_type (1 byte)
+        }
+        write_array_length(&mut response_body, entries.len(), flexible);
+        for entry in entries {
+            write_acl_entry(&mut response_body, entry, flexible);
+            if flexible {
+                response_body.push(0); // tag buffer (1 byte)
+            }
+        }
+        if flexible {
+            response_body.push(0); // tag buffer (1 byte)
+        }
+    }
+    if flexible {
+        response_body.push(0); // tag buffer (1 byte)
+    }
+
+    frame_response(response_body)
+}
+
+pub fn handle_describe_acls_request(context: &RequestContext, message_buffer: &[u8]) -> Vec<u8> {
+    let filter = match parse_describe_acls_request(message_buffer, context.api_version) {
+        Ok(filter) => filter,
+        Err(e) => {
+            println!("error parsing DescribeAcls request: {}", e);
+            return create_describe_acls_error_response(context, message_buffer, 42); // INVALID_REQUEST
+        }
+    };
+    if !authorize(context.connection, AclOperation::Describe, ResourceType::Cluster, CLUSTER_NAME) {
+        return create_describe_acls_error_response(context, message_buffer, 31); // CLUSTER_AUTHORIZATION_FAILED
+    }
+    let Some(authorizer) = authorizer() else {
+        return create_describe_acls_response(context, 54, Some("No Authorizer is configured."), &[]); // SECURITY_DISABLED
+    };
+    if filter.has_unknown_elements() {
+        return create_describe_acls_response(context, 42, Some("The filter contains UNKNOWN elements"), &[]); // INVALID_REQUEST
+    }
+    create_describe_acls_response(context, 0, None, &authorizer.acls(&filter))
+}
+
+pub fn create_describe_acls_error_response(context: &RequestContext, _message_buffer: &[u8], error_code: i16) -> Vec<u8> {
+    create_describe_acls_response(context, error_code, None, &[])
+}
+
+fn parse_create_acls_request(buffer: &[u8], api_version: i16) -> Result<Vec<AclBinding>, &'static str> {
+    // v2+ is flexible
+    let flexible = api_version >= 2;
+    let mut offset = skip_request_header(buffer, flexible)?;
+
+    let (creations_count, new_offset) = read_array_length(buffer, offset, flexible)?;
+    offset = new_offset;
+    let mut creations = Vec::new();
+    for _ in 0..creations_count.unwrap_or(0) {
+        let resource_type = ResourceType::from_code(read_i8(buffer, offset)?);
+        let (name, new_offset) = read_string(buffer, offset + 1, flexible)?;
+        offset = new_offset;
+        // v0 predates prefixed ACLs
+        let mut pattern_type = PatternType::Literal;
+        if api_version >= 1 {
+            pattern_type = PatternType::from_code(read_i8(buffer, offset)?);
+            offset += 1;
+        }
+        let (principal, new_offset) = read_string(buffer, offset, flexible)?;
+        let (host, new_offset) = read_string(buffer, new_offset, flexible)?;
+        let operation = AclOperation::from_code(read_i8(buffer, new_offset)?);
+        let permission_type = AclPermissionType::from_code(read_i8(buffer, new_offset + 1)?);
+        offset = new_offset + 2;
+        if flexible {
+            offset = skip_tagged_fields(buffer, offset)?;
+        }
+        creations.push(AclBinding {
+            pattern: ResourcePattern { resource_type, name, pattern_type },
+            entry: AccessControlEntry { principal, host, operation, permission_type },
+        });
+    }
+    if flexible {
+        skip_tagged_fields(buffer, offset)?;
+    }
+    Ok(creations)
+}
+
+fn create_create_acls_response(context: &RequestContext, results: &[Result<(), (i16, String)>]) -> Vec<u8> {
+    let flexible = context.api_version >= 2;
+    let mut response_body = Vec::new();
+
+    // Response header
+    response_body.extend_from_slice(&context.correlation_id.to_be_bytes()); // correlation_id (4 bytes)
+    if flexible {
+        response_body.push(0); // tag buffer (1 byte)
+    }
+
+    // Response body
+    response_body.extend_from_slice(&context.throttle_time_ms.to_be_bytes()); // throttle_time_ms (4 bytes)
+    write_array_length(&mut response_body, results.len(), flexible);
+    for result in results {
+        let (error_code, error_message) = match result {
+            Ok(()) => (0, None),
+            // Error responses built from just a code carry a null message
+            Err((error_code, error_message)) => (*error_code, Some(error_message.as_str()).filter(|message| !message.is_empty())),
+        };
+        response_body.extend_from_slice(&error_code.to_be_bytes()); // error_code (2 bytes)
+        write_nullable_string(&mut response_body, error_message, flexible);
+        if flexible {
+            response_body.push(0); // tag buffer (1 byte)
+        }
+    }
+    if flexible {
+        response_body.push(0); // tag buffer (1 byte)
+    }
+
+    frame_response(response_body)
+}
+
+pub fn handle_create_acls_request(context: &RequestContext, message_buffer: &[u8]) -> Vec<u8> {
+    let creations = match parse_create_acls_request(message_buffer, context.api_version) {
+        Ok(creations) => creations,
+        Err(e) => {
+            println!("error parsing CreateAcls request: {}", e);
+            return create_create_acls_error_response(context, message_buffer, 42); // INVALID_REQUEST
+        }
+    };
+    if !authorize(context.connection, AclOperation::Alter, ResourceType::Cluster, CLUSTER_NAME) {
+        return create_create_acls_error_response(context, message_buffer, 31); // CLUSTER_AUTHORIZATION_FAILED
+    }
+    let Some(authorizer) = authorizer() else {
+        let results: Vec<_> = creations.iter().map(|_| Err((54, "No Authorizer is configured.".to_string()))).collect(); // SECURITY_DISABLED
+        return create_create_acls_response(context, &results);
+    };
+
+    let descriptions: Vec<String> = creations.iter().map(describe_binding).collect();
+    let results = authorizer.create_acls(creations);
+    for (description, result) in descriptions.iter().zip(&results) {
+        if let Err((_, message)) = result {
+            println!("CreateAcls rejected {}: {}", description, message);
+        }
+    }
+    create_create_acls_response(context, &results)
+}
+
+pub fn create_create_acls_error_response(context: &RequestContext, message_buffer: &[u8], error_code: i16) -> Vec<u8> {
+    let results: Vec<_> = parse_create_acls_request(message_buffer, context.api_version)
+        .unwrap_or_default()
+        .iter()
+        .map(|_| Err((error_code, String::new())))
+        .collect();
+    create_create_acls_response(context, &results)
+}
+
+fn parse_delete_acls_request(buffer: &[u8], api_version: i16) -> Result<Vec<AclBindingFilter>, &'static str> {
+    // v2+ is flexible
+    let flexible = api_version >= 2;
+    let mut offset = skip_request_header(buffer, flexible)?;
+
+    let (filters_count, new_offset) = read_array_length(buffer, offset, flexible)?;
+    offset = new_offset;
+    let mut filters = Vec::new();
+    for _ in 0..filters_count.unwrap_or(0) {
+        let (filter, new_offset) = read_acl_binding_filter(buffer, offset, api_version, flexible)?;
+        offset = if flexible { skip_tagged_fields(buffer, new_offset)? } else { new_offset };
+        filters.push(filter);
+    }
+    if flexible {
+        skip_tagged_fields(buffer, offset)?;
+    }
+    Ok(filters)
+}
+
+type DeleteAclsResult = Result<Vec<AclBinding>, (i16, String)>;
+
+fn create_delete_acls_response(context: &RequestContext, results: &[DeleteAclsResult]) -> Vec<u8> {
+    let api_version = context.api_version;
+    let flexible = api_version >= 2;
+    let mut response_body = Vec::new();
+
+    // Response header
+    response_body.extend_from_slice(&context.correlation_id.to_be_bytes()); // correlation_id (4 bytes)
+    if flexible {
+        response_body.push(0); // tag buffer (1 byte)
+    }
+
+    // Response body
+    response_body.extend_from_slice(&context.throttle_time_ms.to_be_bytes()); // throttle_time_ms (4 bytes)
+    write_array_length(&mut response_body, results.len(), flexible);
+    for result in results {
+        let (error_code, error_message, matching_acls) = match result {
+            Ok(matching_acls) => (0, None, &matching_acls[..]),
+            // Error responses built from just a code carry a null message
+            Err((error_code, error_message)) => (*error_code, Some(error_message.as_str()).filter(|message| !message.is_empty()), &[][..]),
+        };
+        response_body.extend_from_slice(&error_code.to_be_bytes()); // error_code (2 bytes)
+        write_nullable_string(&mut response_body, error_message, flexible);
+
+        write_array_length(&mut response_body, matching_acls.len(), flexible);
+        for binding in matching_acls {
+            response_body.extend_from_slice(&0i16.to_be_bytes());      // error_code (2 bytes)
+            write_nullable_string(&mut response_body, None, flexible); // error_message
+            response_body.push(binding.pattern.resource_type as u8);  // resource_type (1 byte)
+            write_string(&mut response_body, &binding.pattern.name, flexible);
+            if api_version >= 1 {
+                response_body.push(binding.pattern.pattern_type as u8); // pattern_type (1 byte)
+            }
+            write_acl_entry(&mut response_body, &binding.entry, flexible);
+            if flexible {
+                response_body.push(0); // tag buffer (1 byte)
+            }
+        }
+        if flexible {
+            response_body.push(0); // tag buffer (1 byte)
+        }
+    }
+    if flexible {
+        response_body.push(0); // tag buffer (1 byte)
+    }
+
+    frame_response(response_body)
+}
+
+pub fn handle_delete_acls_request(context: &RequestContext, message_buffer: &[u8]) -> Vec<u8> {
+    let filters = match parse_delete_acls_request(message_buffer, context.api_version) {
+        Ok(filters) => filters,
+        Err(e) => {
+            println!("error parsing DeleteAcls request: {}", e);
+            return create_delete_acls_error_response(context, message_buffer, 42); // INVALID_REQUEST
+        }
+    };
+    if !authorize(context.connection, AclOperation::Alter, ResourceType::Cluster, CLUSTER_NAME) {
+        return create_delete_acls_error_response(context, message_buffer, 31); // CLUSTER_AUTHORIZATION_FAILED
+    }
+    let Some(authorizer) = authorizer() else {
+        let results: Vec<_> = filters.iter().map(|_| Err((54, "No Authorizer is configured.".to_string()))).collect(); // SECURITY_DISABLED
+        return create_delete_acls_response(context, &results);
+    };
+
+    let results = authorizer.delete_acls(&filters);
+    create_delete_acls_response(context, &results)
+}
+
+pub fn create_delete_acls_error_response(context: &RequestContext, message_buffer: &[u8], error_code: i16) -> Vec<u8> {
+    let results: Vec<_> = parse_delete_acls_request(message_buffer, context.api_version)
+        .unwrap_or_default()
+        .iter()
+        .map(|_| Err((error_code, String::new())))
+        .collect();
+    create_delete_acls_response(context, &results)
+}
@@ -0,0 +1,73 @@
+// RFC 4648 base64: the standard alphabet with padding, as SCRAM messages use it, and the unpadded
+// URL-safe alphabet of JWTs, JWKS documents and delegation token IDs
+
+const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+const URL_SAFE_ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_";
+
+pub fn encode(data: &[u8]) -> String {
+    encode_with_alphabet(data, ALPHABET)
+}
+
+// base64url without padding, e.g. for delegation token IDs
+pub fn encode_url_safe(data: &[u8]) -> String {
+    encode_with_alphabet(data, URL_SAFE_ALPHABET).trim_end_matches('=').to_string()
+}
+
+fn encode_with_alphabet(data: &[u8], alphabet: &[u8; 64]) -> String {
+    let mut encoded = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let bytes = [chunk[0], chunk.get(1).copied().unwrap_or(0), chunk.get(2).copied().unwrap_or(0)];
+        let group = (bytes[0] as u32) << 16 | (bytes[1] as u32) << 8 | bytes[2] as u32;
+        for index in 0..4 {
+            if index <= chunk.len() {
+                encoded.push(alphabet[(group >> (18 - 6 * index) & 0x3f) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+// Rejects anything but canonical padded input: no whitespace, and padding only at the end
+pub fn decode(encoded: &str) -> Result<Vec<u8>, &'static str> {
+    if encoded.len() % 4 != 0 {
+        return Err("base64 length is not a multiple of 4");
+    }
+    decode_with_alphabet(encoded.as_bytes(), ALPHABET)
+}
+
+// base64url without padding (RFC 7515 section 2)
+pub fn decode_url_safe(encoded: &str) -> Result<Vec<u8>, &'static str> {
+    if encoded.contains('=') {
+        return Err("base64url input is padded");
+    }
+    if encoded.len() % 4 == 1 {
+        return Err("invalid base64url length");
+    }
+    let mut padded = encoded.as_bytes().to_vec();
+    padded.resize(encoded.len().div_ceil(4) * 4, b'=');
+    decode_with_alphabet(&padded, URL_SAFE_ALPHABET)
+}
+
+fn decode_with_alphabet(input: &[u8], alphabet: &[u8; 64]) -> Result<Vec<u8>, &'static str> {
+    let mut decoded = Vec::with_capacity(input.len() / 4 * 3);
+    for (chunk_index, chunk) in input.chunks(4).enumerate() {
+        let is_last = chunk_index == input.len() / 4 - 1;
+        let padding = chunk.iter().rev().take_while(|byte| **byte == b'=').count();
+        if padding > 2 || (padding > 0 && !is_last) {
+            return Err("misplaced base64 padding");
+        }
+
+        let mut group = 0u32;
+        for byte in &chunk[..4 - padding] {
+            let value = alphabet.iter().position(|symbol| symbol == byte).ok_or("invalid base64 character")?;
+            group = group << 6 | value as u32;
+        }
+        group <<= 6 * padding as u32;
+
+        let bytes = group.to_be_bytes();
+        decoded.extend_from_slice(&bytes[1..4 - padding]);
+    }
+    Ok(decoded)
+}
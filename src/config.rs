@@ -0,0 +1,1241 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::net::{IpAddr, ToSocketAddrs};
+use std::path::Path;
+use std::sync::OnceLock;
+
+use crate::authorizer::{authorize, AclOperation, ResourceType, CLUSTER_NAME, STANDARD_AUTHORIZER};
+use crate::sasl::SUPPORTED_MECHANISMS;
+use crate::ssl::{parse_principal_mapping_rules, PrincipalMappingRule, SslClientAuth};
+use crate::{
+    frame_response, read_array_length, read_i8, read_string, skip_request_header, skip_tagged_fields,
+    write_array_length, write_nullable_string, write_string, RequestContext,
+};
+
+// DescribeConfigs config_type codes
+#[derive(Clone, Copy, PartialEq)]
+pub enum ConfigType {
+    Boolean = 1,
+    String = 2,
+    Int = 3,
+    Long = 5,
+    List = 7,
+    Password = 9,
+}
+
+pub struct ConfigDef {
+    pub name: &'static str,
+    pub config_type: ConfigType,
+    pub default: Option<&'static str>,
+    pub documentation: &'static str,
+}
+
+// Every key the broker uses, which DescribeConfigs reports. Any key that is neither here nor in
+// IGNORED_CONFIG_KEYS is a config error.
+pub const CONFIG_DEFS: &[ConfigDef] = &[
+    ConfigDef { name: "node.id", config_type: ConfigType::Int, default: Some("1"),
+                documentation: "The node ID of this server." },
+    ConfigDef { name: "process.roles", config_type: ConfigType::List, default: Some("broker,controller"),
+                documentation: "The roles this process plays: broker, optionally with controller. No controller quorum is run either way." },
+    ConfigDef { name: "cluster.id", config_type: ConfigType::String, default: None,
+                documentation: "The cluster ID. When unset it is read from meta.properties in the metadata log directory." },
+    ConfigDef { name: "listeners", config_type: ConfigType::List, default: Some("PLAINTEXT://127.0.0.1:9092"),
+                documentation: "Comma-separated NAME://host:port URIs the broker listens on. An empty host binds all interfaces." },
+    ConfigDef { name: "advertised.listeners", config_type: ConfigType::List, default: None,
+                documentation: "Listeners to publish to clients if different from listeners." },
+    ConfigDef { name: "listener.security.protocol.map", config_type: ConfigType::List,
+                default: Some("PLAINTEXT:PLAINTEXT,SSL:SSL,SASL_PLAINTEXT:SASL_PLAINTEXT,SASL_SSL:SASL_SSL"),
+                documentation: "Map between listener names and security protocols." },
+    ConfigDef { name: "inter.broker.listener.name", config_type: ConfigType::String, default: None,
+                documentation: "Name of the listener used for communication between brokers." },
+    ConfigDef { name: "controller.listener.names", config_type: ConfigType::List, default: None,
+                documentation: "Names of the listeners used by the controller. They are not served to clients." },
+    ConfigDef { name: "log.dirs", config_type: ConfigType::List, default: None,
+                documentation: "The directories in which the log data is kept. If not set, the value in log.dir is used." },
+    ConfigDef { name: "log.dir", config_type: ConfigType::String, default: Some("/tmp/kraft-combined-logs"),
+                documentation: "The directory in which the log data is kept (supplemental for log.dirs)." },
+    ConfigDef { name: "metadata.log.dir", config_type: ConfigType::String, default: None,
+                documentation: "The directory of the cluster metadata log. If not set, the first log directory is used." },
+    ConfigDef { name: "num.network.threads", config_type: ConfigType::Int, default: Some("3"),
+                documentation: "The number of threads that receive requests from and send responses to the network." },
+    ConfigDef { name: "num.io.threads", config_type: ConfigType::Int, default: Some("8"),
+                documentation: "The number of threads the broker uses for processing requests." },
+    ConfigDef { name: "socket.send.buffer.bytes", config_type: ConfigType::Int, default: Some("102400"),
+                documentation: "The SO_SNDBUF buffer of the socket server sockets. If -1, the OS default is used." },
+    ConfigDef { name: "socket.receive.buffer.bytes", config_type: ConfigType::Int, default: Some("102400"),
+                documentation: "The SO_RCVBUF buffer of the socket server sockets. If -1, the OS default is used." },
+    ConfigDef { name: "socket.request.max.bytes", config_type: ConfigType::Int, default: Some("104857600"),
+                documentation: "The maximum number of bytes in a socket request." },
+    ConfigDef { name: "queued.max.request.bytes", config_type: ConfigType::Long, default: Some("536870912"),
+                documentation: "The number of queued bytes allowed before no more requests are read." },
+    ConfigDef { name: "queued.max.requests", config_type: ConfigType::Int, default: Some("500"),
+                documentation: "The number of requests, across all connections, read but not yet handled before no more requests are read." },
+    ConfigDef { name: "connections.max.idle.ms", config_type: ConfigType::Long, default: Some("600000"),
+                documentation: "Connections that send no request for this many milliseconds are closed." },
+    ConfigDef { name: "socket.listen.backlog.size", config_type: ConfigType::Int, default: Some("50"),
+                documentation: "The maximum number of pending connections on each listener socket." },
+    ConfigDef { name: "max.connections", config_type: ConfigType::Int, default: Some("2147483647"),
+                documentation: "The maximum number of connections open at any time, across all listeners. At the limit, no connection is accepted until one closes." },
+    ConfigDef { name: "max.connections.per.ip", config_type: ConfigType::Int, default: Some("2147483647"),
+                documentation: "The maximum number of connections open from each IP address. Connections over it are closed as soon as they are accepted." },
+    ConfigDef { name: "max.connections.per.ip.overrides", config_type: ConfigType::String, default: Some(""),
+                documentation: "Comma-separated host:count or ip:count overrides of max.connections.per.ip. Hostnames are resolved at startup." },
+    ConfigDef { name: "metrics.http.listener", config_type: ConfigType::String, default: Some(""),
+                documentation: "host:port serving broker metrics, such as open connections per client software, as Prometheus text over HTTP. Empty disables it." },
+    ConfigDef { name: "connections.max.in.flight.requests", config_type: ConfigType::Int, default: Some("5"),
+                documentation: "The maximum number of requests from one connection the broker processes concurrently; beyond it, reading pauses until the oldest response is written. Unrelated to the producer's max.in.flight.requests.per.connection." },
+    ConfigDef { name: "ssl.keystore.type", config_type: ConfigType::String, default: Some("PEM"),
+                documentation: "The file format of the key store file. Only PEM is supported." },
+    ConfigDef { name: "ssl.keystore.location", config_type: ConfigType::String, default: None,
+                documentation: "The PEM file holding the certificate chain and private key of SSL listeners. Changes are picked up without a restart." },
+    ConfigDef { name: "ssl.key.password", config_type: ConfigType::Password, default: None,
+                documentation: "The password of the private key in the key store file. Encrypted keys are not supported." },
+    ConfigDef { name: "ssl.truststore.type", config_type: ConfigType::String, default: Some("PEM"),
+                documentation: "The file format of the trust store file. Only PEM is supported." },
+    ConfigDef { name: "ssl.truststore.location", config_type: ConfigType::String, default: None,
+                documentation: "The PEM file of CA certificates client certificates are verified against. Changes are picked up without a restart." },
+    ConfigDef { name: "ssl.client.auth", config_type: ConfigType::String, default: Some("none"),
+                documentation: "Whether SSL listeners require (required), ask for (requested) or skip (none) client certificates." },
+    ConfigDef { name: "ssl.principal.mapping.rules", config_type: ConfigType::String, default: Some("DEFAULT"),
+                documentation: "Rules mapping a client certificate's distinguished name to a principal name: DEFAULT or RULE:pattern/replacement/[LU], comma-separated." },
+    ConfigDef { name: "sasl.enabled.mechanisms", config_type: ConfigType::List, default: Some("PLAIN"),
+                documentation: "The SASL mechanisms SASL listeners accept: PLAIN, SCRAM-SHA-256, SCRAM-SHA-512 and OAUTHBEARER. SCRAM credentials are managed with AlterUserScramCredentials." },
+    ConfigDef { name: "sasl.plain.credentials.file", config_type: ConfigType::String, default: None,
+                documentation: "Properties file of username=password entries the PLAIN mechanism authenticates against. Edits apply to the next authentication." },
+    ConfigDef { name: "sasl.oauthbearer.jwks.endpoint.url", config_type: ConfigType::String, default: None,
+                documentation: "A file: URL of the JWKS document whose keys OAUTHBEARER tokens are verified against. Edits apply to the next authentication." },
+    ConfigDef { name: "sasl.oauthbearer.expected.audience", config_type: ConfigType::List, default: None,
+                documentation: "Audiences of which an OAUTHBEARER token's aud claim must contain at least one. When unset the audience isn't checked." },
+    ConfigDef { name: "sasl.oauthbearer.expected.issuer", config_type: ConfigType::String, default: None,
+                documentation: "The iss claim OAUTHBEARER tokens must carry. When unset the issuer isn't checked." },
+    ConfigDef { name: "sasl.oauthbearer.sub.claim.name", config_type: ConfigType::String, default: Some("sub"),
+                documentation: "The OAUTHBEARER token claim whose value is the principal name." },
+    ConfigDef { name: "sasl.oauthbearer.clock.skew.seconds", config_type: ConfigType::Int, default: Some("30"),
+                documentation: "Seconds of clock difference with the token issuer tolerated when checking exp, iat and nbf." },
+    ConfigDef { name: "connections.max.reauth.ms", config_type: ConfigType::Long, default: Some("0"),
+                documentation: "When positive, the lifetime of a SASL session; clients must re-authenticate before it ends or the connection is closed. 0 disables re-authentication." },
+    ConfigDef { name: "delegation.token.secret.key", config_type: ConfigType::Password, default: None,
+                documentation: "The secret delegation token HMACs are generated and verified with. Delegation tokens are disabled when unset." },
+    ConfigDef { name: "delegation.token.max.lifetime.ms", config_type: ConfigType::Long, default: Some("604800000"),
+                documentation: "The longest a delegation token can live, however often it is renewed." },
+    ConfigDef { name: "delegation.token.expiry.time.ms", config_type: ConfigType::Long, default: Some("86400000"),
+                documentation: "How long a delegation token stays valid after creation or renewal when the request doesn't say." },
+    ConfigDef { name: "delegation.token.expiry.check.interval.ms", config_type: ConfigType::Long, default: Some("3600000"),
+                documentation: "How often expired delegation tokens are removed." },
+    ConfigDef { name: "authorizer.class.name", config_type: ConfigType::String, default: Some(""),
+                documentation: "The authorizer requests are checked against: org.apache.kafka.metadata.authorizer.StandardAuthorizer, or empty to allow every request." },
+    ConfigDef { name: "super.users", config_type: ConfigType::String, default: None,
+                documentation: "Semicolon-separated principals, e.g. User:admin;User:broker, that the authorizer allows every operation." },
+    ConfigDef { name: "allow.everyone.if.no.acl.found", config_type: ConfigType::Boolean, default: Some("false"),
+                documentation: "Whether the authorizer allows access to resources no ACL matches." },
+    ConfigDef { name: "quota.window.num", config_type: ConfigType::Int, default: Some("11"),
+                documentation: "The number of samples client quota rates are measured over." },
+    ConfigDef { name: "quota.window.size.seconds", config_type: ConfigType::Int, default: Some("1"),
+                documentation: "The time span of each client quota sample." },
+];
+
+// Keys of Kafka's stock server.properties, broker.properties and controller.properties and other common
+// settings for subsystems this broker doesn't have: no controller quorum, partition logs, replication,
+// or group, transaction and share coordinators. They are accepted, so an existing Kafka config loads,
+// but have no effect; the broker says so at startup and DescribeConfigs leaves them out.
+pub const IGNORED_CONFIG_KEYS: &[&str] = &[
+    "controller.quorum.voters", "controller.quorum.bootstrap.servers", "controller.quorum.election.timeout.ms",
+    "controller.quorum.election.backoff.max.ms", "controller.quorum.fetch.timeout.ms", "controller.quorum.request.timeout.ms",
+    "controller.quorum.retry.backoff.ms", "controller.quorum.append.linger.ms", "controller.socket.timeout.ms",
+    "broker.heartbeat.interval.ms", "broker.session.timeout.ms", "initial.broker.registration.timeout.ms", "broker.rack",
+    "metadata.log.segment.bytes", "metadata.log.segment.ms", "metadata.max.retention.bytes", "metadata.max.retention.ms",
+    "metadata.log.max.record.bytes.between.snapshots", "metadata.log.max.snapshot.interval.ms",
+    "background.threads", "num.partitions", "num.recovery.threads.per.data.dir", "default.replication.factor",
+    "min.insync.replicas", "unclean.leader.election.enable", "auto.create.topics.enable", "auto.leader.rebalance.enable",
+    "delete.topic.enable", "num.replica.fetchers", "replica.fetch.max.bytes", "replica.lag.time.max.ms",
+    "message.max.bytes", "compression.type",
+    "log.message.timestamp.type", "log.cleanup.policy", "log.cleaner.enable", "log.cleaner.threads",
+    "log.retention.hours", "log.retention.minutes", "log.retention.ms", "log.retention.bytes",
+    "log.retention.check.interval.ms", "log.roll.hours", "log.roll.ms", "log.segment.bytes", "log.index.size.max.bytes",
+    "log.index.interval.bytes", "log.flush.interval.messages", "log.flush.interval.ms",
+    "offsets.topic.replication.factor", "offsets.topic.num.partitions", "offsets.retention.minutes",
+    "transaction.state.log.replication.factor", "transaction.state.log.min.isr", "transaction.state.log.num.partitions",
+    "transactional.id.expiration.ms",
+    "share.coordinator.state.topic.replication.factor", "share.coordinator.state.topic.min.isr",
+    "share.coordinator.state.topic.num.partitions",
+    "group.initial.rebalance.delay.ms", "group.coordinator.rebalance.protocols", "group.consumer.session.timeout.ms",
+    "group.consumer.heartbeat.interval.ms", "group.max.size",
+    "metric.reporters", "metrics.num.samples", "metrics.sample.window.ms",
+];
+
+pub fn find_config_def(name: &str) -> Option<&'static ConfigDef> {
+    CONFIG_DEFS.iter().find(|def| def.name == name)
+}
+
+pub struct Listener {
+    pub name: String,
+    pub host: String,
+    pub port: u16,
+}
+
+// The protocol a listener speaks, from listener.security.protocol.map
+#[derive(Clone, Copy, PartialEq)]
+pub enum SecurityProtocol {
+    Plaintext,
+    Ssl,
+    SaslPlaintext,
+    SaslSsl,
+}
+
+impl SecurityProtocol {
+    fn parse(name: &str) -> Option<SecurityProtocol> {
+        match name.to_uppercase().as_str() {
+            "PLAINTEXT" => Some(SecurityProtocol::Plaintext),
+            "SSL" => Some(SecurityProtocol::Ssl),
+            "SASL_PLAINTEXT" => Some(SecurityProtocol::SaslPlaintext),
+            "SASL_SSL" => Some(SecurityProtocol::SaslSsl),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            SecurityProtocol::Plaintext => "PLAINTEXT",
+            SecurityProtocol::Ssl => "SSL",
+            SecurityProtocol::SaslPlaintext => "SASL_PLAINTEXT",
+            SecurityProtocol::SaslSsl => "SASL_SSL",
+        }
+    }
+}
+
+pub struct BrokerConfig {
+    // Only the keys set in the config file or by --override; everything else is at its default
+    explicit_values: BTreeMap<String, String>,
+    pub node_id: i32,
+    pub cluster_id: Option<String>,
+    pub listeners: Vec<Listener>,
+    // The address published to clients of each broker listener, keyed by the same listener name
+    pub advertised_listeners: Vec<Listener>,
+    pub listener_security_protocols: BTreeMap<String, SecurityProtocol>,
+    pub controller_listener_names: Vec<String>,
+    pub log_dirs: Vec<String>,
+    pub metadata_log_dir: String,
+    pub num_network_threads: usize,
+    pub num_io_threads: usize,
+    pub socket_send_buffer_bytes: i32,
+    pub socket_receive_buffer_bytes: i32,
+    pub socket_request_max_bytes: usize,
+    pub queued_max_request_bytes: usize,
+    pub connections_max_in_flight_requests: usize,
+    pub queued_max_requests: usize,
+    pub connections_max_idle_ms: u64,
+    pub socket_listen_backlog_size: u32,
+    pub max_connections: usize,
+    pub max_connections_per_ip: usize,
+    // max.connections.per.ip.overrides with hostnames resolved
+    pub max_connections_per_ip_overrides: BTreeMap<IpAddr, usize>,
+    // (host, port) of metrics.http.listener
+    pub metrics_http_listener: Option<(String, u16)>,
+    pub ssl_keystore_location: Option<String>,
+    pub ssl_truststore_location: Option<String>,
+    pub ssl_client_auth: SslClientAuth,
+    pub ssl_principal_mapping_rules: Vec<PrincipalMappingRule>,
+    pub sasl_enabled_mechanisms: Vec<String>,
+    pub sasl_plain_credentials_file: Option<String>,
+    // The path of sasl.oauthbearer.jwks.endpoint.url
+    pub sasl_oauthbearer_jwks_file: Option<String>,
+    pub sasl_oauthbearer_expected_audience: Vec<String>,
+    pub sasl_oauthbearer_expected_issuer: Option<String>,
+    pub sasl_oauthbearer_sub_claim_name: String,
+    pub sasl_oauthbearer_clock_skew_seconds: i64,
+    pub connections_max_reauth_ms: i64,
+    pub delegation_token_secret_key: Option<String>,
+    pub delegation_token_max_lifetime_ms: i64,
+    pub delegation_token_expiry_time_ms: i64,
+    pub delegation_token_expiry_check_interval_ms: i64,
+    pub authorizer_class_name: Option<String>,
+    // Principals as Type:name
+    pub super_users: Vec<String>,
+    pub allow_everyone_if_no_acl_found: bool,
+    pub quota_window_num: usize,
+    pub quota_window_size_seconds: usize,
+}
+
+static BROKER_CONFIG: OnceLock<BrokerConfig> = OnceLock::new();
+
+pub fn init_broker_config(config: BrokerConfig) {
+    if BROKER_CONFIG.set(config).is_err() {
+        println!("broker config was already initialized");
+    }
+}
+
+pub fn broker_config() -> &'static BrokerConfig {
+    BROKER_CONFIG.get().expect("broker config is not initialized")
+}
+
+impl BrokerConfig {
+    // Where a key's current value came from: true when set explicitly, false when it's the default
+    pub fn value(&self, name: &str) -> Option<(Option<&str>, bool)> {
+        let def = find_config_def(name)?;
+        match self.explicit_values.get(name) {
+            Some(value) => Some((Some(value.as_str()), true)),
+            None => Some((def.default, false)),
+        }
+    }
+
+    fn string(&self, name: &str) -> Option<&str> {
+        self.value(name).and_then(|(value, _)| value)
+    }
+
+    // Listeners clients connect through; controller listeners belong to the quorum
+    pub fn broker_listeners(&self) -> impl Iterator<Item = &Listener> {
+        self.listeners.iter().filter(|listener| !self.controller_listener_names.contains(&listener.name))
+    }
+
+    pub fn advertised_listener(&self, listener_name: &str) -> Option<&Listener> {
+        self.advertised_listeners.iter().find(|listener| listener.name == listener_name)
+    }
+
+    pub fn security_protocol(&self, listener_name: &str) -> Option<SecurityProtocol> {
+        self.listener_security_protocols.get(listener_name).copied()
+    }
+}
+
+const USAGE: &str = "usage: codecrafters-kafka [--config <server.properties>] [--override key=value]... [server.properties]";
+
+// Loads the config file and overrides named on the command line. Every problem found is reported,
+// not just the first one.
+pub fn load_broker_config(args: &[String]) -> Result<BrokerConfig, Vec<String>> {
+    let mut errors = Vec::new();
+    let mut config_path: Option<String> = None;
+    let mut overrides = Vec::new();
+
+    let mut index = 0;
+    while index < args.len() {
+        let arg = &args[index];
+        match arg.as_str() {
+            "--config" | "--override" => {
+                let Some(value) = args.get(index + 1) else {
+                    errors.push(format!("{} requires a value; {}", arg, USAGE));
+                    break;
+                };
+                if arg == "--config" {
+                    config_path = Some(value.clone());
+                } else {
+                    overrides.push(value.clone());
+                }
+                index += 2;
+            }
+            // kafka-server-start.sh style: the properties file as a positional argument
+            _ if !arg.starts_with("--") && config_path.is_none() => {
+                config_path = Some(arg.clone());
+                index += 1;
+            }
+            _ => {
+                errors.push(format!("unexpected argument '{}'; {}", arg, USAGE));
+                index += 1;
+            }
+        }
+    }
+
+    let mut entries = Vec::new();
+    if let Some(path) = &config_path {
+        match fs::read_to_string(path) {
+            Ok(contents) => entries.extend(parse_properties(&contents).into_iter().map(|(line, entry)| {
+                (format!("{}:{}", path, line), entry)
+            })),
+            Err(e) => errors.push(format!("{}: unable to read config file: {}", path, e)),
+        }
+    }
+    for value in &overrides {
+        entries.push((format!("--override {}", value), split_property(value)));
+    }
+
+    let mut explicit_values = BTreeMap::new();
+    let mut ignored_keys = Vec::new();
+    for (origin, entry) in entries {
+        match entry {
+            Some((key, value)) => match find_config_def(&key) {
+                Some(def) => match validate_value(def, &value) {
+                    Ok(()) => {
+                        explicit_values.insert(key, value);
+                    }
+                    Err(e) => errors.push(format!("{}: malformed value for {}: {}", origin, key, e)),
+                },
+                None if IGNORED_CONFIG_KEYS.contains(&key.as_str()) => ignored_keys.push(key),
+                None => errors.push(format!("{}: unknown config key {}", origin, key)),
+            },
+            None => errors.push(format!("{}: expected key=value", origin)),
+        }
+    }
+    if !ignored_keys.is_empty() {
+        println!("ignoring config keys for subsystems this broker doesn't have: {}", ignored_keys.join(", "));
+    }
+
+    let config = build_broker_config(explicit_values, &mut errors);
+    if errors.is_empty() {
+        Ok(config)
+    } else {
+        Err(errors)
+    }
+}
+
+// Java properties subset: '#' and '!' comments, '=' or ':' separators, trailing '\' continuations
+pub fn parse_properties(contents: &str) -> Vec<(usize, Option<(String, String)>)> {
+    let mut entries = Vec::new();
+    let mut pending = String::new();
+    let mut pending_line = 0;
+
+    for (index, raw_line) in contents.lines().enumerate() {
+        let line = raw_line.trim_start();
+        if pending.is_empty() {
+            if line.is_empty() || line.starts_with('#') || line.starts_with('!') {
+                continue;
+            }
+            pending_line = index + 1;
+        }
+
+        if let Some(continued) = line.strip_suffix('\\') {
+            pending.push_str(continued);
+            continue;
+        }
+        pending.push_str(line);
+        entries.push((pending_line, split_property(&pending)));
+        pending.clear();
+    }
+    if !pending.is_empty() {
+        entries.push((pending_line, split_property(&pending)));
+    }
+    entries
+}
+
+fn split_property(entry: &str) -> Option<(String, String)> {
+    let separator = entry.find(['=', ':'])?;
+    let key = entry[..separator].trim();
+    if key.is_empty() {
+        return None;
+    }
+    Some((key.to_string(), entry[separator + 1..].trim().to_string()))
+}
+
+fn validate_value(def: &ConfigDef, value: &str) -> Result<(), String> {
+    match def.config_type {
+        ConfigType::Int => value.parse::<i32>().map(|_| ()).map_err(|_| format!("'{}' is not an int", value)),
+        ConfigType::Long => value.parse::<i64>().map(|_| ()).map_err(|_| format!("'{}' is not a long", value)),
+        ConfigType::Boolean if value.eq_ignore_ascii_case("true") || value.eq_ignore_ascii_case("false") => Ok(()),
+        ConfigType::Boolean => Err(format!("'{}' is not a boolean", value)),
+        ConfigType::String | ConfigType::List | ConfigType::Password => Ok(()),
+    }
+}
+
+fn parse_list(value: Option<&str>) -> Vec<String> {
+    value
+        .unwrap_or("")
+        .split(',')
+        .map(|item| item.trim().to_string())
+        .filter(|item| !item.is_empty())
+        .collect()
+}
+
+pub fn parse_listener(uri: &str) -> Result<Listener, String> {
+    let (name, address) = uri.split_once("://").ok_or_else(|| format!("'{}' is not NAME://host:port", uri))?;
+    let (host, port) = address.rsplit_once(':').ok_or_else(|| format!("'{}' has no port", uri))?;
+    let port = port.parse::<u16>().map_err(|_| format!("'{}' has an invalid port", uri))?;
+    if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-') {
+        return Err(format!("'{}' has an invalid listener name", uri));
+    }
+    // IPv6 hosts are written in brackets
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    Ok(Listener { name: name.to_uppercase(), host: host.to_string(), port })
+}
+
+fn positive(name: &str, value: i64, errors: &mut Vec<String>) -> usize {
+    if value <= 0 {
+        errors.push(format!("{} must be positive, got {}", name, value));
+        return 1;
+    }
+    value as usize
+}
+
+fn build_broker_config(explicit_values: BTreeMap<String, String>, errors: &mut Vec<String>) -> BrokerConfig {
+    let mut config = BrokerConfig {
+        explicit_values,
+        node_id: 0,
+        cluster_id: None,
+        listeners: Vec::new(),
+        advertised_listeners: Vec::new(),
+        listener_security_protocols: BTreeMap::new(),
+        controller_listener_names: Vec::new(),
+        log_dirs: Vec::new(),
+        metadata_log_dir: String::new(),
+        num_network_threads: 0,
+        num_io_threads: 0,
+        socket_send_buffer_bytes: 0,
+        socket_receive_buffer_bytes: 0,
+        socket_request_max_bytes: 0,
+        queued_max_request_bytes: 0,
+        connections_max_in_flight_requests: 0,
+        queued_max_requests: 0,
+        connections_max_idle_ms: 0,
+        socket_listen_backlog_size: 0,
+        max_connections: 0,
+        max_connections_per_ip: 0,
+        max_connections_per_ip_overrides: BTreeMap::new(),
+        metrics_http_listener: None,
+        ssl_keystore_location: None,
+        ssl_truststore_location: None,
+        ssl_client_auth: SslClientAuth::None,
+        ssl_principal_mapping_rules: Vec::new(),
+        sasl_enabled_mechanisms: Vec::new(),
+        sasl_plain_credentials_file: None,
+        sasl_oauthbearer_jwks_file: None,
+        sasl_oauthbearer_expected_audience: Vec::new(),
+        sasl_oauthbearer_expected_issuer: None,
+        sasl_oauthbearer_sub_claim_name: String::new(),
+        sasl_oauthbearer_clock_skew_seconds: 0,
+        connections_max_reauth_ms: 0,
+        delegation_token_secret_key: None,
+        delegation_token_max_lifetime_ms: 0,
+        delegation_token_expiry_time_ms: 0,
+        delegation_token_expiry_check_interval_ms: 0,
+        authorizer_class_name: None,
+        super_users: Vec::new(),
+        allow_everyone_if_no_acl_found: false,
+        quota_window_num: 0,
+        quota_window_size_seconds: 0,
+    };
+    // Values were type-checked on load and defaults are well-formed, so these parses can't fail
+    let int = |config: &BrokerConfig, name: &str| config.string(name).and_then(|v| v.parse::<i64>().ok()).unwrap_or(0);
+
+    config.node_id = int(&config, "node.id") as i32;
+    if config.node_id < 0 {
+        errors.push(format!("node.id must not be negative, got {}", config.node_id));
+    }
+    config.num_network_threads = positive("num.network.threads", int(&config, "num.network.threads"), errors);
+    config.num_io_threads = positive("num.io.threads", int(&config, "num.io.threads"), errors);
+    config.socket_send_buffer_bytes = int(&config, "socket.send.buffer.bytes") as i32;
+    config.socket_receive_buffer_bytes = int(&config, "socket.receive.buffer.bytes") as i32;
+    config.socket_request_max_bytes = positive("socket.request.max.bytes", int(&config, "socket.request.max.bytes"), errors);
+    config.queued_max_request_bytes = positive("queued.max.request.bytes", int(&config, "queued.max.request.bytes"), errors);
+    config.connections_max_in_flight_requests = positive("connections.max.in.flight.requests",
+                                                         int(&config, "connections.max.in.flight.requests"), errors);
+    config.queued_max_requests = positive("queued.max.requests", int(&config, "queued.max.requests"), errors);
+    config.connections_max_idle_ms = positive("connections.max.idle.ms", int(&config, "connections.max.idle.ms"), errors) as u64;
+    config.socket_listen_backlog_size = positive("socket.listen.backlog.size", int(&config, "socket.listen.backlog.size"), errors) as u32;
+    config.max_connections = positive("max.connections", int(&config, "max.connections"), errors);
+    resolve_connection_limits_per_ip(&mut config, errors);
+    config.quota_window_num = positive("quota.window.num", int(&config, "quota.window.num"), errors);
+    config.quota_window_size_seconds = positive("quota.window.size.seconds", int(&config, "quota.window.size.seconds"), errors);
+    if let Some(address) = config.string("metrics.http.listener").filter(|address| !address.is_empty()) {
+        match address.rsplit_once(':').and_then(|(host, port)| Some((host, port.parse::<u16>().ok()?))) {
+            Some((host, port)) => config.metrics_http_listener = Some((host.to_string(), port)),
+            None => errors.push(format!("metrics.http.listener: '{}' is not host:port", address)),
+        }
+    }
+    if config.queued_max_request_bytes > u32::MAX as usize {
+        errors.push(format!("queued.max.request.bytes must be at most {}", u32::MAX));
+    }
+
+    config.controller_listener_names = parse_list(config.string("controller.listener.names"))
+        .into_iter()
+        .map(|name| name.to_uppercase())
+        .collect();
+    for uri in parse_list(config.string("listeners")) {
+        match parse_listener(&uri) {
+            Ok(listener) => {
+                if config.listeners.iter().any(|existing: &Listener| existing.name == listener.name) {
+                    errors.push(format!("listeners: listener name {} is used more than once", listener.name));
+                }
+                config.listeners.push(listener);
+            }
+            Err(e) => errors.push(format!("listeners: {}", e)),
+        }
+    }
+    if config.listeners.is_empty() {
+        errors.push("listeners must name at least one listener".to_string());
+    }
+    for (index, listener) in config.listeners.iter().enumerate() {
+        if config.listeners[..index].iter().any(|other| other.port == listener.port && other.host == listener.host) {
+            errors.push(format!("listeners: {} binds {}:{}, which another listener already uses",
+                                listener.name, listener.host, listener.port));
+        }
+    }
+    if config.broker_listeners().next().is_none() && !config.listeners.is_empty() {
+        errors.push("listeners must include a listener that is not a controller listener".to_string());
+    }
+
+    let roles = parse_list(config.string("process.roles"));
+    if !roles.iter().any(|role| role == "broker") || roles.iter().any(|role| role != "broker" && role != "controller") {
+        errors.push(format!("process.roles must be broker or broker,controller, got {}", roles.join(",")));
+    }
+
+    resolve_security_protocols(&mut config, errors);
+    resolve_advertised_listeners(&mut config, errors);
+    resolve_ssl(&mut config, errors);
+    resolve_sasl(&mut config, errors);
+    resolve_delegation_tokens(&mut config, errors);
+    resolve_authorizer(&mut config, errors);
+    if let Some(name) = config.string("inter.broker.listener.name") {
+        let name = name.to_uppercase();
+        if !config.broker_listeners().any(|listener| listener.name == name) {
+            errors.push(format!("inter.broker.listener.name {} must be a broker listener in listeners", name));
+        }
+    }
+
+    config.log_dirs = match config.string("log.dirs") {
+        Some(log_dirs) => parse_list(Some(log_dirs)),
+        None => parse_list(config.string("log.dir")),
+    };
+    if config.log_dirs.is_empty() {
+        errors.push("log.dirs must name at least one directory".to_string());
+    }
+    config.metadata_log_dir = config
+        .string("metadata.log.dir")
+        .map(|dir| dir.to_string())
+        .or_else(|| config.log_dirs.first().cloned())
+        .unwrap_or_default();
+
+    config.cluster_id = config
+        .string("cluster.id")
+        .map(|id| id.to_string())
+        .or_else(|| read_meta_properties_cluster_id(&config.metadata_log_dir));
+    config
+}
+
+// max.connections.per.ip of 0 only makes sense with overrides, which are host:count or ip:count
+fn resolve_connection_limits_per_ip(config: &mut BrokerConfig, errors: &mut Vec<String>) {
+    let max_connections_per_ip = config.string("max.connections.per.ip").and_then(|v| v.parse::<i64>().ok()).unwrap_or(0);
+    if max_connections_per_ip < 0 {
+        errors.push(format!("max.connections.per.ip must not be negative, got {}", max_connections_per_ip));
+    }
+    config.max_connections_per_ip = max_connections_per_ip.max(0) as usize;
+
+    for entry in parse_list(config.string("max.connections.per.ip.overrides")) {
+        let Some((host, count)) = entry.rsplit_once(':').and_then(|(host, count)| Some((host, count.parse::<usize>().ok()?))) else {
+            errors.push(format!("max.connections.per.ip.overrides: '{}' is not host:count", entry));
+            continue;
+        };
+        // IPv6 addresses are written in brackets
+        let host = host.trim_start_matches('[').trim_end_matches(']');
+        match (host, 0).to_socket_addrs() {
+            Ok(addresses) => {
+                for address in addresses {
+                    config.max_connections_per_ip_overrides.insert(address.ip(), count);
+                }
+            }
+            Err(e) => errors.push(format!("max.connections.per.ip.overrides: unable to resolve {}: {}", host, e)),
+        }
+    }
+    if max_connections_per_ip == 0 && config.max_connections_per_ip_overrides.is_empty() {
+        errors.push("max.connections.per.ip can be 0 only if max.connections.per.ip.overrides is set".to_string());
+    }
+}
+
+// listener.security.protocol.map entries are NAME:PROTOCOL; every listener has to be mapped
+fn resolve_security_protocols(config: &mut BrokerConfig, errors: &mut Vec<String>) {
+    for entry in parse_list(config.string("listener.security.protocol.map")) {
+        let Some((name, protocol)) = entry.split_once(':') else {
+            errors.push(format!("listener.security.protocol.map: '{}' is not NAME:PROTOCOL", entry));
+            continue;
+        };
+        match SecurityProtocol::parse(protocol.trim()) {
+            Some(protocol) => {
+                config.listener_security_protocols.insert(name.trim().to_uppercase(), protocol);
+            }
+            None => errors.push(format!("listener.security.protocol.map: unknown security protocol {}", protocol)),
+        }
+    }
+
+    for listener in &config.listeners {
+        if config.security_protocol(&listener.name).is_none() {
+            errors.push(format!("listener {} has no entry in listener.security.protocol.map", listener.name));
+        }
+    }
+}
+
+fn resolve_ssl(config: &mut BrokerConfig, errors: &mut Vec<String>) {
+    for name in ["ssl.keystore.type", "ssl.truststore.type"] {
+        if let Some(store_type) = config.string(name) {
+            if !store_type.eq_ignore_ascii_case("PEM") {
+                errors.push(format!("{} {} is not supported; only PEM is", name, store_type));
+            }
+        }
+    }
+    config.ssl_keystore_location = config.string("ssl.keystore.location").map(|path| path.to_string());
+    config.ssl_truststore_location = config.string("ssl.truststore.location").map(|path| path.to_string());
+
+    let client_auth = config.string("ssl.client.auth").unwrap_or("none");
+    config.ssl_client_auth = SslClientAuth::parse(client_auth).unwrap_or_else(|| {
+        errors.push(format!("ssl.client.auth must be required, requested or none, got {}", client_auth));
+        SslClientAuth::None
+    });
+    match parse_principal_mapping_rules(config.string("ssl.principal.mapping.rules").unwrap_or("DEFAULT")) {
+        Ok(rules) => config.ssl_principal_mapping_rules = rules,
+        Err(e) => errors.push(format!("ssl.principal.mapping.rules: {}", e)),
+    }
+
+    let ssl_listener = config.broker_listeners().find(|listener| {
+        matches!(config.security_protocol(&listener.name), Some(SecurityProtocol::Ssl | SecurityProtocol::SaslSsl))
+    });
+    if let Some(listener) = ssl_listener {
+        if config.ssl_keystore_location.is_none() {
+            errors.push(format!("listener {} uses SSL, which needs ssl.keystore.location", listener.name));
+        }
+        if config.ssl_client_auth != SslClientAuth::None && config.ssl_truststore_location.is_none() {
+            errors.push("ssl.client.auth needs ssl.truststore.location to verify client certificates".to_string());
+        }
+    }
+}
+
+fn resolve_sasl(config: &mut BrokerConfig, errors: &mut Vec<String>) {
+    config.sasl_enabled_mechanisms = parse_list(config.string("sasl.enabled.mechanisms"))
+        .into_iter()
+        .map(|mechanism| mechanism.to_uppercase())
+        .collect();
+    for mechanism in &config.sasl_enabled_mechanisms {
+        if !SUPPORTED_MECHANISMS.contains(&mechanism.as_str()) {
+            errors.push(format!("sasl.enabled.mechanisms: {} is not supported; supported mechanisms are {}",
+                                mechanism, SUPPORTED_MECHANISMS.join(", ")));
+        }
+    }
+    config.sasl_plain_credentials_file = config.string("sasl.plain.credentials.file").map(|path| path.to_string());
+    resolve_oauthbearer(config, errors);
+    config.connections_max_reauth_ms = config.string("connections.max.reauth.ms").and_then(|v| v.parse().ok()).unwrap_or(0);
+    if config.connections_max_reauth_ms < 0 {
+        errors.push(format!("connections.max.reauth.ms must not be negative, got {}", config.connections_max_reauth_ms));
+    }
+
+    let sasl_listener = config.broker_listeners().find(|listener| {
+        matches!(config.security_protocol(&listener.name), Some(SecurityProtocol::SaslPlaintext | SecurityProtocol::SaslSsl))
+    });
+    if let Some(listener) = sasl_listener {
+        if config.sasl_enabled_mechanisms.is_empty() {
+            errors.push(format!("listener {} uses SASL, which needs sasl.enabled.mechanisms", listener.name));
+        }
+        if config.sasl_enabled_mechanisms.iter().any(|mechanism| mechanism == "PLAIN") && config.sasl_plain_credentials_file.is_none() {
+            errors.push("sasl.enabled.mechanisms includes PLAIN, which needs sasl.plain.credentials.file".to_string());
+        }
+        if config.sasl_enabled_mechanisms.iter().any(|mechanism| mechanism == "OAUTHBEARER") && config.sasl_oauthbearer_jwks_file.is_none() {
+            errors.push("sasl.enabled.mechanisms includes OAUTHBEARER, which needs sasl.oauthbearer.jwks.endpoint.url".to_string());
+        }
+    }
+}
+
+fn resolve_delegation_tokens(config: &mut BrokerConfig, errors: &mut Vec<String>) {
+    config.delegation_token_secret_key = config.string("delegation.token.secret.key").map(|key| key.to_string());
+    if config.delegation_token_secret_key.as_deref() == Some("") {
+        errors.push("delegation.token.secret.key must not be empty".to_string());
+    }
+    let long = |config: &BrokerConfig, name: &str| config.string(name).and_then(|v| v.parse::<i64>().ok()).unwrap_or(0);
+    config.delegation_token_max_lifetime_ms = long(config, "delegation.token.max.lifetime.ms");
+    config.delegation_token_expiry_time_ms = long(config, "delegation.token.expiry.time.ms");
+    config.delegation_token_expiry_check_interval_ms = long(config, "delegation.token.expiry.check.interval.ms");
+    for (name, value) in [
+        ("delegation.token.max.lifetime.ms", config.delegation_token_max_lifetime_ms),
+        ("delegation.token.expiry.time.ms", config.delegation_token_expiry_time_ms),
+        ("delegation.token.expiry.check.interval.ms", config.delegation_token_expiry_check_interval_ms),
+    ] {
+        if value < 1 {
+            errors.push(format!("{} must be at least 1, got {}", name, value));
+        }
+    }
+}
+
+fn resolve_authorizer(config: &mut BrokerConfig, errors: &mut Vec<String>) {
+    config.authorizer_class_name = config.string("authorizer.class.name").filter(|name| !name.is_empty()).map(|name| name.to_string());
+    if let Some(name) = &config.authorizer_class_name {
+        if name != STANDARD_AUTHORIZER {
+            errors.push(format!("authorizer.class.name: {} is not supported; only {} is", name, STANDARD_AUTHORIZER));
+        }
+    }
+    // Unlike list keys, super.users is separated by semicolons
+    config.super_users = config
+        .string("super.users")
+        .unwrap_or("")
+        .split(';')
+        .map(|principal| principal.trim().to_string())
+        .filter(|principal| !principal.is_empty())
+        .collect();
+    for principal in &config.super_users {
+        if !principal.contains(':') {
+            errors.push(format!("super.users: '{}' is not a Type:name principal", principal));
+        }
+    }
+    config.allow_everyone_if_no_acl_found = config
+        .string("allow.everyone.if.no.acl.found")
+        .is_some_and(|value| value.eq_ignore_ascii_case("true"));
+}
+
+// Keys are only read locally: the JWKS URL has to be a file: URL
+fn resolve_oauthbearer(config: &mut BrokerConfig, errors: &mut Vec<String>) {
+    if let Some(url) = config.string("sasl.oauthbearer.jwks.endpoint.url") {
+        match url.strip_prefix("file://").or_else(|| url.strip_prefix("file:")) {
+            Some(path) if !path.is_empty() => config.sasl_oauthbearer_jwks_file = Some(path.to_string()),
+            _ => errors.push(format!("sasl.oauthbearer.jwks.endpoint.url: {} is not a file: URL; only local JWKS files are supported", url)),
+        }
+    }
+    config.sasl_oauthbearer_expected_audience = parse_list(config.string("sasl.oauthbearer.expected.audience"));
+    config.sasl_oauthbearer_expected_issuer = config.string("sasl.oauthbearer.expected.issuer").map(|issuer| issuer.to_string());
+    config.sasl_oauthbearer_sub_claim_name = config.string("sasl.oauthbearer.sub.claim.name").unwrap_or("sub").to_string();
+    if config.sasl_oauthbearer_sub_claim_name.is_empty() {
+        errors.push("sasl.oauthbearer.sub.claim.name must not be empty".to_string());
+    }
+    config.sasl_oauthbearer_clock_skew_seconds = config.string("sasl.oauthbearer.clock.skew.seconds").and_then(|v| v.parse().ok()).unwrap_or(0);
+    if config.sasl_oauthbearer_clock_skew_seconds < 0 {
+        errors.push(format!("sasl.oauthbearer.clock.skew.seconds must not be negative, got {}", config.sasl_oauthbearer_clock_skew_seconds));
+    }
+}
+
+// Each broker listener is advertised as its advertised.listeners entry, or as itself when there is
+// none. An empty host advertises this machine's hostname, as Kafka does. Entries for controller
+// listeners are accepted, as in Kafka's stock configs, but never served to clients.
+fn resolve_advertised_listeners(config: &mut BrokerConfig, errors: &mut Vec<String>) {
+    let mut advertised = Vec::new();
+    for uri in parse_list(config.string("advertised.listeners")) {
+        match parse_listener(&uri) {
+            Ok(listener) => {
+                if !config.listeners.iter().any(|existing| existing.name == listener.name) {
+                    errors.push(format!("advertised.listeners: {} is not a listener in listeners", listener.name));
+                } else if advertised.iter().any(|existing: &Listener| existing.name == listener.name) {
+                    errors.push(format!("advertised.listeners: listener name {} is used more than once", listener.name));
+                } else if listener.host == "0.0.0.0" || listener.host == "::" {
+                    errors.push(format!("advertised.listeners: {} cannot advertise the wildcard address {}",
+                                        listener.name, listener.host));
+                }
+                advertised.push(listener);
+            }
+            Err(e) => errors.push(format!("advertised.listeners: {}", e)),
+        }
+    }
+
+    let hostname = local_hostname();
+    config.advertised_listeners = config
+        .broker_listeners()
+        .map(|listener| {
+            let advertised = advertised.iter().find(|advertised| advertised.name == listener.name).unwrap_or(listener);
+            let host = match advertised.host.as_str() {
+                "" | "0.0.0.0" | "::" => hostname.clone(),
+                host => host.to_string(),
+            };
+            Listener { name: listener.name.clone(), host, port: advertised.port }
+        })
+        .collect();
+}
+
+fn local_hostname() -> String {
+    let mut buffer = [0u8; 256];
+    let result = unsafe { libc::gethostname(buffer.as_mut_ptr() as *mut libc::c_char, buffer.len()) };
+    if result != 0 {
+        return "localhost".to_string();
+    }
+    let length = buffer.iter().position(|byte| *byte == 0).unwrap_or(buffer.len());
+    String::from_utf8_lossy(&buffer[..length]).into_owned()
+}
+
+// `kafka-storage.sh format` records the cluster ID in meta.properties
+fn read_meta_properties_cluster_id(log_dir: &str) -> Option<String> {
+    let contents = fs::read_to_string(Path::new(log_dir).join("meta.properties")).ok()?;
+    parse_properties(&contents)
+        .into_iter()
+        .filter_map(|(_, entry)| entry)
+        .find(|(key, _)| key == "cluster.id")
+        .map(|(_, value)| value)
+}
+
+// DescribeConfigs resource types and config sources
+const TOPIC_RESOURCE: i8 = 2;
+const BROKER_RESOURCE: i8 = 4;
+const STATIC_BROKER_CONFIG: i8 = 4;
+const DEFAULT_CONFIG: i8 = 5;
+
+struct DescribeConfigsResource {
+    resource_type: i8,
+    resource_name: String,
+    // None asks for every config
+    configuration_keys: Option<Vec<String>>,
+}
+
+struct DescribeConfigsRequest {
+    resources: Vec<DescribeConfigsResource>,
+    include_synonyms: bool,
+    include_documentation: bool,
+}
+
+fn parse_describe_configs_request(buffer: &[u8], api_version: i16) -> Result<DescribeConfigsRequest, &'static str> {
+    // v4+ is flexible
+    let flexible = api_version >= 4;
+    let mut offset = skip_request_header(buffer, flexible)?;
+
+    let (resources_count, new_offset) = read_array_length(buffer, offset, flexible)?;
+    offset = new_offset;
+    let mut resources = Vec::new();
+    for _ in 0..resources_count.unwrap_or(0) {
+        let resource_type = read_i8(buffer, offset)?;
+        offset += 1;
+        let (resource_name, new_offset) = read_string(buffer, offset, flexible)?;
+        offset = new_offset;
+
+        let (keys_count, new_offset) = read_array_length(buffer, offset, flexible)?;
+        offset = new_offset;
+        let configuration_keys = match keys_count {
+            Some(keys_count) => {
+                let mut keys = Vec::new();
+                for _ in 0..keys_count {
+                    let (key, new_offset) = read_string(buffer, offset, flexible)?;
+                    offset = new_offset;
+                    keys.push(key);
+                }
+                Some(keys)
+            }
+            None => None,
+        };
+        if flexible {
+            offset = skip_tagged_fields(buffer, offset)?;
+        }
+
+        resources.push(DescribeConfigsResource { resource_type, resource_name, configuration_keys });
+    }
+
+    let include_synonyms = if api_version >= 1 {
+        offset += 1;
+        read_i8(buffer, offset - 1)? != 0
+    } else {
+        false
+    };
+    let include_documentation = if api_version >= 3 {
+        offset += 1;
+        read_i8(buffer, offset - 1)? != 0
+    } else {
+        false
+    };
+    if flexible {
+        skip_tagged_fields(buffer, offset)?;
+    }
+
+    Ok(DescribeConfigsRequest { resources, include_synonyms, include_documentation })
+}
+
+struct DescribedConfig {
+    def: &'static ConfigDef,
+    value: Option<String>,
+    explicit: bool,
+}
+
+// Returns (error_code, error_message, configs) for one resource
+fn describe_resource(config: &BrokerConfig, resource: &DescribeConfigsResource) -> (i16, Option<String>, Vec<DescribedConfig>) {
+    match resource.resource_type {
+        BROKER_RESOURCE => {
+            // An empty name is the cluster-wide default resource, which only holds dynamic defaults
+            if resource.resource_name.is_empty() {
+                return (0, None, Vec::new());
+            }
+            if resource.resource_name != config.node_id.to_string() {
+                return (42, Some(format!("Unexpected broker id, expected {} or empty string, but received {}",
+                                         config.node_id, resource.resource_name)), Vec::new()); // INVALID_REQUEST
+            }
+            let configs = CONFIG_DEFS
+                .iter()
+                .filter(|def| match &resource.configuration_keys {
+                    Some(keys) => keys.iter().any(|key| key == def.name),
+                    None => true,
+                })
+                .map(|def| {
+                    let (value, explicit) = config.value(def.name).unwrap_or((None, false));
+                    // Passwords are never sent back, only whether one is set
+                    let value = if def.config_type == ConfigType::Password { None } else { value };
+                    DescribedConfig { def, value: value.map(|value| value.to_string()), explicit }
+                })
+                .collect();
+            (0, None, configs)
+        }
+        // This broker hosts no topics
+        TOPIC_RESOURCE => (3, Some(format!("Topic {} does not exist.", resource.resource_name)), Vec::new()), // UNKNOWN_TOPIC_OR_PARTITION
+        _ => (42, Some(format!("Unsupported resource type {}", resource.resource_type)), Vec::new()), // INVALID_REQUEST
+    }
+}
+
+fn create_describe_configs_response(context: &RequestContext, request: &DescribeConfigsRequest,
+                                    results: &[(i16, Option<String>, Vec<DescribedConfig>)]) -> Vec<u8> {
+    let api_version = context.api_version;
+    let flexible = api_version >= 4;
+    let mut response_body = Vec::new();
+
+    // Response header
+    response_body.extend_from_slice(&context.correlation_id.to_be_bytes()); // correlation_id (4 bytes)
+    if flexible {
+        response_body.push(0); // tag buffer (1 byte)
+    }
+
+    // Response body
+    response_body.extend_from_slice(&context.throttle_time_ms.to_be_bytes()); // throttle_time_ms (4 bytes)
+    write_array_length(&mut response_body, results.len(), flexible);
+    for (resource, (error_code, error_message, configs)) in request.resources.iter().zip(results) {
+        response_body.extend_from_slice(&error_code.to_be_bytes());               // error_code (2 bytes)
+        write_nullable_string(&mut response_body, error_message.as_deref(), flexible);
+        response_body.push(resource.resource_type as u8);                         // resource_type (1 byte)
+        write_string(&mut response_body, &resource.resource_name, flexible);
+
+        write_array_length(&mut response_body, configs.len(), flexible);
+        for config in configs {
+            let source = if config.explicit { STATIC_BROKER_CONFIG } else { DEFAULT_CONFIG };
+            write_string(&mut response_body, config.def.name, flexible);
+            write_nullable_string(&mut response_body, config.value.as_deref(), flexible);
+            response_body.push(1); // read_only: nothing can be altered at runtime (1 byte)
+            if api_version == 0 {
+                response_body.push(!config.explicit as u8); // is_default (1 byte)
+            } else {
+                response_body.push(source as u8);           // config_source (1 byte)
+            }
+            response_body.push((config.def.config_type == ConfigType::Password) as u8); // is_sensitive (1 byte)
+
+            if api_version >= 1 {
+                // Synonyms in precedence order: the static value, then the default it overrides
+                let mut synonyms = Vec::new();
+                if request.include_synonyms {
+                    if config.explicit {
+                        synonyms.push((config.value.as_deref(), STATIC_BROKER_CONFIG));
+                    }
+                    if config.def.default.is_some() {
+                        synonyms.push((config.def.default, DEFAULT_CONFIG));
+                    }
+                }
+                write_array_length(&mut response_body, synonyms.len(), flexible);
+                for (value, source) in synonyms {
+                    write_string(&mut response_body, config.def.name, flexible);
+                    write_nullable_string(&mut response_body, value, flexible);
+                    response_body.push(source as u8); // source (1 byte)
+                    if flexible {
+                        response_body.push(0); // tag buffer (1 byte)
+                    }
+                }
+            }
+
+            if api_version >= 3 {
+                response_body.push(config.def.config_type as u8); // config_type (1 byte)
+                let documentation = if request.include_documentation { Some(config.def.documentation) } else { None };
+                write_nullable_string(&mut response_body, documentation, flexible);
+            }
+            if flexible {
+                response_body.push(0); // tag buffer (1 byte)
+            }
+        }
+        if flexible {
+            response_body.push(0); // tag buffer (1 byte)
+        }
+    }
+    if flexible {
+        response_body.push(0); // tag buffer (1 byte)
+    }
+
+    frame_response(response_body)
+}
+
+pub fn handle_describe_configs_request(context: &RequestContext, message_buffer: &[u8]) -> Vec<u8> {
+    let request = match parse_describe_configs_request(message_buffer, context.api_version) {
+        Ok(request) => request,
+        Err(e) => {
+            println!("error parsing DescribeConfigs request: {}", e);
+            return create_describe_configs_error_response(context, message_buffer, 42); // INVALID_REQUEST
+        }
+    };
+
+    let config = broker_config();
+    let connection = context.connection;
+    let results: Vec<_> = request
+        .resources
+        .iter()
+        .map(|resource| match resource.resource_type {
+            BROKER_RESOURCE if !authorize(connection, AclOperation::DescribeConfigs, ResourceType::Cluster, CLUSTER_NAME) => {
+                (31, None, Vec::new()) // CLUSTER_AUTHORIZATION_FAILED
+            }
+            TOPIC_RESOURCE if !authorize(connection, AclOperation::DescribeConfigs, ResourceType::Topic, &resource.resource_name) => {
+                (29, None, Vec::new()) // TOPIC_AUTHORIZATION_FAILED
+            }
+            _ => describe_resource(config, resource),
+        })
+        .collect();
+    create_describe_configs_response(context, &request, &results)
+}
+
+// DescribeConfigs has no top-level error code; the error goes on every requested resource
+pub fn create_describe_configs_error_response(context: &RequestContext, message_buffer: &[u8], error_code: i16) -> Vec<u8> {
+    let request = parse_describe_configs_request(message_buffer, context.api_version).unwrap_or(DescribeConfigsRequest {
+        resources: Vec::new(),
+        include_synonyms: false,
+        include_documentation: false,
+    });
+    let results: Vec<_> = request.resources.iter().map(|_| (error_code, None, Vec::new())).collect();
+    create_describe_configs_response(context, &request, &results)
+}
+
+// Initializes the broker config once per test process, for tests that serve connections
+#[cfg(test)]
+pub fn init_test_broker_config() {
+    static INITIALIZED: std::sync::Once = std::sync::Once::new();
+    INITIALIZED.call_once(|| {
+        let log_dir = std::env::temp_dir().join(format!("codecrafters-kafka-unit-{}", std::process::id()));
+        let args = [
+            "--override".to_string(),
+            "listeners=PLAINTEXT://127.0.0.1:0".to_string(),
+            "--override".to_string(),
+            format!("log.dirs={}", log_dir.display()),
+        ];
+        init_broker_config(load_broker_config(&args).unwrap());
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    fn write_config(name: &str, contents: &str) -> String {
+        let path = std::env::temp_dir().join(format!("codecrafters-kafka-{}-{}.properties", name, std::process::id()));
+        fs::write(&path, contents).unwrap();
+        path.to_string_lossy().into_owned()
+    }
+
+    const MINIMAL_CONFIG: &str = "# comment\n! also a comment\n\nlisteners=PLAINTEXT://localhost:9092,\\\n    CONTROLLER://localhost:9093\nlog.dirs : /tmp/logs\nnum.io.threads=4\n\
+                                  controller.listener.names=CONTROLLER\nlistener.security.protocol.map=PLAINTEXT:PLAINTEXT,CONTROLLER:PLAINTEXT\n";
+
+    #[test]
+    fn the_config_file_and_overrides_are_merged() {
+        let path = write_config("merged", MINIMAL_CONFIG);
+        let config = load_broker_config(&args(&["--config", &path, "--override", "num.io.threads=6"])).unwrap();
+
+        assert_eq!(config.listeners.iter().map(|listener| listener.name.as_str()).collect::<Vec<_>>(), ["PLAINTEXT", "CONTROLLER"]);
+        assert_eq!(config.log_dirs, ["/tmp/logs"]);
+        assert_eq!(config.num_io_threads, 6, "overrides win over the file");
+        assert_eq!(config.value("num.io.threads"), Some((Some("6"), true)));
+        assert_eq!(config.value("num.network.threads"), Some((Some("3"), false)));
+
+        // kafka-server-start.sh passes the file as a positional argument
+        let config = load_broker_config(&args(&[&path])).unwrap();
+        assert_eq!(config.num_io_threads, 4);
+    }
+
+    #[test]
+    fn every_problem_is_reported_with_its_origin() {
+        let path = write_config("errors", "listeners=PLAINTEXT://localhost:9092\nno.such.key=1\nnum.io.threads=many\nnot a property\n");
+        let errors = load_broker_config(&args(&["--config", &path, "--override", "num.network.threads=0", "--override", "log.dirs=", "--bogus", "--override"]))
+            .err()
+            .unwrap();
+
+        for expected in [
+            format!("{}:2: unknown config key no.such.key", path),
+            format!("{}:3: malformed value for num.io.threads: 'many' is not an int", path),
+            format!("{}:4: expected key=value", path),
+            "num.network.threads must be positive, got 0".to_string(),
+            "unexpected argument '--bogus'".to_string(),
+            "--override requires a value".to_string(),
+            "log.dirs must name at least one directory".to_string(),
+        ] {
+            assert!(errors.iter().any(|error| error.starts_with(&expected)), "no {:?} in {:#?}", expected, errors);
+        }
+
+        let errors = load_broker_config(&args(&["--override", "log.dirs=/tmp/logs", "--override", "listeners=localhost:9092"]))
+            .err()
+            .unwrap();
+        assert!(errors.iter().any(|error| error.contains("is not NAME://host:port")), "{:#?}", errors);
+    }
+
+    #[test]
+    fn describe_configs_reports_where_each_value_came_from() {
+        let config = load_broker_config(&args(&["--config", &write_config("describe", MINIMAL_CONFIG)])).unwrap();
+        let resource = |resource_type: i8, resource_name: &str, keys: Option<&[&str]>| DescribeConfigsResource {
+            resource_type,
+            resource_name: resource_name.to_string(),
+            configuration_keys: keys.map(|keys| keys.iter().map(|key| key.to_string()).collect()),
+        };
+
+        let (error_code, _, configs) = describe_resource(&config, &resource(BROKER_RESOURCE, "1", Some(&["num.io.threads", "num.network.threads"])));
+        assert_eq!(error_code, 0);
+        let described: Vec<_> = configs.iter().map(|config| (config.def.name, config.value.as_deref(), config.explicit)).collect();
+        assert_eq!(described, [("num.network.threads", Some("3"), false), ("num.io.threads", Some("4"), true)]);
+        assert_eq!(describe_resource(&config, &resource(BROKER_RESOURCE, "1", None)).2.len(), CONFIG_DEFS.len());
+
+        assert_eq!(describe_resource(&config, &resource(BROKER_RESOURCE, "", None)).0, 0);
+        assert_eq!(describe_resource(&config, &resource(BROKER_RESOURCE, "2", None)).0, 42); // INVALID_REQUEST
+        assert_eq!(describe_resource(&config, &resource(TOPIC_RESOURCE, "orders", None)).0, 3); // UNKNOWN_TOPIC_OR_PARTITION
+    }
+
+    fn load(properties: &[&str]) -> Result<BrokerConfig, Vec<String>> {
+        let mut arguments = vec!["--override".to_string(), "log.dirs=/tmp/logs".to_string()];
+        for property in properties {
+            arguments.push("--override".to_string());
+            arguments.push(property.to_string());
+        }
+        load_broker_config(&arguments)
+    }
+
+    #[test]
+    fn each_broker_listener_is_advertised_at_its_own_address() {
+        let config = load(&[
+            "listeners=INTERNAL://0.0.0.0:9092,EXTERNAL://0.0.0.0:19092,CONTROLLER://:9093",
+            "controller.listener.names=CONTROLLER",
+            "listener.security.protocol.map=INTERNAL:PLAINTEXT,EXTERNAL:PLAINTEXT,CONTROLLER:PLAINTEXT",
+            "advertised.listeners=EXTERNAL://broker.example.com:29092,CONTROLLER://controller.example.com:9093",
+        ])
+        .unwrap();
+
+        let external = config.advertised_listener("EXTERNAL").unwrap();
+        assert_eq!((external.host.as_str(), external.port), ("broker.example.com", 29092));
+        // Without an entry a listener advertises itself, with the wildcard replaced by the hostname
+        let internal = config.advertised_listener("INTERNAL").unwrap();
+        assert_eq!((internal.host.as_str(), internal.port), (local_hostname().as_str(), 9092));
+        // A controller listener may have an advertised.listeners entry, as in Kafka's stock config, but is
+        // never advertised to clients
+        assert!(config.advertised_listener("CONTROLLER").is_none(), "controller listeners are not advertised");
+    }
+
+    #[test]
+    fn inconsistent_listener_configs_are_rejected() {
+        for (properties, expected) in [
+            (&["listeners=A://:9092,B://:9092", "listener.security.protocol.map=A:PLAINTEXT,B:PLAINTEXT"][..],
+             "listeners: B binds :9092, which another listener already uses"),
+            (&["listeners=INTERNAL://:9092"][..], "listener INTERNAL has no entry in listener.security.protocol.map"),
+            (&["advertised.listeners=OTHER://broker.example.com:9092"][..], "advertised.listeners: OTHER is not a listener in listeners"),
+            (&["advertised.listeners=PLAINTEXT://0.0.0.0:9092"][..], "advertised.listeners: PLAINTEXT cannot advertise the wildcard address 0.0.0.0"),
+            (&["inter.broker.listener.name=OTHER"][..], "inter.broker.listener.name OTHER must be a broker listener in listeners"),
+        ] {
+            let errors = load(properties).err().unwrap_or_default();
+            assert!(errors.iter().any(|error| error == expected), "no {:?} in {:?}", expected, errors);
+        }
+    }
+
+    #[test]
+    fn keys_without_effect_are_accepted_but_not_described() {
+        let config = load(&["log.retention.hours=168", "offsets.topic.replication.factor=1", "share.coordinator.state.topic.min.isr=1"])
+            .unwrap();
+        assert!(config.value("log.retention.hours").is_none());
+        let resource = DescribeConfigsResource {
+            resource_type: BROKER_RESOURCE,
+            resource_name: "1".to_string(),
+            configuration_keys: Some(vec!["log.retention.hours".to_string(), "num.io.threads".to_string()]),
+        };
+        let described: Vec<_> = describe_resource(&config, &resource).2.iter().map(|config| config.def.name).collect();
+        assert_eq!(described, ["num.io.threads"]);
+
+        assert_eq!(load(&["log.retention.hourz=168"]).err().unwrap(), ["--override log.retention.hourz=168: unknown config key log.retention.hourz"]);
+    }
+
+    #[test]
+    fn connection_limits_are_validated() {
+        let config = load(&["max.connections=100", "max.connections.per.ip=0", "max.connections.per.ip.overrides=127.0.0.1:5, [::1]:6"])
+            .unwrap();
+        assert_eq!((config.max_connections, config.max_connections_per_ip), (100, 0));
+        let overrides: Vec<_> = config.max_connections_per_ip_overrides.iter().map(|(ip, count)| (ip.to_string(), *count)).collect();
+        assert_eq!(overrides, [("127.0.0.1".to_string(), 5), ("::1".to_string(), 6)]);
+
+        for (properties, expected) in [
+            (&["max.connections.per.ip=0"][..], "max.connections.per.ip can be 0 only if max.connections.per.ip.overrides is set"),
+            (&["max.connections.per.ip=-1"][..], "max.connections.per.ip must not be negative, got -1"),
+            (&["max.connections.per.ip.overrides=127.0.0.1"][..], "max.connections.per.ip.overrides: '127.0.0.1' is not host:count"),
+            (&["max.connections=0"][..], "max.connections must be positive, got 0"),
+            (&["connections.max.idle.ms=0"][..], "connections.max.idle.ms must be positive, got 0"),
+            (&["queued.max.requests=0"][..], "queued.max.requests must be positive, got 0"),
+            (&["process.roles=controller"][..], "process.roles must be broker or broker,controller, got controller"),
+        ] {
+            let errors = load(properties).err().unwrap_or_default();
+            assert!(errors.iter().any(|error| error == expected), "no {:?} in {:?}", expected, errors);
+        }
+    }
+}
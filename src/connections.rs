@@ -0,0 +1,298 @@
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+use crate::sasl::SaslState;
+
+// Reported for connections that never sent ApiVersions v3+, matching what Kafka brokers report
+const UNKNOWN_CLIENT_SOFTWARE: &str = "unknown";
+
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct ClientSoftware {
+    pub name: String,
+    pub version: String,
+}
+
+impl ClientSoftware {
+    fn unknown() -> ClientSoftware {
+        ClientSoftware {
+            name: UNKNOWN_CLIENT_SOFTWARE.to_string(),
+            version: UNKNOWN_CLIENT_SOFTWARE.to_string(),
+        }
+    }
+}
+
+// Open connections per client software name/version, across all client threads
+static CLIENT_SOFTWARE_CONNECTIONS: Mutex<BTreeMap<ClientSoftware, usize>> = Mutex::new(BTreeMap::new());
+static NEXT_CONNECTION_ID: AtomicU64 = AtomicU64::new(1);
+
+pub struct ClientConnection {
+    pub id: u64,
+    pub peer: String,
+    // Name of the listener the client connected through
+    pub listener: String,
+    // Authenticated principal name; ANONYMOUS until a client certificate or SASL identifies the client
+    principal: Mutex<String>,
+    // Set when SASL/SCRAM authenticated the principal with a delegation token
+    token_authenticated: AtomicBool,
+    pub sasl_state: Mutex<SaslState>,
+    // Set by a handler whose response has to be the last one on this connection
+    close_requested: AtomicBool,
+    client_software: Mutex<ClientSoftware>,
+}
+
+impl ClientConnection {
+    pub fn open(peer: String, listener: String, principal: String) -> ClientConnection {
+        let client_software = ClientSoftware::unknown();
+        adjust_client_software_count(&client_software, 1);
+
+        ClientConnection {
+            id: NEXT_CONNECTION_ID.fetch_add(1, Ordering::Relaxed),
+            peer,
+            listener,
+            principal: Mutex::new(principal),
+            token_authenticated: AtomicBool::new(false),
+            sasl_state: Mutex::new(SaslState::Handshake),
+            close_requested: AtomicBool::new(false),
+            client_software: Mutex::new(client_software),
+        }
+    }
+
+    pub fn principal(&self) -> String {
+        self.principal.lock().unwrap().clone()
+    }
+
+    pub fn set_principal(&self, principal: String) {
+        *self.principal.lock().unwrap() = principal;
+    }
+
+    pub fn token_authenticated(&self) -> bool {
+        self.token_authenticated.load(Ordering::Relaxed)
+    }
+
+    pub fn set_token_authenticated(&self, token_authenticated: bool) {
+        self.token_authenticated.store(token_authenticated, Ordering::Relaxed);
+    }
+
+    pub fn request_close(&self) {
+        self.close_requested.store(true, Ordering::Relaxed);
+    }
+
+    pub fn close_requested(&self) -> bool {
+        self.close_requested.load(Ordering::Relaxed)
+    }
+
+    // Called when ApiVersions v3+ identifies the client; clients may re-send it, so the old entry is moved
+    pub fn set_client_software(&self, name: String, version: String) {
+        let new_software = ClientSoftware { name, version };
+        let mut current = self.client_software.lock().unwrap();
+        if *current == new_software {
+            return;
+        }
+
+        println!("connection {} ({}) identified as {} {}",
+                 self.id, self.peer, new_software.name, new_software.version);
+        adjust_client_software_count(&current, -1);
+        adjust_client_software_count(&new_software, 1);
+        *current = new_software;
+        drop(current);
+        log_client_software_counts();
+    }
+}
+
+impl Drop for ClientConnection {
+    fn drop(&mut self) {
+        let client_software = self.client_software.lock().unwrap().clone();
+        adjust_client_software_count(&client_software, -1);
+    }
+}
+
+fn adjust_client_software_count(client_software: &ClientSoftware, delta: isize) {
+    let mut counts = CLIENT_SOFTWARE_CONNECTIONS.lock().unwrap();
+    let count = counts.entry(client_software.clone()).or_insert(0);
+    *count = count.saturating_add_signed(delta);
+    if *count == 0 {
+        counts.remove(client_software);
+    }
+}
+
+// Broker introspection: open connection counts per client software, sorted by name and version
+pub fn client_software_counts() -> Vec<(ClientSoftware, usize)> {
+    let counts = CLIENT_SOFTWARE_CONNECTIONS.lock().unwrap();
+    counts.iter().map(|(software, count)| (software.clone(), *count)).collect()
+}
+
+pub fn log_client_software_counts() {
+    let counts = client_software_counts();
+    let summary: Vec<String> = counts
+        .iter()
+        .map(|(software, count)| format!("{}/{}={}", software.name, software.version, count))
+        .collect();
+    println!("client software connections: [{}]", summary.join(", "));
+}
+
+// max.connections and max.connections.per.ip, shared by every listener
+pub struct ConnectionLimits {
+    connections: Arc<Semaphore>,
+    max_per_ip: usize,
+    per_ip_overrides: BTreeMap<IpAddr, usize>,
+    open_per_ip: Mutex<BTreeMap<IpAddr, usize>>,
+}
+
+// Held for as long as an accepted connection is open
+pub struct ConnectionSlot {
+    limits: Arc<ConnectionLimits>,
+    ip: IpAddr,
+    _permit: OwnedSemaphorePermit,
+}
+
+impl ConnectionLimits {
+    pub fn new(max_connections: usize, max_per_ip: usize, per_ip_overrides: BTreeMap<IpAddr, usize>) -> ConnectionLimits {
+        ConnectionLimits {
+            connections: Arc::new(Semaphore::new(max_connections.min(Semaphore::MAX_PERMITS))),
+            max_per_ip,
+            per_ip_overrides,
+            open_per_ip: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    // Waits until fewer than max.connections connections are open; the permit is handed to admit
+    pub async fn wait_for_capacity(&self) -> OwnedSemaphorePermit {
+        Arc::clone(&self.connections).acquire_owned().await.expect("the connection semaphore is never closed")
+    }
+
+    // Takes a slot for a connection from ip, or returns the limit when that many are already open from it
+    pub fn admit(self: &Arc<Self>, ip: IpAddr, permit: OwnedSemaphorePermit) -> Result<ConnectionSlot, usize> {
+        let limit = self.per_ip_overrides.get(&ip).copied().unwrap_or(self.max_per_ip);
+        let mut open_per_ip = self.open_per_ip.lock().unwrap();
+        let open = open_per_ip.entry(ip).or_insert(0);
+        if *open >= limit {
+            if *open == 0 {
+                open_per_ip.remove(&ip);
+            }
+            return Err(limit);
+        }
+        *open += 1;
+        Ok(ConnectionSlot { limits: Arc::clone(self), ip, _permit: permit })
+    }
+}
+
+impl Drop for ConnectionSlot {
+    fn drop(&mut self) {
+        let mut open_per_ip = self.limits.open_per_ip.lock().unwrap();
+        if let Some(open) = open_per_ip.get_mut(&self.ip) {
+            *open -= 1;
+            if *open == 0 {
+                open_per_ip.remove(&self.ip);
+            }
+        }
+    }
+}
+
+// Kafka publishes the same counts as the socket-server-metrics connections metric, tagged with
+// clientSoftwareName and clientSoftwareVersion
+fn client_software_metrics() -> String {
+    let mut metrics = String::new();
+    metrics.push_str("# HELP kafka_server_socket_server_metrics_connections Open connections per client software name and version\n");
+    metrics.push_str("# TYPE kafka_server_socket_server_metrics_connections gauge\n");
+    // Names and versions are validated to [a-zA-Z0-9.-], so they need no escaping as label values
+    for (software, count) in client_software_counts() {
+        let _ = writeln!(metrics, "kafka_server_socket_server_metrics_connections{{clientSoftwareName=\"{}\",clientSoftwareVersion=\"{}\"}} {}",
+                         software.name, software.version, count);
+    }
+    metrics
+}
+
+// Serves the metrics as Prometheus text at /metrics on metrics.http.listener
+pub async fn serve_metrics(listener: TcpListener) {
+    loop {
+        match listener.accept().await {
+            Ok((stream, _)) => {
+                tokio::spawn(answer_metrics_request(stream));
+            }
+            Err(e) => println!("error accepting metrics connection: {}", e),
+        }
+    }
+}
+
+async fn answer_metrics_request(mut stream: TcpStream) {
+    // Only the request line matters, but the request is read to its end before answering
+    let mut request = Vec::new();
+    let mut buffer = [0u8; 1024];
+    while !request.windows(4).any(|window| window == b"\r\n\r\n") && request.len() < 8192 {
+        match tokio::time::timeout(Duration::from_secs(10), stream.read(&mut buffer)).await {
+            Ok(Ok(read)) if read > 0 => request.extend_from_slice(&buffer[..read]),
+            _ => return,
+        }
+    }
+
+    let _ = stream.write_all(metrics_response(&request).as_bytes()).await;
+    let _ = stream.shutdown().await;
+}
+
+fn metrics_response(request: &[u8]) -> String {
+    let request_line = request.split(|byte| *byte == b'\r').next().unwrap_or_default();
+    let path = std::str::from_utf8(request_line).unwrap_or_default().split(' ').nth(1).unwrap_or_default();
+    if path.split('?').next() != Some("/metrics") {
+        return "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_string();
+    }
+
+    let body = client_software_metrics();
+    format!("HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            body.len(), body)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+
+    use super::*;
+
+    fn get(port: u16, path: &str) -> String {
+        let mut stream = std::net::TcpStream::connect(("127.0.0.1", port)).unwrap();
+        stream.set_read_timeout(Some(Duration::from_secs(10))).unwrap();
+        write!(stream, "GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response
+    }
+
+    #[test]
+    fn the_metrics_endpoint_serves_client_software_counts() {
+        let runtime = tokio::runtime::Builder::new_multi_thread().worker_threads(2).enable_all().build().unwrap();
+        let listener = runtime.block_on(TcpListener::bind("127.0.0.1:0")).unwrap();
+        let port = listener.local_addr().unwrap().port();
+        runtime.spawn(serve_metrics(listener));
+
+        let connections: Vec<ClientConnection> = (0..2)
+            .map(|_| ClientConnection::open("127.0.0.1:50000".to_string(), "PLAINTEXT".to_string(), "ANONYMOUS".to_string()))
+            .collect();
+        for connection in &connections {
+            connection.set_client_software("metrics-endpoint-test".to_string(), "1.0".to_string());
+        }
+
+        let response = get(port, "/metrics");
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{}", response);
+        assert!(response.contains("\n# TYPE kafka_server_socket_server_metrics_connections gauge\n"), "{}", response);
+        assert!(response.contains("\nkafka_server_socket_server_metrics_connections{clientSoftwareName=\"metrics-endpoint-test\",clientSoftwareVersion=\"1.0\"} 2\n"),
+                "{}", response);
+
+        drop(connections);
+        assert!(!get(port, "/metrics").contains("metrics-endpoint-test"));
+    }
+
+    #[test]
+    fn other_paths_are_not_found() {
+        for request in ["GET / HTTP/1.1\r\n\r\n", "GET /metricsx HTTP/1.1\r\n\r\n", "GET /other/metrics HTTP/1.1\r\n\r\n", "garbage\r\n\r\n"] {
+            assert!(metrics_response(request.as_bytes()).starts_with("HTTP/1.1 404 Not Found\r\n"), "{:?}", request);
+        }
+        assert!(metrics_response(b"GET /metrics?name=x HTTP/1.1\r\n\r\n").starts_with("HTTP/1.1 200 OK\r\n"));
+    }
+}
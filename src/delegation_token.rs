@@ -0,0 +1,599 @@
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use ring::hmac;
+use ring::rand::{SecureRandom, SystemRandom};
+
+use crate::authorizer::{authorize, AclOperation, ResourceType};
+use crate::base64;
+use crate::config::{broker_config, BrokerConfig, SecurityProtocol};
+use crate::connections::ClientConnection;
+use crate::metadata_log::{self, MetadataRecord};
+use crate::sasl::constant_time_eq;
+use crate::ssl::ANONYMOUS_PRINCIPAL;
+use crate::{
+    frame_response, read_array_length, read_bytes, read_compact_string, read_i64_be, read_nullable_string, read_string,
+    read_unsigned_varint, skip_request_header, skip_tagged_fields, write_array_length, write_bytes, write_compact_string,
+    write_string, write_unsigned_varint, RequestContext,
+};
+
+const DELEGATION_TOKEN_RECORD: u32 = 23;
+const REMOVE_DELEGATION_TOKEN_RECORD: u32 = 24;
+
+// Token owners and renewers are always users
+const USER_PRINCIPAL_TYPE: &str = "User";
+
+#[derive(Clone)]
+pub struct TokenInformation {
+    pub token_id: String,
+    // Principal names, all of type User
+    pub owner: String,
+    pub requester: String,
+    pub renewers: Vec<String>,
+    // Milliseconds since the epoch
+    pub issue_timestamp: i64,
+    pub max_timestamp: i64,
+    pub expiry_timestamp: i64,
+    // HMAC-SHA512 of the token ID under delegation.token.secret.key; never persisted
+    pub hmac: Vec<u8>,
+}
+
+impl TokenInformation {
+    fn expired(&self, now: i64) -> bool {
+        self.max_timestamp < now || self.expiry_timestamp < now
+    }
+
+    // The owner, the principal that requested the token on the owner's behalf, and the renewers
+    fn renewable_by(&self, principal: &str) -> bool {
+        self.owner == principal || self.requester == principal || self.renewers.iter().any(|renewer| renewer == principal)
+    }
+}
+
+static DELEGATION_TOKENS: Mutex<BTreeMap<String, TokenInformation>> = Mutex::new(BTreeMap::new());
+
+static TOKEN_UPDATE_LOCK: Mutex<()> = Mutex::new(());
+
+pub fn now_ms() -> i64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|elapsed| elapsed.as_millis() as i64).unwrap_or(0)
+}
+
+fn token_hmac(secret_key: &str, token_id: &str) -> Vec<u8> {
+    hmac::sign(&hmac::Key::new(hmac::HMAC_SHA512, secret_key.as_bytes()), token_id.as_bytes()).as_ref().to_vec()
+}
+
+// The unexpired token SCRAM authenticates a tokenauth client with
+pub fn token_for_authentication(token_id: &str) -> Option<TokenInformation> {
+    DELEGATION_TOKENS.lock().unwrap().get(token_id).filter(|token| !token.expired(now_ms())).cloned()
+}
+
+enum TokenChange {
+    Upsert(TokenInformation),
+    Remove(String),
+}
+
+// Applies a DelegationTokenRecord or RemoveDelegationTokenRecord read back from the metadata log.
+// HMACs are derived from the token ID, so tokens are only usable with the secret they were created under.
+pub fn replay(record: &MetadataRecord) {
+    let Some(secret_key) = broker_config().delegation_token_secret_key.as_deref() else {
+        return;
+    };
+    let change = match record.record_type {
+        DELEGATION_TOKEN_RECORD => decode_delegation_token_record(&record.data, secret_key),
+        REMOVE_DELEGATION_TOKEN_RECORD => read_compact_string(&record.data, 0).map(|(token_id, _)| TokenChange::Remove(token_id)),
+        _ => return,
+    };
+    match change {
+        Ok(change) => apply_token_change(change),
+        Err(e) => println!("error decoding delegation token record at offset {}: {}", record.offset, e),
+    }
+}
+
+fn apply_token_change(change: TokenChange) {
+    let mut tokens = DELEGATION_TOKENS.lock().unwrap();
+    match change {
+        TokenChange::Upsert(token) => {
+            println!("updated delegation token {} of User:{}, expiring at {}", token.token_id, token.owner, token.expiry_timestamp);
+            tokens.insert(token.token_id.clone(), token);
+        }
+        TokenChange::Remove(token_id) => {
+            println!("removed delegation token {}", token_id);
+            tokens.remove(&token_id);
+        }
+    }
+}
+
+// Persists the changes to the metadata log, then applies them
+fn commit_token_changes(changes: Vec<TokenChange>) -> Result<(), String> {
+    let records: Vec<(u32, u32, Vec<u8>)> = changes.iter().map(encode_token_change).collect();
+    metadata_log::append(&records).map_err(|e| format!("Unable to persist delegation tokens: {}", e))?;
+    for change in changes {
+        apply_token_change(change);
+    }
+    Ok(())
+}
+
+fn encode_token_change(change: &TokenChange) -> (u32, u32, Vec<u8>) {
+    let mut data = Vec::new();
+    match change {
+        TokenChange::Upsert(token) => {
+            write_compact_string(&mut data, &format!("{}:{}", USER_PRINCIPAL_TYPE, token.owner));     // owner
+            write_compact_string(&mut data, &format!("{}:{}", USER_PRINCIPAL_TYPE, token.requester)); // requester
+            write_unsigned_varint(&mut data, token.renewers.len() as u32 + 1);                        // renewers
+            for renewer in &token.renewers {
+                write_compact_string(&mut data, &format!("{}:{}", USER_PRINCIPAL_TYPE, renewer));
+            }
+            data.extend_from_slice(&token.issue_timestamp.to_be_bytes());  // issue_timestamp (8 bytes)
+            data.extend_from_slice(&token.max_timestamp.to_be_bytes());    // max_timestamp (8 bytes)
+            data.extend_from_slice(&token.expiry_timestamp.to_be_bytes()); // expiration_timestamp (8 bytes)
+            write_compact_string(&mut data, &token.token_id);              // token_id
+            data.push(0);                                                  // tag buffer (1 byte)
+            (DELEGATION_TOKEN_RECORD, 0, data)
+        }
+        TokenChange::Remove(token_id) => {
+            write_compact_string(&mut data, token_id); // token_id
+            data.push(0);                              // tag buffer (1 byte)
+            (REMOVE_DELEGATION_TOKEN_RECORD, 0, data)
+        }
+    }
+}
+
+fn read_record_principal(data: &[u8], offset: usize) -> Result<(String, usize), &'static str> {
+    let (principal, offset) = read_compact_string(data, offset)?;
+    match principal.split_once(':') {
+        Some((USER_PRINCIPAL_TYPE, name)) => Ok((name.to_string(), offset)),
+        _ => Err("principal is not a User"),
+    }
+}
+
+fn decode_delegation_token_record(data: &[u8], secret_key: &str) -> Result<TokenChange, &'static str> {
+    let (owner, offset) = read_record_principal(data, 0)?;
+    let (requester, offset) = read_record_principal(data, offset)?;
+    let (renewers_count, mut offset) = read_unsigned_varint(data, offset)?;
+    let mut renewers = Vec::new();
+    for _ in 0..renewers_count.saturating_sub(1) {
+        let (renewer, new_offset) = read_record_principal(data, offset)?;
+        renewers.push(renewer);
+        offset = new_offset;
+    }
+    let issue_timestamp = read_i64_be(data, offset)?;
+    let max_timestamp = read_i64_be(data, offset + 8)?;
+    let expiry_timestamp = read_i64_be(data, offset + 16)?;
+    let (token_id, _) = read_compact_string(data, offset + 24)?;
+    let hmac = token_hmac(secret_key, &token_id);
+    Ok(TokenChange::Upsert(TokenInformation {
+        token_id, owner, requester, renewers, issue_timestamp, max_timestamp, expiry_timestamp, hmac,
+    }))
+}
+
+// Removes expired tokens every delegation.token.expiry.check.interval.ms
+pub async fn remove_expired_tokens_periodically(config: &'static BrokerConfig) {
+    if config.delegation_token_secret_key.is_none() {
+        return;
+    }
+    let mut interval = tokio::time::interval(Duration::from_millis(config.delegation_token_expiry_check_interval_ms as u64));
+    loop {
+        interval.tick().await;
+        // The purge waits on the update lock and syncs the metadata log, so it stays off the async workers
+        match tokio::task::spawn_blocking(remove_expired_tokens).await {
+            Ok(Ok(())) => {}
+            Ok(Err(e)) => println!("error removing expired delegation tokens: {}", e),
+            Err(e) => println!("expired delegation token removal panicked: {}", e),
+        }
+    }
+}
+
+fn remove_expired_tokens() -> Result<(), String> {
+    let _update_guard = TOKEN_UPDATE_LOCK.lock().unwrap();
+    let now = now_ms();
+    let expired: Vec<TokenChange> = DELEGATION_TOKENS
+        .lock()
+        .unwrap()
+        .values()
+        .filter(|token| token.expired(now))
+        .map(|token| TokenChange::Remove(token.token_id.clone()))
+        .collect();
+    commit_token_changes(expired)
+}
+
+// Like Kafka, token requests need an authenticated principal that didn't itself authenticate with a
+// token: not PLAINTEXT, not SSL without a client certificate, and not SASL with tokenauth
+fn allow_token_requests(connection: &ClientConnection) -> bool {
+    match broker_config().security_protocol(&connection.listener) {
+        _ if connection.token_authenticated() => false,
+        Some(SecurityProtocol::Plaintext) | None => false,
+        Some(SecurityProtocol::Ssl) => connection.principal() != ANONYMOUS_PRINCIPAL,
+        Some(SecurityProtocol::SaslPlaintext | SecurityProtocol::SaslSsl) => true,
+    }
+}
+
+// Error codes every delegation token API checks for first
+fn check_token_request(connection: &ClientConnection) -> Result<(), i16> {
+    if broker_config().delegation_token_secret_key.is_none() {
+        return Err(61); // DELEGATION_TOKEN_AUTH_DISABLED
+    }
+    if !allow_token_requests(connection) {
+        return Err(64); // DELEGATION_TOKEN_REQUEST_NOT_ALLOWED
+    }
+    Ok(())
+}
+
+// Principals in token requests are (principal_type, principal_name) pairs
+fn read_principal(buffer: &[u8], offset: usize, flexible: bool) -> Result<((String, String), usize), &'static str> {
+    let (principal_type, offset) = read_string(buffer, offset, flexible)?;
+    let (principal_name, offset) = read_string(buffer, offset, flexible)?;
+    let offset = if flexible { skip_tagged_fields(buffer, offset)? } else { offset };
+    Ok(((principal_type, principal_name), offset))
+}
+
+fn write_user_principal(buffer: &mut Vec<u8>, principal_name: &str, flexible: bool) {
+    write_string(buffer, USER_PRINCIPAL_TYPE, flexible); // principal_type
+    write_string(buffer, principal_name, flexible);      // principal_name
+}
+
+struct CreateDelegationTokenRequest {
+    // (principal_type, principal_name) of the owner the token is created for (v3+); None means the requester
+    owner: Option<(String, String)>,
+    renewers: Vec<(String, String)>,
+    max_lifetime_ms: i64,
+}
+
+fn parse_create_delegation_token_request(buffer: &[u8], api_version: i16) -> Result<CreateDelegationTokenRequest, &'static str> {
+    // v2+ is flexible
+    let flexible = api_version >= 2;
+    let mut offset = skip_request_header(buffer, flexible)?;
+
+    let mut owner = None;
+    if api_version >= 3 {
+        let (owner_type, new_offset) = read_nullable_string(buffer, offset, flexible)?;
+        let (owner_name, new_offset) = read_nullable_string(buffer, new_offset, flexible)?;
+        offset = new_offset;
+        owner = owner_type.zip(owner_name);
+    }
+
+    let (renewers_count, new_offset) = read_array_length(buffer, offset, flexible)?;
+    offset = new_offset;
+    let mut renewers = Vec::new();
+    for _ in 0..renewers_count.unwrap_or(0) {
+        let (renewer, new_offset) = read_principal(buffer, offset, flexible)?;
+        renewers.push(renewer);
+        offset = new_offset;
+    }
+
+    let max_lifetime_ms = read_i64_be(buffer, offset)?;
+    offset += 8;
+    if flexible {
+        skip_tagged_fields(buffer, offset)?;
+    }
+    Ok(CreateDelegationTokenRequest { owner, renewers, max_lifetime_ms })
+}
+
+fn create_create_delegation_token_response(context: &RequestContext, error_code: i16, owner: &str, requester: &str,
+                                           token: Option<&TokenInformation>) -> Vec<u8> {
+    let api_version = context.api_version;
+    let flexible = api_version >= 2;
+    let mut response_body = Vec::new();
+
+    // Response header
+    response_body.extend_from_slice(&context.correlation_id.to_be_bytes()); // correlation_id (4 bytes)
+    if flexible {
+        response_body.push(0); // tag buffer (1 byte)
+    }
+
+    // Response body
+    response_body.extend_from_slice(&error_code.to_be_bytes()); // error_code (2 bytes)
+    write_user_principal(&mut response_body, owner, flexible);
+    if api_version >= 3 {
+        write_user_principal(&mut response_body, requester, flexible); // token_requester_principal_type/name
+    }
+    let (issue_timestamp, expiry_timestamp, max_timestamp) = token
+        .map(|token| (token.issue_timestamp, token.expiry_timestamp, token.max_timestamp))
+        .unwrap_or((-1, -1, -1));
+    response_body.extend_from_slice(&issue_timestamp.to_be_bytes());  // issue_timestamp_ms (8 bytes)
+    response_body.extend_from_slice(&expiry_timestamp.to_be_bytes()); // expiry_timestamp_ms (8 bytes)
+    response_body.extend_from_slice(&max_timestamp.to_be_bytes());    // max_timestamp_ms (8 bytes)
+    write_string(&mut response_body, token.map_or("", |token| &token.token_id), flexible);
+    write_bytes(&mut response_body, token.map_or(&[][..], |token| &token.hmac), flexible);
+    response_body.extend_from_slice(&context.throttle_time_ms.to_be_bytes()); // throttle_time_ms (4 bytes)
+    if flexible {
+        response_body.push(0); // tag buffer (1 byte)
+    }
+
+    frame_response(response_body)
+}
+
+pub fn handle_create_delegation_token_request(context: &RequestContext, message_buffer: &[u8]) -> Vec<u8> {
+    let request = match parse_create_delegation_token_request(message_buffer, context.api_version) {
+        Ok(request) => request,
+        Err(e) => {
+            println!("error parsing CreateDelegationToken request: {}", e);
+            return create_create_delegation_token_error_response(context, message_buffer, 42); // INVALID_REQUEST
+        }
+    };
+    let requester = context.connection.principal();
+    let owner = request.owner.as_ref().map_or(requester.clone(), |(_, owner_name)| owner_name.clone());
+    if let Err(error_code) = check_token_request(context.connection) {
+        return create_create_delegation_token_response(context, error_code, &owner, &requester, None);
+    }
+    let principal_types = request.owner.iter().chain(&request.renewers).map(|(principal_type, _)| principal_type);
+    if principal_types.into_iter().any(|principal_type| principal_type != USER_PRINCIPAL_TYPE) {
+        return create_create_delegation_token_response(context, 67, &owner, &requester, None); // INVALID_PRINCIPAL_TYPE
+    }
+    // Creating a token for someone else takes CREATE_TOKENS on that user
+    let owner_resource = format!("{}:{}", USER_PRINCIPAL_TYPE, owner);
+    if owner != requester && !authorize(context.connection, AclOperation::CreateTokens, ResourceType::User, &owner_resource) {
+        return create_create_delegation_token_response(context, 65, &owner, &requester, None); // DELEGATION_TOKEN_AUTHORIZATION_FAILED
+    }
+
+    // A non-positive max_lifetime_ms asks for delegation.token.max.lifetime.ms, which also caps it
+    let config = broker_config();
+    let now = now_ms();
+    let max_lifetime_ms = match request.max_lifetime_ms {
+        lifetime if lifetime <= 0 => config.delegation_token_max_lifetime_ms,
+        lifetime => lifetime.min(config.delegation_token_max_lifetime_ms),
+    };
+    let max_timestamp = now.saturating_add(max_lifetime_ms);
+
+    let mut random = [0u8; 16];
+    if SystemRandom::new().fill(&mut random).is_err() {
+        return create_create_delegation_token_response(context, -1, &owner, &requester, None); // UNKNOWN_SERVER_ERROR
+    }
+    let token_id = base64::encode_url_safe(&random);
+    let token = TokenInformation {
+        hmac: token_hmac(config.delegation_token_secret_key.as_deref().unwrap_or_default(), &token_id),
+        token_id,
+        owner: owner.clone(),
+        requester: requester.clone(),
+        renewers: request.renewers.into_iter().map(|(_, renewer_name)| renewer_name).collect(),
+        issue_timestamp: now,
+        max_timestamp,
+        expiry_timestamp: max_timestamp.min(now.saturating_add(config.delegation_token_expiry_time_ms)),
+    };
+
+    let _update_guard = TOKEN_UPDATE_LOCK.lock().unwrap();
+    if let Err(e) = commit_token_changes(vec![TokenChange::Upsert(token.clone())]) {
+        println!("error creating delegation token: {}", e);
+        return create_create_delegation_token_response(context, -1, &owner, &requester, None); // UNKNOWN_SERVER_ERROR
+    }
+    println!("User:{} created delegation token {} for User:{}", requester, token.token_id, owner);
+    create_create_delegation_token_response(context, 0, &owner, &requester, Some(&token))
+}
+
+pub fn create_create_delegation_token_error_response(context: &RequestContext, _message_buffer: &[u8], error_code: i16) -> Vec<u8> {
+    let principal = context.connection.principal();
+    create_create_delegation_token_response(context, error_code, &principal, &principal, None)
+}
+
+// RenewDelegationToken and ExpireDelegationToken share their request and response layouts:
+// the token's HMAC and a period in, the resulting expiry timestamp out
+fn parse_token_hmac_request(buffer: &[u8], api_version: i16) -> Result<(Vec<u8>, i64), &'static str> {
+    // v2+ is flexible
+    let flexible = api_version >= 2;
+    let offset = skip_request_header(buffer, flexible)?;
+    let (hmac, offset) = read_bytes(buffer, offset, flexible)?;
+    let period_ms = read_i64_be(buffer, offset)?;
+    if flexible {
+        skip_tagged_fields(buffer, offset + 8)?;
+    }
+    Ok((hmac, period_ms))
+}
+
+fn create_token_expiry_response(context: &RequestContext, error_code: i16, expiry_timestamp: i64) -> Vec<u8> {
+    let flexible = context.api_version >= 2;
+    let mut response_body = Vec::new();
+
+    // Response header
+    response_body.extend_from_slice(&context.correlation_id.to_be_bytes()); // correlation_id (4 bytes)
+    if flexible {
+        response_body.push(0); // tag buffer (1 byte)
+    }
+
+    // Response body
+    response_body.extend_from_slice(&error_code.to_be_bytes());               // error_code (2 bytes)
+    response_body.extend_from_slice(&expiry_timestamp.to_be_bytes());         // expiry_timestamp_ms (8 bytes)
+    response_body.extend_from_slice(&context.throttle_time_ms.to_be_bytes()); // throttle_time_ms (4 bytes)
+    if flexible {
+        response_body.push(0); // tag buffer (1 byte)
+    }
+
+    frame_response(response_body)
+}
+
+// Finds the unexpired token with this HMAC that the principal owns or may renew. Errors are error codes.
+fn find_renewable_token(hmac: &[u8], principal: &str, now: i64) -> Result<TokenInformation, i16> {
+    let token = DELEGATION_TOKENS
+        .lock()
+        .unwrap()
+        .values()
+        .find(|token| constant_time_eq(&token.hmac, hmac))
+        .cloned()
+        .ok_or(62i16)?; // DELEGATION_TOKEN_NOT_FOUND
+    if !token.renewable_by(principal) {
+        return Err(63); // DELEGATION_TOKEN_OWNER_MISMATCH
+    }
+    if token.expired(now) {
+        return Err(66); // DELEGATION_TOKEN_EXPIRED
+    }
+    Ok(token)
+}
+
+// A negative renew_period_ms renews for delegation.token.expiry.time.ms; never past the max timestamp
+fn renew_token(connection: &ClientConnection, hmac: &[u8], renew_period_ms: i64) -> Result<i64, i16> {
+    check_token_request(connection)?;
+    let _update_guard = TOKEN_UPDATE_LOCK.lock().unwrap();
+    let now = now_ms();
+    let mut token = find_renewable_token(hmac, &connection.principal(), now)?;
+    let renew_period_ms = if renew_period_ms < 0 { broker_config().delegation_token_expiry_time_ms } else { renew_period_ms };
+    token.expiry_timestamp = token.max_timestamp.min(now.saturating_add(renew_period_ms));
+    let expiry_timestamp = token.expiry_timestamp;
+    commit_token_changes(vec![TokenChange::Upsert(token)]).map_err(|e| {
+        println!("error renewing delegation token: {}", e);
+        -1i16 // UNKNOWN_SERVER_ERROR
+    })?;
+    Ok(expiry_timestamp)
+}
+
+// A negative expiry_time_period_ms expires the token immediately
+fn expire_token(connection: &ClientConnection, hmac: &[u8], expiry_time_period_ms: i64) -> Result<i64, i16> {
+    check_token_request(connection)?;
+    let _update_guard = TOKEN_UPDATE_LOCK.lock().unwrap();
+    let now = now_ms();
+    let mut token = find_renewable_token(hmac, &connection.principal(), now)?;
+    let (change, expiry_timestamp) = if expiry_time_period_ms < 0 {
+        (TokenChange::Remove(token.token_id), now)
+    } else {
+        token.expiry_timestamp = token.max_timestamp.min(now.saturating_add(expiry_time_period_ms));
+        let expiry_timestamp = token.expiry_timestamp;
+        (TokenChange::Upsert(token), expiry_timestamp)
+    };
+    commit_token_changes(vec![change]).map_err(|e| {
+        println!("error expiring delegation token: {}", e);
+        -1i16 // UNKNOWN_SERVER_ERROR
+    })?;
+    Ok(expiry_timestamp)
+}
+
+pub fn handle_renew_delegation_token_request(context: &RequestContext, message_buffer: &[u8]) -> Vec<u8> {
+    let (hmac, renew_period_ms) = match parse_token_hmac_request(message_buffer, context.api_version) {
+        Ok(request) => request,
+        Err(e) => {
+            println!("error parsing RenewDelegationToken request: {}", e);
+            return create_renew_delegation_token_error_response(context, message_buffer, 42); // INVALID_REQUEST
+        }
+    };
+    match renew_token(context.connection, &hmac, renew_period_ms) {
+        Ok(expiry_timestamp) => create_token_expiry_response(context, 0, expiry_timestamp),
+        Err(error_code) => create_token_expiry_response(context, error_code, -1),
+    }
+}
+
+pub fn create_renew_delegation_token_error_response(context: &RequestContext, _message_buffer: &[u8], error_code: i16) -> Vec<u8> {
+    create_token_expiry_response(context, error_code, -1)
+}
+
+pub fn handle_expire_delegation_token_request(context: &RequestContext, message_buffer: &[u8]) -> Vec<u8> {
+    let (hmac, expiry_time_period_ms) = match parse_token_hmac_request(message_buffer, context.api_version) {
+        Ok(request) => request,
+        Err(e) => {
+            println!("error parsing ExpireDelegationToken request: {}", e);
+            return create_expire_delegation_token_error_response(context, message_buffer, 42); // INVALID_REQUEST
+        }
+    };
+    match expire_token(context.connection, &hmac, expiry_time_period_ms) {
+        Ok(expiry_timestamp) => create_token_expiry_response(context, 0, expiry_timestamp),
+        Err(error_code) => create_token_expiry_response(context, error_code, -1),
+    }
+}
+
+pub fn create_expire_delegation_token_error_response(context: &RequestContext, _message_buffer: &[u8], error_code: i16) -> Vec<u8> {
+    create_token_expiry_response(context, error_code, -1)
+}
+
+// None asks for the tokens of every owner
+fn parse_describe_delegation_token_request(buffer: &[u8], api_version: i16) -> Result<Option<Vec<(String, String)>>, &'static str> {
+    // v2+ is flexible
+    let flexible = api_version >= 2;
+    let mut offset = skip_request_header(buffer, flexible)?;
+    let (owners_count, new_offset) = read_array_length(buffer, offset, flexible)?;
+    offset = new_offset;
+
+    let owners = match owners_count {
+        Some(owners_count) => {
+            let mut owners = Vec::new();
+            for _ in 0..owners_count {
+                let (owner, new_offset) = read_principal(buffer, offset, flexible)?;
+                owners.push(owner);
+                offset = new_offset;
+            }
+            Some(owners)
+        }
+        None => None,
+    };
+    if flexible {
+        skip_tagged_fields(buffer, offset)?;
+    }
+    Ok(owners)
+}
+
+fn create_describe_delegation_token_response(context: &RequestContext, error_code: i16, tokens: &[TokenInformation]) -> Vec<u8> {
+    let api_version = context.api_version;
+    let flexible = api_version >= 2;
+    let mut response_body = Vec::new();
+
+    // Response header
+    response_body.extend_from_slice(&context.correlation_id.to_be_bytes()); // correlation_id (4 bytes)
+    if flexible {
+        response_body.push(0); // tag buffer (1 byte)
+    }
+
+    // Response body
+    response_body.extend_from_slice(&error_code.to_be_bytes()); // error_code (2 bytes)
+    write_array_length(&mut response_body, tokens.len(), flexible);
+    for token in tokens {
+        write_user_principal(&mut response_body, &token.owner, flexible);
+        if api_version >= 3 {
+            write_user_principal(&mut response_body, &token.requester, flexible); // token_requester_principal_type/name
+        }
+        response_body.extend_from_slice(&token.issue_timestamp.to_be_bytes());  // issue_timestamp (8 bytes)
+        response_body.extend_from_slice(&token.expiry_timestamp.to_be_bytes()); // expiry_timestamp (8 bytes)
+        response_body.extend_from_slice(&token.max_timestamp.to_be_bytes());    // max_timestamp (8 bytes)
+        write_string(&mut response_body, &token.token_id, flexible);
+        write_bytes(&mut response_body, &token.hmac, flexible);
+        write_array_length(&mut response_body, token.renewers.len(), flexible);
+        for renewer in &token.renewers {
+            write_user_principal(&mut response_body, renewer, flexible);
+            if flexible {
+                response_body.push(0); // tag buffer (1 byte)
+            }
+        }
+        if flexible {
+            response_body.push(0); // tag buffer (1 byte)
+        }
+    }
+    response_body.extend_from_slice(&context.throttle_time_ms.to_be_bytes()); // throttle_time_ms (4 bytes)
+    if flexible {
+        response_body.push(0); // tag buffer (1 byte)
+    }
+
+    frame_response(response_body)
+}
+
+pub fn handle_describe_delegation_token_request(context: &RequestContext, message_buffer: &[u8]) -> Vec<u8> {
+    let owners = match parse_describe_delegation_token_request(message_buffer, context.api_version) {
+        Ok(owners) => owners,
+        Err(e) => {
+            println!("error parsing DescribeDelegationToken request: {}", e);
+            return create_describe_delegation_token_error_response(context, message_buffer, 42); // INVALID_REQUEST
+        }
+    };
+    if let Err(error_code) = check_token_request(context.connection) {
+        return create_describe_delegation_token_response(context, error_code, &[]);
+    }
+
+    // Principals see the tokens they own or may renew, and those the authorizer lets them describe:
+    // by token ID, or every token of an owner with DESCRIBE_TOKENS on that user
+    let principal = context.connection.principal();
+    let describable = |token: &TokenInformation| {
+        token.renewable_by(&principal)
+            || authorize(context.connection, AclOperation::Describe, ResourceType::DelegationToken, &token.token_id)
+            || authorize(context.connection, AclOperation::DescribeTokens, ResourceType::User,
+                         &format!("{}:{}", USER_PRINCIPAL_TYPE, token.owner))
+    };
+    let tokens: Vec<TokenInformation> = DELEGATION_TOKENS
+        .lock()
+        .unwrap()
+        .values()
+        .filter(|token| {
+            owners.as_ref().map_or(true, |owners| {
+                owners.iter().any(|(owner_type, owner_name)| owner_type == USER_PRINCIPAL_TYPE && *owner_name == token.owner)
+            })
+        })
+        .filter(|token| describable(token))
+        .cloned()
+        .collect();
+    create_describe_delegation_token_response(context, 0, &tokens)
+}
+
+pub fn create_describe_delegation_token_error_response(context: &RequestContext, _message_buffer: &[u8], error_code: i16) -> Vec<u8> {
+    create_describe_delegation_token_response(context, error_code, &[])
+}
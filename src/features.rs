@@ -0,0 +1,361 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::Mutex;
+
+use crate::authorizer::{authorize, AclOperation, ResourceType, CLUSTER_NAME};
+use crate::metadata_log::{self, MetadataRecord};
+use crate::{
+    frame_response, read_compact_string, read_i16_be, read_i32_be, read_i8, read_unsigned_varint, skip_request_header,
+    skip_tagged_fields, write_compact_nullable_string, write_compact_string, write_unsigned_varint, RequestContext,
+};
+
+const FEATURE_LEVEL_RECORD: u32 = 12;
+
+pub const METADATA_VERSION: &str = "metadata.version";
+pub const KRAFT_VERSION: &str = "kraft.version";
+pub const TRANSACTION_VERSION: &str = "transaction.version";
+
+pub struct SupportedFeature {
+    pub name: &'static str,
+    pub min_version: i16,
+    pub max_version: i16,
+}
+
+// Feature version ranges this broker can run at; advertised as SupportedFeatures in ApiVersions
+pub const SUPPORTED_FEATURES: &[SupportedFeature] = &[
+    SupportedFeature { name: METADATA_VERSION, min_version: 1, max_version: 21 }, // 3.0-IV1 .. 3.9-IV0
+    SupportedFeature { name: KRAFT_VERSION, min_version: 0, max_version: 1 },
+    SupportedFeature { name: TRANSACTION_VERSION, min_version: 0, max_version: 2 },
+];
+
+// metadata.version levels that changed the metadata record format, indexed by level; downgrading
+// across any of these could drop metadata, so only unchanged ranges may be safely downgraded
+const METADATA_VERSION_CHANGED_METADATA: [bool; 22] = [
+    false, false, false, true, false, true, true, true, true, false, true,
+    true, false, false, true, true, false, true, false, false, false, false,
+];
+
+// UpdateFeatures upgrade types (v1+); v0's allow_downgrade maps to safe downgrade
+pub const UPGRADE: i8 = 1;
+pub const SAFE_DOWNGRADE: i8 = 2;
+pub const UNSAFE_DOWNGRADE: i8 = 3;
+
+pub struct FeatureUpdate {
+    pub feature: String,
+    pub max_version_level: i16,
+    pub upgrade_type: i8,
+}
+
+pub fn find_supported_feature(name: &str) -> Option<&'static SupportedFeature> {
+    SUPPORTED_FEATURES.iter().find(|feature| feature.name == name)
+}
+
+#[derive(Clone)]
+pub struct FinalizedFeatures {
+    // -1 means the finalized levels are not known yet
+    pub epoch: i64,
+    // Features finalized at level 0 are disabled and left out, as Kafka does
+    pub levels: BTreeMap<String, i16>,
+}
+
+static FINALIZED_FEATURES: Mutex<FinalizedFeatures> = Mutex::new(FinalizedFeatures {
+    epoch: -1,
+    levels: BTreeMap::new(),
+});
+
+static FEATURE_UPDATE_LOCK: Mutex<()> = Mutex::new(());
+
+pub fn finalized_features() -> FinalizedFeatures {
+    FINALIZED_FEATURES.lock().unwrap().clone()
+}
+
+// Applies a FeatureLevelRecord read back from the metadata log
+pub fn replay(record: &MetadataRecord) {
+    if record.record_type != FEATURE_LEVEL_RECORD {
+        return;
+    }
+    match decode_feature_level_record(&record.data) {
+        Ok((name, level)) => apply_feature_level(name, level, record.offset),
+        Err(e) => println!("error decoding FeatureLevelRecord at offset {}: {}", record.offset, e),
+    }
+}
+
+// A freshly formatted cluster has no metadata.version yet; finalize the latest one
+pub fn bootstrap_finalized_features() {
+    if finalized_features().levels.contains_key(METADATA_VERSION) {
+        return;
+    }
+    let metadata_version = find_supported_feature(METADATA_VERSION).map(|feature| feature.max_version).unwrap_or(1);
+    let update = FeatureUpdate {
+        feature: METADATA_VERSION.to_string(),
+        max_version_level: metadata_version,
+        upgrade_type: UPGRADE,
+    };
+    if let Err((_, message)) = update_features(&[update], false) {
+        println!("error bootstrapping metadata.version: {}", message);
+    }
+}
+
+// Validates every update first and applies them together, so a request never half-applies.
+// Errors are (error_code, error_message).
+pub fn update_features(updates: &[FeatureUpdate], validate_only: bool) -> Result<(), (i16, String)> {
+    // Requests are handled concurrently; validation and apply must not interleave with another update
+    let _update_guard = FEATURE_UPDATE_LOCK.lock().unwrap();
+    let finalized = finalized_features();
+    for update in updates {
+        let current_level = finalized.levels.get(&update.feature).copied().unwrap_or(0);
+        validate_feature_update(update, current_level).map_err(|message| (95, message))?; // INVALID_UPDATE_VERSION
+    }
+    if validate_only {
+        return Ok(());
+    }
+
+    let records: Vec<(u32, u32, Vec<u8>)> = updates
+        .iter()
+        .map(|update| (FEATURE_LEVEL_RECORD, 0, encode_feature_level_record(&update.feature, update.max_version_level)))
+        .collect();
+    let last_offset = metadata_log::append(&records)
+        .map_err(|e| (96, format!("Unable to persist feature levels: {}", e)))?; // FEATURE_UPDATE_FAILED
+
+    let first_offset = last_offset - updates.len() as i64 + 1;
+    for (index, update) in updates.iter().enumerate() {
+        apply_feature_level(update.feature.clone(), update.max_version_level, first_offset + index as i64);
+    }
+    Ok(())
+}
+
+fn validate_feature_update(update: &FeatureUpdate, current_level: i16) -> Result<(), String> {
+    let feature = find_supported_feature(&update.feature)
+        .ok_or_else(|| format!("The controller does not support the given feature {}.", update.feature))?;
+    let level = update.max_version_level;
+
+    if level != 0 && (level < feature.min_version || level > feature.max_version) {
+        return Err(format!("Invalid update version {} for feature {}. The controller supports versions {} to {}.",
+                           level, feature.name, feature.min_version, feature.max_version));
+    }
+    if !(UPGRADE..=UNSAFE_DOWNGRADE).contains(&update.upgrade_type) {
+        return Err(format!("Unknown upgrade type {} for feature {}.", update.upgrade_type, feature.name));
+    }
+    if level >= current_level {
+        return Ok(());
+    }
+    if update.upgrade_type == UPGRADE {
+        return Err(format!("Can't downgrade the version of feature {} from {} to {} without setting the upgrade type to safe or unsafe downgrade.",
+                           feature.name, current_level, level));
+    }
+    if feature.name != METADATA_VERSION {
+        return Ok(());
+    }
+
+    if level == 0 {
+        return Err("metadata.version cannot be disabled.".to_string());
+    }
+    if update.upgrade_type == UNSAFE_DOWNGRADE {
+        return Err("Unsafe metadata.version downgrades are not supported.".to_string());
+    }
+    let metadata_changed = ((level + 1)..=current_level)
+        .any(|changed_level| METADATA_VERSION_CHANGED_METADATA.get(changed_level as usize).copied().unwrap_or(true));
+    if metadata_changed {
+        return Err(format!("Refusing to downgrade metadata.version from {} to {} because it might delete metadata information.",
+                           current_level, level));
+    }
+    Ok(())
+}
+
+fn apply_feature_level(name: String, level: i16, offset: i64) {
+    let mut finalized = FINALIZED_FEATURES.lock().unwrap();
+    println!("finalized feature {} at level {} (epoch {})", name, level, offset);
+    if level == 0 {
+        finalized.levels.remove(&name);
+    } else {
+        finalized.levels.insert(name, level);
+    }
+    // Like Kafka, the finalized features epoch is the metadata log offset of the latest change
+    finalized.epoch = finalized.epoch.max(offset);
+}
+
+fn encode_feature_level_record(name: &str, level: i16) -> Vec<u8> {
+    let mut data = Vec::new();
+    write_compact_string(&mut data, name);            // name
+    data.extend_from_slice(&level.to_be_bytes());     // feature_level (2 bytes)
+    data.push(0);                                     // tag buffer (1 byte)
+    data
+}
+
+fn decode_feature_level_record(data: &[u8]) -> Result<(String, i16), &'static str> {
+    let (name, offset) = read_compact_string(data, 0)?;
+    let level = read_i16_be(data, offset)?;
+    Ok((name, level))
+}
+
+struct UpdateFeaturesRequest {
+    feature_updates: Vec<FeatureUpdate>,
+    validate_only: bool,
+}
+
+fn parse_update_features_request(buffer: &[u8], api_version: i16) -> Result<UpdateFeaturesRequest, &'static str> {
+    let mut offset = skip_request_header(buffer, true)?;
+    let _timeout_ms = read_i32_be(buffer, offset)?;
+    offset += 4;
+
+    let (updates_count_raw, new_offset) = read_unsigned_varint(buffer, offset)?;
+    offset = new_offset;
+    let updates_count = updates_count_raw.saturating_sub(1);
+
+    let mut feature_updates = Vec::new();
+    for _ in 0..updates_count {
+        let (feature, new_offset) = read_compact_string(buffer, offset)?;
+        offset = new_offset;
+        let max_version_level = read_i16_be(buffer, offset)?;
+        offset += 2;
+
+        // v0 only says whether downgrades are allowed; v1 names the upgrade type
+        let upgrade_type = if api_version == 0 {
+            if read_i8(buffer, offset)? != 0 { SAFE_DOWNGRADE } else { UPGRADE }
+        } else {
+            read_i8(buffer, offset)?
+        };
+        offset += 1;
+        offset = skip_tagged_fields(buffer, offset)?;
+
+        feature_updates.push(FeatureUpdate { feature, max_version_level, upgrade_type });
+    }
+
+    let validate_only = if api_version >= 1 {
+        let validate_only = read_i8(buffer, offset)? != 0;
+        offset += 1;
+        validate_only
+    } else {
+        false
+    };
+    skip_tagged_fields(buffer, offset)?;
+
+    Ok(UpdateFeaturesRequest { feature_updates, validate_only })
+}
+
+fn create_update_features_response(context: &RequestContext, features: &[String], error_code: i16, error_message: Option<&str>) -> Vec<u8> {
+    let mut response_body = Vec::new();
+
+    // Response header
+    response_body.extend_from_slice(&context.correlation_id.to_be_bytes()); // correlation_id (4 bytes)
+    response_body.push(0); // tag buffer (1 byte)
+
+    // Response body
+    response_body.extend_from_slice(&context.throttle_time_ms.to_be_bytes()); // throttle_time_ms (4 bytes)
+    response_body.extend_from_slice(&error_code.to_be_bytes());               // error_code (2 bytes)
+    write_compact_nullable_string(&mut response_body, error_message);
+
+    // Results: updates are applied all-or-nothing, so every feature shares the top-level outcome
+    write_unsigned_varint(&mut response_body, features.len() as u32 + 1);
+    for feature in features {
+        write_compact_string(&mut response_body, feature);          // feature
+        response_body.extend_from_slice(&error_code.to_be_bytes()); // error_code (2 bytes)
+        write_compact_nullable_string(&mut response_body, error_message);
+        response_body.push(0); // tag buffer (1 byte)
+    }
+
+    // Response body tag buffer
+    response_body.push(0); // tag buffer (1 byte)
+
+    frame_response(response_body)
+}
+
+pub fn handle_update_features_request(context: &RequestContext, message_buffer: &[u8]) -> Vec<u8> {
+    let request = match parse_update_features_request(message_buffer, context.api_version) {
+        Ok(request) => request,
+        Err(e) => {
+            println!("error parsing UpdateFeatures request: {}", e);
+            return create_update_features_error_response(context, message_buffer, 42); // INVALID_REQUEST
+        }
+    };
+    if !authorize(context.connection, AclOperation::Alter, ResourceType::Cluster, CLUSTER_NAME) {
+        return create_update_features_error_response(context, message_buffer, 31); // CLUSTER_AUTHORIZATION_FAILED
+    }
+    let features: Vec<String> = request.feature_updates.iter().map(|update| update.feature.clone()).collect();
+
+    let unique_features: BTreeSet<&String> = features.iter().collect();
+    if unique_features.len() != features.len() {
+        return create_update_features_response(context, &features, 42, // INVALID_REQUEST
+                                               Some("The request contains multiple updates for the same feature."));
+    }
+
+    match update_features(&request.feature_updates, request.validate_only) {
+        Ok(()) => {
+            println!("UpdateFeatures applied {} updates (validate_only: {})", features.len(), request.validate_only);
+            create_update_features_response(context, &features, 0, None)
+        }
+        Err((error_code, message)) => {
+            println!("UpdateFeatures rejected: {}", message);
+            create_update_features_response(context, &features, error_code, Some(&message))
+        }
+    }
+}
+
+pub fn create_update_features_error_response(context: &RequestContext, message_buffer: &[u8], error_code: i16) -> Vec<u8> {
+    let features: Vec<String> = parse_update_features_request(message_buffer, context.api_version)
+        .map(|request| request.feature_updates.into_iter().map(|update| update.feature).collect())
+        .unwrap_or_default();
+    create_update_features_response(context, &features, error_code, None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn update(feature: &str, max_version_level: i16, upgrade_type: i8) -> FeatureUpdate {
+        FeatureUpdate { feature: feature.to_string(), max_version_level, upgrade_type }
+    }
+
+    #[test]
+    fn upgrades_and_downgrades_are_validated() {
+        assert!(validate_feature_update(&update(KRAFT_VERSION, 1, UPGRADE), 0).is_ok());
+        assert!(validate_feature_update(&update(KRAFT_VERSION, 2, UPGRADE), 0).is_err(), "beyond the supported range");
+        assert!(validate_feature_update(&update("no.such.feature", 1, UPGRADE), 0).is_err());
+        assert!(validate_feature_update(&update(KRAFT_VERSION, 1, 4), 0).is_err(), "unknown upgrade type");
+
+        // Downgrades need a downgrade type; other features may be downgraded either way
+        assert!(validate_feature_update(&update(TRANSACTION_VERSION, 1, UPGRADE), 2).is_err());
+        assert!(validate_feature_update(&update(TRANSACTION_VERSION, 1, SAFE_DOWNGRADE), 2).is_ok());
+        assert!(validate_feature_update(&update(TRANSACTION_VERSION, 0, UNSAFE_DOWNGRADE), 2).is_ok());
+    }
+
+    #[test]
+    fn metadata_version_only_allows_safe_downgrades_that_keep_the_metadata_format() {
+        // Levels 18 to 21 didn't change the metadata format, 17 did
+        assert!(validate_feature_update(&update(METADATA_VERSION, 17, SAFE_DOWNGRADE), 21).is_ok());
+        assert!(validate_feature_update(&update(METADATA_VERSION, 16, SAFE_DOWNGRADE), 21).is_err());
+        assert!(validate_feature_update(&update(METADATA_VERSION, 20, UNSAFE_DOWNGRADE), 21).is_err());
+        assert!(validate_feature_update(&update(METADATA_VERSION, 0, SAFE_DOWNGRADE), 21).is_err(), "cannot be disabled");
+    }
+
+    #[test]
+    fn validate_only_updates_leave_the_levels_unchanged() {
+        let before = finalized_features();
+        assert!(update_features(&[update(KRAFT_VERSION, 1, UPGRADE)], true).is_ok());
+        assert_eq!(finalized_features().levels.get(KRAFT_VERSION), before.levels.get(KRAFT_VERSION));
+
+        // An invalid update fails the whole request
+        let updates = [update(KRAFT_VERSION, 1, UPGRADE), update(KRAFT_VERSION, 5, UPGRADE)];
+        assert_eq!(update_features(&updates, true).map_err(|(error_code, _)| error_code), Err(95)); // INVALID_UPDATE_VERSION
+    }
+
+    #[test]
+    fn applied_updates_are_persisted_and_replayed() {
+        metadata_log::open_scratch_log();
+        assert!(update_features(&[update(TRANSACTION_VERSION, 2, UPGRADE)], false).is_ok());
+        let finalized = finalized_features();
+        assert_eq!(finalized.levels.get(TRANSACTION_VERSION), Some(&2));
+
+        assert_eq!(update_features(&[update(TRANSACTION_VERSION, 1, UPGRADE)], false).map_err(|(error_code, _)| error_code), Err(95));
+        assert_eq!(finalized_features().levels.get(TRANSACTION_VERSION), Some(&2));
+
+        // Replaying a FeatureLevelRecord applies it at the record's offset
+        let record = MetadataRecord {
+            offset: finalized.epoch + 100,
+            record_type: FEATURE_LEVEL_RECORD,
+            data: encode_feature_level_record(TRANSACTION_VERSION, 0),
+        };
+        replay(&record);
+        let finalized = finalized_features();
+        assert_eq!((finalized.levels.get(TRANSACTION_VERSION), finalized.epoch), (None, record.offset));
+    }
+}
@@ -1,22 +1,70 @@
 #![allow(unused_imports)]
-use std::io::{Read, Write};
-use std::net::{TcpListener, TcpStream};
 use std::os::unix::io::AsRawFd;
-use std::thread;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, WriteHalf};
+use tokio::net::{TcpListener, TcpSocket, TcpStream};
+use tokio::sync::{mpsc, OwnedSemaphorePermit, Semaphore};
+use tokio::task::JoinHandle;
 
 extern crate libc;
 
+mod authorizer;
+mod base64;
+mod config;
+mod connections;
+mod delegation_token;
+mod features;
+mod metadata;
+mod metadata_log;
+mod oauthbearer;
+mod quotas;
+mod sasl;
+mod scram;
+mod ssl;
+
+use authorizer::{
+    authorize, authorized_operations, create_create_acls_error_response, create_delete_acls_error_response,
+    create_describe_acls_error_response, handle_create_acls_request, handle_delete_acls_request,
+    handle_describe_acls_request, AclOperation, ResourceType, TOPIC_OPERATIONS,
+};
+use config::{
+    broker_config, create_describe_configs_error_response, handle_describe_configs_request, init_broker_config,
+    load_broker_config, SecurityProtocol,
+};
+use connections::{log_client_software_counts, ClientConnection, ConnectionLimits, ConnectionSlot};
+use metadata::{create_metadata_error_response, handle_metadata_request};
+use quotas::{
+    create_alter_client_quotas_error_response, create_describe_client_quotas_error_response,
+    handle_alter_client_quotas_request, handle_describe_client_quotas_request,
+};
+use sasl::{
+    create_sasl_authenticate_error_response, create_sasl_handshake_error_response, handle_sasl_authenticate_request,
+    handle_sasl_handshake_request, SASL_AUTHENTICATE_KEY, SASL_HANDSHAKE_KEY,
+};
+use scram::{
+    create_alter_user_scram_credentials_error_response, create_describe_user_scram_credentials_error_response,
+    handle_alter_user_scram_credentials_request, handle_describe_user_scram_credentials_request,
+};
+use delegation_token::{
+    create_create_delegation_token_error_response, create_describe_delegation_token_error_response,
+    create_expire_delegation_token_error_response, create_renew_delegation_token_error_response,
+    handle_create_delegation_token_request, handle_describe_delegation_token_request,
+    handle_expire_delegation_token_request, handle_renew_delegation_token_request,
+};
+use features::{
+    bootstrap_finalized_features, create_update_features_error_response, finalized_features,
+    handle_update_features_request, SUPPORTED_FEATURES,
+};
+
 // Helper functions for safe byte parsing
-fn read_exact_bytes(stream: &mut TcpStream, buffer: &mut [u8]) -> Result<(), std::io::Error> {
-    let mut bytes_read = 0;
-    while bytes_read < buffer.len() {
-        match stream.read(&mut buffer[bytes_read..]) {
-            Ok(0) => return Err(std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "Connection closed")),
-            Ok(n) => bytes_read += n,
-            Err(e) => return Err(e),
-        }
+fn read_i64_be(buffer: &[u8], offset: usize) -> Result<i64, &'static str> {
+    if offset + 8 > buffer.len() {
+        return Err("Buffer too short for i64");
     }
-    Ok(())
+    let bytes: [u8; 8] = buffer[offset..offset + 8].try_into().unwrap();
+    Ok(i64::from_be_bytes(bytes))
 }
 
 fn read_i32_be(buffer: &[u8], offset: usize) -> Result<i32, &'static str> {
@@ -35,17 +83,407 @@ fn read_i16_be(buffer: &[u8], offset: usize) -> Result<i16, &'static str> {
     Ok(i16::from_be_bytes(bytes))
 }
 
-fn read_compact_string(buffer: &[u8], offset: usize) -> Result<(String, usize), &'static str> {
+fn read_i8(buffer: &[u8], offset: usize) -> Result<i8, &'static str> {
     if offset >= buffer.len() {
-        return Err("Buffer too short for string length");
+        return Err("Buffer too short for i8");
+    }
+    Ok(buffer[offset] as i8)
+}
+
+fn read_unsigned_varint(buffer: &[u8], mut offset: usize) -> Result<(u32, usize), &'static str> {
+    let mut value: u32 = 0;
+    let mut shift = 0;
+    loop {
+        if offset >= buffer.len() {
+            return Err("Buffer too short for varint");
+        }
+        if shift > 28 {
+            return Err("Varint is too long");
+        }
+        let byte = buffer[offset];
+        offset += 1;
+        value |= ((byte & 0x7f) as u32) << shift;
+        if byte & 0x80 == 0 {
+            return Ok((value, offset));
+        }
+        shift += 7;
+    }
+}
+
+fn read_compact_string(buffer: &[u8], offset: usize) -> Result<(String, usize), &'static str> {
+    // COMPACT_STRING length is encoded as an unsigned varint of actual_length + 1
+    let (length_plus_one, offset) = read_unsigned_varint(buffer, offset)?;
+    if length_plus_one == 0 {
+        return Err("Unexpected null compact string");
     }
-    let length = buffer[offset] as usize;
-    if offset + 1 + length > buffer.len() {
+    let length = (length_plus_one - 1) as usize;
+    if offset + length > buffer.len() {
         return Err("Buffer too short for string content");
     }
-    let string_bytes = &buffer[offset + 1..offset + 1 + length];
+    let string_bytes = &buffer[offset..offset + length];
     let string = String::from_utf8(string_bytes.to_vec()).map_err(|_| "Invalid UTF-8")?;
-    Ok((string, offset + 1 + length))
+    Ok((string, offset + length))
+}
+
+fn skip_tagged_fields(buffer: &[u8], offset: usize) -> Result<usize, &'static str> {
+    let (field_count, mut offset) = read_unsigned_varint(buffer, offset)?;
+    for _ in 0..field_count {
+        let (_tag, new_offset) = read_unsigned_varint(buffer, offset)?;
+        let (size, new_offset) = read_unsigned_varint(buffer, new_offset)?;
+        offset = new_offset + size as usize;
+        if offset > buffer.len() {
+            return Err("Buffer too short for tagged field");
+        }
+    }
+    Ok(offset)
+}
+
+// Returns the offset of the request body: skips api_key, api_version, correlation_id, client_id
+// and, for flexible request headers (v2), the header tag buffer
+fn skip_request_header(buffer: &[u8], flexible: bool) -> Result<usize, &'static str> {
+    let client_id_length = read_i16_be(buffer, 8)?;
+    let mut offset = 10;
+    if client_id_length > 0 {
+        offset += client_id_length as usize;
+    }
+    if offset > buffer.len() {
+        return Err("Buffer too short for client_id");
+    }
+    if flexible {
+        offset = skip_tagged_fields(buffer, offset)?;
+    }
+    Ok(offset)
+}
+
+// Reads a NULLABLE_STRING, or a COMPACT_NULLABLE_STRING in flexible versions; None is null
+fn read_nullable_string(buffer: &[u8], offset: usize, flexible: bool) -> Result<(Option<String>, usize), &'static str> {
+    let (length, offset) = if flexible {
+        let (length_plus_one, offset) = read_unsigned_varint(buffer, offset)?;
+        (length_plus_one as i64 - 1, offset)
+    } else {
+        (read_i16_be(buffer, offset)? as i64, offset + 2)
+    };
+    if length < 0 {
+        return Ok((None, offset));
+    }
+    let end = offset + length as usize;
+    if end > buffer.len() {
+        return Err("Buffer too short for string content");
+    }
+    let string = String::from_utf8(buffer[offset..end].to_vec()).map_err(|_| "Invalid UTF-8")?;
+    Ok((Some(string), end))
+}
+
+fn read_string(buffer: &[u8], offset: usize, flexible: bool) -> Result<(String, usize), &'static str> {
+    match read_nullable_string(buffer, offset, flexible)? {
+        (Some(string), offset) => Ok((string, offset)),
+        (None, _) => Err("Unexpected null string"),
+    }
+}
+
+// Reads BYTES, or COMPACT_BYTES in flexible versions
+fn read_bytes(buffer: &[u8], offset: usize, flexible: bool) -> Result<(Vec<u8>, usize), &'static str> {
+    let (length, offset) = if flexible {
+        let (length_plus_one, offset) = read_unsigned_varint(buffer, offset)?;
+        (length_plus_one as i64 - 1, offset)
+    } else {
+        (read_i32_be(buffer, offset)? as i64, offset + 4)
+    };
+    if length < 0 {
+        return Err("Unexpected null bytes");
+    }
+    let end = offset + length as usize;
+    if end > buffer.len() {
+        return Err("Buffer too short for bytes content");
+    }
+    Ok((buffer[offset..end].to_vec(), end))
+}
+
+// Reads an ARRAY length, or a COMPACT_ARRAY length in flexible versions; None is a null array
+fn read_array_length(buffer: &[u8], offset: usize, flexible: bool) -> Result<(Option<usize>, usize), &'static str> {
+    let (length, offset) = if flexible {
+        let (length_plus_one, offset) = read_unsigned_varint(buffer, offset)?;
+        (length_plus_one as i64 - 1, offset)
+    } else {
+        (read_i32_be(buffer, offset)? as i64, offset + 4)
+    };
+    if length < 0 {
+        return Ok((None, offset));
+    }
+    // Every element takes at least a byte, which bounds lengths from malformed requests
+    if length as usize > buffer.len() - offset {
+        return Err("Array length exceeds buffer");
+    }
+    Ok((Some(length as usize), offset))
+}
+
+fn write_unsigned_varint(buffer: &mut Vec<u8>, mut value: u32) {
+    while value >= 0x80 {
+        buffer.push((value as u8 & 0x7f) | 0x80);
+        value >>= 7;
+    }
+    buffer.push(value as u8);
+}
+
+fn write_compact_string(buffer: &mut Vec<u8>, value: &str) {
+    write_unsigned_varint(buffer, value.len() as u32 + 1);
+    buffer.extend_from_slice(value.as_bytes());
+}
+
+fn write_compact_nullable_string(buffer: &mut Vec<u8>, value: Option<&str>) {
+    match value {
+        Some(value) => write_compact_string(buffer, value),
+        None => buffer.push(0), // null is encoded as length 0
+    }
+}
+
+fn write_string(buffer: &mut Vec<u8>, value: &str, flexible: bool) {
+    write_nullable_string(buffer, Some(value), flexible);
+}
+
+fn write_nullable_string(buffer: &mut Vec<u8>, value: Option<&str>, flexible: bool) {
+    match (value, flexible) {
+        (value, true) => write_compact_nullable_string(buffer, value),
+        (Some(value), false) => {
+            buffer.extend_from_slice(&(value.len() as i16).to_be_bytes());
+            buffer.extend_from_slice(value.as_bytes());
+        }
+        (None, false) => buffer.extend_from_slice(&(-1i16).to_be_bytes()),
+    }
+}
+
+fn write_bytes(buffer: &mut Vec<u8>, value: &[u8], flexible: bool) {
+    if flexible {
+        write_unsigned_varint(buffer, value.len() as u32 + 1);
+    } else {
+        buffer.extend_from_slice(&(value.len() as i32).to_be_bytes());
+    }
+    buffer.extend_from_slice(value);
+}
+
+fn write_array_length(buffer: &mut Vec<u8>, length: usize, flexible: bool) {
+    if flexible {
+        write_unsigned_varint(buffer, length as u32 + 1);
+    } else {
+        buffer.extend_from_slice(&(length as i32).to_be_bytes());
+    }
+}
+
+// Frames a response: message_size (4 bytes) followed by the header and body
+fn frame_response(response_body: Vec<u8>) -> Vec<u8> {
+    let message_size = response_body.len() as i32;
+    let mut response = Vec::new();
+    response.extend_from_slice(&message_size.to_be_bytes());
+    response.extend_from_slice(&response_body);
+    response
+}
+
+fn write_tagged_field(buffer: &mut Vec<u8>, tag: u32, data: &[u8]) {
+    write_unsigned_varint(buffer, tag);
+    write_unsigned_varint(buffer, data.len() as u32);
+    buffer.extend_from_slice(data);
+}
+
+// Control requests are small; anything bigger than this is not a well-formed request
+const CONTROL_REQUEST_MAX_BYTES: usize = 1024 * 1024;
+
+// api_key (2) + api_version (2) + correlation_id (4)
+const REQUEST_HEADER_SIZE: usize = 8;
+
+struct ConnectionSettings {
+    max_in_flight_requests: usize,
+    socket_request_max_bytes: usize,
+    request_memory_pool: Arc<Semaphore>,
+    // queued.max.requests: one permit per request read but not yet handled, across all connections
+    request_queue: Arc<Semaphore>,
+    // connections.max.idle.ms
+    max_idle_time: Duration,
+    connection_limits: Arc<ConnectionLimits>,
+}
+
+const API_VERSIONS_KEY: i16 = 18;
+
+struct RequestContext<'a> {
+    correlation_id: i32,
+    api_version: i16,
+    connection: &'a ClientConnection,
+    // Written into the response's throttle_time_ms; non-zero while the client is over a quota
+    throttle_time_ms: i32,
+}
+
+// Every request handler receives the request context and the full message and returns the framed response
+type RequestHandler = fn(&RequestContext, &[u8]) -> Vec<u8>;
+
+// Builds a response in the API's own schema (header version, flexible layout) that only carries an error code
+type ErrorResponseBuilder = fn(&RequestContext, &[u8], i16) -> Vec<u8>;
+
+struct ApiHandler {
+    api_key: i16,
+    min_version: i16,
+    max_version: i16,
+    // Requests of this API larger than this close the connection, even when under socket.request.max.bytes
+    max_request_bytes: usize,
+    handle: RequestHandler,
+    error_response: ErrorResponseBuilder,
+}
+
+impl ApiHandler {
+    fn supports_version(&self, api_version: i16) -> bool {
+        (self.min_version..=self.max_version).contains(&api_version)
+    }
+}
+
+// Handler registry: ApiVersions advertises exactly these entries and handle_client routes through them
+const API_HANDLERS: &[ApiHandler] = &[
+    ApiHandler {
+        api_key: 3,
+        min_version: 0,
+        max_version: 12,
+        max_request_bytes: CONTROL_REQUEST_MAX_BYTES,
+        handle: handle_metadata_request,
+        error_response: create_metadata_error_response,
+    },
+    ApiHandler {
+        api_key: SASL_HANDSHAKE_KEY,
+        // v0 exchanged raw SASL tokens outside Kafka framing; clients use SaslAuthenticate since v1
+        min_version: 1,
+        max_version: 1,
+        max_request_bytes: CONTROL_REQUEST_MAX_BYTES,
+        handle: handle_sasl_handshake_request,
+        error_response: create_sasl_handshake_error_response,
+    },
+    ApiHandler {
+        api_key: API_VERSIONS_KEY,
+        min_version: 0,
+        max_version: 4,
+        max_request_bytes: CONTROL_REQUEST_MAX_BYTES,
+        handle: handle_api_versions_request,
+        error_response: create_api_versions_error_response,
+    },
+    ApiHandler {
+        api_key: 29,
+        min_version: 0,
+        max_version: 3,
+        max_request_bytes: CONTROL_REQUEST_MAX_BYTES,
+        handle: handle_describe_acls_request,
+        error_response: create_describe_acls_error_response,
+    },
+    ApiHandler {
+        api_key: 30,
+        min_version: 0,
+        max_version: 3,
+        max_request_bytes: CONTROL_REQUEST_MAX_BYTES,
+        handle: handle_create_acls_request,
+        error_response: create_create_acls_error_response,
+    },
+    ApiHandler {
+        api_key: 31,
+        min_version: 0,
+        max_version: 3,
+        max_request_bytes: CONTROL_REQUEST_MAX_BYTES,
+        handle: handle_delete_acls_request,
+        error_response: create_delete_acls_error_response,
+    },
+    ApiHandler {
+        api_key: 32,
+        min_version: 0,
+        max_version: 4,
+        max_request_bytes: CONTROL_REQUEST_MAX_BYTES,
+        handle: handle_describe_configs_request,
+        error_response: create_describe_configs_error_response,
+    },
+    ApiHandler {
+        api_key: SASL_AUTHENTICATE_KEY,
+        min_version: 0,
+        max_version: 2,
+        max_request_bytes: CONTROL_REQUEST_MAX_BYTES,
+        handle: handle_sasl_authenticate_request,
+        error_response: create_sasl_authenticate_error_response,
+    },
+    ApiHandler {
+        api_key: 38,
+        min_version: 0,
+        max_version: 3,
+        max_request_bytes: CONTROL_REQUEST_MAX_BYTES,
+        handle: handle_create_delegation_token_request,
+        error_response: create_create_delegation_token_error_response,
+    },
+    ApiHandler {
+        api_key: 39,
+        min_version: 0,
+        max_version: 2,
+        max_request_bytes: CONTROL_REQUEST_MAX_BYTES,
+        handle: handle_renew_delegation_token_request,
+        error_response: create_renew_delegation_token_error_response,
+    },
+    ApiHandler {
+        api_key: 40,
+        min_version: 0,
+        max_version: 2,
+        max_request_bytes: CONTROL_REQUEST_MAX_BYTES,
+        handle: handle_expire_delegation_token_request,
+        error_response: create_expire_delegation_token_error_response,
+    },
+    ApiHandler {
+        api_key: 41,
+        min_version: 0,
+        max_version: 3,
+        max_request_bytes: CONTROL_REQUEST_MAX_BYTES,
+        handle: handle_describe_delegation_token_request,
+        error_response: create_describe_delegation_token_error_response,
+    },
+    ApiHandler {
+        api_key: 48,
+        min_version: 0,
+        max_version: 1,
+        max_request_bytes: CONTROL_REQUEST_MAX_BYTES,
+        handle: handle_describe_client_quotas_request,
+        error_response: create_describe_client_quotas_error_response,
+    },
+    ApiHandler {
+        api_key: 49,
+        min_version: 0,
+        max_version: 1,
+        max_request_bytes: CONTROL_REQUEST_MAX_BYTES,
+        handle: handle_alter_client_quotas_request,
+        error_response: create_alter_client_quotas_error_response,
+    },
+    ApiHandler {
+        api_key: 50,
+        min_version: 0,
+        max_version: 0,
+        max_request_bytes: CONTROL_REQUEST_MAX_BYTES,
+        handle: handle_describe_user_scram_credentials_request,
+        error_response: create_describe_user_scram_credentials_error_response,
+    },
+    ApiHandler {
+        api_key: 51,
+        min_version: 0,
+        max_version: 0,
+        max_request_bytes: CONTROL_REQUEST_MAX_BYTES,
+        handle: handle_alter_user_scram_credentials_request,
+        error_response: create_alter_user_scram_credentials_error_response,
+    },
+    ApiHandler {
+        api_key: 57,
+        min_version: 0,
+        max_version: 1,
+        max_request_bytes: CONTROL_REQUEST_MAX_BYTES,
+        handle: handle_update_features_request,
+        error_response: create_update_features_error_response,
+    },
+    ApiHandler {
+        api_key: 75,
+        min_version: 0,
+        max_version: 0,
+        max_request_bytes: CONTROL_REQUEST_MAX_BYTES,
+        handle: handle_describe_topic_partitions_request,
+        error_response: create_describe_topic_partitions_error_response,
+    },
+];
+
+fn find_api_handler(api_key: i16) -> Option<&'static ApiHandler> {
+    API_HANDLERS.iter().find(|handler| handler.api_key == api_key)
 }
 
 struct ParsedTopic {
@@ -96,7 +534,9 @@ fn parse_describe_topic_partitions_request(buffer: &[u8], mut offset: usize) ->
     Ok(topics)
 }
 
-fn create_describe_topic_partitions_response(correlation_id: i32, topics: &[ParsedTopic]) -> Vec<u8> {
+// Each topic is answered with its own error code
+fn create_describe_topic_partitions_response(context: &RequestContext, topics: &[(&ParsedTopic, i16)]) -> Vec<u8> {
+    let correlation_id = context.correlation_id;
     let mut response_body = Vec::new();
     
     // Response header
@@ -104,19 +544,17 @@ fn create_describe_topic_partitions_response(correlation_id: i32, topics: &[Pars
     response_body.push(0); // tag buffer (1 byte)
     
     // Response body
-    response_body.extend_from_slice(&0i32.to_be_bytes()); // throttle_time_ms (4 bytes)
+    response_body.extend_from_slice(&context.throttle_time_ms.to_be_bytes()); // throttle_time_ms (4 bytes)
     
-    // Topics array length (1 byte) - COMPACT_ARRAY format: actual_length + 1
-    let compact_array_length = if topics.is_empty() { 0 } else { topics.len() + 1 };
-    response_body.push(compact_array_length as u8);
+    // Topics array length - COMPACT_ARRAY format: actual_length + 1; 0 would be a null array, which
+    // the schema doesn't allow, so no topics is written as 1
+    write_unsigned_varint(&mut response_body, topics.len() as u32 + 1);
     
-    for topic in topics {
-        // Error code: UNKNOWN_TOPIC_OR_PARTITION = 3
-        response_body.extend_from_slice(&3i16.to_be_bytes()); // error_code (2 bytes)
+    for (topic, error_code) in topics {
+        response_body.extend_from_slice(&error_code.to_be_bytes()); // error_code (2 bytes)
         
-        // Topic name: length + content
-        response_body.push(topic.name.len() as u8); // topic name length (1 byte)
-        response_body.extend_from_slice(topic.name.as_bytes()); // topic name content
+        // Topic name: COMPACT_STRING, the name comes from the request and may be any length
+        write_compact_string(&mut response_body, &topic.name);
         
         // Topic ID: 16 bytes of zeros (00000000-0000-0000-0000-000000000000)
         response_body.extend_from_slice(&[0u8; 16]);
@@ -127,8 +565,9 @@ fn create_describe_topic_partitions_response(correlation_id: i32, topics: &[Pars
         // Partitions array: empty
         response_body.push(0); // partitions array length (1 byte)
         
-        // Topic authorized operations: none
-        response_body.extend_from_slice(&0i32.to_be_bytes()); // topic_authorized_operations (4 bytes)
+        // Topic authorized operations: what the client may do with the topic
+        let topic_authorized_operations = authorized_operations(context.connection, ResourceType::Topic, &topic.name, TOPIC_OPERATIONS);
+        response_body.extend_from_slice(&topic_authorized_operations.to_be_bytes()); // topic_authorized_operations (4 bytes)
         
         // Topic tag buffer
         response_body.push(0); // tag buffer (1 byte)
@@ -151,38 +590,112 @@ fn create_describe_topic_partitions_response(correlation_id: i32, topics: &[Pars
     response
 }
 
-fn handle_client(mut stream: TcpStream) {
-    println!("accepted new connection");
+// Applies socket options and, on SSL listeners, completes the handshake before serving requests. The
+// slot counts the connection against max.connections and max.connections.per.ip until it closes.
+async fn open_connection(stream: TcpStream, listener_name: String, settings: Arc<ConnectionSettings>, _slot: ConnectionSlot) {
+    let peer = stream.peer_addr().map(|addr| addr.to_string()).unwrap_or_default();
 
     // Disable Nagle's algorithm for low-latency responses
     if let Err(e) = disable_nagle_algorithm(&stream) {
         println!("Warning: Failed to disable Nagle's algorithm: {}", e);
     }
 
-    // Handle multiple sequential requests on the same connection
+    // socket.send.buffer.bytes / socket.receive.buffer.bytes; -1 keeps the OS default
+    let config = broker_config();
+    for (option, size) in [(libc::SO_SNDBUF, config.socket_send_buffer_bytes),
+                           (libc::SO_RCVBUF, config.socket_receive_buffer_bytes)] {
+        if size != -1 {
+            if let Err(e) = set_socket_buffer_size(&stream, option, size) {
+                println!("Warning: Failed to set socket buffer size: {}", e);
+            }
+        }
+    }
+
+    match config.security_protocol(&listener_name) {
+        Some(SecurityProtocol::Ssl) => {
+            match ssl::accept(stream).await.and_then(|tls_stream| Ok((ssl::principal(&tls_stream, config)?, tls_stream))) {
+                Ok((principal, tls_stream)) => {
+                    let connection = ClientConnection::open(peer, listener_name, principal);
+                    handle_client(tls_stream, connection, settings).await;
+                }
+                Err(e) => println!("closing connection from {} on {}: SSL authentication failed: {}", peer, listener_name, e),
+            }
+        }
+        // On SASL_SSL the principal comes from SASL, not from a client certificate
+        Some(SecurityProtocol::SaslSsl) => match ssl::accept(stream).await {
+            Ok(tls_stream) => {
+                let connection = ClientConnection::open(peer, listener_name, ssl::ANONYMOUS_PRINCIPAL.to_string());
+                handle_client(tls_stream, connection, settings).await;
+            }
+            Err(e) => println!("closing connection from {} on {}: SSL handshake failed: {}", peer, listener_name, e),
+        },
+        _ => {
+            let connection = ClientConnection::open(peer, listener_name, ssl::ANONYMOUS_PRINCIPAL.to_string());
+            handle_client(stream, connection, settings).await;
+        }
+    }
+}
+
+async fn handle_client<S>(stream: S, connection: ClientConnection, settings: Arc<ConnectionSettings>)
+where
+    S: AsyncRead + AsyncWrite + Send + 'static,
+{
+    let connection = Arc::new(connection);
+    println!("accepted new connection {} from {} on {} as User:{}",
+             connection.id, connection.peer, connection.listener, connection.principal());
+
+    // Requests are dispatched as soon as they are read; the writer task awaits their responses in
+    // request order, and each in-flight request holds a permit until its response is written. The
+    // permits already bound the queued responses, and the channel is sized to match.
+    let (mut reader, writer) = tokio::io::split(stream);
+    let (response_sender, response_receiver) = mpsc::channel(settings.max_in_flight_requests);
+    let writer_task = tokio::spawn(write_responses(writer, response_receiver));
+    let in_flight_requests = Arc::new(Semaphore::new(settings.max_in_flight_requests));
+
+    // Handle multiple pipelined requests on the same connection
     loop {
-        // Read message size first (4 bytes)
+        // With the in-flight cap reached, stop reading until the oldest response has been written
+        let in_flight_permit = match Arc::clone(&in_flight_requests).acquire_owned().await {
+            Ok(permit) => permit,
+            Err(_) => break,
+        };
+
+        // Read message size first (4 bytes); a client that sends nothing for connections.max.idle.ms is dropped
         let mut size_buffer = [0u8; 4];
-        if let Err(e) = read_exact_bytes(&mut stream, &mut size_buffer) {
-            println!("error reading message size: {}", e);
-            break;
+        match tokio::time::timeout(settings.max_idle_time, reader.read_exact(&mut size_buffer)).await {
+            Ok(Ok(_)) => {}
+            Ok(Err(e)) => {
+                println!("error reading message size: {}", e);
+                break;
+            }
+            Err(_) => {
+                println!("closing connection {}: idle for longer than connections.max.idle.ms ({} ms)",
+                         connection.id, settings.max_idle_time.as_millis());
+                break;
+            }
         }
         
         let message_size = i32::from_be_bytes(size_buffer);
-        if message_size < 8 || message_size > 10000 {
-            println!("invalid message size: {}", message_size);
+        if message_size < REQUEST_HEADER_SIZE as i32 {
+            println!("closing connection {}: invalid message size {}", connection.id, message_size);
             break;
         }
-        
-        // Read the full message
-        let mut message_buffer = vec![0u8; message_size as usize];
-        if let Err(e) = read_exact_bytes(&mut stream, &mut message_buffer) {
-            println!("error reading message body: {}", e);
+        let message_size = message_size as usize;
+        if message_size > settings.socket_request_max_bytes {
+            println!("closing connection {}: request of {} bytes exceeds socket.request.max.bytes ({})",
+                     connection.id, message_size, settings.socket_request_max_bytes);
+            break;
+        }
+
+        // Read the fixed request header first, so the per-API limit applies before the body is buffered
+        let mut header_buffer = [0u8; REQUEST_HEADER_SIZE];
+        if let Err(e) = reader.read_exact(&mut header_buffer).await {
+            println!("error reading request header: {}", e);
             break;
         }
 
         // Parse request header
-        let api_key = match read_i16_be(&message_buffer, 0) {
+        let api_key = match read_i16_be(&header_buffer, 0) {
             Ok(key) => key,
             Err(e) => {
                 println!("error parsing api_key: {}", e);
@@ -190,7 +703,7 @@ fn handle_client(mut stream: TcpStream) {
             }
         };
         
-        let api_version = match read_i16_be(&message_buffer, 2) {
+        let api_version = match read_i16_be(&header_buffer, 2) {
             Ok(version) => version,
             Err(e) => {
                 println!("error parsing api_version: {}", e);
@@ -198,7 +711,7 @@ fn handle_client(mut stream: TcpStream) {
             }
         };
         
-        let correlation_id = match read_i32_be(&message_buffer, 4) {
+        let correlation_id = match read_i32_be(&header_buffer, 4) {
             Ok(id) => id,
             Err(e) => {
                 println!("error parsing correlation_id: {}", e);
@@ -210,28 +723,143 @@ fn handle_client(mut stream: TcpStream) {
                  api_key, api_version, correlation_id);
 
         // Route based on API key
-        let response = match api_key {
-            18 => {
-                // ApiVersions request
-                handle_api_versions_request(correlation_id, api_version)
-            },
-            75 => {
-                // DescribeTopicPartitions request
-                if api_version != 0 {
-                    // Unsupported version
-                    create_error_response(correlation_id, 35) // UNSUPPORTED_VERSION
-                } else {
-                    handle_describe_topic_partitions_request(correlation_id, &message_buffer)
+        let handler = match find_api_handler(api_key) {
+            Some(handler) => handler,
+            None => {
+                // Without a schema there is no response the client could decode, so it is told by the close instead
+                println!("closing connection {}: unknown api_key {} (api_version {}, correlation_id {})",
+                         connection.id, api_key, api_version, correlation_id);
+                break;
+            }
+        };
+        if let Err(reason) = sasl::check_request_allowed(&connection, api_key) {
+            println!("closing connection {}: {}", connection.id, reason);
+            break;
+        }
+        if message_size > handler.max_request_bytes {
+            println!("closing connection {}: request of {} bytes exceeds the {} byte limit for api_key {}",
+                     connection.id, message_size, handler.max_request_bytes, api_key);
+            break;
+        }
+
+        // Every buffered request is accounted against the shared pool, so many large requests at
+        // once wait for memory instead of exhausting it
+        let memory_permit = match Arc::clone(&settings.request_memory_pool).try_acquire_many_owned(message_size as u32) {
+            Ok(permit) => permit,
+            Err(_) => {
+                println!("connection {} waiting for {} bytes of request memory", connection.id, message_size);
+                match Arc::clone(&settings.request_memory_pool).acquire_many_owned(message_size as u32).await {
+                    Ok(permit) => permit,
+                    Err(_) => break,
                 }
-            },
-            _ => {
-                println!("unsupported api_key: {}", api_key);
-                continue;
+            }
+        };
+
+        // With queued.max.requests unhandled requests across all connections, reading waits for one to finish
+        let queue_permit = match Arc::clone(&settings.request_queue).acquire_owned().await {
+            Ok(permit) => permit,
+            Err(_) => break,
+        };
+
+        // Read the rest of the message
+        let mut message_buffer = vec![0u8; message_size];
+        message_buffer[..REQUEST_HEADER_SIZE].copy_from_slice(&header_buffer);
+        if let Err(e) = reader.read_exact(&mut message_buffer[REQUEST_HEADER_SIZE..]).await {
+            println!("error reading message body: {}", e);
+            break;
+        }
+
+        // Request quotas are per user and client-id. A request's handler time is only known once its response
+        // is built, so it counts toward the throttle time of the requests after it. SASL requests have no
+        // throttle_time_ms and are never throttled
+        let is_sasl_request = api_key == SASL_HANDSHAKE_KEY || api_key == SASL_AUTHENTICATE_KEY;
+        let principal = connection.principal();
+        let client_id = read_nullable_string(&message_buffer, REQUEST_HEADER_SIZE, false)
+            .ok()
+            .and_then(|(client_id, _)| client_id)
+            .unwrap_or_default();
+        let throttle_time_ms = if is_sasl_request { 0 } else { quotas::request_throttle_time_ms(&principal, &client_id) };
+
+        // Handlers are synchronous and may touch disk, so they run on the bounded blocking pool
+        let handler_connection = Arc::clone(&connection);
+        let quota_client_id = client_id.clone();
+        // SASL requests decide what the connection may send next, so nothing more is read until they're processed
+        let (processed_sender, processed) = tokio::sync::oneshot::channel();
+        let pending_response = tokio::task::spawn_blocking(move || {
+            let handler_start = Instant::now();
+            let response = process_request(handler, &handler_connection, correlation_id, api_version, throttle_time_ms, &message_buffer);
+            if !is_sasl_request {
+                quotas::record_request_time(&principal, &quota_client_id, handler_start.elapsed());
+            }
+            // The request buffer is released here, together with its share of the memory pool
+            drop(message_buffer);
+            drop(memory_permit);
+            drop(queue_permit);
+            let _ = processed_sender.send(());
+            response
+        });
+
+        // The writer task only stops once the socket can't be written to
+        if response_sender.send((pending_response, in_flight_permit)).await.is_err() {
+            break;
+        }
+        if is_sasl_request {
+            let _ = processed.await;
+        }
+        // The response goes out right away with its throttle time; the channel stays muted, with nothing
+        // more read from the client, until the throttle time is up
+        if throttle_time_ms > 0 {
+            println!("muting connection {} for {} ms: request quota exceeded by User:{} client-id '{}'",
+                     connection.id, throttle_time_ms, connection.principal(), client_id);
+            tokio::time::sleep(Duration::from_millis(throttle_time_ms as u64)).await;
+        }
+        // The response asking for the close is still written; the writer drains before shutting down
+        if connection.close_requested() {
+            break;
+        }
+    }
+
+    drop(response_sender);
+    let _ = writer_task.await;
+
+    println!("connection {} closed", connection.id);
+    drop(connection);
+    log_client_software_counts();
+}
+
+fn process_request(handler: &ApiHandler, connection: &ClientConnection, correlation_id: i32, api_version: i16,
+                   throttle_time_ms: i32, message_buffer: &[u8]) -> Vec<u8> {
+    // ApiVersions answers unsupported versions itself so the client still learns the supported range
+    if handler.api_key != API_VERSIONS_KEY && !handler.supports_version(api_version) {
+        println!("unsupported api_version {} for api_key {}", api_version, handler.api_key);
+        // The error is encoded with the closest version the broker knows the schema of
+        let response_version = api_version.clamp(handler.min_version, handler.max_version);
+        let context = RequestContext { correlation_id, api_version: response_version, connection, throttle_time_ms };
+        (handler.error_response)(&context, message_buffer, 35) // UNSUPPORTED_VERSION
+    } else {
+        let context = RequestContext { correlation_id, api_version, connection, throttle_time_ms };
+        (handler.handle)(&context, message_buffer)
+    }
+}
+
+// Pending responses arrive in request order; awaiting each in turn keeps responses in that order
+// even when a later request finishes first
+async fn write_responses<S: AsyncWrite>(
+    mut writer: WriteHalf<S>,
+    mut pending_responses: mpsc::Receiver<(JoinHandle<Vec<u8>>, OwnedSemaphorePermit)>,
+) {
+    while let Some((pending_response, _in_flight_permit)) = pending_responses.recv().await {
+        let response = match pending_response.await {
+            Ok(response) => response,
+            Err(e) => {
+                // A response can't be skipped without breaking the order, so the connection goes
+                println!("closing connection: request handler failed: {}", e);
+                break;
             }
         };
 
         // Send the response
-        match stream.write_all(&response) {
+        match writer.write_all(&response).await {
             Ok(_) => println!("response sent successfully"),
             Err(e) => {
                 println!("error sending response: {}", e);
@@ -239,58 +867,114 @@ fn handle_client(mut stream: TcpStream) {
             }
         }
     }
+    let _ = writer.shutdown().await;
+}
 
-    println!("connection closed");
+struct ApiVersionsRequest {
+    client_software_name: String,
+    client_software_version: String,
 }
 
-fn handle_api_versions_request(correlation_id: i32, api_version: i16) -> Vec<u8> {
-    // Validate API version (broker supports versions 0-4)
-    let error_code = if api_version >= 0 && api_version <= 4 {
-        0i16  // SUCCESS
-    } else {
-        35i16 // UNSUPPORTED_VERSION
-    };
+fn parse_api_versions_request(buffer: &[u8]) -> Result<ApiVersionsRequest, &'static str> {
+    // v3+ requests use the flexible request header (v2)
+    let offset = skip_request_header(buffer, true)?;
+    let (client_software_name, offset) = read_compact_string(buffer, offset)?;
+    let (client_software_version, offset) = read_compact_string(buffer, offset)?;
+    skip_tagged_fields(buffer, offset)?;
 
-    let array_length: u8 = 0x05; // varint for 4 API versions + 1
+    Ok(ApiVersionsRequest { client_software_name, client_software_version })
+}
 
-    // API Version entries
-    let mut api_version_1 = Vec::new();
-    api_version_1.extend_from_slice(&18i16.to_be_bytes()); // API key 18 (API_VERSIONS)
-    api_version_1.extend_from_slice(&0i16.to_be_bytes());  // Min version 0
-    api_version_1.extend_from_slice(&4i16.to_be_bytes());  // Max version 4
-    api_version_1.extend_from_slice(&[0u8]);              // Tag buffer
+// Kafka only accepts [a-zA-Z0-9](?:[a-zA-Z0-9\-.]*[a-zA-Z0-9])? for client software name and version
+fn is_valid_client_software_field(value: &str) -> bool {
+    let bytes = value.as_bytes();
+    match (bytes.first(), bytes.last()) {
+        (Some(first), Some(last)) => {
+            first.is_ascii_alphanumeric()
+                && last.is_ascii_alphanumeric()
+                && bytes.iter().all(|b| b.is_ascii_alphanumeric() || *b == b'-' || *b == b'.')
+        }
+        _ => false,
+    }
+}
 
-    let mut api_version_2 = Vec::new();
-    api_version_2.extend_from_slice(&1i16.to_be_bytes());  // API key 1 (Fetch)
-    api_version_2.extend_from_slice(&0i16.to_be_bytes());  // Min version 0
-    api_version_2.extend_from_slice(&16i16.to_be_bytes()); // Max version 16
-    api_version_2.extend_from_slice(&[0u8]);              // Tag buffer
+fn handle_api_versions_request(context: &RequestContext, message_buffer: &[u8]) -> Vec<u8> {
+    let api_version = context.api_version;
 
-    let mut api_version_3 = Vec::new();
-    api_version_3.extend_from_slice(&2i16.to_be_bytes());  // API key 2 (Offsets)
-    api_version_3.extend_from_slice(&0i16.to_be_bytes());  // Min version 0
-    api_version_3.extend_from_slice(&8i16.to_be_bytes());  // Max version 8
-    api_version_3.extend_from_slice(&[0u8]);              // Tag buffer
+    // Validate API version against the range ApiVersions registered for itself
+    let supported = find_api_handler(API_VERSIONS_KEY)
+        .map(|handler| handler.supports_version(api_version))
+        .unwrap_or(false);
+    if !supported {
+        return create_api_versions_error_response(context, message_buffer, 35); // UNSUPPORTED_VERSION
+    }
 
-    let mut api_version_4 = Vec::new();
-    api_version_4.extend_from_slice(&75i16.to_be_bytes()); // API key 75 (DescribeTopicPartitions)
-    api_version_4.extend_from_slice(&0i16.to_be_bytes());  // Min version 0
-    api_version_4.extend_from_slice(&0i16.to_be_bytes());  // Max version 0
-    api_version_4.extend_from_slice(&[0u8]);              // Tag buffer
+    // v3+ requests identify the client software, which is recorded on the connection
+    if api_version >= 3 {
+        match parse_api_versions_request(message_buffer) {
+            Ok(request) if is_valid_client_software_field(&request.client_software_name)
+                && is_valid_client_software_field(&request.client_software_version) => {
+                context.connection.set_client_software(request.client_software_name, request.client_software_version);
+            }
+            Ok(request) => {
+                println!("invalid client software name '{}' or version '{}'",
+                         request.client_software_name, request.client_software_version);
+                return create_api_versions_error_response(context, message_buffer, 42); // INVALID_REQUEST
+            }
+            Err(e) => {
+                println!("error parsing ApiVersions request: {}", e);
+                return create_api_versions_error_response(context, message_buffer, 42); // INVALID_REQUEST
+            }
+        }
+    }
 
-    let throttle_time: i32 = 0; // No throttling
+    create_api_versions_response(context, api_version, 0, API_HANDLERS)
+}
 
-    // Build response body
+// Error responses carry no API keys, like Kafka's, except UNSUPPORTED_VERSION
+fn create_api_versions_error_response(context: &RequestContext, _message_buffer: &[u8], error_code: i16) -> Vec<u8> {
+    if error_code == 35 {
+        // The client can't know which layout a version we don't support would use, so the protocol
+        // fixes the fallback at v0 and includes the supported ranges for the client to retry with
+        return create_api_versions_response(context, 0, error_code, API_HANDLERS);
+    }
+    create_api_versions_response(context, context.api_version, error_code, &[])
+}
+
+fn create_api_versions_response(context: &RequestContext, api_version: i16, error_code: i16, advertised_handlers: &[ApiHandler]) -> Vec<u8> {
+    // v3+ is flexible: compact arrays and tag buffers
+    let flexible = api_version >= 3;
+
+    // Build response body (ApiVersions always uses response header v0: just the correlation_id)
     let mut response_body = Vec::new();
-    response_body.extend_from_slice(&correlation_id.to_be_bytes()); // correlation_id (4 bytes)
+    response_body.extend_from_slice(&context.correlation_id.to_be_bytes()); // correlation_id (4 bytes)
     response_body.extend_from_slice(&error_code.to_be_bytes());     // error_code (2 bytes)
-    response_body.extend_from_slice(&[array_length]);              // array_length (1 byte)
-    response_body.extend_from_slice(&api_version_1);               // API version 1
-    response_body.extend_from_slice(&api_version_2);               // API version 2
-    response_body.extend_from_slice(&api_version_3);               // API version 3
-    response_body.extend_from_slice(&api_version_4);               // API version 4
-    response_body.extend_from_slice(&throttle_time.to_be_bytes()); // throttle_time (4 bytes)
-    response_body.extend_from_slice(&[0u8]);                       // tag_buffer (1 byte)
+
+    // API keys: one entry per registered handler
+    if flexible {
+        write_unsigned_varint(&mut response_body, advertised_handlers.len() as u32 + 1); // COMPACT_ARRAY length
+    } else {
+        response_body.extend_from_slice(&(advertised_handlers.len() as i32).to_be_bytes()); // ARRAY length (4 bytes)
+    }
+    for handler in advertised_handlers {
+        response_body.extend_from_slice(&handler.api_key.to_be_bytes());     // api_key (2 bytes)
+        response_body.extend_from_slice(&handler.min_version.to_be_bytes()); // min_version (2 bytes)
+        response_body.extend_from_slice(&handler.max_version.to_be_bytes()); // max_version (2 bytes)
+        if flexible {
+            response_body.push(0);                                           // tag buffer (1 byte)
+        }
+    }
+
+    if api_version >= 1 {
+        response_body.extend_from_slice(&context.throttle_time_ms.to_be_bytes()); // throttle_time (4 bytes)
+    }
+
+    // Feature negotiation travels in the v3+ response tagged fields
+    if flexible && error_code == 0 {
+        write_api_versions_feature_fields(&mut response_body);
+    } else if flexible {
+        response_body.push(0); // tag_buffer (1 byte)
+    }
 
     // Calculate message size and build final response
     let message_size = response_body.len() as i32;
@@ -300,7 +984,38 @@ fn handle_api_versions_request(correlation_id: i32, api_version: i16) -> Vec<u8>
     response
 }
 
-fn handle_describe_topic_partitions_request(correlation_id: i32, message_buffer: &[u8]) -> Vec<u8> {
+fn write_api_versions_feature_fields(response_body: &mut Vec<u8>) {
+    let finalized = finalized_features();
+
+    // Tag 0: SupportedFeatures
+    let mut supported_features = Vec::new();
+    write_unsigned_varint(&mut supported_features, SUPPORTED_FEATURES.len() as u32 + 1);
+    for feature in SUPPORTED_FEATURES {
+        write_compact_string(&mut supported_features, feature.name);               // name
+        supported_features.extend_from_slice(&feature.min_version.to_be_bytes()); // min_version (2 bytes)
+        supported_features.extend_from_slice(&feature.max_version.to_be_bytes()); // max_version (2 bytes)
+        supported_features.push(0);                                               // tag buffer (1 byte)
+    }
+
+    // Tag 2: FinalizedFeatures
+    let mut finalized_levels = Vec::new();
+    write_unsigned_varint(&mut finalized_levels, finalized.levels.len() as u32 + 1);
+    for (name, level) in &finalized.levels {
+        write_compact_string(&mut finalized_levels, name);        // name
+        finalized_levels.extend_from_slice(&level.to_be_bytes()); // max_version_level (2 bytes)
+        finalized_levels.extend_from_slice(&level.to_be_bytes()); // min_version_level (2 bytes)
+        finalized_levels.push(0);                                 // tag buffer (1 byte)
+    }
+
+    // Tagged fields must be written in ascending tag order
+    write_unsigned_varint(response_body, 4); // number of tagged fields
+    write_tagged_field(response_body, 0, &supported_features);
+    write_tagged_field(response_body, 1, &finalized.epoch.to_be_bytes()); // FinalizedFeaturesEpoch (8 bytes)
+    write_tagged_field(response_body, 2, &finalized_levels);
+    write_tagged_field(response_body, 3, &[0u8]); // ZkMigrationReady: false, this is a KRaft-only broker
+}
+
+fn handle_describe_topic_partitions_request(context: &RequestContext, message_buffer: &[u8]) -> Vec<u8> {
     // Parse the request starting after the request header (api_key, api_version, correlation_id)
     match parse_describe_topic_partitions_request(message_buffer, 8) {
         Ok(topics) => {
@@ -308,28 +1023,34 @@ fn handle_describe_topic_partitions_request(correlation_id: i32, message_buffer:
             for topic in &topics {
                 println!("Topic: {}", topic.name);
             }
-            create_describe_topic_partitions_response(correlation_id, &topics)
+            // Topics the client may not describe are unauthorized rather than unknown
+            let results: Vec<(&ParsedTopic, i16)> = topics
+                .iter()
+                .map(|topic| {
+                    if authorize(context.connection, AclOperation::Describe, ResourceType::Topic, &topic.name) {
+                        (topic, 3) // UNKNOWN_TOPIC_OR_PARTITION
+                    } else {
+                        (topic, 29) // TOPIC_AUTHORIZATION_FAILED
+                    }
+                })
+                .collect();
+            create_describe_topic_partitions_response(context, &results)
         },
         Err(e) => {
             println!("error parsing DescribeTopicPartitions request: {}", e);
-            create_error_response(correlation_id, 2) // INVALID_REQUEST
+            create_describe_topic_partitions_error_response(context, message_buffer, 42) // INVALID_REQUEST
         }
     }
 }
 
-fn create_error_response(correlation_id: i32, error_code: i16) -> Vec<u8> {
-    let mut response_body = Vec::new();
-    response_body.extend_from_slice(&correlation_id.to_be_bytes());
-    response_body.extend_from_slice(&error_code.to_be_bytes());
-    
-    let message_size = response_body.len() as i32;
-    let mut response = Vec::new();
-    response.extend_from_slice(&message_size.to_be_bytes());
-    response.extend_from_slice(&response_body);
-    response
+// DescribeTopicPartitions has no top-level error code; like Kafka, the error goes on every requested topic
+fn create_describe_topic_partitions_error_response(context: &RequestContext, message_buffer: &[u8], error_code: i16) -> Vec<u8> {
+    let topics = parse_describe_topic_partitions_request(message_buffer, 8).unwrap_or_default();
+    let results: Vec<(&ParsedTopic, i16)> = topics.iter().map(|topic| (topic, error_code)).collect();
+    create_describe_topic_partitions_response(context, &results)
 }
 
-fn disable_nagle_algorithm(stream: &std::net::TcpStream) -> Result<(), std::io::Error> {
+fn disable_nagle_algorithm(stream: &impl AsRawFd) -> Result<(), std::io::Error> {
     // Get the raw file descriptor
     let fd = stream.as_raw_fd();
 
@@ -354,25 +1075,620 @@ fn disable_nagle_algorithm(stream: &std::net::TcpStream) -> Result<(), std::io::
     Ok(())
 }
 
+fn set_socket_buffer_size(stream: &impl AsRawFd, option: libc::c_int, size: i32) -> Result<(), std::io::Error> {
+    let value: libc::c_int = size;
+    let result = unsafe {
+        libc::setsockopt(
+            stream.as_raw_fd(),
+            libc::SOL_SOCKET,
+            option,
+            &value as *const _ as *const libc::c_void,
+            std::mem::size_of_val(&value) as libc::socklen_t,
+        )
+    };
+
+    if result == -1 {
+        return Err(std::io::Error::last_os_error());
+    }
+
+    Ok(())
+}
+
 fn main() {
     // You can use print statements as follows for debugging, they'll be visible when running tests.
     println!("Logs from your program will appear here!");
 
-    // Uncomment this block to pass the first stage
-    //
-    let listener = TcpListener::bind("127.0.0.1:9092").unwrap();
-
-    for stream in listener.incoming() {
-        match stream {
-            Ok(stream) => {
-                // Spawn a new thread for each client connection to handle concurrent requests
-                thread::spawn(move || {
-                    handle_client(stream);
-                });
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match load_broker_config(&args) {
+        Ok(config) => init_broker_config(config),
+        Err(errors) => {
+            for error in errors {
+                println!("config error: {}", error);
             }
+            std::process::exit(1);
+        }
+    }
+    let config = broker_config();
+    authorizer::init_authorizer(config);
+
+    // Replay persisted cluster metadata before accepting connections
+    let metadata_records = match metadata_log::open(&config.metadata_log_dir) {
+        Ok(records) => records,
+        Err(e) => {
+            println!("error opening metadata log: {}", e);
+            std::process::exit(1);
+        }
+    };
+    for record in &metadata_records {
+        features::replay(record);
+        scram::replay(record);
+        delegation_token::replay(record);
+        authorizer::replay(record);
+        quotas::replay(record);
+    }
+    bootstrap_finalized_features();
+
+    let listener_protocols: Vec<_> = config
+        .broker_listeners()
+        .filter_map(|listener| config.security_protocol(&listener.name))
+        .collect();
+    if listener_protocols.iter().any(|protocol| matches!(protocol, SecurityProtocol::Ssl | SecurityProtocol::SaslSsl)) {
+        if let Err(e) = ssl::init_ssl(config) {
+            println!("error loading SSL certificates: {}", e);
+            std::process::exit(1);
+        }
+    }
+    if listener_protocols.iter().any(|protocol| matches!(protocol, SecurityProtocol::SaslPlaintext | SecurityProtocol::SaslSsl)) {
+        if let Err(e) = sasl::init_sasl(config) {
+            println!("error setting up SASL: {}", e);
+            std::process::exit(1);
+        }
+    }
+
+    // A few network threads drive every connection; request handlers get a separate bounded pool,
+    // so thread count stays flat however many clients connect
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .worker_threads(config.num_network_threads)
+        .max_blocking_threads(config.num_io_threads)
+        .enable_all()
+        .build()
+        .unwrap();
+    runtime.block_on(serve());
+}
+
+async fn serve() {
+    let config = broker_config();
+    let settings = Arc::new(ConnectionSettings {
+        max_in_flight_requests: config.connections_max_in_flight_requests,
+        // A request bigger than the whole pool could never be admitted
+        socket_request_max_bytes: config.socket_request_max_bytes.min(config.queued_max_request_bytes),
+        request_memory_pool: Arc::new(Semaphore::new(config.queued_max_request_bytes)),
+        request_queue: Arc::new(Semaphore::new(config.queued_max_requests)),
+        max_idle_time: Duration::from_millis(config.connections_max_idle_ms),
+        connection_limits: Arc::new(ConnectionLimits::new(config.max_connections, config.max_connections_per_ip,
+                                                          config.max_connections_per_ip_overrides.clone())),
+    });
+
+    // Controller listeners belong to the quorum, which this broker doesn't run
+    let mut accept_loops = Vec::new();
+    for listener_config in config.broker_listeners() {
+        // An empty host binds every interface
+        let host = if listener_config.host.is_empty() { "0.0.0.0" } else { listener_config.host.as_str() };
+        let listener = match bind_listener(host, listener_config.port, config.socket_listen_backlog_size).await {
+            Ok(listener) => listener,
+            Err(e) => {
+                println!("error binding listener {} to {}:{}: {}", listener_config.name, host, listener_config.port, e);
+                std::process::exit(1);
+            }
+        };
+        let advertised = config.advertised_listener(&listener_config.name);
+        let protocol = config.security_protocol(&listener_config.name).map(|protocol| protocol.name()).unwrap_or_default();
+        println!("listening on {} ({} {}:{}), advertised as {}", listener_config.name, protocol, host, listener_config.port,
+                 advertised.map(|advertised| format!("{}:{}", advertised.host, advertised.port)).unwrap_or_default());
+        accept_loops.push(tokio::spawn(accept_connections(listener, listener_config.name.clone(), Arc::clone(&settings))));
+    }
+    if let Some((host, port)) = &config.metrics_http_listener {
+        match TcpListener::bind((host.as_str(), *port)).await {
+            Ok(listener) => {
+                println!("serving metrics on http://{}:{}/metrics", host, port);
+                tokio::spawn(connections::serve_metrics(listener));
+            }
+            Err(e) => {
+                println!("error binding metrics.http.listener to {}:{}: {}", host, port, e);
+                std::process::exit(1);
+            }
+        }
+    }
+    // A no-op unless an SSL listener loaded certificates
+    tokio::spawn(ssl::reload_certificates_on_change(config));
+    tokio::spawn(delegation_token::remove_expired_tokens_periodically(config));
+
+    for accept_loop in accept_loops {
+        let _ = accept_loop.await;
+    }
+}
+
+// Like TcpListener::bind, with socket.listen.backlog.size as the pending connection queue length
+async fn bind_listener(host: &str, port: u16, backlog: u32) -> std::io::Result<TcpListener> {
+    let mut last_error = None;
+    for address in tokio::net::lookup_host((host, port)).await? {
+        let socket = if address.is_ipv4() { TcpSocket::new_v4()? } else { TcpSocket::new_v6()? };
+        socket.set_reuseaddr(true)?;
+        match socket.bind(address).and_then(|()| socket.listen(backlog)) {
+            Ok(listener) => return Ok(listener),
+            Err(e) => last_error = Some(e),
+        }
+    }
+    Err(last_error.unwrap_or_else(|| std::io::Error::new(std::io::ErrorKind::InvalidInput, "no addresses to bind")))
+}
+
+async fn accept_connections(listener: TcpListener, listener_name: String, settings: Arc<ConnectionSettings>) {
+    loop {
+        // At max.connections, connections wait in the listen backlog until one closes
+        let permit = settings.connection_limits.wait_for_capacity().await;
+        match listener.accept().await {
+            Ok((stream, peer)) => match settings.connection_limits.admit(peer.ip(), permit) {
+                // Each connection is a lightweight task rather than an OS thread
+                Ok(slot) => {
+                    tokio::spawn(open_connection(stream, listener_name.clone(), Arc::clone(&settings), slot));
+                }
+                Err(limit) => {
+                    println!("closing connection from {} on {}: max.connections.per.ip ({}) reached for {}",
+                             peer, listener_name, limit, peer.ip());
+                }
+            },
             Err(e) => {
                 println!("error accepting connection: {}", e);
             }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use std::io::{Read, Write};
+
+    use super::*;
+    use connections::client_software_counts;
+
+    fn test_connection() -> ClientConnection {
+        ClientConnection::open("127.0.0.1:50000".to_string(), "PLAINTEXT".to_string(), "ANONYMOUS".to_string())
+    }
+
+    // A request message as handle_client passes it to handlers: the v2 request header, then the body
+    fn request(api_key: i16, api_version: i16, body: &[u8]) -> Vec<u8> {
+        let mut message = Vec::new();
+        message.extend_from_slice(&api_key.to_be_bytes());
+        message.extend_from_slice(&api_version.to_be_bytes());
+        message.extend_from_slice(&7i32.to_be_bytes()); // correlation_id
+        message.extend_from_slice(&9i16.to_be_bytes()); // client_id
+        message.extend_from_slice(b"unit-test");
+        message.push(0); // header tag buffer
+        message.extend_from_slice(body);
+        message
+    }
+
+    fn api_versions_v3_body(client_software_name: &str, client_software_version: &str) -> Vec<u8> {
+        let mut body = Vec::new();
+        for value in [client_software_name, client_software_version] {
+            write_unsigned_varint(&mut body, value.len() as u32 + 1);
+            body.extend_from_slice(value.as_bytes());
+        }
+        body.push(0); // tag buffer
+        body
+    }
+
+    // Returns (error_code, number of API keys) of an ApiVersions response
+    fn api_versions_result(response: &[u8]) -> (i16, u32) {
+        let error_code = read_i16_be(response, 8).unwrap();
+        let (api_key_count, _) = read_unsigned_varint(response, 10).unwrap();
+        (error_code, api_key_count.saturating_sub(1))
+    }
+
+    fn handle(connection: &ClientConnection, api_version: i16, message: &[u8]) -> Vec<u8> {
+        let context = RequestContext { correlation_id: 7, api_version, connection, throttle_time_ms: 0 };
+        handle_api_versions_request(&context, message)
+    }
+
+    // The tagged fields of a v3+ ApiVersions response as (tag, data)
+    fn api_versions_tagged_fields(response: &[u8]) -> Vec<(u32, Vec<u8>)> {
+        let (api_key_count, mut offset) = read_unsigned_varint(response, 10).unwrap();
+        offset += api_key_count.saturating_sub(1) as usize * 7 + 4; // api keys, throttle_time_ms
+        let (field_count, mut offset) = read_unsigned_varint(response, offset).unwrap();
+        let mut fields = Vec::new();
+        for _ in 0..field_count {
+            let (tag, next) = read_unsigned_varint(response, offset).unwrap();
+            let (size, next) = read_unsigned_varint(response, next).unwrap();
+            fields.push((tag, response[next..next + size as usize].to_vec()));
+            offset = next + size as usize;
+        }
+        assert_eq!(offset, response.len());
+        fields
+    }
+
+    #[test]
+    fn api_versions_v3_rejects_illegal_client_software_fields() {
+        let connection = test_connection();
+        for (name, version) in [("bad name", "1.0"), ("-leading-dash", "1.0"), ("client", "1.0-"), ("", "1.0"), ("client", "")] {
+            let message = request(API_VERSIONS_KEY, 3, &api_versions_v3_body(name, version));
+            // INVALID_REQUEST with no API keys
+            assert_eq!(api_versions_result(&handle(&connection, 3, &message)), (42, 0), "{:?} {:?}", name, version);
+        }
+
+        // A truncated body is INVALID_REQUEST too
+        let message = request(API_VERSIONS_KEY, 3, &[5, b'a']);
+        assert_eq!(api_versions_result(&handle(&connection, 3, &message)).0, 42);
+    }
+
+    #[test]
+    fn api_versions_v3_records_the_client_software() {
+        let connection = test_connection();
+        let message = request(API_VERSIONS_KEY, 3, &api_versions_v3_body("api-versions-test", "1.2.3"));
+        let (error_code, api_key_count) = api_versions_result(&handle(&connection, 3, &message));
+        assert_eq!((error_code, api_key_count as usize), (0, API_HANDLERS.len()));
+
+        let count = |counts: Vec<(connections::ClientSoftware, usize)>| {
+            counts.into_iter().find(|(software, _)| software.name == "api-versions-test").map(|(software, count)| (software.version, count))
+        };
+        assert_eq!(count(client_software_counts()), Some(("1.2.3".to_string(), 1)));
+        drop(connection);
+        assert_eq!(count(client_software_counts()), None);
+    }
+
+    #[test]
+    fn api_versions_v3_advertises_supported_and_finalized_features() {
+        metadata_log::open_scratch_log();
+        bootstrap_finalized_features();
+        let connection = test_connection();
+        let message = request(API_VERSIONS_KEY, 3, &api_versions_v3_body("features-test", "1.0"));
+        let fields = api_versions_tagged_fields(&handle(&connection, 3, &message));
+        assert_eq!(fields.iter().map(|(tag, _)| *tag).collect::<Vec<_>>(), vec![0, 1, 2, 3]);
+
+        // SupportedFeatures lists every feature with its range
+        let supported_features = &fields[0].1;
+        let (count, mut offset) = read_unsigned_varint(supported_features, 0).unwrap();
+        assert_eq!(count as usize, SUPPORTED_FEATURES.len() + 1);
+        for feature in SUPPORTED_FEATURES {
+            let (name, next) = read_compact_string(supported_features, offset).unwrap();
+            assert_eq!(name, feature.name);
+            assert_eq!(read_i16_be(supported_features, next).unwrap(), feature.min_version);
+            assert_eq!(read_i16_be(supported_features, next + 2).unwrap(), feature.max_version);
+            offset = next + 5;
+        }
+
+        // metadata.version is always finalized; ZkMigrationReady is false
+        let finalized_features = &fields[2].1;
+        let (count, mut offset) = read_unsigned_varint(finalized_features, 0).unwrap();
+        let mut names = Vec::new();
+        for _ in 1..count {
+            let (name, next) = read_compact_string(finalized_features, offset).unwrap();
+            names.push(name);
+            offset = next + 5;
+        }
+        assert!(names.iter().any(|name| name == features::METADATA_VERSION), "{:?}", names);
+        assert_eq!(fields[3].1, vec![0]);
+
+        // Failed responses keep an empty tag buffer
+        let message = request(API_VERSIONS_KEY, 3, &api_versions_v3_body("bad name", "1.0"));
+        assert!(api_versions_tagged_fields(&handle(&connection, 3, &message)).is_empty());
+    }
+
+    fn describe_topic_partitions_body(topics: &[&str]) -> Vec<u8> {
+        let mut body = Vec::new();
+        write_unsigned_varint(&mut body, topics.len() as u32 + 1);
+        for topic in topics {
+            write_compact_string(&mut body, topic);
+            body.push(0); // tag buffer
+        }
+        body.extend_from_slice(&100i32.to_be_bytes()); // response_partition_limit
+        body.push(0xff); // cursor: null
+        body.push(0); // tag buffer
+        body
+    }
+
+    #[test]
+    fn describe_topic_partitions_errors_go_on_every_topic() {
+        let connection = test_connection();
+        let context = RequestContext { correlation_id: 7, api_version: 0, connection: &connection, throttle_time_ms: 0 };
+        let message = request(75, 1, &describe_topic_partitions_body(&["orders", "payments"]));
+        let response = create_describe_topic_partitions_error_response(&context, &message, 35);
+
+        // message_size, correlation_id, header tag buffer, throttle_time_ms, then the topics
+        assert_eq!(read_i32_be(&response, 4).unwrap(), 7);
+        let (topic_count, mut offset) = read_unsigned_varint(&response, 13).unwrap();
+        assert_eq!(topic_count, 3);
+        for topic in ["orders", "payments"] {
+            assert_eq!(read_i16_be(&response, offset).unwrap(), 35);
+            let (name, next) = read_compact_string(&response, offset + 2).unwrap();
+            assert_eq!(name, topic);
+            offset = next + 16 + 1 + 1 + 4 + 1; // topic_id, is_internal, partitions, authorized operations, tags
+        }
+    }
+
+    #[test]
+    fn describe_topic_partitions_without_topics_answers_an_empty_array() {
+        let connection = test_connection();
+        let context = RequestContext { correlation_id: 7, api_version: 0, connection: &connection, throttle_time_ms: 0 };
+        let response = handle_describe_topic_partitions_request(&context, &request(75, 0, &describe_topic_partitions_body(&[])));
+
+        assert_eq!(read_unsigned_varint(&response, 13).unwrap(), (1, 14));
+    }
+
+    #[test]
+    fn update_features_errors_go_on_the_request_and_every_update() {
+        let connection = test_connection();
+        let context = RequestContext { correlation_id: 7, api_version: 1, connection: &connection, throttle_time_ms: 0 };
+        let mut body = 30000i32.to_be_bytes().to_vec(); // timeout_ms
+        write_unsigned_varint(&mut body, 2);
+        write_compact_string(&mut body, features::KRAFT_VERSION);
+        body.extend_from_slice(&1i16.to_be_bytes()); // max_version_level
+        body.push(1); // upgrade_type
+        body.push(0); // tag buffer
+        body.push(0); // validate_only
+        body.push(0); // tag buffer
+        let response = create_update_features_error_response(&context, &request(57, 2, &body), 35);
+
+        // message_size, correlation_id, header tag buffer, throttle_time_ms, error_code, error_message
+        assert_eq!(read_i16_be(&response, 13).unwrap(), 35);
+        let (result_count, offset) = read_unsigned_varint(&response, 16).unwrap();
+        assert_eq!(result_count, 2);
+        let (feature, offset) = read_compact_string(&response, offset).unwrap();
+        assert_eq!((feature.as_str(), read_i16_be(&response, offset).unwrap()), (features::KRAFT_VERSION, 35));
+    }
+
+    #[test]
+    fn unsupported_api_versions_requests_fall_back_to_v0() {
+        let connection = test_connection();
+        let response = handle(&connection, 5, &request(API_VERSIONS_KEY, 5, &api_versions_v3_body("client", "1.0")));
+
+        // v0: error_code and a non-compact array of every supported range, no throttle time or tag buffers
+        assert_eq!(read_i16_be(&response, 8).unwrap(), 35);
+        assert_eq!(read_i32_be(&response, 10).unwrap() as usize, API_HANDLERS.len());
+        assert_eq!(response.len(), 14 + API_HANDLERS.len() * 6);
+        for (index, handler) in API_HANDLERS.iter().enumerate() {
+            let offset = 14 + index * 6;
+            assert_eq!(read_i16_be(&response, offset).unwrap(), handler.api_key);
+            assert_eq!(read_i16_be(&response, offset + 2).unwrap(), handler.min_version);
+            assert_eq!(read_i16_be(&response, offset + 4).unwrap(), handler.max_version);
+        }
+    }
+
+    // Serves connections on a free port the way serve() does; returns the port
+    fn spawn_test_listener(runtime: &tokio::runtime::Runtime, settings: Arc<ConnectionSettings>) -> u16 {
+        config::init_test_broker_config();
+        let listener = runtime.block_on(TcpListener::bind("127.0.0.1:0")).unwrap();
+        let port = listener.local_addr().unwrap().port();
+        runtime.spawn(accept_connections(listener, "PLAINTEXT".to_string(), settings));
+        port
+    }
+
+    // The config defaults
+    const CONNECTIONS_MAX_IN_FLIGHT_REQUESTS: usize = 5;
+    const SOCKET_REQUEST_MAX_BYTES: usize = 100 * 1024 * 1024;
+    const QUEUED_MAX_REQUEST_BYTES: usize = 512 * 1024 * 1024;
+    const QUEUED_MAX_REQUESTS: usize = 500;
+    const CONNECTIONS_MAX_IDLE_MS: u64 = 600_000;
+    const MAX_CONNECTIONS: usize = i32::MAX as usize;
+
+    fn default_settings() -> ConnectionSettings {
+        ConnectionSettings {
+            max_in_flight_requests: CONNECTIONS_MAX_IN_FLIGHT_REQUESTS,
+            socket_request_max_bytes: SOCKET_REQUEST_MAX_BYTES,
+            request_memory_pool: Arc::new(Semaphore::new(QUEUED_MAX_REQUEST_BYTES)),
+            request_queue: Arc::new(Semaphore::new(QUEUED_MAX_REQUESTS)),
+            max_idle_time: Duration::from_millis(CONNECTIONS_MAX_IDLE_MS),
+            connection_limits: Arc::new(ConnectionLimits::new(MAX_CONNECTIONS, MAX_CONNECTIONS, BTreeMap::new())),
+        }
+    }
+
+    fn test_settings(socket_request_max_bytes: usize, queued_max_request_bytes: usize) -> Arc<ConnectionSettings> {
+        Arc::new(ConnectionSettings {
+            socket_request_max_bytes,
+            request_memory_pool: Arc::new(Semaphore::new(queued_max_request_bytes)),
+            ..default_settings()
+        })
+    }
+
+    fn test_runtime() -> tokio::runtime::Runtime {
+        tokio::runtime::Builder::new_multi_thread().worker_threads(2).enable_all().build().unwrap()
+    }
+
+    fn connect(port: u16) -> std::net::TcpStream {
+        let stream = std::net::TcpStream::connect(("127.0.0.1", port)).unwrap();
+        stream.set_read_timeout(Some(std::time::Duration::from_secs(10))).unwrap();
+        stream
+    }
+
+    fn send(stream: &mut std::net::TcpStream, message: &[u8]) {
+        let mut frame = (message.len() as i32).to_be_bytes().to_vec();
+        frame.extend_from_slice(message);
+        stream.write_all(&frame).unwrap();
+    }
+
+    // Reads one response, size prefix included like the handlers' return values
+    fn receive(stream: &mut std::net::TcpStream) -> Vec<u8> {
+        let mut response = vec![0u8; 4];
+        stream.read_exact(&mut response).unwrap();
+        let size = read_i32_be(&response, 0).unwrap() as usize;
+        response.resize(4 + size, 0);
+        stream.read_exact(&mut response[4..]).unwrap();
+        response
+    }
+
+    #[test]
+    fn many_connections_are_served_at_once() {
+        let runtime = test_runtime();
+        let port = spawn_test_listener(&runtime, test_settings(SOCKET_REQUEST_MAX_BYTES, QUEUED_MAX_REQUEST_BYTES));
+
+        // Every connection stays open while the others are served
+        let mut streams: Vec<_> = (0..50).map(|_| connect(port)).collect();
+        for stream in streams.iter_mut().rev() {
+            send(stream, &request(API_VERSIONS_KEY, 3, &api_versions_v3_body("client", "1.0")));
+            let response = receive(stream);
+            assert_eq!(read_i32_be(&response, 4).unwrap(), 7);
+            assert_eq!(read_i16_be(&response, 8).unwrap(), 0);
+        }
+
+        // Unknown API keys close the connection
+        let stream = &mut streams[0];
+        send(stream, &request(9999, 0, &[]));
+        assert_eq!(stream.read(&mut [0u8; 1]).unwrap(), 0);
+    }
+
+    #[test]
+    fn pipelined_requests_are_answered_in_order() {
+        let runtime = test_runtime();
+        let mut stream = connect(spawn_test_listener(&runtime, test_settings(SOCKET_REQUEST_MAX_BYTES, QUEUED_MAX_REQUEST_BYTES)));
+
+        // Far more requests than the in-flight cap, all written before any response is read
+        for correlation_id in 0..50i32 {
+            let mut message = request(API_VERSIONS_KEY, 3, &api_versions_v3_body("client", "1.0"));
+            message[4..8].copy_from_slice(&correlation_id.to_be_bytes());
+            send(&mut stream, &message);
+        }
+        for correlation_id in 0..50i32 {
+            assert_eq!(read_i32_be(&receive(&mut stream), 4).unwrap(), correlation_id);
+        }
+    }
+
+    fn is_closed(stream: &mut std::net::TcpStream) -> bool {
+        matches!(stream.read(&mut [0u8; 1]), Ok(0) | Err(_))
+    }
+
+    #[test]
+    fn requests_over_socket_request_max_bytes_close_the_connection() {
+        let runtime = test_runtime();
+        let port = spawn_test_listener(&runtime, test_settings(100, QUEUED_MAX_REQUEST_BYTES));
+
+        let mut stream = connect(port);
+        send(&mut stream, &request(API_VERSIONS_KEY, 3, &api_versions_v3_body("client", "1.0")));
+        assert_eq!(read_i16_be(&receive(&mut stream), 8).unwrap(), 0);
+        send(&mut stream, &request(API_VERSIONS_KEY, 3, &api_versions_v3_body(&"a".repeat(100), "1.0")));
+        assert!(is_closed(&mut stream));
+    }
+
+    #[test]
+    fn requests_over_their_api_limit_close_the_connection_before_the_body_is_read() {
+        let runtime = test_runtime();
+        let port = spawn_test_listener(&runtime, test_settings(SOCKET_REQUEST_MAX_BYTES, QUEUED_MAX_REQUEST_BYTES));
+
+        // Only the size and the request header are sent; the broker never waits for the 2 MiB body
+        let mut stream = connect(port);
+        let mut frame = (2 * 1024 * 1024i32).to_be_bytes().to_vec();
+        frame.extend_from_slice(&request(API_VERSIONS_KEY, 3, &[])[..REQUEST_HEADER_SIZE]);
+        stream.write_all(&frame).unwrap();
+        assert!(is_closed(&mut stream));
+    }
+
+    #[test]
+    fn request_memory_is_returned_to_the_pool_once_handled() {
+        let runtime = test_runtime();
+        let settings = test_settings(200, 200);
+        let port = spawn_test_listener(&runtime, Arc::clone(&settings));
+
+        // Together the requests need far more than the pool; each waits for the previous one's memory
+        let mut stream = connect(port);
+        for _ in 0..20 {
+            send(&mut stream, &request(API_VERSIONS_KEY, 3, &api_versions_v3_body(&"a".repeat(100), "1.0")));
+        }
+        for _ in 0..20 {
+            assert_eq!(read_i16_be(&receive(&mut stream), 8).unwrap(), 0);
+        }
+        assert_eq!(settings.request_memory_pool.available_permits(), 200);
+    }
+
+    fn api_versions_succeeds(stream: &mut std::net::TcpStream) -> bool {
+        send(stream, &request(API_VERSIONS_KEY, 3, &api_versions_v3_body("client", "1.0")));
+        read_i16_be(&receive(stream), 8).unwrap() == 0
+    }
+
+    #[test]
+    fn request_queue_permits_are_returned_once_handled() {
+        let runtime = test_runtime();
+        let settings = Arc::new(ConnectionSettings { request_queue: Arc::new(Semaphore::new(1)), ..default_settings() });
+        let port = spawn_test_listener(&runtime, Arc::clone(&settings));
+
+        // With a queue of one, each pipelined request is read once the previous one is handled
+        let mut stream = connect(port);
+        for _ in 0..20 {
+            send(&mut stream, &request(API_VERSIONS_KEY, 3, &api_versions_v3_body("client", "1.0")));
+        }
+        for _ in 0..20 {
+            assert_eq!(read_i16_be(&receive(&mut stream), 8).unwrap(), 0);
+        }
+        assert_eq!(settings.request_queue.available_permits(), 1);
+    }
+
+    #[test]
+    fn idle_connections_are_closed() {
+        let runtime = test_runtime();
+        let settings = Arc::new(ConnectionSettings { max_idle_time: Duration::from_millis(300), ..default_settings() });
+        let port = spawn_test_listener(&runtime, settings);
+
+        // Requests keep the connection open
+        let mut stream = connect(port);
+        for _ in 0..3 {
+            std::thread::sleep(Duration::from_millis(150));
+            assert!(api_versions_succeeds(&mut stream));
+        }
+        std::thread::sleep(Duration::from_millis(600));
+        assert!(is_closed(&mut stream));
+    }
+
+    #[test]
+    fn connections_over_max_connections_per_ip_are_closed() {
+        let runtime = test_runtime();
+        let localhost = "127.0.0.1".parse().unwrap();
+        let settings = Arc::new(ConnectionSettings {
+            connection_limits: Arc::new(ConnectionLimits::new(MAX_CONNECTIONS, 1, BTreeMap::from([(localhost, 2)]))),
+            ..default_settings()
+        });
+        let port = spawn_test_listener(&runtime, settings);
+
+        // The override for 127.0.0.1 raises the limit from 1 to 2
+        let mut first = connect(port);
+        let mut second = connect(port);
+        assert!(api_versions_succeeds(&mut first));
+        assert!(api_versions_succeeds(&mut second));
+        let mut third = connect(port);
+        assert!(is_closed(&mut third));
+
+        // Once a connection closes, its slot is free again
+        drop(first);
+        let started = Instant::now();
+        loop {
+            let mut stream = connect(port);
+            send(&mut stream, &request(API_VERSIONS_KEY, 3, &api_versions_v3_body("client", "1.0")));
+            if !is_closed(&mut stream) {
+                break;
+            }
+            assert!(started.elapsed() < Duration::from_secs(10), "the closed connection's slot was not released");
+            std::thread::sleep(Duration::from_millis(50));
+        }
+    }
+
+    #[test]
+    fn connections_over_max_connections_wait_until_one_closes() {
+        let runtime = test_runtime();
+        let settings = Arc::new(ConnectionSettings {
+            connection_limits: Arc::new(ConnectionLimits::new(1, MAX_CONNECTIONS, BTreeMap::new())),
+            ..default_settings()
+        });
+        let port = spawn_test_listener(&runtime, settings);
+
+        let mut first = connect(port);
+        assert!(api_versions_succeeds(&mut first));
+
+        // The second connection sits in the listen backlog, unanswered, while the first is open
+        let mut second = connect(port);
+        send(&mut second, &request(API_VERSIONS_KEY, 3, &api_versions_v3_body("client", "1.0")));
+        second.set_read_timeout(Some(Duration::from_millis(300))).unwrap();
+        let error = second.read(&mut [0u8; 1]).unwrap_err();
+        assert!(matches!(error.kind(), std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut), "{}", error);
+
+        drop(first);
+        second.set_read_timeout(Some(Duration::from_secs(10))).unwrap();
+        assert_eq!(read_i16_be(&receive(&mut second), 8).unwrap(), 0);
+    }
+}
@@ -0,0 +1,239 @@
+use crate::authorizer::{
+    authorize, authorized_operations, AclOperation, ResourceType, CLUSTER_NAME, CLUSTER_OPERATIONS, TOPIC_OPERATIONS,
+};
+use crate::config::broker_config;
+use crate::{
+    frame_response, read_array_length, read_i8, read_nullable_string, skip_request_header, skip_tagged_fields,
+    write_array_length, write_nullable_string, write_string, RequestContext,
+};
+
+// Authorized operations are only computed on request; this marks them as omitted
+const AUTHORIZED_OPERATIONS_OMITTED: i32 = i32::MIN;
+
+struct MetadataTopic {
+    // v10+ may identify a topic by ID alone, leaving the name null
+    topic_id: [u8; 16],
+    name: Option<String>,
+}
+
+struct MetadataRequest {
+    // None asks for every topic
+    topics: Option<Vec<MetadataTopic>>,
+    include_cluster_authorized_operations: bool,
+    include_topic_authorized_operations: bool,
+}
+
+fn parse_metadata_request(buffer: &[u8], api_version: i16) -> Result<MetadataRequest, &'static str> {
+    // v9+ is flexible
+    let flexible = api_version >= 9;
+    let mut offset = skip_request_header(buffer, flexible)?;
+
+    let (topics_count, new_offset) = read_array_length(buffer, offset, flexible)?;
+    offset = new_offset;
+    let topics = match topics_count {
+        // v0 has no null array; an empty one means every topic
+        Some(0) if api_version == 0 => None,
+        Some(topics_count) => {
+            let mut topics = Vec::new();
+            for _ in 0..topics_count {
+                let mut topic_id = [0u8; 16];
+                if api_version >= 10 {
+                    if offset + 16 > buffer.len() {
+                        return Err("Buffer too short for topic_id");
+                    }
+                    topic_id.copy_from_slice(&buffer[offset..offset + 16]);
+                    offset += 16;
+                }
+                let (name, new_offset) = read_nullable_string(buffer, offset, flexible)?;
+                offset = new_offset;
+                if flexible {
+                    offset = skip_tagged_fields(buffer, offset)?;
+                }
+                topics.push(MetadataTopic { topic_id, name });
+            }
+            Some(topics)
+        }
+        None => None,
+    };
+
+    // allow_auto_topic_creation (v4+) changes nothing here: no topics can be created
+    if api_version >= 4 {
+        read_i8(buffer, offset)?;
+        offset += 1;
+    }
+    let mut include_cluster_authorized_operations = false;
+    if (8..=10).contains(&api_version) {
+        include_cluster_authorized_operations = read_i8(buffer, offset)? != 0;
+        offset += 1;
+    }
+    let mut include_topic_authorized_operations = false;
+    if api_version >= 8 {
+        include_topic_authorized_operations = read_i8(buffer, offset)? != 0;
+        offset += 1;
+    }
+    if flexible {
+        skip_tagged_fields(buffer, offset)?;
+    }
+
+    Ok(MetadataRequest { topics, include_cluster_authorized_operations, include_topic_authorized_operations })
+}
+
+// Each topic is answered with its own error code. Authorized operations are only computed for
+// requests that ask for them.
+fn create_metadata_response(context: &RequestContext, request: &MetadataRequest, topics: &[(&MetadataTopic, i16)]) -> Vec<u8> {
+    let api_version = context.api_version;
+    let flexible = api_version >= 9;
+    let config = broker_config();
+    let mut response_body = Vec::new();
+
+    // Response header
+    response_body.extend_from_slice(&context.correlation_id.to_be_bytes()); // correlation_id (4 bytes)
+    if flexible {
+        response_body.push(0); // tag buffer (1 byte)
+    }
+
+    // Response body
+    if api_version >= 3 {
+        response_body.extend_from_slice(&context.throttle_time_ms.to_be_bytes()); // throttle_time_ms (4 bytes)
+    }
+
+    // Brokers: this broker, at the address advertised for the listener the client came in on, so
+    // clients behind NAT are sent back to the address they can reach
+    let brokers: Vec<_> = config.advertised_listener(&context.connection.listener).into_iter().collect();
+    write_array_length(&mut response_body, brokers.len(), flexible);
+    for listener in brokers {
+        response_body.extend_from_slice(&config.node_id.to_be_bytes());       // node_id (4 bytes)
+        write_string(&mut response_body, &listener.host, flexible);
+        response_body.extend_from_slice(&(listener.port as i32).to_be_bytes()); // port (4 bytes)
+        if api_version >= 1 {
+            write_nullable_string(&mut response_body, None, flexible);        // rack
+        }
+        if flexible {
+            response_body.push(0); // tag buffer (1 byte)
+        }
+    }
+
+    if api_version >= 2 {
+        write_nullable_string(&mut response_body, config.cluster_id.as_deref(), flexible);
+    }
+    if api_version >= 1 {
+        // KRaft brokers don't expose the controller; clients are pointed at a broker instead
+        response_body.extend_from_slice(&config.node_id.to_be_bytes()); // controller_id (4 bytes)
+    }
+
+    write_array_length(&mut response_body, topics.len(), flexible);
+    for (topic, error_code) in topics {
+        response_body.extend_from_slice(&error_code.to_be_bytes()); // error_code (2 bytes)
+        if api_version >= 12 {
+            write_nullable_string(&mut response_body, topic.name.as_deref(), flexible);
+        } else {
+            write_string(&mut response_body, topic.name.as_deref().unwrap_or(""), flexible);
+        }
+        if api_version >= 10 {
+            response_body.extend_from_slice(&topic.topic_id); // topic_id (16 bytes)
+        }
+        if api_version >= 1 {
+            response_body.push(0); // is_internal (1 byte)
+        }
+        write_array_length(&mut response_body, 0, flexible); // partitions: none
+        if api_version >= 8 {
+            let topic_authorized_operations = match &topic.name {
+                Some(name) if request.include_topic_authorized_operations => {
+                    authorized_operations(context.connection, ResourceType::Topic, name, TOPIC_OPERATIONS)
+                }
+                _ => AUTHORIZED_OPERATIONS_OMITTED,
+            };
+            response_body.extend_from_slice(&topic_authorized_operations.to_be_bytes()); // topic_authorized_operations (4 bytes)
+        }
+        if flexible {
+            response_body.push(0); // tag buffer (1 byte)
+        }
+    }
+
+    if (8..=10).contains(&api_version) {
+        let cluster_authorized_operations = if request.include_cluster_authorized_operations {
+            authorized_operations(context.connection, ResourceType::Cluster, CLUSTER_NAME, CLUSTER_OPERATIONS)
+        } else {
+            AUTHORIZED_OPERATIONS_OMITTED
+        };
+        response_body.extend_from_slice(&cluster_authorized_operations.to_be_bytes()); // cluster_authorized_operations (4 bytes)
+    }
+    if flexible {
+        response_body.push(0); // tag buffer (1 byte)
+    }
+
+    frame_response(response_body)
+}
+
+pub fn handle_metadata_request(context: &RequestContext, message_buffer: &[u8]) -> Vec<u8> {
+    let request = match parse_metadata_request(message_buffer, context.api_version) {
+        Ok(request) => request,
+        Err(e) => {
+            println!("error parsing Metadata request: {}", e);
+            return create_metadata_error_response(context, message_buffer, 42); // INVALID_REQUEST
+        }
+    };
+
+    // This broker hosts no topics: listing all of them returns none, and each named one is unknown,
+    // or unauthorized when the client may not describe it. Topics asked for by ID alone are unknown
+    // by ID rather than by name.
+    let topics = request.topics.as_deref().unwrap_or_default();
+    let results: Vec<(&MetadataTopic, i16)> = topics
+        .iter()
+        .map(|topic| match &topic.name {
+            None => (topic, 100), // UNKNOWN_TOPIC_ID
+            Some(name) if !authorize(context.connection, AclOperation::Describe, ResourceType::Topic, name) => {
+                (topic, 29) // TOPIC_AUTHORIZATION_FAILED
+            }
+            Some(_) => (topic, 3), // UNKNOWN_TOPIC_OR_PARTITION
+        })
+        .collect();
+    create_metadata_response(context, &request, &results)
+}
+
+// Metadata has no top-level error code; the error goes on every requested topic
+pub fn create_metadata_error_response(context: &RequestContext, message_buffer: &[u8], error_code: i16) -> Vec<u8> {
+    let request = parse_metadata_request(message_buffer, context.api_version).unwrap_or(MetadataRequest {
+        topics: None,
+        include_cluster_authorized_operations: false,
+        include_topic_authorized_operations: false,
+    });
+    let topics = request.topics.as_deref().unwrap_or_default();
+    let results: Vec<(&MetadataTopic, i16)> = topics.iter().map(|topic| (topic, error_code)).collect();
+    create_metadata_response(context, &request, &results)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::init_test_broker_config;
+    use crate::connections::ClientConnection;
+    use crate::{read_compact_string, read_i32_be, read_unsigned_varint};
+
+    // Metadata v12 for every topic; returns the (host, port) of each broker in the response
+    fn advertised_brokers(listener: &str) -> Vec<(String, i32)> {
+        init_test_broker_config();
+        let mut message = vec![0, 3, 0, 12, 0, 0, 0, 7, 0xff, 0xff, 0]; // header v2 with a null client_id
+        message.extend_from_slice(&[0, 0, 0, 0]); // topics: null, allow_auto_topic_creation, include_topic_authorized_operations, tags
+        let connection = ClientConnection::open("127.0.0.1:50000".to_string(), listener.to_string(), "ANONYMOUS".to_string());
+        let context = RequestContext { correlation_id: 7, api_version: 12, connection: &connection, throttle_time_ms: 0 };
+        let response = handle_metadata_request(&context, &message);
+
+        // message_size, correlation_id, header tag buffer, throttle_time_ms, then the brokers
+        let (broker_count, mut offset) = read_unsigned_varint(&response, 13).unwrap();
+        let mut brokers = Vec::new();
+        for _ in 1..broker_count {
+            let (host, next) = read_compact_string(&response, offset + 4).unwrap();
+            brokers.push((host, read_i32_be(&response, next).unwrap()));
+            offset = next + 4 + 1 + 1; // port, rack, tags
+        }
+        brokers
+    }
+
+    #[test]
+    fn clients_are_sent_the_address_advertised_for_their_listener() {
+        // The test config has a single PLAINTEXT://127.0.0.1:0 listener
+        assert_eq!(advertised_brokers("PLAINTEXT"), vec![("127.0.0.1".to_string(), 0)]);
+        assert_eq!(advertised_brokers("OTHER"), Vec::new());
+    }
+}
@@ -0,0 +1,337 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::{read_i16_be, read_i32_be, read_unsigned_varint, write_unsigned_varint};
+
+// KRaft keeps cluster metadata in partition 0 of __cluster_metadata under the broker's log directory
+const METADATA_PARTITION_DIR: &str = "__cluster_metadata-0";
+const SEGMENT_FILE_NAME: &str = "00000000000000000000.log";
+
+// Record batch v2 layout: everything before `records` is 61 bytes
+const BATCH_HEADER_SIZE: usize = 61;
+const BATCH_LENGTH_OFFSET: usize = 8;
+const BATCH_CRC_OFFSET: usize = 17;
+const BATCH_ATTRIBUTES_OFFSET: usize = 21;
+const CONTROL_BATCH_FLAG: i16 = 0x20;
+const COMPRESSION_CODEC_MASK: i16 = 0x07;
+
+// Metadata record values start with frame version 1, then the record type and version
+const METADATA_FRAME_VERSION: u32 = 1;
+
+pub struct MetadataRecord {
+    pub offset: i64,
+    pub record_type: u32,
+    // Record fields after the frame header (type and version), in the record type's own layout
+    pub data: Vec<u8>,
+}
+
+struct MetadataLog {
+    segment_path: PathBuf,
+    next_offset: i64,
+}
+
+static METADATA_LOG: Mutex<Option<MetadataLog>> = Mutex::new(None);
+
+// Opens (creating if needed) the metadata log and returns every record in it for replay
+pub fn open(log_dir: &str) -> io::Result<Vec<MetadataRecord>> {
+    let partition_dir = PathBuf::from(log_dir).join(METADATA_PARTITION_DIR);
+    fs::create_dir_all(&partition_dir)?;
+    let segment_path = partition_dir.join(SEGMENT_FILE_NAME);
+
+    let mut contents = Vec::new();
+    if segment_path.exists() {
+        File::open(&segment_path)?.read_to_end(&mut contents)?;
+    }
+
+    let (records, valid_length, next_offset) = read_batches(&contents)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", segment_path.display(), e)))?;
+    if valid_length < contents.len() {
+        // A torn last batch is left behind by a crash mid-append; drop it so appends stay readable
+        println!("truncating metadata log {} from {} to {} bytes",
+                 segment_path.display(), contents.len(), valid_length);
+        OpenOptions::new().write(true).open(&segment_path)?.set_len(valid_length as u64)?;
+    }
+
+    println!("loaded {} metadata records from {}, next offset {}",
+             records.len(), segment_path.display(), next_offset);
+    *METADATA_LOG.lock().unwrap() = Some(MetadataLog { segment_path, next_offset });
+    Ok(records)
+}
+
+// Appends records of (record_type, version, data) as one batch and returns the offset of the last one
+pub fn append(records: &[(u32, u32, Vec<u8>)]) -> io::Result<i64> {
+    let mut guard = METADATA_LOG.lock().unwrap();
+    let log = guard.as_mut().ok_or_else(|| io::Error::other("metadata log is not open"))?;
+    if records.is_empty() {
+        return Ok(log.next_offset - 1);
+    }
+
+    let batch = encode_batch(log.next_offset, records);
+    let mut file = OpenOptions::new().create(true).append(true).open(&log.segment_path)?;
+    file.write_all(&batch)?;
+    file.sync_data()?;
+
+    log.next_offset += records.len() as i64;
+    Ok(log.next_offset - 1)
+}
+
+// Returns the records, the length of the log up to any torn tail, and the next offset. Only a short
+// last batch or a CRC failure on the last batch is a torn tail; damage before it is an error, since
+// truncating there or skipping the batch would silently drop committed metadata
+fn read_batches(contents: &[u8]) -> Result<(Vec<MetadataRecord>, usize, i64), String> {
+    let mut records = Vec::new();
+    let mut position = 0;
+    let mut next_offset = 0;
+
+    while position + BATCH_HEADER_SIZE <= contents.len() {
+        let batch_length = match read_i32_be(contents, position + BATCH_LENGTH_OFFSET) {
+            Ok(length) if length >= (BATCH_HEADER_SIZE - 12) as i32 => length as usize,
+            _ => return Err(format!("batch at position {} has an invalid length", position)),
+        };
+        let batch_end = position + 12 + batch_length;
+        if batch_end > contents.len() {
+            println!("metadata log batch at position {} is incomplete", position);
+            break;
+        }
+        let batch = &contents[position..batch_end];
+
+        let stored_crc = u32::from_be_bytes(batch[BATCH_CRC_OFFSET..BATCH_CRC_OFFSET + 4].try_into().unwrap());
+        if crc32c(&batch[BATCH_ATTRIBUTES_OFFSET..]) != stored_crc {
+            if batch_end < contents.len() {
+                return Err(format!("batch at position {} failed its CRC check and is not the last batch", position));
+            }
+            println!("metadata log batch at position {} failed its CRC check", position);
+            break;
+        }
+
+        let base_offset = i64::from_be_bytes(batch[0..8].try_into().unwrap());
+        let attributes = read_i16_be(batch, BATCH_ATTRIBUTES_OFFSET).unwrap_or(0);
+        let last_offset_delta = read_i32_be(batch, 23).unwrap_or(0);
+        let record_count = read_i32_be(batch, 57).unwrap_or(0);
+
+        if attributes & CONTROL_BATCH_FLAG != 0 {
+            // Leader change and snapshot markers carry no metadata
+        } else if attributes & COMPRESSION_CODEC_MASK != 0 {
+            println!("skipping compressed metadata batch at offset {}", base_offset);
+        } else if let Err(e) = decode_records(batch, base_offset, record_count, &mut records) {
+            return Err(format!("error decoding batch at offset {}: {}", base_offset, e));
+        }
+
+        next_offset = base_offset + last_offset_delta as i64 + 1;
+        position = batch_end;
+    }
+
+    Ok((records, position, next_offset))
+}
+
+fn decode_records(batch: &[u8], base_offset: i64, record_count: i32, records: &mut Vec<MetadataRecord>) -> Result<(), &'static str> {
+    let mut offset = BATCH_HEADER_SIZE;
+    for _ in 0..record_count {
+        let (record_length, record_start) = read_varint(batch, offset)?;
+        if record_length < 0 {
+            return Err("Negative record length");
+        }
+        let record_end = match record_start.checked_add(record_length as usize) {
+            Some(end) if end <= batch.len() => end,
+            _ => return Err("Record length exceeds batch"),
+        };
+
+        let mut position = record_start + 1; // attributes (1 byte)
+        let (_timestamp_delta, new_position) = read_varlong(batch, position)?;
+        let (offset_delta, new_position) = read_varint(batch, new_position)?;
+        let (key_length, new_position) = read_varint(batch, new_position)?;
+        position = match new_position.checked_add(key_length.max(0) as usize) {
+            Some(position) if position <= record_end => position,
+            _ => return Err("Record key exceeds record"),
+        };
+        let (value_length, value_start) = read_varint(batch, position)?;
+        if value_length < 0 {
+            return Err("Negative record value length");
+        }
+        let value_end = match value_start.checked_add(value_length as usize) {
+            Some(end) if end <= record_end => end,
+            _ => return Err("Record value exceeds record"),
+        };
+        let value = &batch[value_start..value_end];
+
+        let (frame_version, position) = read_unsigned_varint(value, 0)?;
+        if frame_version != METADATA_FRAME_VERSION {
+            return Err("Unsupported metadata record frame version");
+        }
+        let (record_type, position) = read_unsigned_varint(value, position)?;
+        let (_version, position) = read_unsigned_varint(value, position)?;
+
+        records.push(MetadataRecord {
+            offset: base_offset + offset_delta as i64,
+            record_type,
+            data: value[position..].to_vec(),
+        });
+        offset = record_end;
+    }
+    Ok(())
+}
+
+fn encode_batch(base_offset: i64, records: &[(u32, u32, Vec<u8>)]) -> Vec<u8> {
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_millis() as i64)
+        .unwrap_or(0);
+
+    let mut encoded_records = Vec::new();
+    for (offset_delta, (record_type, version, data)) in records.iter().enumerate() {
+        let mut value = Vec::new();
+        write_unsigned_varint(&mut value, METADATA_FRAME_VERSION);
+        write_unsigned_varint(&mut value, *record_type);
+        write_unsigned_varint(&mut value, *version);
+        value.extend_from_slice(data);
+
+        let mut record = Vec::new();
+        record.push(0);                                   // attributes (1 byte)
+        write_varint(&mut record, 0);                     // timestamp_delta
+        write_varint(&mut record, offset_delta as i32);   // offset_delta
+        write_varint(&mut record, -1);                    // key_length: null key
+        write_varint(&mut record, value.len() as i32);    // value_length
+        record.extend_from_slice(&value);                 // value
+        write_varint(&mut record, 0);                     // headers count
+
+        write_varint(&mut encoded_records, record.len() as i32);
+        encoded_records.extend_from_slice(&record);
+    }
+
+    // Everything covered by the CRC: attributes onwards
+    let mut crc_section = Vec::new();
+    crc_section.extend_from_slice(&0i16.to_be_bytes());                          // attributes (2 bytes)
+    crc_section.extend_from_slice(&(records.len() as i32 - 1).to_be_bytes());    // last_offset_delta (4 bytes)
+    crc_section.extend_from_slice(&timestamp.to_be_bytes());                     // base_timestamp (8 bytes)
+    crc_section.extend_from_slice(&timestamp.to_be_bytes());                     // max_timestamp (8 bytes)
+    crc_section.extend_from_slice(&(-1i64).to_be_bytes());                       // producer_id (8 bytes)
+    crc_section.extend_from_slice(&(-1i16).to_be_bytes());                       // producer_epoch (2 bytes)
+    crc_section.extend_from_slice(&(-1i32).to_be_bytes());                       // base_sequence (4 bytes)
+    crc_section.extend_from_slice(&(records.len() as i32).to_be_bytes());        // records count (4 bytes)
+    crc_section.extend_from_slice(&encoded_records);
+
+    let mut batch = Vec::new();
+    batch.extend_from_slice(&base_offset.to_be_bytes());                          // base_offset (8 bytes)
+    batch.extend_from_slice(&((crc_section.len() + 9) as i32).to_be_bytes());     // batch_length (4 bytes)
+    batch.extend_from_slice(&0i32.to_be_bytes());                                 // partition_leader_epoch (4 bytes)
+    batch.push(2);                                                                // magic (1 byte)
+    batch.extend_from_slice(&crc32c(&crc_section).to_be_bytes());                 // crc (4 bytes)
+    batch.extend_from_slice(&crc_section);
+    batch
+}
+
+// Record fields use zigzag-encoded varints
+fn read_varint(buffer: &[u8], offset: usize) -> Result<(i32, usize), &'static str> {
+    let (raw, offset) = read_unsigned_varint(buffer, offset)?;
+    Ok(((raw >> 1) as i32 ^ -((raw & 1) as i32), offset))
+}
+
+fn read_varlong(buffer: &[u8], mut offset: usize) -> Result<(i64, usize), &'static str> {
+    let mut raw: u64 = 0;
+    let mut shift = 0;
+    loop {
+        if offset >= buffer.len() {
+            return Err("Buffer too short for varlong");
+        }
+        if shift > 63 {
+            return Err("Varlong is too long");
+        }
+        let byte = buffer[offset];
+        offset += 1;
+        raw |= ((byte & 0x7f) as u64) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(((raw >> 1) as i64 ^ -((raw & 1) as i64), offset));
+        }
+        shift += 7;
+    }
+}
+
+fn write_varint(buffer: &mut Vec<u8>, value: i32) {
+    write_unsigned_varint(buffer, ((value << 1) ^ (value >> 31)) as u32);
+}
+
+// CRC-32C (Castagnoli), which record batches use for their checksum
+fn crc32c(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for byte in data {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0x82f6_3b78 & mask);
+        }
+    }
+    !crc
+}
+
+// Opens a metadata log in a scratch directory, once per test process, for tests that append to it
+#[cfg(test)]
+pub fn open_scratch_log() {
+    static OPENED: std::sync::Once = std::sync::Once::new();
+    OPENED.call_once(|| {
+        let log_dir = std::env::temp_dir().join(format!("codecrafters-kafka-unit-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&log_dir);
+        open(log_dir.to_str().unwrap()).unwrap();
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn records(contents: &[u8]) -> Vec<(i64, u32, Vec<u8>)> {
+        let (records, _, _) = read_batches(contents).unwrap();
+        records.into_iter().map(|record| (record.offset, record.record_type, record.data)).collect()
+    }
+
+    #[test]
+    fn batches_round_trip() {
+        let mut contents = encode_batch(0, &[(12, 0, vec![1, 2, 3]), (12, 0, vec![4])]);
+        contents.extend(encode_batch(2, &[(5, 1, Vec::new())]));
+
+        let (_, valid_length, next_offset) = read_batches(&contents).unwrap();
+        assert_eq!((valid_length, next_offset), (contents.len(), 3));
+        assert_eq!(records(&contents), vec![(0, 12, vec![1, 2, 3]), (1, 12, vec![4]), (2, 5, Vec::new())]);
+    }
+
+    #[test]
+    fn a_torn_tail_is_cut_off() {
+        let first = encode_batch(0, &[(12, 0, vec![1])]);
+        let second = encode_batch(1, &[(12, 0, vec![2])]);
+        for torn_length in [1, BATCH_HEADER_SIZE - 1, BATCH_HEADER_SIZE + 1, second.len() - 1] {
+            let mut contents = first.clone();
+            contents.extend_from_slice(&second[..torn_length]);
+
+            let (_, valid_length, next_offset) = read_batches(&contents).unwrap();
+            assert_eq!((valid_length, next_offset), (first.len(), 1), "torn after {} bytes", torn_length);
+            assert_eq!(records(&contents), vec![(0, 12, vec![1])]);
+        }
+    }
+
+    #[test]
+    fn a_batch_failing_its_crc_check_is_cut_off() {
+        let first = encode_batch(0, &[(12, 0, vec![1])]);
+        let mut second = encode_batch(1, &[(12, 0, vec![2])]);
+        let last = second.len() - 1;
+        second[last - 2] ^= 0xff; // inside the record value
+        let mut contents = first.clone();
+        contents.extend_from_slice(&second);
+
+        let (_, valid_length, next_offset) = read_batches(&contents).unwrap();
+        assert_eq!((valid_length, next_offset), (first.len(), 1));
+        assert_eq!(records(&contents), vec![(0, 12, vec![1])]);
+    }
+
+    #[test]
+    fn a_batch_failing_its_crc_check_before_the_last_is_an_error() {
+        let mut first = encode_batch(0, &[(12, 0, vec![1])]);
+        let last = first.len() - 1;
+        first[last - 2] ^= 0xff;
+        let mut contents = first;
+        contents.extend(encode_batch(1, &[(12, 0, vec![2])]));
+
+        assert!(read_batches(&contents).is_err());
+    }
+}
@@ -0,0 +1,258 @@
+use std::fs;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use ring::signature::{self, RsaPublicKeyComponents, UnparsedPublicKey};
+use serde_json::{Map, Value};
+
+use crate::base64;
+use crate::config::{broker_config, BrokerConfig};
+use crate::sasl::{SaslOutcome, SaslServer};
+
+// Sent as the challenge when a token is rejected; the client answers with a single %x01 (RFC 7628 section 3.2.2)
+const INVALID_TOKEN_CHALLENGE: &[u8] = br#"{"status":"invalid_token"}"#;
+
+enum PublicKey {
+    Rsa { modulus: Vec<u8>, exponent: Vec<u8> },
+    // Uncompressed SEC1 point: 0x04 || x || y
+    EcP256 { point: Vec<u8> },
+}
+
+// A signature verification key from the JWKS document
+pub struct JsonWebKey {
+    kid: Option<String>,
+    alg: Option<String>,
+    key: PublicKey,
+}
+
+impl JsonWebKey {
+    fn parse(jwk: &Map<String, Value>) -> Result<Option<JsonWebKey>, String> {
+        let field = |name: &str| jwk.get(name).and_then(Value::as_str);
+        let decoded_field = |name: &str| -> Result<Vec<u8>, String> {
+            let value = field(name).ok_or_else(|| format!("key is missing {}", name))?;
+            base64::decode_url_safe(value).map_err(|e| format!("key field {}: {}", name, e))
+        };
+        // Encryption keys have no place in signature verification
+        if field("use").is_some_and(|key_use| key_use != "sig") {
+            return Ok(None);
+        }
+
+        let key = match (field("kty"), field("crv")) {
+            (Some("RSA"), _) => PublicKey::Rsa { modulus: decoded_field("n")?, exponent: decoded_field("e")? },
+            (Some("EC"), Some("P-256")) => {
+                let (x, y) = (decoded_field("x")?, decoded_field("y")?);
+                if x.len() != 32 || y.len() != 32 {
+                    return Err("P-256 key coordinates must be 32 bytes".to_string());
+                }
+                PublicKey::EcP256 { point: [&[0x04][..], &x, &y].concat() }
+            }
+            _ => return Ok(None),
+        };
+        Ok(Some(JsonWebKey { kid: field("kid").map(str::to_string), alg: field("alg").map(str::to_string), key }))
+    }
+
+    // Whether this key can check a JWS signed with alg (RS256 or ES256)
+    fn accepts(&self, alg: &str) -> bool {
+        let key_algorithm = match self.key {
+            PublicKey::Rsa { .. } => "RS256",
+            PublicKey::EcP256 { .. } => "ES256",
+        };
+        key_algorithm == alg && self.alg.as_deref().map_or(true, |key_alg| key_alg == alg)
+    }
+
+    fn verify(&self, message: &[u8], signature: &[u8]) -> bool {
+        match &self.key {
+            PublicKey::Rsa { modulus, exponent } => RsaPublicKeyComponents { n: modulus, e: exponent }
+                .verify(&signature::RSA_PKCS1_2048_8192_SHA256, message, signature)
+                .is_ok(),
+            // JWS carries ECDSA signatures as fixed-size r || s
+            PublicKey::EcP256 { point } => UnparsedPublicKey::new(&signature::ECDSA_P256_SHA256_FIXED, point)
+                .verify(message, signature)
+                .is_ok(),
+        }
+    }
+}
+
+// Reads the JWKS file; keys that aren't RS256 or ES256 signing keys are skipped
+pub fn load_jwks(path: &str) -> Result<Vec<JsonWebKey>, String> {
+    let contents = fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+    let document: Value = serde_json::from_str(&contents).map_err(|e| format!("{}: {}", path, e))?;
+    let jwks = document.get("keys").and_then(Value::as_array).ok_or_else(|| format!("{}: no keys array", path))?;
+
+    let mut keys = Vec::new();
+    for jwk in jwks {
+        let jwk = jwk.as_object().ok_or_else(|| format!("{}: keys must be JSON objects", path))?;
+        if let Some(key) = JsonWebKey::parse(jwk).map_err(|e| format!("{}: {}", path, e))? {
+            keys.push(key);
+        }
+    }
+    if keys.is_empty() {
+        return Err(format!("{}: no RSA or P-256 signing keys", path));
+    }
+    Ok(keys)
+}
+
+fn decode_json_part(part: &str, name: &str) -> Result<Map<String, Value>, String> {
+    let json = base64::decode_url_safe(part).map_err(|e| format!("malformed token {}: {}", name, e))?;
+    match serde_json::from_slice(&json) {
+        Ok(Value::Object(object)) => Ok(object),
+        _ => Err(format!("malformed token {}: not a JSON object", name)),
+    }
+}
+
+fn numeric_date(claims: &Map<String, Value>, name: &str) -> Result<Option<i64>, String> {
+    match claims.get(name) {
+        None => Ok(None),
+        Some(value) => value.as_f64().map(|seconds| Some(seconds as i64)).ok_or_else(|| format!("{} claim is not a number", name)),
+    }
+}
+
+// Verifies a compact JWS against the JWKS file and checks its claims. Returns the principal name and
+// the expiration time in seconds since the epoch.
+fn validate_token(token: &str, config: &BrokerConfig, now: i64) -> Result<(String, i64), String> {
+    let parts: Vec<&str> = token.split('.').collect();
+    let [header_part, payload_part, signature_part] = parts[..] else {
+        return Err("malformed token: expected header.payload.signature".to_string());
+    };
+    let header = decode_json_part(header_part, "header")?;
+    let claims = decode_json_part(payload_part, "payload")?;
+    let signature = base64::decode_url_safe(signature_part).map_err(|e| format!("malformed token signature: {}", e))?;
+
+    let alg = header.get("alg").and_then(Value::as_str).unwrap_or("none");
+    if alg != "RS256" && alg != "ES256" {
+        return Err(format!("token algorithm {} is not allowed; only RS256 and ES256 are", alg));
+    }
+    if header.contains_key("crit") {
+        return Err("token has critical header parameters this broker doesn't understand".to_string());
+    }
+
+    // Keys are read on every authentication so a rotated JWKS file applies immediately
+    let path = config.sasl_oauthbearer_jwks_file.as_deref().ok_or("no JWKS file is configured")?;
+    let keys = load_jwks(path)?;
+    let kid = header.get("kid").and_then(Value::as_str);
+    let candidates: Vec<&JsonWebKey> = keys
+        .iter()
+        .filter(|key| key.accepts(alg) && kid.map_or(true, |kid| key.kid.as_deref() == Some(kid)))
+        .collect();
+    if candidates.is_empty() {
+        return Err(format!("no {} key in the JWKS file matches kid {}", alg, kid.unwrap_or("(none)")));
+    }
+    let signing_input = &token[..header_part.len() + 1 + payload_part.len()];
+    if !candidates.iter().any(|key| key.verify(signing_input.as_bytes(), &signature)) {
+        return Err("token signature is invalid".to_string());
+    }
+
+    let skew = config.sasl_oauthbearer_clock_skew_seconds;
+    let expires_at = numeric_date(&claims, "exp")?.ok_or("token has no exp claim")?;
+    if now > expires_at + skew {
+        return Err(format!("token expired at {}", expires_at));
+    }
+    let issued_at = numeric_date(&claims, "iat")?.ok_or("token has no iat claim")?;
+    if issued_at > now + skew {
+        return Err(format!("token is issued in the future at {}", issued_at));
+    }
+    if let Some(not_before) = numeric_date(&claims, "nbf")? {
+        if now + skew < not_before {
+            return Err(format!("token is not valid before {}", not_before));
+        }
+    }
+
+    if let Some(expected_issuer) = &config.sasl_oauthbearer_expected_issuer {
+        if claims.get("iss").and_then(Value::as_str) != Some(expected_issuer) {
+            return Err(format!("token issuer is not {}", expected_issuer));
+        }
+    }
+    if !config.sasl_oauthbearer_expected_audience.is_empty() {
+        // aud is a single string or an array of them
+        let audiences: Vec<&str> = match claims.get("aud") {
+            Some(Value::String(audience)) => vec![audience],
+            Some(Value::Array(audiences)) => audiences.iter().filter_map(Value::as_str).collect(),
+            _ => Vec::new(),
+        };
+        if !audiences.iter().any(|audience| config.sasl_oauthbearer_expected_audience.iter().any(|expected| expected == audience)) {
+            return Err("token audience is not one of sasl.oauthbearer.expected.audience".to_string());
+        }
+    }
+
+    let claim_name = &config.sasl_oauthbearer_sub_claim_name;
+    match claims.get(claim_name).and_then(Value::as_str) {
+        Some(principal) if !principal.is_empty() => Ok((principal.to_string(), expires_at)),
+        _ => Err(format!("token has no {} claim to take the principal from", claim_name)),
+    }
+}
+
+// RFC 7628 client first message: gs2-header, then %x01-separated key=value pairs ending in %x01%x01,
+// e.g. "n,a=alice,\x01auth=Bearer <token>\x01\x01". Returns the authorization id and the token.
+fn parse_client_first_message(message: &str) -> Result<(Option<&str>, &str), String> {
+    let invalid = || "Invalid OAUTHBEARER client first message".to_string();
+    let rest = message.strip_prefix("n,").ok_or_else(invalid)?;
+    let (authzid, rest) = rest.split_once(',').ok_or_else(invalid)?;
+    let authorization_id = match authzid {
+        "" => None,
+        _ => Some(authzid.strip_prefix("a=").ok_or_else(invalid)?),
+    };
+    let pairs = rest.strip_prefix('\x01').and_then(|rest| rest.strip_suffix("\x01\x01")).ok_or_else(invalid)?;
+
+    // Extensions other than auth are accepted and ignored
+    let mut token = None;
+    for pair in pairs.split('\x01') {
+        let (key, value) = pair.split_once('=').ok_or_else(invalid)?;
+        if key == "auth" {
+            let (scheme, value) = value.split_once(' ').ok_or_else(invalid)?;
+            if !scheme.eq_ignore_ascii_case("Bearer") {
+                return Err(format!("Invalid OAUTHBEARER auth scheme {}", scheme));
+            }
+            token = Some(value.trim_start_matches(' '));
+        }
+    }
+    let token = token.filter(|token| !token.is_empty()).ok_or("Invalid OAUTHBEARER client first message: no token")?;
+    Ok((authorization_id, token))
+}
+
+enum OAuthBearerExchange {
+    ClientFirst,
+    // The token was rejected and the error challenge sent; the exchange fails with this message
+    ErrorSent(String),
+    Finished,
+}
+
+pub struct OAuthBearerServer {
+    exchange: OAuthBearerExchange,
+}
+
+impl OAuthBearerServer {
+    pub fn new() -> OAuthBearerServer {
+        OAuthBearerServer { exchange: OAuthBearerExchange::ClientFirst }
+    }
+}
+
+impl SaslServer for OAuthBearerServer {
+    fn evaluate_response(&mut self, response: &[u8]) -> Result<SaslOutcome, String> {
+        match std::mem::replace(&mut self.exchange, OAuthBearerExchange::Finished) {
+            OAuthBearerExchange::ClientFirst => {}
+            OAuthBearerExchange::ErrorSent(message) => return Err(message),
+            OAuthBearerExchange::Finished => return Err("Unexpected OAUTHBEARER message after authentication completed".to_string()),
+        }
+
+        let message = std::str::from_utf8(response).map_err(|_| "Invalid OAUTHBEARER message: not UTF-8".to_string())?;
+        let (authorization_id, token) = parse_client_first_message(message)?;
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).map(|elapsed| elapsed.as_secs() as i64).unwrap_or(0);
+        let (principal, expires_at) = match validate_token(token, broker_config(), now) {
+            Ok(validated) => validated,
+            Err(reason) => {
+                self.exchange = OAuthBearerExchange::ErrorSent(format!("Authentication failed: {}", reason));
+                return Ok(SaslOutcome::Challenge(INVALID_TOKEN_CHALLENGE.to_vec()));
+            }
+        };
+        if authorization_id.is_some_and(|authorization_id| authorization_id != principal) {
+            return Err(format!("Authentication failed: Client requested an authorization id ({}) that is different from the token's principal name ({})",
+                               authorization_id.unwrap_or_default(), principal));
+        }
+
+        Ok(SaslOutcome::Complete {
+            authorization_id: principal,
+            response: Vec::new(),
+            credential_lifetime: Some(Duration::from_secs(expires_at.saturating_sub(now).max(0) as u64)),
+            token_authenticated: false,
+        })
+    }
+}
@@ -0,0 +1,656 @@
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::authorizer::{authorize, AclOperation, ResourceType, CLUSTER_NAME};
+use crate::config::broker_config;
+use crate::metadata_log::{self, MetadataRecord};
+use crate::{
+    frame_response, read_array_length, read_compact_string, read_i64_be, read_i8, read_nullable_string, read_string,
+    skip_request_header, skip_tagged_fields, write_array_length, write_compact_nullable_string, write_compact_string,
+    write_nullable_string, write_string, write_unsigned_varint, RequestContext,
+};
+
+const CLIENT_QUOTA_RECORD: u32 = 14;
+
+const USER_ENTITY_TYPE: &str = "user";
+const CLIENT_ID_ENTITY_TYPE: &str = "client-id";
+
+const REQUEST_PERCENTAGE: &str = "request_percentage";
+
+// The quota keys Kafka accepts for user and client-id entities, and whether their values are whole numbers.
+// Only request_percentage is metered: this broker serves neither Produce and Fetch for the byte rates
+// nor topic creation for the mutation rate
+const QUOTA_KEYS: &[(&str, bool)] = &[
+    ("producer_byte_rate", true),
+    ("consumer_byte_rate", true),
+    (REQUEST_PERCENTAGE, false),
+    ("controller_mutation_rate", false),
+];
+
+// DescribeClientQuotas filter match types
+const MATCH_TYPE_EXACT: i8 = 0;
+const MATCH_TYPE_DEFAULT: i8 = 1;
+const MATCH_TYPE_SPECIFIED: i8 = 2;
+
+// A user, client-id or user and client-id quota entity. None leaves the entity type out; Some(None) is
+// the type's default entity, which applies to every user or client-id without a quota of its own
+#[derive(Clone, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+struct QuotaEntity {
+    user: Option<Option<String>>,
+    client_id: Option<Option<String>>,
+}
+
+impl QuotaEntity {
+    // Entities travel as (entity_type, entity_name) pairs, a null name standing for the default entity
+    fn from_components(components: &[(String, Option<String>)]) -> Result<QuotaEntity, String> {
+        if components.is_empty() {
+            return Err("Invalid empty client quota entity".to_string());
+        }
+        let mut entity = QuotaEntity::default();
+        for (entity_type, entity_name) in components {
+            let slot = match entity_type.as_str() {
+                USER_ENTITY_TYPE => &mut entity.user,
+                CLIENT_ID_ENTITY_TYPE => &mut entity.client_id,
+                _ => return Err(format!("Unhandled client quota entity type: {}", entity_type)),
+            };
+            if slot.is_some() {
+                return Err(format!("Duplicate entity type: {}", entity_type));
+            }
+            if entity_name.as_deref() == Some("") {
+                return Err(format!("Invalid empty {} name", entity_type));
+            }
+            *slot = Some(entity_name.clone());
+        }
+        Ok(entity)
+    }
+
+    fn components(&self) -> Vec<(&'static str, Option<&str>)> {
+        let mut components = Vec::new();
+        if let Some(user) = &self.user {
+            components.push((USER_ENTITY_TYPE, user.as_deref()));
+        }
+        if let Some(client_id) = &self.client_id {
+            components.push((CLIENT_ID_ENTITY_TYPE, client_id.as_deref()));
+        }
+        components
+    }
+
+    fn component(&self, entity_type: &str) -> Option<&Option<String>> {
+        match entity_type {
+            USER_ENTITY_TYPE => self.user.as_ref(),
+            CLIENT_ID_ENTITY_TYPE => self.client_id.as_ref(),
+            _ => None,
+        }
+    }
+
+    fn describe(&self) -> String {
+        let components: Vec<String> = self
+            .components()
+            .iter()
+            .map(|(entity_type, entity_name)| format!("{}={}", entity_type, entity_name.unwrap_or("<default>")))
+            .collect();
+        components.join(",")
+    }
+}
+
+// Quota values per entity, as replayed from the metadata log; entities without values are dropped
+static CLIENT_QUOTAS: Mutex<BTreeMap<QuotaEntity, BTreeMap<String, f64>>> = Mutex::new(BTreeMap::new());
+
+static QUOTA_UPDATE_LOCK: Mutex<()> = Mutex::new(());
+
+struct QuotaChange {
+    entity: QuotaEntity,
+    key: String,
+    // None removes the key
+    value: Option<f64>,
+}
+
+// Applies a ClientQuotaRecord read back from the metadata log
+pub fn replay(record: &MetadataRecord) {
+    if record.record_type != CLIENT_QUOTA_RECORD {
+        return;
+    }
+    match decode_client_quota_record(&record.data) {
+        Ok(change) => apply_quota_change(change),
+        Err(e) => println!("error decoding client quota record at offset {}: {}", record.offset, e),
+    }
+}
+
+fn apply_quota_change(change: QuotaChange) {
+    let mut quotas = CLIENT_QUOTAS.lock().unwrap();
+    match change.value {
+        Some(value) => {
+            println!("set client quota {}={} for {}", change.key, value, change.entity.describe());
+            quotas.entry(change.entity).or_default().insert(change.key, value);
+        }
+        None => {
+            if let Some(values) = quotas.get_mut(&change.entity) {
+                println!("removed client quota {} for {}", change.key, change.entity.describe());
+                values.remove(&change.key);
+                if values.is_empty() {
+                    quotas.remove(&change.entity);
+                }
+            }
+        }
+    }
+}
+
+fn encode_quota_change(change: &QuotaChange) -> (u32, u32, Vec<u8>) {
+    let mut data = Vec::new();
+    let components = change.entity.components();
+    write_unsigned_varint(&mut data, components.len() as u32 + 1);
+    for (entity_type, entity_name) in components {
+        write_compact_string(&mut data, entity_type);              // entity_type
+        write_compact_nullable_string(&mut data, entity_name);     // entity_name
+        data.push(0);                                              // tag buffer (1 byte)
+    }
+    write_compact_string(&mut data, &change.key);                  // key
+    data.extend_from_slice(&change.value.unwrap_or(0.0).to_be_bytes()); // value (8 bytes)
+    data.push(change.value.is_none() as u8);                       // remove (1 byte)
+    data.push(0);                                                  // tag buffer (1 byte)
+    (CLIENT_QUOTA_RECORD, 0, data)
+}
+
+fn decode_client_quota_record(data: &[u8]) -> Result<QuotaChange, &'static str> {
+    let (components_count, mut offset) = read_array_length(data, 0, true)?;
+    let mut components = Vec::new();
+    for _ in 0..components_count.unwrap_or(0) {
+        let (entity_type, new_offset) = read_compact_string(data, offset)?;
+        let (entity_name, new_offset) = read_nullable_string(data, new_offset, true)?;
+        offset = skip_tagged_fields(data, new_offset)?;
+        components.push((entity_type, entity_name));
+    }
+    let entity = QuotaEntity::from_components(&components).map_err(|_| "invalid client quota entity")?;
+    let (key, offset) = read_compact_string(data, offset)?;
+    let value = f64::from_bits(read_i64_be(data, offset)? as u64);
+    let remove = read_i8(data, offset + 8)? != 0;
+    Ok(QuotaChange { entity, key, value: if remove { None } else { Some(value) } })
+}
+
+// The quota a client's user and client-id get for a key, and the entity it is set on. Kafka's precedence:
+// the user's own entities, then the default user's, then client-id alone; within each, the named client-id
+// before the default client-id before no client-id
+fn resolve_quota(key: &str, user: &str, client_id: &str) -> Option<(QuotaEntity, f64)> {
+    let named = |name: &str| Some(Some(name.to_string()));
+    let candidates = [
+        QuotaEntity { user: named(user), client_id: named(client_id) },
+        QuotaEntity { user: named(user), client_id: Some(None) },
+        QuotaEntity { user: named(user), client_id: None },
+        QuotaEntity { user: Some(None), client_id: named(client_id) },
+        QuotaEntity { user: Some(None), client_id: Some(None) },
+        QuotaEntity { user: Some(None), client_id: None },
+        QuotaEntity { user: None, client_id: named(client_id) },
+        QuotaEntity { user: None, client_id: Some(None) },
+    ];
+    let quotas = CLIENT_QUOTAS.lock().unwrap();
+    candidates
+        .into_iter()
+        .find_map(|entity| quotas.get(&entity).and_then(|values| values.get(key)).map(|value| (entity, *value)))
+}
+
+// Clients sharing a quota share its meter: a user-level quota is metered across all of the user's
+// client-ids, a client-id-level one across all users of the client-id
+fn meter_key(entity: &QuotaEntity, user: &str, client_id: &str) -> (String, String) {
+    let user = if entity.user.is_some() { user } else { "" };
+    let client_id = if entity.client_id.is_some() { client_id } else { "" };
+    (user.to_string(), client_id.to_string())
+}
+
+// Kafka's windowed rate: the total recorded over the last quota.window.num samples of
+// quota.window.size.seconds each, per second
+struct Rate {
+    // (start, total) of each sample, oldest first
+    samples: VecDeque<(Instant, f64)>,
+}
+
+struct QuotaWindow {
+    samples: u32,
+    sample_size: Duration,
+}
+
+impl QuotaWindow {
+    fn from_config() -> QuotaWindow {
+        let config = broker_config();
+        QuotaWindow {
+            samples: config.quota_window_num as u32,
+            sample_size: Duration::from_secs(config.quota_window_size_seconds as u64),
+        }
+    }
+
+    fn span(&self) -> Duration {
+        self.sample_size * self.samples
+    }
+}
+
+impl Rate {
+    fn purge(&mut self, now: Instant, window: &QuotaWindow) {
+        while self.samples.front().is_some_and(|(start, _)| *start + window.span() <= now) {
+            self.samples.pop_front();
+        }
+    }
+
+    fn record(&mut self, value: f64, now: Instant, window: &QuotaWindow) {
+        self.purge(now, window);
+        match self.samples.back_mut() {
+            Some((start, total)) if *start + window.sample_size > now => *total += value,
+            _ => self.samples.push_back((now, value)),
+        }
+    }
+
+    // The time the rate is measured over. Until the samples cover the window, it counts as all but one
+    // sample long, so a client's first burst isn't measured over a few milliseconds
+    fn elapsed(&self, now: Instant, window: &QuotaWindow) -> Duration {
+        let elapsed = self.samples.front().map_or(Duration::ZERO, |(start, _)| now.duration_since(*start));
+        elapsed.max(window.sample_size * (window.samples - 1)).max(window.sample_size)
+    }
+
+    fn per_second(&mut self, now: Instant, window: &QuotaWindow) -> f64 {
+        self.purge(now, window);
+        let total: f64 = self.samples.iter().map(|(_, total)| total).sum();
+        total / self.elapsed(now, window).as_secs_f64()
+    }
+}
+
+// Request handler time per (user, client-id) meter, in percent of one handler thread
+static REQUEST_TIME_RATES: Mutex<BTreeMap<(String, String), Rate>> = Mutex::new(BTreeMap::new());
+
+// How long a client over its request_percentage quota is throttled: long enough for its rate to fall
+// back to the quota, as Kafka computes it, capped at the quota window
+pub fn request_throttle_time_ms(user: &str, client_id: &str) -> i32 {
+    let Some((entity, quota)) = resolve_quota(REQUEST_PERCENTAGE, user, client_id) else {
+        return 0;
+    };
+    let window = QuotaWindow::from_config();
+    let now = Instant::now();
+    let mut rates = REQUEST_TIME_RATES.lock().unwrap();
+    let Some(rate) = rates.get_mut(&meter_key(&entity, user, client_id)) else {
+        return 0;
+    };
+    let observed = rate.per_second(now, &window);
+    if observed <= quota {
+        return 0;
+    }
+    let throttle = (observed - quota) / quota * rate.elapsed(now, &window).as_millis() as f64;
+    throttle.min(window.span().as_millis() as f64).round() as i32
+}
+
+// Records the time a request of the client spent in its handler, when a request_percentage quota applies
+pub fn record_request_time(user: &str, client_id: &str, handler_time: Duration) {
+    let Some((entity, _)) = resolve_quota(REQUEST_PERCENTAGE, user, client_id) else {
+        return;
+    };
+    let window = QuotaWindow::from_config();
+    let now = Instant::now();
+    let mut rates = REQUEST_TIME_RATES.lock().unwrap();
+    // Meters of clients that went quiet hold nothing the window still counts
+    rates.retain(|_, rate| {
+        rate.purge(now, &window);
+        !rate.samples.is_empty()
+    });
+    rates
+        .entry(meter_key(&entity, user, client_id))
+        .or_insert_with(|| Rate { samples: VecDeque::new() })
+        .record(handler_time.as_secs_f64() * 100.0, now, &window);
+}
+
+struct FilterComponent {
+    entity_type: String,
+    match_type: i8,
+    match_name: Option<String>,
+}
+
+struct DescribeClientQuotasRequest {
+    components: Vec<FilterComponent>,
+    // Only entities with no entity types beyond the components match
+    strict: bool,
+}
+
+fn parse_describe_client_quotas_request(buffer: &[u8], api_version: i16) -> Result<DescribeClientQuotasRequest, &'static str> {
+    // v1+ is flexible
+    let flexible = api_version >= 1;
+    let mut offset = skip_request_header(buffer, flexible)?;
+
+    let (components_count, new_offset) = read_array_length(buffer, offset, flexible)?;
+    offset = new_offset;
+    let mut components = Vec::new();
+    for _ in 0..components_count.unwrap_or(0) {
+        let (entity_type, new_offset) = read_string(buffer, offset, flexible)?;
+        let match_type = read_i8(buffer, new_offset)?;
+        let (match_name, new_offset) = read_nullable_string(buffer, new_offset + 1, flexible)?;
+        offset = if flexible { skip_tagged_fields(buffer, new_offset)? } else { new_offset };
+        components.push(FilterComponent { entity_type, match_type, match_name });
+    }
+    let strict = read_i8(buffer, offset)? != 0;
+    if flexible {
+        skip_tagged_fields(buffer, offset + 1)?;
+    }
+    Ok(DescribeClientQuotasRequest { components, strict })
+}
+
+fn validate_filter(components: &[FilterComponent]) -> Result<(), String> {
+    for (index, component) in components.iter().enumerate() {
+        if components[..index].iter().any(|other| other.entity_type == component.entity_type) {
+            return Err(format!("Entity type {} cannot appear more than once in the filter.", component.entity_type));
+        }
+        match component.match_type {
+            MATCH_TYPE_EXACT if component.match_name.is_none() => {
+                return Err("Request specified MATCH_TYPE_EXACT, but set match string to null".to_string());
+            }
+            MATCH_TYPE_DEFAULT | MATCH_TYPE_SPECIFIED if component.match_name.is_some() => {
+                return Err("Request specified MATCH_TYPE_DEFAULT or MATCH_TYPE_SPECIFIED, but also specified a match string".to_string());
+            }
+            MATCH_TYPE_EXACT | MATCH_TYPE_DEFAULT | MATCH_TYPE_SPECIFIED => {}
+            match_type => return Err(format!("Unexpected match type: {}", match_type)),
+        }
+    }
+    Ok(())
+}
+
+fn filter_matches(request: &DescribeClientQuotasRequest, entity: &QuotaEntity) -> bool {
+    let components_match = request.components.iter().all(|component| match entity.component(&component.entity_type) {
+        Some(entity_name) => match component.match_type {
+            MATCH_TYPE_EXACT => *entity_name == component.match_name,
+            MATCH_TYPE_DEFAULT => entity_name.is_none(),
+            _ => true,
+        },
+        None => false,
+    });
+    let no_other_types = entity
+        .components()
+        .iter()
+        .all(|(entity_type, _)| request.components.iter().any(|component| component.entity_type == *entity_type));
+    components_match && (!request.strict || no_other_types)
+}
+
+type DescribedQuotas = Vec<(QuotaEntity, BTreeMap<String, f64>)>;
+
+fn write_entity(buffer: &mut Vec<u8>, entity: &QuotaEntity, flexible: bool) {
+    let components = entity.components();
+    write_array_length(buffer, components.len(), flexible);
+    for (entity_type, entity_name) in components {
+        write_string(buffer, entity_type, flexible);
+        write_nullable_string(buffer, entity_name, flexible);
+        if flexible {
+            buffer.push(0); // tag buffer (1 byte)
+        }
+    }
+}
+
+fn create_describe_client_quotas_response(context: &RequestContext, error_code: i16, error_message: Option<&str>,
+                                          entries: Option<&DescribedQuotas>) -> Vec<u8> {
+    let flexible = context.api_version >= 1;
+    let mut response_body = Vec::new();
+
+    // Response header
+    response_body.extend_from_slice(&context.correlation_id.to_be_bytes()); // correlation_id (4 bytes)
+    if flexible {
+        response_body.push(0); // tag buffer (1 byte)
+    }
+
+    // Response body
+    response_body.extend_from_slice(&context.throttle_time_ms.to_be_bytes()); // throttle_time_ms (4 bytes)
+    response_body.extend_from_slice(&error_code.to_be_bytes());              // error_code (2 bytes)
+    write_nullable_string(&mut response_body, error_message, flexible);
+
+    // Errors leave the entries null
+    match entries {
+        Some(entries) => {
+            write_array_length(&mut response_body, entries.len(), flexible);
+            for (entity, values) in entries {
+                write_entity(&mut response_body, entity, flexible);
+                write_array_length(&mut response_body, values.len(), flexible);
+                for (key, value) in values {
+                    write_string(&mut response_body, key, flexible);
+                    response_body.extend_from_slice(&value.to_be_bytes()); // value (8 bytes)
+                    if flexible {
+                        response_body.push(0); // tag buffer (1 byte)
+                    }
+                }
+                if flexible {
+                    response_body.push(0); // tag buffer (1 byte)
+                }
+            }
+        }
+        None if flexible => response_body.push(0),                                   // entries: null COMPACT_ARRAY
+        None => response_body.extend_from_slice(&(-1i32).to_be_bytes()),              // entries: null ARRAY
+    }
+    if flexible {
+        response_body.push(0); // tag buffer (1 byte)
+    }
+
+    frame_response(response_body)
+}
+
+pub fn handle_describe_client_quotas_request(context: &RequestContext, message_buffer: &[u8]) -> Vec<u8> {
+    let request = match parse_describe_client_quotas_request(message_buffer, context.api_version) {
+        Ok(request) => request,
+        Err(e) => {
+            println!("error parsing DescribeClientQuotas request: {}", e);
+            return create_describe_client_quotas_error_response(context, message_buffer, 42); // INVALID_REQUEST
+        }
+    };
+    if !authorize(context.connection, AclOperation::DescribeConfigs, ResourceType::Cluster, CLUSTER_NAME) {
+        return create_describe_client_quotas_error_response(context, message_buffer, 31); // CLUSTER_AUTHORIZATION_FAILED
+    }
+    if let Err(message) = validate_filter(&request.components) {
+        return create_describe_client_quotas_response(context, 42, Some(&message), None); // INVALID_REQUEST
+    }
+
+    let entries: DescribedQuotas = CLIENT_QUOTAS
+        .lock()
+        .unwrap()
+        .iter()
+        .filter(|(entity, _)| filter_matches(&request, entity))
+        .map(|(entity, values)| (entity.clone(), values.clone()))
+        .collect();
+    create_describe_client_quotas_response(context, 0, None, Some(&entries))
+}
+
+pub fn create_describe_client_quotas_error_response(context: &RequestContext, _message_buffer: &[u8], error_code: i16) -> Vec<u8> {
+    create_describe_client_quotas_response(context, error_code, None, None)
+}
+
+struct QuotaAlteration {
+    key: String,
+    value: f64,
+    remove: bool,
+}
+
+struct AlterClientQuotasEntry {
+    // The entity as the request named it, echoed back in its result
+    components: Vec<(String, Option<String>)>,
+    alterations: Vec<QuotaAlteration>,
+}
+
+struct AlterClientQuotasRequest {
+    entries: Vec<AlterClientQuotasEntry>,
+    validate_only: bool,
+}
+
+fn parse_alter_client_quotas_request(buffer: &[u8], api_version: i16) -> Result<AlterClientQuotasRequest, &'static str> {
+    // v1+ is flexible
+    let flexible = api_version >= 1;
+    let mut offset = skip_request_header(buffer, flexible)?;
+
+    let (entries_count, new_offset) = read_array_length(buffer, offset, flexible)?;
+    offset = new_offset;
+    let mut entries = Vec::new();
+    for _ in 0..entries_count.unwrap_or(0) {
+        let (components_count, new_offset) = read_array_length(buffer, offset, flexible)?;
+        offset = new_offset;
+        let mut components = Vec::new();
+        for _ in 0..components_count.unwrap_or(0) {
+            let (entity_type, new_offset) = read_string(buffer, offset, flexible)?;
+            let (entity_name, new_offset) = read_nullable_string(buffer, new_offset, flexible)?;
+            offset = if flexible { skip_tagged_fields(buffer, new_offset)? } else { new_offset };
+            components.push((entity_type, entity_name));
+        }
+
+        let (ops_count, new_offset) = read_array_length(buffer, offset, flexible)?;
+        offset = new_offset;
+        let mut alterations = Vec::new();
+        for _ in 0..ops_count.unwrap_or(0) {
+            let (key, new_offset) = read_string(buffer, offset, flexible)?;
+            let value = f64::from_bits(read_i64_be(buffer, new_offset)? as u64);
+            let remove = read_i8(buffer, new_offset + 8)? != 0;
+            offset = new_offset + 9;
+            if flexible {
+                offset = skip_tagged_fields(buffer, offset)?;
+            }
+            alterations.push(QuotaAlteration { key, value, remove });
+        }
+        if flexible {
+            offset = skip_tagged_fields(buffer, offset)?;
+        }
+        entries.push(AlterClientQuotasEntry { components, alterations });
+    }
+    let validate_only = read_i8(buffer, offset)? != 0;
+    if flexible {
+        skip_tagged_fields(buffer, offset + 1)?;
+    }
+    Ok(AlterClientQuotasRequest { entries, validate_only })
+}
+
+fn validate_entry(entry: &AlterClientQuotasEntry) -> Result<Vec<QuotaChange>, String> {
+    let entity = QuotaEntity::from_components(&entry.components)?;
+    let mut changes: Vec<QuotaChange> = Vec::new();
+    for alteration in &entry.alterations {
+        let Some((_, whole_number)) = QUOTA_KEYS.iter().find(|(key, _)| *key == alteration.key) else {
+            return Err(format!("Invalid configuration key {}", alteration.key));
+        };
+        if changes.iter().any(|change| change.key == alteration.key) {
+            return Err(format!("Duplicate quota key {}", alteration.key));
+        }
+        if alteration.remove {
+            changes.push(QuotaChange { entity: entity.clone(), key: alteration.key.clone(), value: None });
+            continue;
+        }
+        if !alteration.value.is_finite() || alteration.value <= 0.0 {
+            return Err(format!("Quota {} must be positive, got {}", alteration.key, alteration.value));
+        }
+        if *whole_number && (alteration.value.fract() != 0.0 || alteration.value > i64::MAX as f64) {
+            return Err(format!("Quota {} must be a Long value, got {}", alteration.key, alteration.value));
+        }
+        changes.push(QuotaChange { entity: entity.clone(), key: alteration.key.clone(), value: Some(alteration.value) });
+    }
+    Ok(changes)
+}
+
+// Entries succeed or fail independently of each other; within an entry, one invalid alteration fails
+// all of them. Errors are (error_code, error_message).
+fn alter_client_quotas(request: &AlterClientQuotasRequest) -> Vec<Result<(), (i16, String)>> {
+    let _update_guard = QUOTA_UPDATE_LOCK.lock().unwrap();
+    let mut results = Vec::new();
+    let mut changes = Vec::new();
+    for entry in &request.entries {
+        match validate_entry(entry) {
+            Ok(entry_changes) => {
+                changes.extend(entry_changes);
+                results.push(Ok(()));
+            }
+            Err(message) => results.push(Err((42, message))), // INVALID_REQUEST
+        }
+    }
+    if request.validate_only {
+        return results;
+    }
+
+    // Removing a key the entity doesn't have changes nothing, so it isn't written
+    let quotas = CLIENT_QUOTAS.lock().unwrap();
+    changes.retain(|change| {
+        change.value.is_some() || quotas.get(&change.entity).is_some_and(|values| values.contains_key(&change.key))
+    });
+    drop(quotas);
+
+    let records: Vec<(u32, u32, Vec<u8>)> = changes.iter().map(encode_quota_change).collect();
+    if let Err(e) = metadata_log::append(&records) {
+        println!("error persisting client quotas: {}", e);
+        let error = (-1, format!("Unable to persist client quotas: {}", e)); // UNKNOWN_SERVER_ERROR
+        return results.into_iter().map(|result| result.and(Err(error.clone()))).collect();
+    }
+    for change in changes {
+        apply_quota_change(change);
+    }
+    results
+}
+
+type AlteredEntity<'a> = (&'a [(String, Option<String>)], Result<(), (i16, String)>);
+
+fn create_alter_client_quotas_response(context: &RequestContext, results: &[AlteredEntity]) -> Vec<u8> {
+    let flexible = context.api_version >= 1;
+    let mut response_body = Vec::new();
+
+    // Response header
+    response_body.extend_from_slice(&context.correlation_id.to_be_bytes()); // correlation_id (4 bytes)
+    if flexible {
+        response_body.push(0); // tag buffer (1 byte)
+    }
+
+    // Response body
+    response_body.extend_from_slice(&context.throttle_time_ms.to_be_bytes()); // throttle_time_ms (4 bytes)
+    write_array_length(&mut response_body, results.len(), flexible);
+    for (components, result) in results {
+        let (error_code, error_message) = match result {
+            Ok(()) => (0, None),
+            // Error responses built from just a code carry a null message
+            Err((error_code, error_message)) => (*error_code, Some(error_message.as_str()).filter(|message| !message.is_empty())),
+        };
+        response_body.extend_from_slice(&error_code.to_be_bytes()); // error_code (2 bytes)
+        write_nullable_string(&mut response_body, error_message, flexible);
+        write_array_length(&mut response_body, components.len(), flexible);
+        for (entity_type, entity_name) in components.iter() {
+            write_string(&mut response_body, entity_type, flexible);
+            write_nullable_string(&mut response_body, entity_name.as_deref(), flexible);
+            if flexible {
+                response_body.push(0); // tag buffer (1 byte)
+            }
+        }
+        if flexible {
+            response_body.push(0); // tag buffer (1 byte)
+        }
+    }
+    if flexible {
+        response_body.push(0); // tag buffer (1 byte)
+    }
+
+    frame_response(response_body)
+}
+
+pub fn handle_alter_client_quotas_request(context: &RequestContext, message_buffer: &[u8]) -> Vec<u8> {
+    let request = match parse_alter_client_quotas_request(message_buffer, context.api_version) {
+        Ok(request) => request,
+        Err(e) => {
+            println!("error parsing AlterClientQuotas request: {}", e);
+            return create_alter_client_quotas_error_response(context, message_buffer, 42); // INVALID_REQUEST
+        }
+    };
+    if !authorize(context.connection, AclOperation::AlterConfigs, ResourceType::Cluster, CLUSTER_NAME) {
+        return create_alter_client_quotas_error_response(context, message_buffer, 31); // CLUSTER_AUTHORIZATION_FAILED
+    }
+
+    let results = alter_client_quotas(&request);
+    let results: Vec<AlteredEntity> = request
+        .entries
+        .iter()
+        .zip(results)
+        .map(|(entry, result)| (&entry.components[..], result))
+        .collect();
+    for (components, result) in &results {
+        if let Err((_, message)) = result {
+            println!("AlterClientQuotas rejected for {:?}: {}", components, message);
+        }
+    }
+    create_alter_client_quotas_response(context, &results)
+}
+
+pub fn create_alter_client_quotas_error_response(context: &RequestContext, message_buffer: &[u8], error_code: i16) -> Vec<u8> {
+    let entries = parse_alter_client_quotas_request(message_buffer, context.api_version)
+        .map(|request| request.entries)
+        .unwrap_or_default();
+    let results: Vec<AlteredEntity> = entries
+        .iter()
+        .map(|entry| (&entry.components[..], Err((error_code, String::new()))))
+        .collect();
+    create_alter_client_quotas_response(context, &results)
+}
@@ -0,0 +1,366 @@
+use std::fs;
+use std::path::PathBuf;
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
+
+use crate::config::{broker_config, parse_properties, BrokerConfig, SecurityProtocol};
+use crate::connections::ClientConnection;
+use crate::oauthbearer::{load_jwks, OAuthBearerServer};
+use crate::scram::{ScramMechanism, ScramServer};
+use crate::{
+    frame_response, read_bytes, read_string, skip_request_header, skip_tagged_fields, write_array_length,
+    write_bytes, write_nullable_string, write_string, RequestContext, API_VERSIONS_KEY,
+};
+
+pub const SASL_HANDSHAKE_KEY: i16 = 17;
+pub const SASL_AUTHENTICATE_KEY: i16 = 36;
+
+// Mechanisms sasl.enabled.mechanisms may name
+pub const SUPPORTED_MECHANISMS: &[&str] = &["PLAIN", "SCRAM-SHA-256", "SCRAM-SHA-512", "OAUTHBEARER"];
+
+// A challenge keeps the exchange going; Complete carries the authenticated identity, the final
+// server message and, for credentials that expire, how long the credential remains valid
+pub enum SaslOutcome {
+    Challenge(Vec<u8>),
+    Complete {
+        authorization_id: String,
+        response: Vec<u8>,
+        credential_lifetime: Option<Duration>,
+        // Whether a delegation token, rather than the principal's own credential, was presented
+        token_authenticated: bool,
+    },
+}
+
+// Server side of one SASL mechanism for one authentication exchange. Err carries the message sent
+// back to the client with SASL_AUTHENTICATION_FAILED.
+pub trait SaslServer: Send {
+    fn evaluate_response(&mut self, response: &[u8]) -> Result<SaslOutcome, String>;
+}
+
+pub enum SaslState {
+    // Waiting for SaslHandshake
+    Handshake,
+    Authenticating {
+        mechanism: String,
+        server: Box<dyn SaslServer>,
+        // Set when an authenticated client is re-authenticating (KIP-368)
+        reauthentication: bool,
+    },
+    Authenticated {
+        mechanism: String,
+        // None when connections.max.reauth.ms is 0 and the session never expires
+        session_expires_at: Option<Instant>,
+    },
+}
+
+// Where PLAIN looks up passwords. Another store can be installed with set_credential_store before
+// the broker starts serving; otherwise sasl.plain.credentials.file is used.
+pub trait CredentialStore: Send + Sync {
+    // Err means the store couldn't be consulted, as opposed to a wrong password
+    fn check_password(&self, username: &str, password: &str) -> Result<bool, String>;
+}
+
+// A properties file of username=password entries, read on every lookup so edits apply immediately
+pub struct FileCredentialStore {
+    path: PathBuf,
+}
+
+impl FileCredentialStore {
+    pub fn new(path: &str) -> Result<FileCredentialStore, String> {
+        fs::metadata(path).map_err(|e| format!("{}: {}", path, e))?;
+        Ok(FileCredentialStore { path: PathBuf::from(path) })
+    }
+}
+
+impl CredentialStore for FileCredentialStore {
+    fn check_password(&self, username: &str, password: &str) -> Result<bool, String> {
+        let contents = fs::read_to_string(&self.path).map_err(|e| format!("{}: {}", self.path.display(), e))?;
+        let stored_password = parse_properties(&contents)
+            .into_iter()
+            .filter_map(|(_, entry)| entry)
+            .find(|(stored_username, _)| stored_username == username)
+            .map(|(_, stored_password)| stored_password);
+        Ok(stored_password.is_some_and(|stored_password| constant_time_eq(stored_password.as_bytes(), password.as_bytes())))
+    }
+}
+
+// Compares without an early exit, so response time doesn't reveal how much of a password matched
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    let mut difference = a.len() ^ b.len();
+    for (index, byte) in a.iter().enumerate() {
+        difference |= (*byte ^ b.get(index).copied().unwrap_or(0)) as usize;
+    }
+    difference == 0
+}
+
+static CREDENTIAL_STORE: OnceLock<Box<dyn CredentialStore>> = OnceLock::new();
+
+pub fn set_credential_store(store: Box<dyn CredentialStore>) {
+    if CREDENTIAL_STORE.set(store).is_err() {
+        println!("SASL credential store was already initialized");
+    }
+}
+
+// Checks the JWKS file of OAUTHBEARER loads and sets up the file-backed credential store of PLAIN
+// unless another one was installed; called once at startup
+pub fn init_sasl(config: &BrokerConfig) -> Result<(), String> {
+    let enabled = |name: &str| config.sasl_enabled_mechanisms.iter().any(|mechanism| mechanism == name);
+    if enabled("OAUTHBEARER") {
+        let path = config.sasl_oauthbearer_jwks_file.as_deref().ok_or("OAUTHBEARER needs sasl.oauthbearer.jwks.endpoint.url")?;
+        load_jwks(path)?;
+    }
+    if CREDENTIAL_STORE.get().is_some() || !enabled("PLAIN") {
+        return Ok(());
+    }
+    let path = config.sasl_plain_credentials_file.as_deref().ok_or("PLAIN needs sasl.plain.credentials.file")?;
+    set_credential_store(Box::new(FileCredentialStore::new(path)?));
+    Ok(())
+}
+
+// RFC 4616: [authzid] NUL authcid NUL passwd
+struct PlainServer;
+
+impl SaslServer for PlainServer {
+    fn evaluate_response(&mut self, response: &[u8]) -> Result<SaslOutcome, String> {
+        let tokens: Vec<&[u8]> = response.split(|byte| *byte == 0).collect();
+        if tokens.len() != 3 {
+            return Err(format!("Invalid SASL/PLAIN response: expected 3 tokens, got {}", tokens.len()));
+        }
+        let token = |index: usize| String::from_utf8(tokens[index].to_vec()).map_err(|_| "Invalid SASL/PLAIN response: not UTF-8".to_string());
+        let (authorization_id, username, password) = (token(0)?, token(1)?, token(2)?);
+        if username.is_empty() {
+            return Err("Authentication failed: username not specified".to_string());
+        }
+        if password.is_empty() {
+            return Err("Authentication failed: password not specified".to_string());
+        }
+        if !authorization_id.is_empty() && authorization_id != username {
+            return Err("Authentication failed: Client requested an authorization id that is different from username".to_string());
+        }
+
+        let store = CREDENTIAL_STORE.get().ok_or("Authentication failed: no credential store")?;
+        match store.check_password(&username, &password) {
+            Ok(true) => Ok(SaslOutcome::Complete { authorization_id: username, response: Vec::new(), credential_lifetime: None, token_authenticated: false }),
+            Ok(false) => Err("Authentication failed: Invalid username or password".to_string()),
+            Err(e) => {
+                println!("error reading SASL credentials: {}", e);
+                Err("Authentication failed: credentials are unavailable".to_string())
+            }
+        }
+    }
+}
+
+fn create_sasl_server(mechanism: &str) -> Option<Box<dyn SaslServer>> {
+    match mechanism {
+        "PLAIN" => Some(Box::new(PlainServer)),
+        "OAUTHBEARER" => Some(Box::new(OAuthBearerServer::new())),
+        _ => ScramMechanism::from_name(mechanism).map(|mechanism| Box::new(ScramServer::new(mechanism)) as Box<dyn SaslServer>),
+    }
+}
+
+fn is_sasl_listener(connection: &ClientConnection) -> bool {
+    matches!(broker_config().security_protocol(&connection.listener),
+             Some(SecurityProtocol::SaslPlaintext | SecurityProtocol::SaslSsl))
+}
+
+// Gate for every request on SASL listeners: until authentication completes, and once a session has
+// expired without re-authentication, only ApiVersions and the SASL requests themselves are accepted
+pub fn check_request_allowed(connection: &ClientConnection, api_key: i16) -> Result<(), String> {
+    if !is_sasl_listener(connection) || [API_VERSIONS_KEY, SASL_HANDSHAKE_KEY, SASL_AUTHENTICATE_KEY].contains(&api_key) {
+        return Ok(());
+    }
+    match &*connection.sasl_state.lock().unwrap() {
+        SaslState::Authenticated { session_expires_at: Some(expires_at), .. } if Instant::now() >= *expires_at => {
+            Err(format!("api_key {} received after the SASL session expired", api_key))
+        }
+        SaslState::Authenticated { .. } => Ok(()),
+        _ => Err(format!("api_key {} received before SASL authentication completed", api_key)),
+    }
+}
+
+fn parse_sasl_handshake_request(buffer: &[u8]) -> Result<String, &'static str> {
+    let offset = skip_request_header(buffer, false)?;
+    let (mechanism, _) = read_string(buffer, offset, false)?;
+    Ok(mechanism)
+}
+
+fn create_sasl_handshake_response(correlation_id: i32, error_code: i16) -> Vec<u8> {
+    let mut response_body = Vec::new();
+
+    // Response header
+    response_body.extend_from_slice(&correlation_id.to_be_bytes()); // correlation_id (4 bytes)
+
+    // Response body
+    response_body.extend_from_slice(&error_code.to_be_bytes()); // error_code (2 bytes)
+    let mechanisms = &broker_config().sasl_enabled_mechanisms;
+    write_array_length(&mut response_body, mechanisms.len(), false);
+    for mechanism in mechanisms {
+        write_string(&mut response_body, mechanism, false);
+    }
+
+    frame_response(response_body)
+}
+
+pub fn handle_sasl_handshake_request(context: &RequestContext, message_buffer: &[u8]) -> Vec<u8> {
+    let connection = context.connection;
+    let mechanism = match parse_sasl_handshake_request(message_buffer) {
+        Ok(mechanism) => mechanism,
+        Err(e) => {
+            println!("error parsing SaslHandshake request: {}", e);
+            return create_sasl_handshake_error_response(context, message_buffer, 42); // INVALID_REQUEST
+        }
+    };
+    if !is_sasl_listener(connection) {
+        return create_sasl_handshake_response(context.correlation_id, 34); // ILLEGAL_SASL_STATE
+    }
+
+    let mut state = connection.sasl_state.lock().unwrap();
+    let reauthentication = match &*state {
+        SaslState::Handshake => false,
+        // A handshake on an authenticated connection starts re-authentication, which only sessions
+        // with a lifetime take part in and which has to keep the mechanism
+        SaslState::Authenticated { mechanism: current, session_expires_at: Some(_) } => {
+            if *current != mechanism {
+                println!("closing connection {}: re-authentication changed SASL mechanism from {} to {}",
+                         connection.id, current, mechanism);
+                connection.request_close();
+                return create_sasl_handshake_response(context.correlation_id, 33); // UNSUPPORTED_SASL_MECHANISM
+            }
+            true
+        }
+        SaslState::Authenticated { session_expires_at: None, .. } => {
+            return create_sasl_handshake_response(context.correlation_id, 34); // ILLEGAL_SASL_STATE
+        }
+        SaslState::Authenticating { .. } => {
+            println!("closing connection {}: SaslHandshake received during SASL authentication", connection.id);
+            connection.request_close();
+            return create_sasl_handshake_response(context.correlation_id, 34); // ILLEGAL_SASL_STATE
+        }
+    };
+
+    let enabled = broker_config().sasl_enabled_mechanisms.contains(&mechanism);
+    match create_sasl_server(&mechanism).filter(|_| enabled) {
+        Some(server) => {
+            *state = SaslState::Authenticating { mechanism, server, reauthentication };
+            create_sasl_handshake_response(context.correlation_id, 0)
+        }
+        None => {
+            println!("closing connection {}: unsupported SASL mechanism {}", connection.id, mechanism);
+            connection.request_close();
+            create_sasl_handshake_response(context.correlation_id, 33) // UNSUPPORTED_SASL_MECHANISM
+        }
+    }
+}
+
+pub fn create_sasl_handshake_error_response(context: &RequestContext, _message_buffer: &[u8], error_code: i16) -> Vec<u8> {
+    create_sasl_handshake_response(context.correlation_id, error_code)
+}
+
+fn parse_sasl_authenticate_request(buffer: &[u8], api_version: i16) -> Result<Vec<u8>, &'static str> {
+    // v2+ is flexible
+    let flexible = api_version >= 2;
+    let offset = skip_request_header(buffer, flexible)?;
+    let (auth_bytes, offset) = read_bytes(buffer, offset, flexible)?;
+    if flexible {
+        skip_tagged_fields(buffer, offset)?;
+    }
+    Ok(auth_bytes)
+}
+
+fn create_sasl_authenticate_response(context: &RequestContext, error_code: i16, error_message: Option<&str>,
+                                     auth_bytes: &[u8], session_lifetime_ms: i64) -> Vec<u8> {
+    let api_version = context.api_version;
+    let flexible = api_version >= 2;
+    let mut response_body = Vec::new();
+
+    // Response header
+    response_body.extend_from_slice(&context.correlation_id.to_be_bytes()); // correlation_id (4 bytes)
+    if flexible {
+        response_body.push(0); // tag buffer (1 byte)
+    }
+
+    // Response body
+    response_body.extend_from_slice(&error_code.to_be_bytes()); // error_code (2 bytes)
+    write_nullable_string(&mut response_body, error_message, flexible);
+    write_bytes(&mut response_body, auth_bytes, flexible);
+    if api_version >= 1 {
+        response_body.extend_from_slice(&session_lifetime_ms.to_be_bytes()); // session_lifetime_ms (8 bytes)
+    }
+    if flexible {
+        response_body.push(0); // tag buffer (1 byte)
+    }
+
+    frame_response(response_body)
+}
+
+pub fn handle_sasl_authenticate_request(context: &RequestContext, message_buffer: &[u8]) -> Vec<u8> {
+    let connection = context.connection;
+    let auth_bytes = match parse_sasl_authenticate_request(message_buffer, context.api_version) {
+        Ok(auth_bytes) => auth_bytes,
+        Err(e) => {
+            println!("error parsing SaslAuthenticate request: {}", e);
+            return create_sasl_authenticate_error_response(context, message_buffer, 42); // INVALID_REQUEST
+        }
+    };
+    if !is_sasl_listener(connection) {
+        return create_sasl_authenticate_response(context, 34, Some("SaslAuthenticate request received on a listener without SASL"), &[], 0); // ILLEGAL_SASL_STATE
+    }
+
+    let mut state = connection.sasl_state.lock().unwrap();
+    let (mechanism, server, reauthentication) = match &mut *state {
+        SaslState::Authenticating { mechanism, server, reauthentication } => (mechanism.clone(), server, *reauthentication),
+        SaslState::Authenticated { .. } => {
+            return create_sasl_authenticate_response(context, 34, Some("SaslAuthenticate request received after successful authentication"), &[], 0); // ILLEGAL_SASL_STATE
+        }
+        SaslState::Handshake => {
+            println!("closing connection {}: SaslAuthenticate received before SaslHandshake", connection.id);
+            connection.request_close();
+            return create_sasl_authenticate_response(context, 34, Some("Unexpected SaslAuthenticate request before SaslHandshake"), &[], 0); // ILLEGAL_SASL_STATE
+        }
+    };
+
+    let (authorization_id, response, credential_lifetime, token_authenticated) = match server.evaluate_response(&auth_bytes) {
+        Ok(SaslOutcome::Complete { authorization_id, response, credential_lifetime, token_authenticated }) => {
+            (authorization_id, response, credential_lifetime, token_authenticated)
+        }
+        Ok(SaslOutcome::Challenge(challenge)) => {
+            return create_sasl_authenticate_response(context, 0, None, &challenge, 0);
+        }
+        Err(message) => {
+            println!("closing connection {}: SASL/{} authentication failed: {}", connection.id, mechanism, message);
+            connection.request_close();
+            return create_sasl_authenticate_response(context, 58, Some(&message), &[], 0); // SASL_AUTHENTICATION_FAILED
+        }
+    };
+
+    let principal = connection.principal();
+    if reauthentication && authorization_id != principal {
+        println!("closing connection {}: re-authentication as User:{} on a connection of User:{}",
+                 connection.id, authorization_id, principal);
+        connection.request_close();
+        let message = format!("Cannot change principals during re-authentication from User:{} to User:{}", principal, authorization_id);
+        return create_sasl_authenticate_response(context, 58, Some(&message), &[], 0); // SASL_AUTHENTICATION_FAILED
+    }
+
+    // connections.max.reauth.ms bounds the session, and so does the credential's expiry when
+    // re-authentication is on; the client has to re-authenticate before the session ends
+    let max_reauth_ms = broker_config().connections_max_reauth_ms;
+    let session_lifetime_ms = match credential_lifetime {
+        Some(lifetime) if max_reauth_ms > 0 => max_reauth_ms.min(lifetime.as_millis() as i64).max(1),
+        _ => max_reauth_ms,
+    };
+    let session_expires_at = (session_lifetime_ms > 0).then(|| Instant::now() + Duration::from_millis(session_lifetime_ms as u64));
+    *state = SaslState::Authenticated { mechanism: mechanism.clone(), session_expires_at };
+    drop(state);
+
+    connection.set_principal(authorization_id);
+    connection.set_token_authenticated(token_authenticated);
+    println!("connection {} {} as User:{} via SASL/{}{}", connection.id,
+             if reauthentication { "re-authenticated" } else { "authenticated" }, connection.principal(), mechanism,
+             if token_authenticated { " with a delegation token" } else { "" });
+    create_sasl_authenticate_response(context, 0, None, &response, session_lifetime_ms)
+}
+
+pub fn create_sasl_authenticate_error_response(context: &RequestContext, _message_buffer: &[u8], error_code: i16) -> Vec<u8> {
+    create_sasl_authenticate_response(context, error_code, None, &[], 0)
+}
@@ -0,0 +1,676 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::num::NonZeroU32;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use ring::rand::{SecureRandom, SystemRandom};
+use ring::{digest, hmac, pbkdf2};
+
+use crate::authorizer::{authorize, AclOperation, ResourceType, CLUSTER_NAME};
+use crate::base64;
+use crate::delegation_token::{now_ms, token_for_authentication};
+use crate::features::{finalized_features, METADATA_VERSION};
+use crate::metadata_log::{self, MetadataRecord};
+use crate::sasl::{constant_time_eq, SaslOutcome, SaslServer};
+use crate::{
+    frame_response, read_array_length, read_bytes, read_compact_string, read_i32_be, read_i8, skip_request_header,
+    skip_tagged_fields, write_bytes, write_compact_nullable_string, write_compact_string, write_unsigned_varint,
+    RequestContext,
+};
+
+const USER_SCRAM_CREDENTIAL_RECORD: u32 = 11;
+const REMOVE_USER_SCRAM_CREDENTIAL_RECORD: u32 = 22;
+
+// metadata.version 3.5-IV2 introduced SCRAM credential records
+const SCRAM_METADATA_VERSION: i16 = 11;
+
+// Iteration bounds Kafka enforces for SCRAM credentials
+const MIN_ITERATIONS: i32 = 4096;
+const MAX_ITERATIONS: i32 = 16384;
+
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ScramMechanism {
+    Sha256,
+    Sha512,
+}
+
+impl ScramMechanism {
+    pub fn from_name(name: &str) -> Option<ScramMechanism> {
+        match name {
+            "SCRAM-SHA-256" => Some(ScramMechanism::Sha256),
+            "SCRAM-SHA-512" => Some(ScramMechanism::Sha512),
+            _ => None,
+        }
+    }
+
+    // The mechanism's int8 in the admin APIs and metadata records; 0 is UNKNOWN
+    fn from_type(mechanism_type: i8) -> Option<ScramMechanism> {
+        match mechanism_type {
+            1 => Some(ScramMechanism::Sha256),
+            2 => Some(ScramMechanism::Sha512),
+            _ => None,
+        }
+    }
+
+    fn mechanism_type(self) -> i8 {
+        match self {
+            ScramMechanism::Sha256 => 1,
+            ScramMechanism::Sha512 => 2,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ScramMechanism::Sha256 => "SCRAM-SHA-256",
+            ScramMechanism::Sha512 => "SCRAM-SHA-512",
+        }
+    }
+
+    fn hmac(self, key: &[u8], data: &[u8]) -> Vec<u8> {
+        let algorithm = match self {
+            ScramMechanism::Sha256 => hmac::HMAC_SHA256,
+            ScramMechanism::Sha512 => hmac::HMAC_SHA512,
+        };
+        hmac::sign(&hmac::Key::new(algorithm, key), data).as_ref().to_vec()
+    }
+
+    // Length of the hash output, and so of a salted password
+    fn digest_length(self) -> usize {
+        match self {
+            ScramMechanism::Sha256 => 32,
+            ScramMechanism::Sha512 => 64,
+        }
+    }
+
+    fn hash(self, data: &[u8]) -> Vec<u8> {
+        let algorithm = match self {
+            ScramMechanism::Sha256 => &digest::SHA256,
+            ScramMechanism::Sha512 => &digest::SHA512,
+        };
+        digest::digest(algorithm, data).as_ref().to_vec()
+    }
+}
+
+// What the broker keeps per user and mechanism (RFC 5802 section 3); the password itself is never stored
+#[derive(Clone)]
+pub struct ScramCredential {
+    pub salt: Vec<u8>,
+    pub stored_key: Vec<u8>,
+    pub server_key: Vec<u8>,
+    pub iterations: i32,
+}
+
+impl ScramCredential {
+    // A credential for a password with a fresh random salt, as a client derives it (RFC 5802 Hi())
+    fn from_password(mechanism: ScramMechanism, password: &[u8], iterations: i32) -> Result<ScramCredential, String> {
+        let mut salt = [0u8; 32];
+        SystemRandom::new().fill(&mut salt).map_err(|_| "Authentication failed: unable to generate salt")?;
+        let algorithm = match mechanism {
+            ScramMechanism::Sha256 => pbkdf2::PBKDF2_HMAC_SHA256,
+            ScramMechanism::Sha512 => pbkdf2::PBKDF2_HMAC_SHA512,
+        };
+        let mut salted_password = vec![0u8; mechanism.digest_length()];
+        let rounds = NonZeroU32::new(iterations as u32).ok_or("SCRAM iterations must be positive")?;
+        pbkdf2::derive(algorithm, rounds, &salt, password, &mut salted_password);
+        Ok(ScramCredential::from_salted_password(mechanism, salt.to_vec(), &salted_password, iterations))
+    }
+
+    fn from_salted_password(mechanism: ScramMechanism, salt: Vec<u8>, salted_password: &[u8], iterations: i32) -> ScramCredential {
+        let client_key = mechanism.hmac(salted_password, b"Client Key");
+        ScramCredential {
+            salt,
+            stored_key: mechanism.hash(&client_key),
+            server_key: mechanism.hmac(salted_password, b"Server Key"),
+            iterations,
+        }
+    }
+}
+
+static SCRAM_CREDENTIALS: Mutex<BTreeMap<(String, ScramMechanism), ScramCredential>> = Mutex::new(BTreeMap::new());
+
+static SCRAM_UPDATE_LOCK: Mutex<()> = Mutex::new(());
+
+pub fn scram_credential(user: &str, mechanism: ScramMechanism) -> Option<ScramCredential> {
+    SCRAM_CREDENTIALS.lock().unwrap().get(&(user.to_string(), mechanism)).cloned()
+}
+
+enum CredentialChange {
+    Upsert(String, ScramMechanism, ScramCredential),
+    Remove(String, ScramMechanism),
+}
+
+// Applies a UserScramCredentialRecord or RemoveUserScramCredentialRecord read back from the metadata log
+pub fn replay(record: &MetadataRecord) {
+    let change = match record.record_type {
+        USER_SCRAM_CREDENTIAL_RECORD => decode_user_scram_credential_record(&record.data),
+        REMOVE_USER_SCRAM_CREDENTIAL_RECORD => decode_remove_user_scram_credential_record(&record.data),
+        _ => return,
+    };
+    match change {
+        Ok(change) => apply_credential_change(change),
+        Err(e) => println!("error decoding SCRAM credential record at offset {}: {}", record.offset, e),
+    }
+}
+
+fn apply_credential_change(change: CredentialChange) {
+    let mut credentials = SCRAM_CREDENTIALS.lock().unwrap();
+    match change {
+        CredentialChange::Upsert(user, mechanism, credential) => {
+            println!("updated {} credential for user {}", mechanism.name(), user);
+            credentials.insert((user, mechanism), credential);
+        }
+        CredentialChange::Remove(user, mechanism) => {
+            println!("removed {} credential for user {}", mechanism.name(), user);
+            credentials.remove(&(user, mechanism));
+        }
+    }
+}
+
+fn encode_credential_change(change: &CredentialChange) -> (u32, u32, Vec<u8>) {
+    let mut data = Vec::new();
+    match change {
+        CredentialChange::Upsert(user, mechanism, credential) => {
+            write_compact_string(&mut data, user);                            // name
+            data.push(mechanism.mechanism_type() as u8);                      // mechanism (1 byte)
+            write_bytes(&mut data, &credential.salt, true);               // salt
+            write_bytes(&mut data, &credential.stored_key, true);         // stored_key
+            write_bytes(&mut data, &credential.server_key, true);         // server_key
+            data.extend_from_slice(&credential.iterations.to_be_bytes());     // iterations (4 bytes)
+            data.push(0);                                                     // tag buffer (1 byte)
+            (USER_SCRAM_CREDENTIAL_RECORD, 0, data)
+        }
+        CredentialChange::Remove(user, mechanism) => {
+            write_compact_string(&mut data, user);                            // name
+            data.push(mechanism.mechanism_type() as u8);                      // mechanism (1 byte)
+            data.push(0);                                                     // tag buffer (1 byte)
+            (REMOVE_USER_SCRAM_CREDENTIAL_RECORD, 0, data)
+        }
+    }
+}
+
+fn read_record_mechanism(data: &[u8], offset: usize) -> Result<ScramMechanism, &'static str> {
+    ScramMechanism::from_type(read_i8(data, offset)?).ok_or("unknown SCRAM mechanism")
+}
+
+fn decode_user_scram_credential_record(data: &[u8]) -> Result<CredentialChange, &'static str> {
+    let (user, offset) = read_compact_string(data, 0)?;
+    let mechanism = read_record_mechanism(data, offset)?;
+    let (salt, offset) = read_bytes(data, offset + 1, true)?;
+    let (stored_key, offset) = read_bytes(data, offset, true)?;
+    let (server_key, offset) = read_bytes(data, offset, true)?;
+    let iterations = read_i32_be(data, offset)?;
+    Ok(CredentialChange::Upsert(user, mechanism, ScramCredential { salt, stored_key, server_key, iterations }))
+}
+
+fn decode_remove_user_scram_credential_record(data: &[u8]) -> Result<CredentialChange, &'static str> {
+    let (user, offset) = read_compact_string(data, 0)?;
+    let mechanism = read_record_mechanism(data, offset)?;
+    Ok(CredentialChange::Remove(user, mechanism))
+}
+
+// RFC 5802 saslname: ',' and '=' arrive as "=2C" and "=3D"
+fn decode_saslname(value: &str) -> Result<String, String> {
+    let mut decoded = String::new();
+    let mut rest = value;
+    while let Some(index) = rest.find('=') {
+        decoded.push_str(&rest[..index]);
+        match rest.get(index..index + 3) {
+            Some("=2C") => decoded.push(','),
+            Some("=3D") => decoded.push('='),
+            _ => return Err(format!("Invalid SCRAM username: {}", value)),
+        }
+        rest = &rest[index + 3..];
+    }
+    decoded.push_str(rest);
+    Ok(decoded)
+}
+
+// Splits a SCRAM message into its attribute=value pairs. RFC 5802 attributes have one-letter names;
+// Kafka's extensions, such as tokenauth, are longer.
+fn parse_attributes(message: &str) -> Result<Vec<(&str, &str)>, String> {
+    message
+        .split(',')
+        .map(|attribute| match attribute.split_once('=') {
+            Some((name, value)) if !name.is_empty() => Ok((name, value)),
+            _ => Err(format!("Invalid SCRAM attribute: {}", attribute)),
+        })
+        .collect()
+}
+
+enum ScramExchange {
+    ClientFirst,
+    ClientFinal {
+        authorization_id: String,
+        // Expiry of the delegation token being authenticated with, in ms since the epoch
+        token_expiry_timestamp: Option<i64>,
+        credential: Box<ScramCredential>,
+        gs2_header: String,
+        nonce: String,
+        client_first_bare: String,
+        server_first: String,
+    },
+    Finished,
+}
+
+// RFC 5802: client-first -> server-first challenge, then client-final -> server-final
+pub struct ScramServer {
+    mechanism: ScramMechanism,
+    exchange: ScramExchange,
+}
+
+impl ScramServer {
+    pub fn new(mechanism: ScramMechanism) -> ScramServer {
+        ScramServer { mechanism, exchange: ScramExchange::ClientFirst }
+    }
+
+    fn receive_client_first(&mut self, message: &str) -> Result<SaslOutcome, String> {
+        // gs2-header: channel binding flag, then an optional authorization id
+        let mut parts = message.splitn(3, ',');
+        let (cbind_flag, authzid, client_first_bare) = match (parts.next(), parts.next(), parts.next()) {
+            (Some(cbind_flag), Some(authzid), Some(client_first_bare)) => (cbind_flag, authzid, client_first_bare),
+            _ => return Err("Invalid SCRAM client first message".to_string()),
+        };
+        if cbind_flag != "n" && cbind_flag != "y" {
+            return Err("Authentication failed: channel binding is not supported".to_string());
+        }
+        let authorization_id = match authzid {
+            "" => None,
+            _ => Some(decode_saslname(authzid.strip_prefix("a=").ok_or("Invalid SCRAM client first message")?)?),
+        };
+
+        let attributes = parse_attributes(client_first_bare)?;
+        let (username, client_nonce) = match attributes.as_slice() {
+            [("n", username), ("r", client_nonce), ..] if !client_nonce.is_empty() => (decode_saslname(username)?, *client_nonce),
+            _ => return Err("Invalid SCRAM client first message".to_string()),
+        };
+        let extensions = &attributes[2..];
+        if let Some((name, _)) = extensions.iter().find(|(name, _)| *name == "m") {
+            return Err(format!("Authentication failed: unsupported SCRAM extension {}", name));
+        }
+        if authorization_id.is_some_and(|authorization_id| authorization_id != username) {
+            return Err("Authentication failed: Client requested an authorization id that is different from username".to_string());
+        }
+
+        // With tokenauth=true the username is a delegation token ID and the password is its HMAC;
+        // the client is authenticated as the token's owner
+        let token_auth = extensions.iter().any(|(name, value)| *name == "tokenauth" && value.eq_ignore_ascii_case("true"));
+        let (authorization_id, credential, token_expiry_timestamp) = if token_auth {
+            let token = token_for_authentication(&username).ok_or("Authentication failed: Invalid user credentials")?;
+            let password = base64::encode(&token.hmac);
+            let credential = ScramCredential::from_password(self.mechanism, password.as_bytes(), MIN_ITERATIONS)?;
+            (token.owner, credential, Some(token.expiry_timestamp))
+        } else {
+            let credential = scram_credential(&username, self.mechanism).ok_or("Authentication failed: Invalid user credentials")?;
+            (username, credential, None)
+        };
+
+        let mut random = [0u8; 24];
+        SystemRandom::new().fill(&mut random).map_err(|_| "Authentication failed: unable to generate nonce")?;
+        let nonce = format!("{}{}", client_nonce, base64::encode(&random));
+        let server_first = format!("r={},s={},i={}", nonce, base64::encode(&credential.salt), credential.iterations);
+
+        self.exchange = ScramExchange::ClientFinal {
+            authorization_id,
+            token_expiry_timestamp,
+            credential: Box::new(credential),
+            gs2_header: format!("{},{},", cbind_flag, authzid),
+            nonce,
+            client_first_bare: client_first_bare.to_string(),
+            server_first: server_first.clone(),
+        };
+        Ok(SaslOutcome::Challenge(server_first.into_bytes()))
+    }
+}
+
+impl SaslServer for ScramServer {
+    fn evaluate_response(&mut self, response: &[u8]) -> Result<SaslOutcome, String> {
+        let message = std::str::from_utf8(response).map_err(|_| "Invalid SCRAM message: not UTF-8".to_string())?;
+        match std::mem::replace(&mut self.exchange, ScramExchange::Finished) {
+            ScramExchange::ClientFirst => self.receive_client_first(message),
+            ScramExchange::ClientFinal {
+                authorization_id, token_expiry_timestamp, credential, gs2_header, nonce, client_first_bare, server_first,
+            } => {
+                let (without_proof, proof) = message.rsplit_once(",p=").ok_or("Invalid SCRAM client final message")?;
+                let attributes = parse_attributes(without_proof)?;
+                let (channel_binding, final_nonce) = match attributes.as_slice() {
+                    [("c", channel_binding), ("r", final_nonce), ..] => (*channel_binding, *final_nonce),
+                    _ => return Err("Invalid SCRAM client final message".to_string()),
+                };
+                if channel_binding != base64::encode(gs2_header.as_bytes()) {
+                    return Err("Authentication failed: Invalid channel binding".to_string());
+                }
+                if final_nonce != nonce {
+                    return Err("Authentication failed: Invalid server nonce".to_string());
+                }
+
+                // ClientKey = ClientProof XOR HMAC(StoredKey, AuthMessage), which must hash to StoredKey
+                let auth_message = format!("{},{},{}", client_first_bare, server_first, without_proof);
+                let proof = base64::decode(proof).map_err(|e| format!("Invalid SCRAM client proof: {}", e))?;
+                let client_signature = self.mechanism.hmac(&credential.stored_key, auth_message.as_bytes());
+                if proof.len() != client_signature.len() {
+                    return Err("Authentication failed: Invalid client credentials".to_string());
+                }
+                let client_key: Vec<u8> = proof.iter().zip(&client_signature).map(|(a, b)| a ^ b).collect();
+                if !constant_time_eq(&self.mechanism.hash(&client_key), &credential.stored_key) {
+                    return Err("Authentication failed: Invalid client credentials".to_string());
+                }
+
+                let server_signature = self.mechanism.hmac(&credential.server_key, auth_message.as_bytes());
+                let server_final = format!("v={}", base64::encode(&server_signature));
+                let credential_lifetime = token_expiry_timestamp
+                    .map(|expiry_timestamp| Duration::from_millis(expiry_timestamp.saturating_sub(now_ms()).max(0) as u64));
+                Ok(SaslOutcome::Complete {
+                    authorization_id,
+                    response: server_final.into_bytes(),
+                    credential_lifetime,
+                    token_authenticated: token_expiry_timestamp.is_some(),
+                })
+            }
+            ScramExchange::Finished => Err("Unexpected SCRAM message after authentication completed".to_string()),
+        }
+    }
+}
+
+enum ScramCredentialAlteration {
+    Deletion { mechanism_type: i8 },
+    Upsertion { mechanism_type: i8, iterations: i32, salt: Vec<u8>, salted_password: Vec<u8> },
+}
+
+struct AlterUserScramCredentialsRequest {
+    // (user, alteration), deletions first, in request order
+    alterations: Vec<(String, ScramCredentialAlteration)>,
+}
+
+fn parse_alter_user_scram_credentials_request(buffer: &[u8]) -> Result<AlterUserScramCredentialsRequest, &'static str> {
+    let mut offset = skip_request_header(buffer, true)?;
+    let mut alterations = Vec::new();
+
+    let (deletions_count, new_offset) = read_array_length(buffer, offset, true)?;
+    offset = new_offset;
+    for _ in 0..deletions_count.unwrap_or(0) {
+        let (user, new_offset) = read_compact_string(buffer, offset)?;
+        let mechanism_type = read_i8(buffer, new_offset)?;
+        offset = skip_tagged_fields(buffer, new_offset + 1)?;
+        alterations.push((user, ScramCredentialAlteration::Deletion { mechanism_type }));
+    }
+
+    let (upsertions_count, new_offset) = read_array_length(buffer, offset, true)?;
+    offset = new_offset;
+    for _ in 0..upsertions_count.unwrap_or(0) {
+        let (user, new_offset) = read_compact_string(buffer, offset)?;
+        let mechanism_type = read_i8(buffer, new_offset)?;
+        let iterations = read_i32_be(buffer, new_offset + 1)?;
+        let (salt, new_offset) = read_bytes(buffer, new_offset + 5, true)?;
+        let (salted_password, new_offset) = read_bytes(buffer, new_offset, true)?;
+        offset = skip_tagged_fields(buffer, new_offset)?;
+        alterations.push((user, ScramCredentialAlteration::Upsertion { mechanism_type, iterations, salt, salted_password }));
+    }
+    skip_tagged_fields(buffer, offset)?;
+
+    Ok(AlterUserScramCredentialsRequest { alterations })
+}
+
+fn validate_alteration(user: &str, alteration: ScramCredentialAlteration) -> Result<CredentialChange, (i16, String)> {
+    if user.is_empty() {
+        return Err((93, "Username must not be empty".to_string())); // UNACCEPTABLE_CREDENTIAL
+    }
+    match alteration {
+        ScramCredentialAlteration::Deletion { mechanism_type } => {
+            let mechanism = ScramMechanism::from_type(mechanism_type)
+                .ok_or((33, "Unknown SCRAM mechanism".to_string()))?; // UNSUPPORTED_SASL_MECHANISM
+            if scram_credential(user, mechanism).is_none() {
+                return Err((91, "Attempt to delete a user credential that does not exist".to_string())); // RESOURCE_NOT_FOUND
+            }
+            Ok(CredentialChange::Remove(user.to_string(), mechanism))
+        }
+        ScramCredentialAlteration::Upsertion { mechanism_type, iterations, salt, salted_password } => {
+            let mechanism = ScramMechanism::from_type(mechanism_type)
+                .ok_or((33, "Unknown SCRAM mechanism".to_string()))?; // UNSUPPORTED_SASL_MECHANISM
+            if iterations < MIN_ITERATIONS {
+                return Err((93, "Too few iterations".to_string())); // UNACCEPTABLE_CREDENTIAL
+            }
+            if iterations > MAX_ITERATIONS {
+                return Err((93, "Too many iterations".to_string())); // UNACCEPTABLE_CREDENTIAL
+            }
+            if salt.is_empty() {
+                return Err((93, "Salt must not be empty".to_string())); // UNACCEPTABLE_CREDENTIAL
+            }
+            if salted_password.is_empty() {
+                return Err((93, "Salted password must not be empty".to_string())); // UNACCEPTABLE_CREDENTIAL
+            }
+            if salted_password.len() != mechanism.digest_length() {
+                return Err((93, format!("Salted password must be {} bytes for {}",
+                                        mechanism.digest_length(), mechanism.name()))); // UNACCEPTABLE_CREDENTIAL
+            }
+            let credential = ScramCredential::from_salted_password(mechanism, salt, &salted_password, iterations);
+            Ok(CredentialChange::Upsert(user.to_string(), mechanism, credential))
+        }
+    }
+}
+
+// Per user: success, or (error_code, error_message)
+type AlteredUser = (String, Result<(), (i16, String)>);
+
+// Each user gets one result. Like Kafka, a user may appear in only one alteration per request, and
+// users succeed or fail independently of each other. Errors are (error_code, error_message).
+fn alter_user_scram_credentials(request: AlterUserScramCredentialsRequest) -> Vec<AlteredUser> {
+    // The alteration is taken out once it has been validated
+    let mut results: Vec<(String, Result<Option<ScramCredentialAlteration>, _>)> = Vec::new();
+    let mut result_index: HashMap<String, usize> = HashMap::new();
+    for (user, alteration) in request.alterations {
+        match result_index.get(&user) {
+            Some(index) => {
+                results[*index].1 = Err((92, "A user credential cannot be altered twice in the same request".to_string())); // DUPLICATE_RESOURCE
+            }
+            None => {
+                result_index.insert(user.clone(), results.len());
+                results.push((user, Ok(Some(alteration))));
+            }
+        }
+    }
+
+    let metadata_version = finalized_features().levels.get(METADATA_VERSION).copied().unwrap_or(0);
+    if metadata_version < SCRAM_METADATA_VERSION {
+        let error = (35, "The current metadata.version does not support SCRAM".to_string()); // UNSUPPORTED_VERSION
+        return results.into_iter().map(|(user, _)| (user, Err(error.clone()))).collect();
+    }
+
+    // Validation against the current credentials and the append must not interleave with another request
+    let _update_guard = SCRAM_UPDATE_LOCK.lock().unwrap();
+    let mut changes = Vec::new();
+    for (user, result) in &mut results {
+        if let Ok(alteration) = result {
+            match validate_alteration(user, alteration.take().unwrap()) {
+                Ok(change) => changes.push(change),
+                Err(error) => *result = Err(error),
+            }
+        }
+    }
+
+    let records: Vec<(u32, u32, Vec<u8>)> = changes.iter().map(encode_credential_change).collect();
+    if let Err(e) = metadata_log::append(&records) {
+        println!("error persisting SCRAM credentials: {}", e);
+        let error = (-1, format!("Unable to persist SCRAM credentials: {}", e)); // UNKNOWN_SERVER_ERROR
+        return results
+            .into_iter()
+            .map(|(user, result)| (user, result.and(Err::<(), _>(error.clone()))))
+            .collect();
+    }
+    for change in changes {
+        apply_credential_change(change);
+    }
+
+    results.into_iter().map(|(user, result)| (user, result.map(|_| ()))).collect()
+}
+
+fn create_alter_user_scram_credentials_response(context: &RequestContext, results: &[AlteredUser]) -> Vec<u8> {
+    let mut response_body = Vec::new();
+
+    // Response header
+    response_body.extend_from_slice(&context.correlation_id.to_be_bytes()); // correlation_id (4 bytes)
+    response_body.push(0); // tag buffer (1 byte)
+
+    // Response body
+    response_body.extend_from_slice(&context.throttle_time_ms.to_be_bytes()); // throttle_time_ms (4 bytes)
+    write_unsigned_varint(&mut response_body, results.len() as u32 + 1);
+    for (user, result) in results {
+        let (error_code, error_message) = match result {
+            Ok(()) => (0, None),
+            // Error responses built from just a code carry a null message
+            Err((error_code, error_message)) => (*error_code, Some(error_message.as_str()).filter(|message| !message.is_empty())),
+        };
+        write_compact_string(&mut response_body, user);              // user
+        response_body.extend_from_slice(&error_code.to_be_bytes()); // error_code (2 bytes)
+        write_compact_nullable_string(&mut response_body, error_message);
+        response_body.push(0); // tag buffer (1 byte)
+    }
+
+    // Response body tag buffer
+    response_body.push(0); // tag buffer (1 byte)
+
+    frame_response(response_body)
+}
+
+pub fn handle_alter_user_scram_credentials_request(context: &RequestContext, message_buffer: &[u8]) -> Vec<u8> {
+    let request = match parse_alter_user_scram_credentials_request(message_buffer) {
+        Ok(request) => request,
+        Err(e) => {
+            println!("error parsing AlterUserScramCredentials request: {}", e);
+            return create_alter_user_scram_credentials_error_response(context, message_buffer, 42); // INVALID_REQUEST
+        }
+    };
+    if !authorize(context.connection, AclOperation::Alter, ResourceType::Cluster, CLUSTER_NAME) {
+        return create_alter_user_scram_credentials_error_response(context, message_buffer, 31); // CLUSTER_AUTHORIZATION_FAILED
+    }
+
+    let results = alter_user_scram_credentials(request);
+    for (user, result) in &results {
+        if let Err((_, message)) = result {
+            println!("AlterUserScramCredentials rejected for user {}: {}", user, message);
+        }
+    }
+    create_alter_user_scram_credentials_response(context, &results)
+}
+
+pub fn create_alter_user_scram_credentials_error_response(context: &RequestContext, message_buffer: &[u8], error_code: i16) -> Vec<u8> {
+    let mut users: Vec<String> = parse_alter_user_scram_credentials_request(message_buffer)
+        .map(|request| request.alterations.into_iter().map(|(user, _)| user).collect())
+        .unwrap_or_default();
+    let mut seen = HashSet::new();
+    users.retain(|user| seen.insert(user.clone()));
+    let results: Vec<_> = users.into_iter().map(|user| (user, Err((error_code, String::new())))).collect();
+    create_alter_user_scram_credentials_response(context, &results)
+}
+
+// None asks for every user with a credential
+fn parse_describe_user_scram_credentials_request(buffer: &[u8]) -> Result<Option<Vec<String>>, &'static str> {
+    let mut offset = skip_request_header(buffer, true)?;
+    let (users_count, new_offset) = read_array_length(buffer, offset, true)?;
+    offset = new_offset;
+
+    let users = match users_count {
+        Some(users_count) => {
+            let mut users = Vec::new();
+            for _ in 0..users_count {
+                let (user, new_offset) = read_compact_string(buffer, offset)?;
+                offset = skip_tagged_fields(buffer, new_offset)?;
+                users.push(user);
+            }
+            Some(users)
+        }
+        None => None,
+    };
+    skip_tagged_fields(buffer, offset)?;
+
+    Ok(users)
+}
+
+// Per user: the (mechanism, iterations) of each credential, or (error_code, error_message)
+type DescribedUser = (String, Result<Vec<(ScramMechanism, i32)>, (i16, String)>);
+
+fn describe_user_scram_credentials(users: Option<Vec<String>>) -> Vec<DescribedUser> {
+    let credentials = SCRAM_CREDENTIALS.lock().unwrap();
+    let mut described: BTreeMap<String, Vec<(ScramMechanism, i32)>> = BTreeMap::new();
+    for ((user, mechanism), credential) in credentials.iter() {
+        described.entry(user.clone()).or_default().push((*mechanism, credential.iterations));
+    }
+
+    // An absent or empty user list describes every user that has a credential
+    let users = match users.filter(|users| !users.is_empty()) {
+        Some(users) => users,
+        None => return described.into_iter().map(|(user, infos)| (user, Ok(infos))).collect(),
+    };
+
+    let mut results: Vec<DescribedUser> = Vec::new();
+    for user in users {
+        if let Some(result) = results.iter_mut().find(|(described_user, _)| *described_user == user) {
+            result.1 = Err((92, "Cannot describe SCRAM credentials for the same user twice in a single request".to_string())); // DUPLICATE_RESOURCE
+            continue;
+        }
+        let result = described
+            .get(&user)
+            .cloned()
+            .ok_or((91, "Attempt to describe a user credential that does not exist".to_string())); // RESOURCE_NOT_FOUND
+        results.push((user, result));
+    }
+    results
+}
+
+fn create_describe_user_scram_credentials_response(context: &RequestContext, error_code: i16, results: &[DescribedUser]) -> Vec<u8> {
+    let mut response_body = Vec::new();
+
+    // Response header
+    response_body.extend_from_slice(&context.correlation_id.to_be_bytes()); // correlation_id (4 bytes)
+    response_body.push(0); // tag buffer (1 byte)
+
+    // Response body
+    response_body.extend_from_slice(&context.throttle_time_ms.to_be_bytes()); // throttle_time_ms (4 bytes)
+    response_body.extend_from_slice(&error_code.to_be_bytes());               // error_code (2 bytes)
+    write_compact_nullable_string(&mut response_body, None);                  // error_message
+    write_unsigned_varint(&mut response_body, results.len() as u32 + 1);
+    for (user, result) in results {
+        write_compact_string(&mut response_body, user); // user
+        match result {
+            Ok(credential_infos) => {
+                response_body.extend_from_slice(&0i16.to_be_bytes()); // error_code (2 bytes)
+                write_compact_nullable_string(&mut response_body, None);
+                write_unsigned_varint(&mut response_body, credential_infos.len() as u32 + 1);
+                for (mechanism, iterations) in credential_infos {
+                    response_body.push(mechanism.mechanism_type() as u8);        // mechanism (1 byte)
+                    response_body.extend_from_slice(&iterations.to_be_bytes()); // iterations (4 bytes)
+                    response_body.push(0); // tag buffer (1 byte)
+                }
+            }
+            Err((error_code, error_message)) => {
+                response_body.extend_from_slice(&error_code.to_be_bytes()); // error_code (2 bytes)
+                write_compact_nullable_string(&mut response_body, Some(error_message));
+                write_unsigned_varint(&mut response_body, 1); // empty credential_infos
+            }
+        }
+        response_body.push(0); // tag buffer (1 byte)
+    }
+
+    // Response body tag buffer
+    response_body.push(0); // tag buffer (1 byte)
+
+    frame_response(response_body)
+}
+
+pub fn handle_describe_user_scram_credentials_request(context: &RequestContext, message_buffer: &[u8]) -> Vec<u8> {
+    let users = match parse_describe_user_scram_credentials_request(message_buffer) {
+        Ok(users) => users,
+        Err(e) => {
+            println!("error parsing DescribeUserScramCredentials request: {}", e);
+            return create_describe_user_scram_credentials_error_response(context, message_buffer, 42); // INVALID_REQUEST
+        }
+    };
+    if !authorize(context.connection, AclOperation::Describe, ResourceType::Cluster, CLUSTER_NAME) {
+        return create_describe_user_scram_credentials_error_response(context, message_buffer, 31); // CLUSTER_AUTHORIZATION_FAILED
+    }
+    let results = describe_user_scram_credentials(users);
+    create_describe_user_scram_credentials_response(context, 0, &results)
+}
+
+pub fn create_describe_user_scram_credentials_error_response(context: &RequestContext, _message_buffer: &[u8], error_code: i16) -> Vec<u8> {
+    create_describe_user_scram_credentials_response(context, error_code, &[])
+}
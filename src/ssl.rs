@@ -0,0 +1,340 @@
+use std::fs;
+use std::io::BufReader;
+use std::path::PathBuf;
+use std::sync::{Arc, OnceLock, RwLock};
+use std::time::{Duration, SystemTime};
+
+use regex::Regex;
+use tokio::net::TcpStream;
+use tokio_rustls::rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use tokio_rustls::rustls::server::WebPkiClientVerifier;
+use tokio_rustls::rustls::{crypto, RootCertStore, ServerConfig};
+use tokio_rustls::server::TlsStream;
+use tokio_rustls::TlsAcceptor;
+use x509_parser::prelude::{FromDer, X509Certificate};
+
+use crate::config::BrokerConfig;
+
+// How often the keystore and truststore files are checked for changes
+const CERTIFICATE_RELOAD_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+
+// How long a client has to complete the TLS handshake before its connection is closed
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+// Principal of a connection that didn't present a client certificate
+pub const ANONYMOUS_PRINCIPAL: &str = "ANONYMOUS";
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum SslClientAuth {
+    Required,
+    Requested,
+    None,
+}
+
+impl SslClientAuth {
+    pub fn parse(value: &str) -> Option<SslClientAuth> {
+        match value.to_lowercase().as_str() {
+            "required" => Some(SslClientAuth::Required),
+            "requested" => Some(SslClientAuth::Requested),
+            "none" => Some(SslClientAuth::None),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+pub enum CaseConversion {
+    Lower,
+    Upper,
+}
+
+// One entry of ssl.principal.mapping.rules: DEFAULT, or RULE:pattern/replacement/[LU]
+pub enum PrincipalMappingRule {
+    Default,
+    Rule {
+        pattern: Regex,
+        replacement: String,
+        case: Option<CaseConversion>,
+    },
+}
+
+pub fn parse_principal_mapping_rules(rules: &str) -> Result<Vec<PrincipalMappingRule>, String> {
+    let mut parsed = Vec::new();
+    let mut remaining = rules.trim();
+    while !remaining.is_empty() {
+        if let Some(rest) = remaining.strip_prefix("DEFAULT") {
+            parsed.push(PrincipalMappingRule::Default);
+            remaining = rest;
+        } else if let Some(rest) = remaining.strip_prefix("RULE:") {
+            let (pattern, rest) = split_rule_part(rest).ok_or_else(|| format!("'{}' has no replacement", remaining))?;
+            let (replacement, rest) = split_rule_part(rest).ok_or_else(|| format!("'{}' is missing its closing '/'", remaining))?;
+            let (case, rest) = match rest.chars().next() {
+                Some('L') => (Some(CaseConversion::Lower), &rest[1..]),
+                Some('U') => (Some(CaseConversion::Upper), &rest[1..]),
+                _ => (None, rest),
+            };
+            // Like Java's Matcher.matches(), a rule applies only when its pattern matches the whole name
+            let pattern = Regex::new(&format!("^(?:{})$", pattern)).map_err(|e| format!("invalid pattern '{}': {}", pattern, e))?;
+            parsed.push(PrincipalMappingRule::Rule { pattern, replacement: java_replacement(&replacement), case });
+            remaining = rest;
+        } else {
+            return Err(format!("'{}' is not DEFAULT or RULE:pattern/replacement/[LU]", remaining));
+        }
+
+        remaining = remaining.trim_start();
+        match remaining.strip_prefix(',') {
+            Some(rest) => remaining = rest.trim_start(),
+            None if remaining.is_empty() => {}
+            None => return Err(format!("expected ',' before '{}'", remaining)),
+        }
+    }
+    if parsed.is_empty() {
+        return Err("no rules given".to_string());
+    }
+    Ok(parsed)
+}
+
+// Reads up to the next unescaped '/'; "\/" stands for a literal slash
+fn split_rule_part(input: &str) -> Option<(String, &str)> {
+    let mut part = String::new();
+    let mut chars = input.char_indices();
+    while let Some((index, c)) = chars.next() {
+        match c {
+            '/' => return Some((part, &input[index + 1..])),
+            '\\' => match chars.next() {
+                Some((_, '/')) => part.push('/'),
+                Some((_, escaped)) => {
+                    part.push('\\');
+                    part.push(escaped);
+                }
+                None => part.push('\\'),
+            },
+            c => part.push(c),
+        }
+    }
+    None
+}
+
+// Java's "$1abc" is group 1 followed by "abc"; the regex crate would read a group named "1abc"
+fn java_replacement(replacement: &str) -> String {
+    let mut converted = String::new();
+    let mut chars = replacement.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c == '$' && chars.peek().is_some_and(|next| next.is_ascii_digit()) {
+            converted.push_str("${");
+            while let Some(digit) = chars.next_if(|next| next.is_ascii_digit()) {
+                converted.push(digit);
+            }
+            converted.push('}');
+        } else {
+            converted.push(c);
+        }
+    }
+    converted
+}
+
+// The first rule matching the distinguished name decides the principal; none matching rejects it
+fn map_principal(rules: &[PrincipalMappingRule], distinguished_name: &str) -> Option<String> {
+    for rule in rules {
+        match rule {
+            PrincipalMappingRule::Default => return Some(distinguished_name.to_string()),
+            PrincipalMappingRule::Rule { pattern, replacement, case } => {
+                if !pattern.is_match(distinguished_name) {
+                    continue;
+                }
+                let name = pattern.replace_all(distinguished_name, replacement.as_str()).into_owned();
+                return Some(match case {
+                    Some(CaseConversion::Lower) => name.to_lowercase(),
+                    Some(CaseConversion::Upper) => name.to_uppercase(),
+                    None => name,
+                });
+            }
+        }
+    }
+    None
+}
+
+// RFC 2253 form, most specific RDN first, which is what mapping rules are written against
+fn distinguished_name(certificate: &X509Certificate) -> String {
+    let mut rdns: Vec<String> = certificate
+        .subject()
+        .iter_rdn()
+        .map(|rdn| {
+            let attributes: Vec<String> = rdn
+                .iter()
+                .map(|attribute| {
+                    let name = x509_parser::objects::oid2abbrev(attribute.attr_type(), x509_parser::objects::oid_registry())
+                        .map(|name| name.to_string())
+                        .unwrap_or_else(|_| attribute.attr_type().to_id_string());
+                    let value = attribute.as_str().map(escape_dn_value).unwrap_or_default();
+                    format!("{}={}", name, value)
+                })
+                .collect();
+            attributes.join("+")
+        })
+        .collect();
+    rdns.reverse();
+    rdns.join(",")
+}
+
+fn escape_dn_value(value: &str) -> String {
+    let mut escaped = String::new();
+    for (index, c) in value.chars().enumerate() {
+        let leading = index == 0 && (c == '#' || c == ' ');
+        let trailing = index == value.chars().count() - 1 && c == ' ';
+        if leading || trailing || matches!(c, ',' | '+' | '"' | '\\' | '<' | '>' | ';') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+struct SslContext {
+    acceptor: RwLock<TlsAcceptor>,
+    // Files the acceptor was built from, with the modification times they had then
+    watched_files: RwLock<Vec<(PathBuf, Option<SystemTime>)>>,
+}
+
+static SSL_CONTEXT: OnceLock<SslContext> = OnceLock::new();
+
+// Loads the keystore and truststore for SSL listeners; called once at startup
+pub fn init_ssl(config: &BrokerConfig) -> Result<(), String> {
+    let watched_files = watched_files(config);
+    let acceptor = load_acceptor(config)?;
+    let _ = SSL_CONTEXT.set(SslContext {
+        acceptor: RwLock::new(acceptor),
+        watched_files: RwLock::new(watched_files),
+    });
+    Ok(())
+}
+
+fn watched_files(config: &BrokerConfig) -> Vec<(PathBuf, Option<SystemTime>)> {
+    [&config.ssl_keystore_location, &config.ssl_truststore_location]
+        .into_iter()
+        .flatten()
+        .map(|path| {
+            let path = PathBuf::from(path);
+            let modified = fs::metadata(&path).and_then(|metadata| metadata.modified()).ok();
+            (path, modified)
+        })
+        .collect()
+}
+
+fn load_acceptor(config: &BrokerConfig) -> Result<TlsAcceptor, String> {
+    let keystore = config.ssl_keystore_location.as_deref().ok_or("ssl.keystore.location is not set")?;
+    let (certificates, private_key) = load_keystore(keystore)?;
+
+    let builder = ServerConfig::builder_with_provider(Arc::new(crypto::ring::default_provider()))
+        .with_safe_default_protocol_versions()
+        .map_err(|e| e.to_string())?;
+    let builder = match config.ssl_client_auth {
+        SslClientAuth::None => builder.with_no_client_auth(),
+        client_auth => {
+            let truststore = config.ssl_truststore_location.as_deref().ok_or("ssl.truststore.location is not set")?;
+            let roots = load_truststore(truststore)?;
+            let verifier = WebPkiClientVerifier::builder_with_provider(
+                Arc::new(roots),
+                Arc::new(crypto::ring::default_provider()),
+            );
+            let verifier = if client_auth == SslClientAuth::Requested {
+                verifier.allow_unauthenticated()
+            } else {
+                verifier
+            };
+            builder.with_client_cert_verifier(verifier.build().map_err(|e| format!("{}: {}", truststore, e))?)
+        }
+    };
+    let server_config = builder
+        .with_single_cert(certificates, private_key)
+        .map_err(|e| format!("{}: {}", keystore, e))?;
+    Ok(TlsAcceptor::from(Arc::new(server_config)))
+}
+
+// A PEM keystore holds the certificate chain and its unencrypted private key
+fn load_keystore(path: &str) -> Result<(Vec<CertificateDer<'static>>, PrivateKeyDer<'static>), String> {
+    let contents = fs::read(path).map_err(|e| format!("{}: {}", path, e))?;
+    if contents.windows(b"ENCRYPTED PRIVATE KEY".len()).any(|window| window == b"ENCRYPTED PRIVATE KEY") {
+        return Err(format!("{}: encrypted private keys are not supported", path));
+    }
+
+    let certificates = rustls_pemfile::certs(&mut BufReader::new(contents.as_slice()))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| format!("{}: {}", path, e))?;
+    if certificates.is_empty() {
+        return Err(format!("{}: no certificates found", path));
+    }
+    let private_key = rustls_pemfile::private_key(&mut BufReader::new(contents.as_slice()))
+        .map_err(|e| format!("{}: {}", path, e))?
+        .ok_or_else(|| format!("{}: no private key found", path))?;
+    Ok((certificates, private_key))
+}
+
+fn load_truststore(path: &str) -> Result<RootCertStore, String> {
+    let contents = fs::read(path).map_err(|e| format!("{}: {}", path, e))?;
+    let mut roots = RootCertStore::empty();
+    for certificate in rustls_pemfile::certs(&mut BufReader::new(contents.as_slice())) {
+        let certificate = certificate.map_err(|e| format!("{}: {}", path, e))?;
+        roots.add(certificate).map_err(|e| format!("{}: {}", path, e))?;
+    }
+    if roots.is_empty() {
+        return Err(format!("{}: no certificates found", path));
+    }
+    Ok(roots)
+}
+
+// Rebuilds the acceptor whenever the keystore or truststore changes on disk. Existing connections
+// keep their session; new ones get the new certificates. A broken file keeps the previous ones.
+pub async fn reload_certificates_on_change(config: &'static BrokerConfig) {
+    let Some(context) = SSL_CONTEXT.get() else {
+        return;
+    };
+    let mut interval = tokio::time::interval(CERTIFICATE_RELOAD_CHECK_INTERVAL);
+    loop {
+        interval.tick().await;
+        let current = watched_files(config);
+        if *context.watched_files.read().unwrap() == current {
+            continue;
+        }
+
+        match load_acceptor(config) {
+            Ok(acceptor) => {
+                *context.acceptor.write().unwrap() = acceptor;
+                println!("reloaded SSL certificates");
+            }
+            Err(e) => println!("error reloading SSL certificates, keeping the previous ones: {}", e),
+        }
+        // Either way, wait for the next change before trying again
+        *context.watched_files.write().unwrap() = current;
+    }
+}
+
+// Completes the TLS handshake, giving up on clients that stall it
+pub async fn accept(stream: TcpStream) -> Result<TlsStream<TcpStream>, String> {
+    let acceptor = SSL_CONTEXT
+        .get()
+        .ok_or("SSL is not initialized")?
+        .acceptor
+        .read()
+        .unwrap()
+        .clone();
+    match tokio::time::timeout(HANDSHAKE_TIMEOUT, acceptor.accept(stream)).await {
+        Ok(result) => result.map_err(|e| e.to_string()),
+        Err(_) => Err(format!("handshake did not complete within {} seconds", HANDSHAKE_TIMEOUT.as_secs())),
+    }
+}
+
+// The principal an SSL connection's client certificate maps to, or ANONYMOUS without one
+pub fn principal(tls_stream: &TlsStream<TcpStream>, config: &BrokerConfig) -> Result<String, String> {
+    let peer_certificate = tls_stream.get_ref().1.peer_certificates().and_then(|certificates| certificates.first());
+    match peer_certificate {
+        Some(certificate) => {
+            let (_, certificate) = X509Certificate::from_der(certificate.as_ref())
+                .map_err(|e| format!("unreadable client certificate: {}", e))?;
+            let distinguished_name = distinguished_name(&certificate);
+            map_principal(&config.ssl_principal_mapping_rules, &distinguished_name)
+                .ok_or_else(|| format!("no ssl.principal.mapping.rules entry matches {}", distinguished_name))
+        }
+        None => Ok(ANONYMOUS_PRINCIPAL.to_string()),
+    }
+}
@@ -7,18 +7,23 @@ fn main() {
     let mut stream = TcpStream::connect("127.0.0.1:9092").unwrap();
 
     // API Versions request (v4)
-    // Format: message_size(4) + api_key(2) + api_version(2) + correlation_id(4) + client_id + client_software_version + tag_buffer
+    // Format: message_size(4) + api_key(2) + api_version(2) + correlation_id(4) + client_id + header tag_buffer
+    //         + client_software_name + client_software_version + tag_buffer
 
     let api_key: i16 = 18; // API_VERSIONS
     let api_version: i16 = 4;
     let correlation_id: i32 = 12345;
 
-    // Client ID: length(1) + "test"(4 bytes)
-    let client_id_length: u8 = 4;
+    // Client ID: length(2) + "test"(4 bytes)
+    let client_id_length: i16 = 4;
     let client_id = b"test";
 
-    // Client software version: length(1) + "1.0.0"(5 bytes)
-    let client_sw_length: u8 = 5;
+    // Client software name: COMPACT_STRING length(1) + "test-client"(11 bytes)
+    let client_sw_name_length: u8 = 12;
+    let client_sw_name = b"test-client";
+
+    // Client software version: COMPACT_STRING length(1) + "1.0.0"(5 bytes)
+    let client_sw_length: u8 = 6;
     let client_sw = b"1.0.0";
 
     // Build request body
@@ -26,8 +31,11 @@ fn main() {
     request_body.extend_from_slice(&api_key.to_be_bytes());
     request_body.extend_from_slice(&api_version.to_be_bytes());
     request_body.extend_from_slice(&correlation_id.to_be_bytes());
-    request_body.extend_from_slice(&[client_id_length]);
+    request_body.extend_from_slice(&client_id_length.to_be_bytes());
     request_body.extend_from_slice(client_id);
+    request_body.extend_from_slice(&[0u8]); // header tag buffer
+    request_body.extend_from_slice(&[client_sw_name_length]);
+    request_body.extend_from_slice(client_sw_name);
     request_body.extend_from_slice(&[client_sw_length]);
     request_body.extend_from_slice(client_sw);
     request_body.extend_from_slice(&[0u8]); // tag buffer
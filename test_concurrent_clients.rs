@@ -22,13 +22,17 @@ fn simulate_client(client_id: u32) {
         let api_key: i16 = 18; // API_VERSIONS
         let api_version: i16 = 4;
 
-        // Client ID: length(1) + client-specific ID
+        // Client ID: length(2) + client-specific ID
         let client_id_str = format!("client{}", client_id);
-        let client_id_length: u8 = client_id_str.len() as u8;
+        let client_id_length: i16 = client_id_str.len() as i16;
         let client_id_bytes = client_id_str.as_bytes();
 
-        // Client software version: length(1) + "1.0.0"(5 bytes)
-        let client_sw_length: u8 = 5;
+        // Client software name: COMPACT_STRING length(1) + "test-client"(11 bytes)
+        let client_sw_name_length: u8 = 12;
+        let client_sw_name = b"test-client";
+
+        // Client software version: COMPACT_STRING length(1) + "1.0.0"(5 bytes)
+        let client_sw_length: u8 = 6;
         let client_sw = b"1.0.0";
 
         // Build request body
@@ -36,8 +40,11 @@ fn simulate_client(client_id: u32) {
         request_body.extend_from_slice(&api_key.to_be_bytes());
         request_body.extend_from_slice(&api_version.to_be_bytes());
         request_body.extend_from_slice(&correlation_id.to_be_bytes());
-        request_body.extend_from_slice(&[client_id_length]);
+        request_body.extend_from_slice(&client_id_length.to_be_bytes());
         request_body.extend_from_slice(client_id_bytes);
+        request_body.extend_from_slice(&[0u8]); // header tag buffer
+        request_body.extend_from_slice(&[client_sw_name_length]);
+        request_body.extend_from_slice(client_sw_name);
         request_body.extend_from_slice(&[client_sw_length]);
         request_body.extend_from_slice(client_sw);
         request_body.extend_from_slice(&[0u8]); // tag buffer
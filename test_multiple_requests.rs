@@ -18,12 +18,16 @@ fn main() {
         let api_version: i16 = 4;
         let correlation_id: i32 = 1000 + request_num; // Different correlation ID for each request
 
-        // Client ID: length(1) + "test"(4 bytes)
-        let client_id_length: u8 = 4;
+        // Client ID: length(2) + "test"(4 bytes)
+        let client_id_length: i16 = 4;
         let client_id = b"test";
 
-        // Client software version: length(1) + "1.0.0"(5 bytes)
-        let client_sw_length: u8 = 5;
+        // Client software name: COMPACT_STRING length(1) + "test-client"(11 bytes)
+        let client_sw_name_length: u8 = 12;
+        let client_sw_name = b"test-client";
+
+        // Client software version: COMPACT_STRING length(1) + "1.0.0"(5 bytes)
+        let client_sw_length: u8 = 6;
         let client_sw = b"1.0.0";
 
         // Build request body
@@ -31,8 +35,11 @@ fn main() {
         request_body.extend_from_slice(&api_key.to_be_bytes());
         request_body.extend_from_slice(&api_version.to_be_bytes());
         request_body.extend_from_slice(&correlation_id.to_be_bytes());
-        request_body.extend_from_slice(&[client_id_length]);
+        request_body.extend_from_slice(&client_id_length.to_be_bytes());
         request_body.extend_from_slice(client_id);
+        request_body.extend_from_slice(&[0u8]); // header tag buffer
+        request_body.extend_from_slice(&[client_sw_name_length]);
+        request_body.extend_from_slice(client_sw_name);
         request_body.extend_from_slice(&[client_sw_length]);
         request_body.extend_from_slice(client_sw);
         request_body.extend_from_slice(&[0u8]); // tag buffer
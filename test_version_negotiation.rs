@@ -0,0 +1,526 @@
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::convert::TryInto;
+
+// Version negotiation matrix: for every API the broker advertises, send a request at
+// min_version - 1, min_version, max_version and max_version + 1 and check the error code.
+
+const METADATA: i16 = 3;
+const SASL_HANDSHAKE: i16 = 17;
+const API_VERSIONS: i16 = 18;
+const DESCRIBE_ACLS: i16 = 29;
+const CREATE_ACLS: i16 = 30;
+const DELETE_ACLS: i16 = 31;
+const DESCRIBE_CONFIGS: i16 = 32;
+const SASL_AUTHENTICATE: i16 = 36;
+const CREATE_DELEGATION_TOKEN: i16 = 38;
+const RENEW_DELEGATION_TOKEN: i16 = 39;
+const EXPIRE_DELEGATION_TOKEN: i16 = 40;
+const DESCRIBE_DELEGATION_TOKEN: i16 = 41;
+const DESCRIBE_CLIENT_QUOTAS: i16 = 48;
+const ALTER_CLIENT_QUOTAS: i16 = 49;
+const DESCRIBE_USER_SCRAM_CREDENTIALS: i16 = 50;
+const ALTER_USER_SCRAM_CREDENTIALS: i16 = 51;
+const UPDATE_FEATURES: i16 = 57;
+const DESCRIBE_TOPIC_PARTITIONS: i16 = 75;
+
+const UNSUPPORTED_VERSION: i16 = 35;
+const UNKNOWN_TOPIC_OR_PARTITION: i16 = 3;
+const ILLEGAL_SASL_STATE: i16 = 34;
+const SECURITY_DISABLED: i16 = 54;
+const DELEGATION_TOKEN_AUTH_DISABLED: i16 = 61;
+const RESOURCE_NOT_FOUND: i16 = 91;
+
+fn build_request(api_key: i16, api_version: i16, correlation_id: i32) -> Vec<u8> {
+    // Request header: api_key(2) + api_version(2) + correlation_id(4) + client_id(2 + 4)
+    let mut request_body = Vec::new();
+    request_body.extend_from_slice(&api_key.to_be_bytes());
+    request_body.extend_from_slice(&api_version.to_be_bytes());
+    request_body.extend_from_slice(&correlation_id.to_be_bytes());
+    request_body.extend_from_slice(&4i16.to_be_bytes());
+    request_body.extend_from_slice(b"test");
+
+    match api_key {
+        METADATA => {
+            // v9+ is flexible; v10+ puts a topic_id before each topic name
+            if api_version >= 9 {
+                request_body.extend_from_slice(&[0u8]); // header tag buffer
+                request_body.extend_from_slice(&[2u8]); // topics: COMPACT_ARRAY of 1
+                if api_version >= 10 {
+                    request_body.extend_from_slice(&[0u8; 16]); // topic_id
+                }
+                request_body.extend_from_slice(&[11u8]);
+                request_body.extend_from_slice(b"negotiated");
+                request_body.extend_from_slice(&[0u8]); // topic tag buffer
+            } else {
+                request_body.extend_from_slice(&1i32.to_be_bytes()); // topics: ARRAY of 1
+                request_body.extend_from_slice(&10i16.to_be_bytes());
+                request_body.extend_from_slice(b"negotiated");
+            }
+            if api_version >= 4 {
+                request_body.extend_from_slice(&[0u8]); // allow_auto_topic_creation
+            }
+            if (8..=10).contains(&api_version) {
+                request_body.extend_from_slice(&[0u8]); // include_cluster_authorized_operations
+            }
+            if api_version >= 8 {
+                request_body.extend_from_slice(&[0u8]); // include_topic_authorized_operations
+            }
+            if api_version >= 9 {
+                request_body.extend_from_slice(&[0u8]); // tag buffer
+            }
+        }
+        SASL_HANDSHAKE => {
+            request_body.extend_from_slice(&5i16.to_be_bytes()); // mechanism
+            request_body.extend_from_slice(b"PLAIN");
+        }
+        SASL_AUTHENTICATE => {
+            // v2+ is flexible; auth_bytes: PLAIN token for user "test"
+            let auth_bytes = b"\0test\0test-secret";
+            if api_version >= 2 {
+                request_body.extend_from_slice(&[0u8]); // header tag buffer
+                request_body.extend_from_slice(&[auth_bytes.len() as u8 + 1]);
+                request_body.extend_from_slice(auth_bytes);
+                request_body.extend_from_slice(&[0u8]); // tag buffer
+            } else {
+                request_body.extend_from_slice(&(auth_bytes.len() as i32).to_be_bytes());
+                request_body.extend_from_slice(auth_bytes);
+            }
+        }
+        API_VERSIONS => {
+            // v3+ uses the flexible header and carries client software name and version
+            if api_version >= 3 {
+                request_body.extend_from_slice(&[0u8]); // header tag buffer
+                request_body.extend_from_slice(&[12u8]);
+                request_body.extend_from_slice(b"test-client");
+                request_body.extend_from_slice(&[6u8]);
+                request_body.extend_from_slice(b"1.0.0");
+                request_body.extend_from_slice(&[0u8]); // tag buffer
+            }
+        }
+        DESCRIBE_ACLS | DELETE_ACLS => {
+            // v2+ is flexible; one filter matching every ACL on the topic "negotiated", wrapped in an array for DeleteAcls
+            if api_version >= 2 {
+                request_body.extend_from_slice(&[0u8]); // header tag buffer
+                if api_key == DELETE_ACLS {
+                    request_body.extend_from_slice(&[2u8]); // filters: COMPACT_ARRAY of 1
+                }
+                request_body.extend_from_slice(&[2u8]); // resource_type_filter: TOPIC
+                request_body.extend_from_slice(&[11u8]);
+                request_body.extend_from_slice(b"negotiated");
+                request_body.extend_from_slice(&[3u8]); // pattern_type_filter: LITERAL
+                request_body.extend_from_slice(&[0u8, 0u8]); // principal_filter, host_filter: null
+            } else {
+                if api_key == DELETE_ACLS {
+                    request_body.extend_from_slice(&1i32.to_be_bytes()); // filters: ARRAY of 1
+                }
+                request_body.extend_from_slice(&[2u8]); // resource_type_filter: TOPIC
+                request_body.extend_from_slice(&10i16.to_be_bytes());
+                request_body.extend_from_slice(b"negotiated");
+                if api_version >= 1 {
+                    request_body.extend_from_slice(&[3u8]); // pattern_type_filter: LITERAL
+                }
+                request_body.extend_from_slice(&(-1i16).to_be_bytes()); // principal_filter: null
+                request_body.extend_from_slice(&(-1i16).to_be_bytes()); // host_filter: null
+            }
+            request_body.extend_from_slice(&[1u8]); // operation: ANY
+            request_body.extend_from_slice(&[1u8]); // permission_type: ANY
+            if api_version >= 2 {
+                if api_key == DELETE_ACLS {
+                    request_body.extend_from_slice(&[0u8]); // filter tag buffer
+                }
+                request_body.extend_from_slice(&[0u8]); // tag buffer
+            }
+        }
+        CREATE_ACLS => {
+            // v2+ is flexible; creations: one ALLOW READ for User:negotiated on the topic "negotiated"
+            if api_version >= 2 {
+                request_body.extend_from_slice(&[0u8]); // header tag buffer
+                request_body.extend_from_slice(&[2u8]); // creations: COMPACT_ARRAY of 1
+                request_body.extend_from_slice(&[2u8]); // resource_type: TOPIC
+                request_body.extend_from_slice(&[11u8]);
+                request_body.extend_from_slice(b"negotiated");
+                request_body.extend_from_slice(&[3u8]); // resource_pattern_type: LITERAL
+                request_body.extend_from_slice(&[16u8]);
+                request_body.extend_from_slice(b"User:negotiated");
+                request_body.extend_from_slice(&[2u8]);
+                request_body.extend_from_slice(b"*");
+            } else {
+                request_body.extend_from_slice(&1i32.to_be_bytes()); // creations: ARRAY of 1
+                request_body.extend_from_slice(&[2u8]); // resource_type: TOPIC
+                request_body.extend_from_slice(&10i16.to_be_bytes());
+                request_body.extend_from_slice(b"negotiated");
+                if api_version >= 1 {
+                    request_body.extend_from_slice(&[3u8]); // resource_pattern_type: LITERAL
+                }
+                request_body.extend_from_slice(&15i16.to_be_bytes());
+                request_body.extend_from_slice(b"User:negotiated");
+                request_body.extend_from_slice(&1i16.to_be_bytes());
+                request_body.extend_from_slice(b"*");
+            }
+            request_body.extend_from_slice(&[3u8]); // operation: READ
+            request_body.extend_from_slice(&[3u8]); // permission_type: ALLOW
+            if api_version >= 2 {
+                request_body.extend_from_slice(&[0u8]); // creation tag buffer
+                request_body.extend_from_slice(&[0u8]); // tag buffer
+            }
+        }
+        DESCRIBE_CONFIGS => {
+            // v4+ is flexible; resources: one TOPIC resource, all keys
+            if api_version >= 4 {
+                request_body.extend_from_slice(&[0u8]); // header tag buffer
+                request_body.extend_from_slice(&[2u8]); // resources: COMPACT_ARRAY of 1
+                request_body.extend_from_slice(&[2u8]); // resource_type: TOPIC
+                request_body.extend_from_slice(&[11u8]);
+                request_body.extend_from_slice(b"negotiated");
+                request_body.extend_from_slice(&[0u8]); // configuration_keys: null
+                request_body.extend_from_slice(&[0u8]); // resource tag buffer
+            } else {
+                request_body.extend_from_slice(&1i32.to_be_bytes()); // resources: ARRAY of 1
+                request_body.extend_from_slice(&[2u8]); // resource_type: TOPIC
+                request_body.extend_from_slice(&10i16.to_be_bytes());
+                request_body.extend_from_slice(b"negotiated");
+                request_body.extend_from_slice(&(-1i32).to_be_bytes()); // configuration_keys: null
+            }
+            if api_version >= 1 {
+                request_body.extend_from_slice(&[0u8]); // include_synonyms
+            }
+            if api_version >= 3 {
+                request_body.extend_from_slice(&[0u8]); // include_documentation
+            }
+            if api_version >= 4 {
+                request_body.extend_from_slice(&[0u8]); // tag buffer
+            }
+        }
+        CREATE_DELEGATION_TOKEN => {
+            // v2+ is flexible; v3+ names the owner, null here for the requester itself; renewers: none
+            if api_version >= 2 {
+                request_body.extend_from_slice(&[0u8]); // header tag buffer
+                if api_version >= 3 {
+                    request_body.extend_from_slice(&[0u8, 0u8]); // owner_principal_type, owner_principal_name: null
+                }
+                request_body.extend_from_slice(&[1u8]); // renewers: empty COMPACT_ARRAY
+            } else {
+                request_body.extend_from_slice(&0i32.to_be_bytes()); // renewers: empty ARRAY
+            }
+            request_body.extend_from_slice(&(-1i64).to_be_bytes()); // max_lifetime_ms: broker default
+            if api_version >= 2 {
+                request_body.extend_from_slice(&[0u8]); // tag buffer
+            }
+        }
+        RENEW_DELEGATION_TOKEN | EXPIRE_DELEGATION_TOKEN => {
+            // v2+ is flexible; hmac: empty
+            if api_version >= 2 {
+                request_body.extend_from_slice(&[0u8]); // header tag buffer
+                request_body.extend_from_slice(&[1u8]); // hmac: empty COMPACT_BYTES
+            } else {
+                request_body.extend_from_slice(&0i32.to_be_bytes()); // hmac: empty BYTES
+            }
+            request_body.extend_from_slice(&(-1i64).to_be_bytes()); // renew/expiry period
+            if api_version >= 2 {
+                request_body.extend_from_slice(&[0u8]); // tag buffer
+            }
+        }
+        DESCRIBE_DELEGATION_TOKEN => {
+            // v2+ is flexible; owners: null for every token
+            if api_version >= 2 {
+                request_body.extend_from_slice(&[0u8]); // header tag buffer
+                request_body.extend_from_slice(&[0u8]); // owners: null COMPACT_ARRAY
+                request_body.extend_from_slice(&[0u8]); // tag buffer
+            } else {
+                request_body.extend_from_slice(&(-1i32).to_be_bytes()); // owners: null ARRAY
+            }
+        }
+        DESCRIBE_CLIENT_QUOTAS => {
+            // v1+ is flexible; components: the user "negotiated", exactly
+            if api_version >= 1 {
+                request_body.extend_from_slice(&[0u8]); // header tag buffer
+                request_body.extend_from_slice(&[2u8]); // components: COMPACT_ARRAY of 1
+                request_body.extend_from_slice(&[5u8]);
+                request_body.extend_from_slice(b"user");
+                request_body.extend_from_slice(&[0u8]); // match_type: EXACT
+                request_body.extend_from_slice(&[11u8]);
+                request_body.extend_from_slice(b"negotiated");
+                request_body.extend_from_slice(&[0u8]); // component tag buffer
+            } else {
+                request_body.extend_from_slice(&1i32.to_be_bytes()); // components: ARRAY of 1
+                request_body.extend_from_slice(&4i16.to_be_bytes());
+                request_body.extend_from_slice(b"user");
+                request_body.extend_from_slice(&[0u8]); // match_type: EXACT
+                request_body.extend_from_slice(&10i16.to_be_bytes());
+                request_body.extend_from_slice(b"negotiated");
+            }
+            request_body.extend_from_slice(&[0u8]); // strict: false
+            if api_version >= 1 {
+                request_body.extend_from_slice(&[0u8]); // tag buffer
+            }
+        }
+        ALTER_CLIENT_QUOTAS => {
+            // v1+ is flexible; entries: remove request_percentage, which isn't set, from the user "negotiated"
+            if api_version >= 1 {
+                request_body.extend_from_slice(&[0u8]); // header tag buffer
+                request_body.extend_from_slice(&[2u8]); // entries: COMPACT_ARRAY of 1
+                request_body.extend_from_slice(&[2u8]); // entity: COMPACT_ARRAY of 1
+                request_body.extend_from_slice(&[5u8]);
+                request_body.extend_from_slice(b"user");
+                request_body.extend_from_slice(&[11u8]);
+                request_body.extend_from_slice(b"negotiated");
+                request_body.extend_from_slice(&[0u8]); // entity tag buffer
+                request_body.extend_from_slice(&[2u8]); // ops: COMPACT_ARRAY of 1
+                request_body.extend_from_slice(&[19u8]);
+                request_body.extend_from_slice(b"request_percentage");
+            } else {
+                request_body.extend_from_slice(&1i32.to_be_bytes()); // entries: ARRAY of 1
+                request_body.extend_from_slice(&1i32.to_be_bytes()); // entity: ARRAY of 1
+                request_body.extend_from_slice(&4i16.to_be_bytes());
+                request_body.extend_from_slice(b"user");
+                request_body.extend_from_slice(&10i16.to_be_bytes());
+                request_body.extend_from_slice(b"negotiated");
+                request_body.extend_from_slice(&1i32.to_be_bytes()); // ops: ARRAY of 1
+                request_body.extend_from_slice(&18i16.to_be_bytes());
+                request_body.extend_from_slice(b"request_percentage");
+            }
+            request_body.extend_from_slice(&0f64.to_be_bytes()); // value
+            request_body.extend_from_slice(&[1u8]); // remove: true
+            if api_version >= 1 {
+                request_body.extend_from_slice(&[0u8]); // op tag buffer
+                request_body.extend_from_slice(&[0u8]); // entry tag buffer
+            }
+            request_body.extend_from_slice(&[0u8]); // validate_only: false
+            if api_version >= 1 {
+                request_body.extend_from_slice(&[0u8]); // tag buffer
+            }
+        }
+        DESCRIBE_USER_SCRAM_CREDENTIALS => {
+            request_body.extend_from_slice(&[0u8]); // header tag buffer
+            request_body.extend_from_slice(&[2u8]); // users: COMPACT_ARRAY of 1
+            request_body.extend_from_slice(&[11u8]);
+            request_body.extend_from_slice(b"negotiated");
+            request_body.extend_from_slice(&[0u8]); // user tag buffer
+            request_body.extend_from_slice(&[0u8]); // tag buffer
+        }
+        ALTER_USER_SCRAM_CREDENTIALS => {
+            // deletions: one SCRAM-SHA-256 credential that doesn't exist; upsertions: none
+            request_body.extend_from_slice(&[0u8]); // header tag buffer
+            request_body.extend_from_slice(&[2u8]); // deletions: COMPACT_ARRAY of 1
+            request_body.extend_from_slice(&[11u8]);
+            request_body.extend_from_slice(b"negotiated");
+            request_body.extend_from_slice(&[1u8]); // mechanism: SCRAM-SHA-256
+            request_body.extend_from_slice(&[0u8]); // deletion tag buffer
+            request_body.extend_from_slice(&[1u8]); // upsertions: empty COMPACT_ARRAY
+            request_body.extend_from_slice(&[0u8]); // tag buffer
+        }
+        UPDATE_FEATURES => {
+            request_body.extend_from_slice(&[0u8]); // header tag buffer
+            request_body.extend_from_slice(&60000i32.to_be_bytes()); // timeout_ms
+            request_body.extend_from_slice(&[1u8]); // feature_updates: empty COMPACT_ARRAY
+            if api_version >= 1 {
+                request_body.extend_from_slice(&[1u8]); // validate_only: true
+            }
+            request_body.extend_from_slice(&[0u8]); // tag buffer
+        }
+        DESCRIBE_TOPIC_PARTITIONS => {
+            request_body.extend_from_slice(&[0u8]); // header tag buffer
+            request_body.extend_from_slice(&[2u8]); // topics: COMPACT_ARRAY of 1
+            request_body.extend_from_slice(&[11u8]);
+            request_body.extend_from_slice(b"negotiated");
+            request_body.extend_from_slice(&[0u8]); // topic tag buffer
+            request_body.extend_from_slice(&100i32.to_be_bytes()); // response_partition_limit
+            request_body.extend_from_slice(&[0xffu8]); // cursor: null
+            request_body.extend_from_slice(&[0u8]); // tag buffer
+        }
+        _ => {}
+    }
+
+    let mut request = Vec::new();
+    request.extend_from_slice(&(request_body.len() as i32).to_be_bytes());
+    request.extend_from_slice(&request_body);
+    request
+}
+
+fn read_response(stream: &mut TcpStream) -> Vec<u8> {
+    let mut size_buffer = [0u8; 4];
+    stream.read_exact(&mut size_buffer).unwrap();
+    let mut response = vec![0u8; i32::from_be_bytes(size_buffer) as usize];
+    stream.read_exact(&mut response).unwrap();
+    response
+}
+
+// Returns the error code the broker answered with, decoded with the layout the response version uses
+// Metadata puts the broker list ahead of the topics, so the first topic's error code has to be found
+fn metadata_topic_error_code(api_version: i16, response: &[u8]) -> i16 {
+    let flexible = api_version >= 9;
+    let read_i16 = |offset: usize| i16::from_be_bytes(response[offset..offset + 2].try_into().unwrap());
+    // Strings and arrays here are all short enough for a one-byte COMPACT length
+    let read_length = |offset: &mut usize, size: usize| -> i32 {
+        if flexible {
+            *offset += 1;
+            response[*offset - 1] as i32 - 1
+        } else if size == 2 {
+            *offset += 2;
+            read_i16(*offset - 2) as i32
+        } else {
+            *offset += 4;
+            i32::from_be_bytes(response[*offset - 4..*offset].try_into().unwrap())
+        }
+    };
+
+    let mut offset = 4; // correlation_id
+    if flexible {
+        offset += 1; // header tag buffer
+    }
+    if api_version >= 3 {
+        offset += 4; // throttle_time_ms
+    }
+    for _ in 0..read_length(&mut offset, 4) {
+        offset += 4; // node_id
+        let host_length = read_length(&mut offset, 2);
+        offset += host_length as usize + 4; // host + port
+        if api_version >= 1 {
+            let rack_length = read_length(&mut offset, 2);
+            offset += rack_length.max(0) as usize;
+        }
+        if flexible {
+            offset += 1; // tag buffer
+        }
+    }
+    if api_version >= 2 {
+        let cluster_id_length = read_length(&mut offset, 2);
+        offset += cluster_id_length.max(0) as usize;
+    }
+    if api_version >= 1 {
+        offset += 4; // controller_id
+    }
+    read_length(&mut offset, 4); // topics length
+    read_i16(offset)
+}
+
+fn response_error_code(api_key: i16, api_version: i16, response: &[u8]) -> i16 {
+    match api_key {
+        // an unsupported version above the range is answered in the highest supported layout
+        METADATA => metadata_topic_error_code(api_version.min(12), response),
+        // DescribeAcls v2+: correlation_id(4) + tag buffer(1) + throttle_time_ms(4) + error_code(2)
+        DESCRIBE_ACLS if api_version >= 2 => i16::from_be_bytes(response[9..11].try_into().unwrap()),
+        // DescribeAcls v0-v1: correlation_id(4) + throttle_time_ms(4) + error_code(2)
+        DESCRIBE_ACLS => i16::from_be_bytes(response[8..10].try_into().unwrap()),
+        // CreateAcls and DeleteAcls v2+: correlation_id(4) + tag buffer(1) + throttle_time_ms(4) + results length(1) + error_code(2)
+        CREATE_ACLS | DELETE_ACLS if api_version >= 2 => i16::from_be_bytes(response[10..12].try_into().unwrap()),
+        // CreateAcls and DeleteAcls v0-v1: correlation_id(4) + throttle_time_ms(4) + results length(4) + error_code(2)
+        CREATE_ACLS | DELETE_ACLS => i16::from_be_bytes(response[12..14].try_into().unwrap()),
+        // DescribeConfigs v4+: correlation_id(4) + tag buffer(1) + throttle_time_ms(4) + results length(1) + error_code(2)
+        DESCRIBE_CONFIGS if api_version >= 4 => i16::from_be_bytes(response[10..12].try_into().unwrap()),
+        // DescribeConfigs v0-v3: correlation_id(4) + throttle_time_ms(4) + results length(4) + error_code(2)
+        DESCRIBE_CONFIGS => i16::from_be_bytes(response[12..14].try_into().unwrap()),
+        // SaslAuthenticate v2+: correlation_id(4) + tag buffer(1) + error_code(2)
+        SASL_AUTHENTICATE if api_version >= 2 => i16::from_be_bytes(response[5..7].try_into().unwrap()),
+        // SaslHandshake, ApiVersions and SaslAuthenticate v0-v1: correlation_id(4) + error_code(2); the header has no tag buffer
+        SASL_HANDSHAKE | API_VERSIONS | SASL_AUTHENTICATE => i16::from_be_bytes(response[4..6].try_into().unwrap()),
+        // Delegation token APIs v2+: correlation_id(4) + tag buffer(1) + error_code(2)
+        CREATE_DELEGATION_TOKEN | RENEW_DELEGATION_TOKEN | EXPIRE_DELEGATION_TOKEN | DESCRIBE_DELEGATION_TOKEN if api_version >= 2 => {
+            i16::from_be_bytes(response[5..7].try_into().unwrap())
+        }
+        // Delegation token APIs v0-v1: correlation_id(4) + error_code(2)
+        CREATE_DELEGATION_TOKEN | RENEW_DELEGATION_TOKEN | EXPIRE_DELEGATION_TOKEN | DESCRIBE_DELEGATION_TOKEN => {
+            i16::from_be_bytes(response[4..6].try_into().unwrap())
+        }
+        // DescribeClientQuotas v1+: correlation_id(4) + tag buffer(1) + throttle_time_ms(4) + error_code(2)
+        DESCRIBE_CLIENT_QUOTAS if api_version >= 1 => i16::from_be_bytes(response[9..11].try_into().unwrap()),
+        // DescribeClientQuotas v0: correlation_id(4) + throttle_time_ms(4) + error_code(2)
+        DESCRIBE_CLIENT_QUOTAS => i16::from_be_bytes(response[8..10].try_into().unwrap()),
+        // AlterClientQuotas v1+: correlation_id(4) + tag buffer(1) + throttle_time_ms(4) + entries length(1) + error_code(2)
+        ALTER_CLIENT_QUOTAS if api_version >= 1 => i16::from_be_bytes(response[10..12].try_into().unwrap()),
+        // AlterClientQuotas v0: correlation_id(4) + throttle_time_ms(4) + entries length(4) + error_code(2)
+        ALTER_CLIENT_QUOTAS => i16::from_be_bytes(response[12..14].try_into().unwrap()),
+        // DescribeUserScramCredentials: correlation_id(4) + tag buffer(1) + throttle_time_ms(4) + error_code(2)
+        DESCRIBE_USER_SCRAM_CREDENTIALS => i16::from_be_bytes(response[9..11].try_into().unwrap()),
+        // AlterUserScramCredentials: correlation_id(4) + tag buffer(1) + throttle_time_ms(4) + results length(1) + user(11) + error_code(2)
+        ALTER_USER_SCRAM_CREDENTIALS => i16::from_be_bytes(response[21..23].try_into().unwrap()),
+        // UpdateFeatures: correlation_id(4) + tag buffer(1) + throttle_time_ms(4) + error_code(2)
+        UPDATE_FEATURES => i16::from_be_bytes(response[9..11].try_into().unwrap()),
+        // DescribeTopicPartitions: correlation_id(4) + tag buffer(1) + throttle_time_ms(4) + topics length(1) + error_code(2)
+        DESCRIBE_TOPIC_PARTITIONS => i16::from_be_bytes(response[10..12].try_into().unwrap()),
+        _ => panic!("no decoder for api_key {}", api_key),
+    }
+}
+
+fn expected_error_code(api_key: i16, supported: bool) -> i16 {
+    match (api_key, supported) {
+        (_, false) => UNSUPPORTED_VERSION,
+        // This client is on a PLAINTEXT listener, where SASL requests have no exchange to be part of
+        (SASL_HANDSHAKE, true) | (SASL_AUTHENTICATE, true) => ILLEGAL_SASL_STATE,
+        (ALTER_USER_SCRAM_CREDENTIALS, true) => RESOURCE_NOT_FOUND,
+        // The broker under test has no authorizer.class.name
+        (DESCRIBE_ACLS, true) | (CREATE_ACLS, true) | (DELETE_ACLS, true) => SECURITY_DISABLED,
+        // The broker under test has no delegation.token.secret.key
+        (CREATE_DELEGATION_TOKEN, true) | (RENEW_DELEGATION_TOKEN, true) | (EXPIRE_DELEGATION_TOKEN, true) | (DESCRIBE_DELEGATION_TOKEN, true) => {
+            DELEGATION_TOKEN_AUTH_DISABLED
+        }
+        (METADATA, true) | (DESCRIBE_CONFIGS, true) | (DESCRIBE_TOPIC_PARTITIONS, true) => UNKNOWN_TOPIC_OR_PARTITION,
+        (_, true) => 0,
+    }
+}
+
+// The UNSUPPORTED_VERSION fallback is always encoded as ApiVersions v0: an ARRAY of (api_key, min, max)
+fn parse_v0_api_keys(response: &[u8]) -> Vec<(i16, i16, i16)> {
+    let count = i32::from_be_bytes(response[6..10].try_into().unwrap());
+    let mut api_keys = Vec::new();
+    let mut offset = 10;
+    for _ in 0..count {
+        let api_key = i16::from_be_bytes(response[offset..offset + 2].try_into().unwrap());
+        let min_version = i16::from_be_bytes(response[offset + 2..offset + 4].try_into().unwrap());
+        let max_version = i16::from_be_bytes(response[offset + 4..offset + 6].try_into().unwrap());
+        api_keys.push((api_key, min_version, max_version));
+        offset += 6;
+    }
+    if offset != response.len() {
+        println!("❌ v0 fallback has {} trailing bytes", response.len() - offset);
+    }
+    api_keys
+}
+
+fn main() {
+    let mut stream = TcpStream::connect("127.0.0.1:9092").unwrap();
+    println!("Connected to server");
+
+    // Ask for an ApiVersions version the broker can't support to get the v0 fallback with its ranges
+    stream.write_all(&build_request(API_VERSIONS, i16::MAX, 1)).unwrap();
+    let fallback = read_response(&mut stream);
+    let error_code = response_error_code(API_VERSIONS, 0, &fallback);
+    let api_keys = parse_v0_api_keys(&fallback);
+    if error_code == UNSUPPORTED_VERSION && !api_keys.is_empty() {
+        println!("✅ ApiVersions v{} fell back to v0 with {} API keys", i16::MAX, api_keys.len());
+    } else {
+        println!("❌ ApiVersions fallback: error_code {}, {} API keys", error_code, api_keys.len());
+    }
+
+    let mut correlation_id = 100;
+    let mut failures = 0;
+    for (api_key, min_version, max_version) in api_keys {
+        for (api_version, supported) in [
+            (min_version - 1, false),
+            (min_version, true),
+            (max_version, true),
+            (max_version + 1, false),
+        ] {
+            if api_version < 0 {
+                continue;
+            }
+            correlation_id += 1;
+            stream.write_all(&build_request(api_key, api_version, correlation_id)).unwrap();
+            let response = read_response(&mut stream);
+
+            let response_correlation_id = i32::from_be_bytes(response[0..4].try_into().unwrap());
+            let error_code = response_error_code(api_key, api_version, &response);
+            let expected = expected_error_code(api_key, supported);
+            if response_correlation_id == correlation_id && error_code == expected {
+                println!("✅ api_key {} v{}: error_code {}", api_key, api_version, error_code);
+            } else {
+                println!("❌ api_key {} v{}: correlation_id {} (expected {}), error_code {} (expected {})",
+                         api_key, api_version, response_correlation_id, correlation_id, error_code, expected);
+                failures += 1;
+            }
+        }
+    }
+
+    println!("\n--- Version Negotiation Test Complete: {} failures ---", failures);
+}
@@ -0,0 +1,309 @@
+// The ACL authorizer: super users, ACL matching, and the ACL APIs, against a broker with authorizer.class.name set
+mod common;
+
+use common::{metadata, plain_login, Broker, Client, Request};
+
+const DESCRIBE_ACLS: i16 = 29;
+const CREATE_ACLS: i16 = 30;
+const DELETE_ACLS: i16 = 31;
+const DESCRIBE_TOPIC_PARTITIONS: i16 = 75;
+
+const TOPIC: i8 = 2;
+const CLUSTER: i8 = 4;
+const ANY: i8 = 1;
+const LITERAL: i8 = 3;
+const PREFIXED: i8 = 4;
+const READ: i8 = 3;
+const WRITE: i8 = 4;
+const ALTER: i8 = 7;
+const DESCRIBE: i8 = 8;
+const DENY: i8 = 2;
+const ALLOW: i8 = 3;
+
+const UNKNOWN_TOPIC_OR_PARTITION: i16 = 3;
+const TOPIC_AUTHORIZATION_FAILED: i16 = 29;
+const CLUSTER_AUTHORIZATION_FAILED: i16 = 31;
+
+struct Acl {
+    resource_type: i8,
+    resource_name: &'static str,
+    pattern_type: i8,
+    principal: &'static str,
+    host: &'static str,
+    operation: i8,
+    permission_type: i8,
+}
+
+fn topic_acl(pattern_type: i8, resource_name: &'static str, principal: &'static str, host: &'static str, operation: i8, permission_type: i8) -> Acl {
+    Acl { resource_type: TOPIC, resource_name, pattern_type, principal, host, operation, permission_type }
+}
+
+fn authorizer_broker() -> Broker {
+    let broker = Broker::new(&["PLAINTEXT", "SASL_PLAINTEXT"]);
+    let users = broker.write_file("users.properties", "admin=admin-secret\nalice=alice-secret\nbob=bob-secret\n");
+    broker.start(&[
+        ("sasl.enabled.mechanisms", "PLAIN"),
+        ("sasl.plain.credentials.file", &users),
+        ("authorizer.class.name", "org.apache.kafka.metadata.authorizer.StandardAuthorizer"),
+        ("super.users", "User:admin"),
+    ])
+}
+
+fn login(broker: &Broker, user: &str) -> Client {
+    let mut client = broker.connect("SASL_PLAINTEXT");
+    assert_eq!(plain_login(&mut client, user, &format!("{}-secret", user)).error_code, 0);
+    client
+}
+
+fn create_acls(client: &mut Client, acls: &[Acl]) -> Vec<i16> {
+    let mut request = Request::new(CREATE_ACLS, 3, true).array(acls.len());
+    for acl in acls {
+        request = request
+            .i8(acl.resource_type)
+            .string(acl.resource_name)
+            .i8(acl.pattern_type)
+            .string(acl.principal)
+            .string(acl.host)
+            .i8(acl.operation)
+            .i8(acl.permission_type)
+            .tags();
+    }
+    let mut response = client.send(request.tags());
+
+    response.i32(); // throttle_time_ms
+    let mut error_codes = Vec::new();
+    for _ in 0..response.array() {
+        error_codes.push(response.i16());
+        response.nullable_string(); // error_message
+        response.tags();
+    }
+    response.tags();
+    response.finish();
+    error_codes
+}
+
+// DescribeAcls for every ACL; returns the error code and (resource_name, principal, operation, permission_type) per ACL
+fn describe_all_acls(client: &mut Client) -> (i16, Vec<(String, String, i8, i8)>) {
+    let request = Request::new(DESCRIBE_ACLS, 3, true)
+        .i8(ANY)
+        .nullable_string(None)
+        .i8(ANY)
+        .nullable_string(None)
+        .nullable_string(None)
+        .i8(ANY)
+        .i8(ANY)
+        .tags();
+    let mut response = client.send(request);
+
+    response.i32(); // throttle_time_ms
+    let error_code = response.i16();
+    response.nullable_string(); // error_message
+    let mut acls = Vec::new();
+    for _ in 0..response.array() {
+        response.i8(); // resource_type
+        let resource_name = response.string();
+        response.i8(); // pattern_type
+        for _ in 0..response.array() {
+            let principal = response.string();
+            response.string(); // host
+            acls.push((resource_name.clone(), principal, response.i8(), response.i8()));
+            response.tags();
+        }
+        response.tags();
+    }
+    response.tags();
+    response.finish();
+    acls.sort();
+    (error_code, acls)
+}
+
+// DeleteAcls with one filter matching every ACL of a principal; returns the filter's error code and match count
+fn delete_acls_of(client: &mut Client, principal: &str) -> (i16, i32) {
+    let request = Request::new(DELETE_ACLS, 3, true)
+        .array(1)
+        .i8(ANY)
+        .nullable_string(None)
+        .i8(ANY)
+        .string(principal)
+        .nullable_string(None)
+        .i8(ANY)
+        .i8(ANY)
+        .tags()
+        .tags();
+    let mut response = client.send(request);
+
+    response.i32(); // throttle_time_ms
+    assert_eq!(response.array(), 1);
+    let error_code = response.i16();
+    response.nullable_string(); // error_message
+    let matches = response.array();
+    for _ in 0..matches {
+        response.i16(); // error_code
+        response.nullable_string(); // error_message
+        response.i8(); // resource_type
+        response.string(); // resource_name
+        response.i8(); // pattern_type
+        response.string(); // principal
+        response.string(); // host
+        response.i8(); // operation
+        response.i8(); // permission_type
+        response.tags();
+    }
+    response.tags();
+    response.tags();
+    response.finish();
+    (error_code, matches)
+}
+
+fn describe_topic_partitions(client: &mut Client, topics: &[&str]) -> Vec<(String, i16)> {
+    let mut request = Request::new(DESCRIBE_TOPIC_PARTITIONS, 0, true).array(topics.len());
+    for topic in topics {
+        request = request.string(topic).tags();
+    }
+    let mut response = client.send(request.i32(100).raw(&[0xff]).tags()); // response_partition_limit, null cursor
+
+    response.i32(); // throttle_time_ms
+    let mut results = Vec::new();
+    for _ in 0..response.array() {
+        let error_code = response.i16();
+        let name = response.nullable_string().unwrap_or_default();
+        response.skip(17); // topic_id, is_internal
+        assert!(response.array() <= 0, "no partitions");
+        response.i32(); // topic_authorized_operations
+        response.tags();
+        results.push((name, error_code));
+    }
+    // next_cursor follows; it has no bearing on authorization
+    results
+}
+
+// Metadata and DescribeTopicPartitions must agree on each topic: unknown when describable, else unauthorized
+fn assert_topic_errors(client: &mut Client, expected: &[(&str, i16)]) {
+    let topics: Vec<&str> = expected.iter().map(|(topic, _)| *topic).collect();
+    let expected: Vec<(String, i16)> = expected.iter().map(|(topic, error_code)| (topic.to_string(), *error_code)).collect();
+    assert_eq!(metadata(client, "authorizer", &topics).0, expected, "Metadata");
+    assert_eq!(describe_topic_partitions(client, &topics), expected, "DescribeTopicPartitions");
+}
+
+#[test]
+fn super_users_are_allowed_everything() {
+    let broker = authorizer_broker();
+
+    let mut admin = login(&broker, "admin");
+    assert_topic_errors(&mut admin, &[("orders", UNKNOWN_TOPIC_OR_PARTITION)]);
+    assert_eq!(describe_all_acls(&mut admin), (0, Vec::new()));
+
+    // Everyone else is denied without a matching ACL, including the anonymous PLAINTEXT principal
+    let mut alice = login(&broker, "alice");
+    assert_topic_errors(&mut alice, &[("orders", TOPIC_AUTHORIZATION_FAILED)]);
+    assert_eq!(describe_all_acls(&mut alice).0, CLUSTER_AUTHORIZATION_FAILED);
+    let mut anonymous = broker.connect("PLAINTEXT");
+    assert_topic_errors(&mut anonymous, &[("orders", TOPIC_AUTHORIZATION_FAILED)]);
+}
+
+#[test]
+fn acl_apis_need_cluster_permissions() {
+    let broker = authorizer_broker();
+    let mut alice = login(&broker, "alice");
+    let mut admin = login(&broker, "admin");
+
+    let acl = topic_acl(LITERAL, "orders", "User:alice", "*", READ, ALLOW);
+    assert_eq!(create_acls(&mut alice, &[acl]), vec![CLUSTER_AUTHORIZATION_FAILED]);
+    assert_eq!(delete_acls_of(&mut alice, "User:alice").0, CLUSTER_AUTHORIZATION_FAILED);
+
+    // ALTER on the cluster allows creating and deleting ACLs, DESCRIBE allows listing them
+    let cluster_acls = [
+        Acl { resource_type: CLUSTER, resource_name: "kafka-cluster", pattern_type: LITERAL, principal: "User:alice", host: "*", operation: ALTER, permission_type: ALLOW },
+        Acl { resource_type: CLUSTER, resource_name: "kafka-cluster", pattern_type: LITERAL, principal: "User:alice", host: "*", operation: DESCRIBE, permission_type: ALLOW },
+    ];
+    assert_eq!(create_acls(&mut admin, &cluster_acls), vec![0, 0]);
+    let acl = topic_acl(LITERAL, "orders", "User:bob", "*", READ, ALLOW);
+    assert_eq!(create_acls(&mut alice, &[acl]), vec![0]);
+    assert_eq!(describe_all_acls(&mut alice).1.len(), 3);
+    assert_eq!(delete_acls_of(&mut alice, "User:bob"), (0, 1));
+}
+
+#[test]
+fn deny_wins_over_allow_and_operations_imply_describe() {
+    let broker = authorizer_broker();
+    let mut admin = login(&broker, "admin");
+    let acls = [
+        // READ and WRITE imply DESCRIBE
+        topic_acl(LITERAL, "orders", "User:alice", "*", READ, ALLOW),
+        topic_acl(LITERAL, "invoices", "User:alice", "*", WRITE, ALLOW),
+        // A prefixed ALLOW for every pay* topic, with one literal DENY inside it
+        topic_acl(PREFIXED, "pay", "User:alice", "*", DESCRIBE, ALLOW),
+        topic_acl(LITERAL, "payroll", "User:alice", "*", DESCRIBE, DENY),
+        // A DENY of READ doesn't deny DESCRIBE; a wildcard principal ALLOW applies to everyone
+        topic_acl(LITERAL, "audit", "User:alice", "*", READ, DENY),
+        topic_acl(LITERAL, "audit", "User:*", "*", DESCRIBE, ALLOW),
+        topic_acl(LITERAL, "restricted", "User:*", "*", DESCRIBE, ALLOW),
+        topic_acl(LITERAL, "restricted", "User:alice", "*", DESCRIBE, DENY),
+    ];
+    assert_eq!(create_acls(&mut admin, &acls), vec![0; 8]);
+
+    let mut alice = login(&broker, "alice");
+    assert_topic_errors(&mut alice, &[
+        ("orders", UNKNOWN_TOPIC_OR_PARTITION),
+        ("invoices", UNKNOWN_TOPIC_OR_PARTITION),
+        ("payments", UNKNOWN_TOPIC_OR_PARTITION),
+        ("pay", UNKNOWN_TOPIC_OR_PARTITION),
+        ("payroll", TOPIC_AUTHORIZATION_FAILED),
+        ("pa", TOPIC_AUTHORIZATION_FAILED),
+        ("audit", UNKNOWN_TOPIC_OR_PARTITION),
+        ("restricted", TOPIC_AUTHORIZATION_FAILED),
+        ("orders-archive", TOPIC_AUTHORIZATION_FAILED),
+    ]);
+
+    let mut bob = login(&broker, "bob");
+    assert_topic_errors(&mut bob, &[
+        ("orders", TOPIC_AUTHORIZATION_FAILED),
+        ("payments", TOPIC_AUTHORIZATION_FAILED),
+        ("audit", UNKNOWN_TOPIC_OR_PARTITION),
+        ("restricted", UNKNOWN_TOPIC_OR_PARTITION),
+    ]);
+}
+
+#[test]
+fn acls_match_the_client_host() {
+    let broker = authorizer_broker();
+    let mut admin = login(&broker, "admin");
+    let acls = [
+        topic_acl(LITERAL, "local", "User:alice", "127.0.0.1", DESCRIBE, ALLOW),
+        topic_acl(LITERAL, "remote", "User:alice", "10.0.0.1", DESCRIBE, ALLOW),
+        topic_acl(LITERAL, "anywhere", "User:alice", "*", DESCRIBE, ALLOW),
+        topic_acl(LITERAL, "anywhere", "User:alice", "127.0.0.1", DESCRIBE, DENY),
+    ];
+    assert_eq!(create_acls(&mut admin, &acls), vec![0; 4]);
+
+    let mut alice = login(&broker, "alice");
+    assert_topic_errors(&mut alice, &[
+        ("local", UNKNOWN_TOPIC_OR_PARTITION),
+        ("remote", TOPIC_AUTHORIZATION_FAILED),
+        ("anywhere", TOPIC_AUTHORIZATION_FAILED),
+    ]);
+}
+
+#[test]
+fn acls_are_replayed_from_the_metadata_log() {
+    let mut broker = authorizer_broker();
+    let mut admin = login(&broker, "admin");
+    let acls = [
+        topic_acl(PREFIXED, "pay", "User:alice", "*", READ, ALLOW),
+        topic_acl(LITERAL, "payroll", "User:alice", "*", DESCRIBE, DENY),
+        topic_acl(LITERAL, "orders", "User:bob", "*", READ, ALLOW),
+    ];
+    assert_eq!(create_acls(&mut admin, &acls), vec![0; 3]);
+    assert_eq!(delete_acls_of(&mut admin, "User:bob"), (0, 1));
+    let (_, acls_before_restart) = describe_all_acls(&mut admin);
+
+    broker.restart();
+
+    let mut admin = login(&broker, "admin");
+    assert_eq!(describe_all_acls(&mut admin), (0, acls_before_restart));
+    assert_eq!(describe_all_acls(&mut admin).1.len(), 2);
+    let mut alice = login(&broker, "alice");
+    assert_topic_errors(&mut alice, &[("payments", UNKNOWN_TOPIC_OR_PARTITION), ("payroll", TOPIC_AUTHORIZATION_FAILED)]);
+    let mut bob = login(&broker, "bob");
+    assert_topic_errors(&mut bob, &[("orders", TOPIC_AUTHORIZATION_FAILED)]);
+}
@@ -0,0 +1,658 @@
+// Shared by the integration tests: each test starts its own broker process on free ports, with a
+// scratch directory for its config and logs, and talks to it over the wire protocol
+#![allow(dead_code)]
+
+use std::fs;
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::num::NonZeroU32;
+use std::path::PathBuf;
+use std::process::{Child, Command, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use ring::rand::{SecureRandom, SystemRandom};
+use ring::{digest, hmac, pbkdf2};
+
+pub const API_VERSIONS: i16 = 18;
+pub const METADATA: i16 = 3;
+pub const SASL_HANDSHAKE: i16 = 17;
+pub const SASL_AUTHENTICATE: i16 = 36;
+pub const CREATE_DELEGATION_TOKEN: i16 = 38;
+
+const STARTUP_TIMEOUT: Duration = Duration::from_secs(10);
+const LOG_TIMEOUT: Duration = Duration::from_secs(10);
+const READ_TIMEOUT: Duration = Duration::from_secs(10);
+
+static NEXT_DIRECTORY: AtomicUsize = AtomicUsize::new(0);
+
+pub fn fixture(name: &str) -> String {
+    format!("{}/tests/fixtures/{}", env!("CARGO_MANIFEST_DIR"), name)
+}
+
+// A broker process. Each listener is named after its security protocol.
+pub struct Broker {
+    child: Option<Child>,
+    directory: PathBuf,
+    listeners: Vec<(String, u16)>,
+    properties: Vec<(String, String)>,
+}
+
+impl Broker {
+    pub fn new(listeners: &[&str]) -> Broker {
+        let directory = std::env::temp_dir().join(format!(
+            "codecrafters-kafka-test-{}-{}",
+            std::process::id(),
+            NEXT_DIRECTORY.fetch_add(1, Ordering::SeqCst)
+        ));
+        let _ = fs::remove_dir_all(&directory);
+        fs::create_dir_all(&directory).unwrap();
+        let listeners = listeners.iter().map(|name| (name.to_string(), free_port())).collect();
+        Broker { child: None, directory, listeners, properties: Vec::new() }
+    }
+
+    // Writes a file into the broker's directory and returns its path
+    pub fn write_file(&self, name: &str, contents: impl AsRef<[u8]>) -> String {
+        let path = self.directory.join(name);
+        fs::write(&path, contents).unwrap();
+        path.to_string_lossy().into_owned()
+    }
+
+    pub fn start(mut self, properties: &[(&str, &str)]) -> Broker {
+        self.properties = properties.iter().map(|(key, value)| (key.to_string(), value.to_string())).collect();
+        self.spawn();
+        self
+    }
+
+    // Stops the broker and starts it again on the same ports and log directory
+    pub fn restart(&mut self) {
+        self.stop();
+        self.spawn();
+    }
+
+    pub fn port(&self, listener: &str) -> u16 {
+        self.listeners.iter().find(|(name, _)| name == listener).map(|(_, port)| *port).unwrap()
+    }
+
+    pub fn connect(&self, listener: &str) -> Client {
+        Client::connect(self.port(listener))
+    }
+
+    // Everything the broker printed so far
+    pub fn log(&self) -> String {
+        fs::read_to_string(self.directory.join("broker.log")).unwrap_or_default()
+    }
+
+    // Connections can be closed before the broker logs why, so a test polls for the line
+    pub fn wait_for_log(&self, text: &str) -> bool {
+        let started = Instant::now();
+        while !self.log().contains(text) {
+            if started.elapsed() > LOG_TIMEOUT {
+                return false;
+            }
+            thread::sleep(Duration::from_millis(20));
+        }
+        true
+    }
+
+    fn spawn(&mut self) {
+        let listeners: Vec<String> = self.listeners.iter().map(|(name, port)| format!("{}://127.0.0.1:{}", name, port)).collect();
+        let mut config = format!("node.id=1\nlisteners={}\nlog.dirs={}\n",
+                                 listeners.join(","), self.directory.join("logs").display());
+        for (key, value) in &self.properties {
+            config.push_str(&format!("{}={}\n", key, value));
+        }
+        let config_path = self.write_file("server.properties", config);
+
+        let log = fs::OpenOptions::new().create(true).append(true).open(self.directory.join("broker.log")).unwrap();
+        let child = Command::new(env!("CARGO_BIN_EXE_codecrafters-kafka"))
+            .arg(&config_path)
+            .stdout(Stdio::from(log.try_clone().unwrap()))
+            .stderr(Stdio::from(log))
+            .spawn()
+            .unwrap();
+        self.child = Some(child);
+
+        let started = Instant::now();
+        for (_, port) in &self.listeners {
+            while TcpStream::connect(("127.0.0.1", *port)).is_err() {
+                if let Some(status) = self.child.as_mut().unwrap().try_wait().unwrap() {
+                    panic!("broker exited with {} during startup:\n{}", status, self.log());
+                }
+                assert!(started.elapsed() < STARTUP_TIMEOUT, "broker did not start listening:\n{}", self.log());
+                thread::sleep(Duration::from_millis(20));
+            }
+        }
+    }
+
+    fn stop(&mut self) {
+        if let Some(mut child) = self.child.take() {
+            let _ = child.kill();
+            let _ = child.wait();
+        }
+    }
+}
+
+impl Drop for Broker {
+    fn drop(&mut self) {
+        self.stop();
+        let _ = fs::remove_dir_all(&self.directory);
+    }
+}
+
+fn free_port() -> u16 {
+    TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port()
+}
+
+// A request body in the encoding of its version: compact strings, arrays and tag buffers when flexible
+pub struct Request {
+    api_key: i16,
+    api_version: i16,
+    flexible: bool,
+    client_id: String,
+    body: Vec<u8>,
+}
+
+impl Request {
+    pub fn new(api_key: i16, api_version: i16, flexible: bool) -> Request {
+        Request { api_key, api_version, flexible, client_id: "integration-test".to_string(), body: Vec::new() }
+    }
+
+    pub fn client_id(mut self, client_id: &str) -> Request {
+        self.client_id = client_id.to_string();
+        self
+    }
+
+    pub fn raw(mut self, bytes: &[u8]) -> Request {
+        self.body.extend_from_slice(bytes);
+        self
+    }
+
+    pub fn i8(self, value: i8) -> Request {
+        self.raw(&value.to_be_bytes())
+    }
+
+    pub fn i16(self, value: i16) -> Request {
+        self.raw(&value.to_be_bytes())
+    }
+
+    pub fn i32(self, value: i32) -> Request {
+        self.raw(&value.to_be_bytes())
+    }
+
+    pub fn i64(self, value: i64) -> Request {
+        self.raw(&value.to_be_bytes())
+    }
+
+    pub fn f64(self, value: f64) -> Request {
+        self.raw(&value.to_be_bytes())
+    }
+
+    pub fn bool(self, value: bool) -> Request {
+        self.raw(&[value as u8])
+    }
+
+    pub fn string(self, value: &str) -> Request {
+        self.nullable_string(Some(value))
+    }
+
+    pub fn nullable_string(mut self, value: Option<&str>) -> Request {
+        match (value, self.flexible) {
+            (None, true) => self.body.push(0),
+            (None, false) => self.body.extend_from_slice(&(-1i16).to_be_bytes()),
+            (Some(value), true) => {
+                write_unsigned_varint(&mut self.body, value.len() as u32 + 1);
+                self.body.extend_from_slice(value.as_bytes());
+            }
+            (Some(value), false) => {
+                self.body.extend_from_slice(&(value.len() as i16).to_be_bytes());
+                self.body.extend_from_slice(value.as_bytes());
+            }
+        }
+        self
+    }
+
+    pub fn bytes(mut self, value: &[u8]) -> Request {
+        if self.flexible {
+            write_unsigned_varint(&mut self.body, value.len() as u32 + 1);
+        } else {
+            self.body.extend_from_slice(&(value.len() as i32).to_be_bytes());
+        }
+        self.raw(value)
+    }
+
+    pub fn array(mut self, length: usize) -> Request {
+        if self.flexible {
+            write_unsigned_varint(&mut self.body, length as u32 + 1);
+            self
+        } else {
+            self.i32(length as i32)
+        }
+    }
+
+    pub fn null_array(self) -> Request {
+        if self.flexible {
+            self.raw(&[0])
+        } else {
+            self.i32(-1)
+        }
+    }
+
+    // An empty tag buffer; nothing in non-flexible versions
+    pub fn tags(self) -> Request {
+        if self.flexible {
+            self.raw(&[0])
+        } else {
+            self
+        }
+    }
+
+    fn encode(&self, correlation_id: i32) -> Vec<u8> {
+        let mut message = Vec::new();
+        message.extend_from_slice(&self.api_key.to_be_bytes());
+        message.extend_from_slice(&self.api_version.to_be_bytes());
+        message.extend_from_slice(&correlation_id.to_be_bytes());
+        message.extend_from_slice(&(self.client_id.len() as i16).to_be_bytes());
+        message.extend_from_slice(self.client_id.as_bytes());
+        if self.flexible {
+            message.push(0); // request header tag buffer
+        }
+        message.extend_from_slice(&self.body);
+
+        let mut frame = (message.len() as i32).to_be_bytes().to_vec();
+        frame.extend_from_slice(&message);
+        frame
+    }
+}
+
+// A response body, read field by field in the encoding of its version
+pub struct Response {
+    data: Vec<u8>,
+    position: usize,
+    flexible: bool,
+}
+
+impl Response {
+    fn take(&mut self, length: usize) -> &[u8] {
+        assert!(self.position + length <= self.data.len(), "response ends {} bytes early", self.position + length - self.data.len());
+        let bytes = &self.data[self.position..self.position + length];
+        self.position += length;
+        bytes
+    }
+
+    pub fn skip(&mut self, length: usize) {
+        self.take(length);
+    }
+
+    pub fn i8(&mut self) -> i8 {
+        self.take(1)[0] as i8
+    }
+
+    pub fn i16(&mut self) -> i16 {
+        i16::from_be_bytes(self.take(2).try_into().unwrap())
+    }
+
+    pub fn i32(&mut self) -> i32 {
+        i32::from_be_bytes(self.take(4).try_into().unwrap())
+    }
+
+    pub fn i64(&mut self) -> i64 {
+        i64::from_be_bytes(self.take(8).try_into().unwrap())
+    }
+
+    pub fn f64(&mut self) -> f64 {
+        f64::from_be_bytes(self.take(8).try_into().unwrap())
+    }
+
+    pub fn bool(&mut self) -> bool {
+        self.take(1)[0] != 0
+    }
+
+    fn unsigned_varint(&mut self) -> u32 {
+        let mut value = 0u32;
+        let mut shift = 0;
+        loop {
+            let byte = self.take(1)[0];
+            value |= ((byte & 0x7f) as u32) << shift;
+            if byte & 0x80 == 0 {
+                return value;
+            }
+            shift += 7;
+        }
+    }
+
+    // Length of a string, bytes or array field; -1 is null
+    fn length(&mut self, nonflexible_width: usize) -> i32 {
+        if self.flexible {
+            self.unsigned_varint() as i32 - 1
+        } else if nonflexible_width == 2 {
+            self.i16() as i32
+        } else {
+            self.i32()
+        }
+    }
+
+    pub fn string(&mut self) -> String {
+        self.nullable_string().expect("non-null string")
+    }
+
+    pub fn nullable_string(&mut self) -> Option<String> {
+        let length = self.length(2);
+        (length >= 0).then(|| String::from_utf8(self.take(length as usize).to_vec()).unwrap())
+    }
+
+    pub fn bytes(&mut self) -> Vec<u8> {
+        let length = self.length(4);
+        self.take(length.max(0) as usize).to_vec()
+    }
+
+    pub fn array(&mut self) -> i32 {
+        self.length(4)
+    }
+
+    pub fn tags(&mut self) {
+        if self.flexible {
+            for _ in 0..self.unsigned_varint() {
+                self.unsigned_varint();
+                let size = self.unsigned_varint() as usize;
+                self.skip(size);
+            }
+        }
+    }
+
+    // Asserts that every byte of the response was read
+    pub fn finish(&self) {
+        assert_eq!(self.position, self.data.len(), "response has {} unread bytes", self.data.len() - self.position);
+    }
+}
+
+pub trait Stream: Read + Write + Send {}
+
+impl<T: Read + Write + Send> Stream for T {}
+
+pub struct Client {
+    stream: Box<dyn Stream>,
+    next_correlation_id: i32,
+}
+
+impl Client {
+    pub fn connect(port: u16) -> Client {
+        let stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+        stream.set_read_timeout(Some(READ_TIMEOUT)).unwrap();
+        Client::over(stream)
+    }
+
+    pub fn over(stream: impl Stream + 'static) -> Client {
+        Client { stream: Box::new(stream), next_correlation_id: 1 }
+    }
+
+    pub fn send(&mut self, request: Request) -> Response {
+        self.try_send(request).expect("response")
+    }
+
+    // Fails when the broker closed the connection instead of answering
+    pub fn try_send(&mut self, request: Request) -> io::Result<Response> {
+        let correlation_id = self.next_correlation_id;
+        self.next_correlation_id += 1;
+        self.stream.write_all(&request.encode(correlation_id))?;
+
+        let mut size = [0u8; 4];
+        self.stream.read_exact(&mut size)?;
+        let mut data = vec![0u8; i32::from_be_bytes(size) as usize];
+        self.stream.read_exact(&mut data)?;
+
+        let mut response = Response { data, position: 0, flexible: request.flexible };
+        assert_eq!(response.i32(), correlation_id, "correlation id");
+        // ApiVersions responses keep header v0 so any client can read them
+        if request.api_key != API_VERSIONS {
+            response.tags();
+        }
+        Ok(response)
+    }
+
+    // Whether the broker has closed the connection: a read sees end of stream or an error
+    pub fn is_closed(&mut self) -> bool {
+        let mut byte = [0u8; 1];
+        !matches!(self.stream.read(&mut byte), Ok(1..))
+    }
+}
+
+pub fn write_unsigned_varint(buffer: &mut Vec<u8>, mut value: u32) {
+    while value >= 0x80 {
+        buffer.push((value as u8 & 0x7f) | 0x80);
+        value >>= 7;
+    }
+    buffer.push(value as u8);
+}
+
+// Metadata v12 for the named topics; returns each topic's (name, error_code) and the throttle time
+pub fn metadata(client: &mut Client, client_id: &str, topics: &[&str]) -> (Vec<(String, i16)>, i32) {
+    let mut request = Request::new(METADATA, 12, true).client_id(client_id).array(topics.len());
+    for topic in topics {
+        request = request.raw(&[0; 16]).string(topic).tags(); // topic_id, name
+    }
+    let mut response = client.send(request.bool(false).bool(false).tags());
+
+    let throttle_time_ms = response.i32();
+    for _ in 0..response.array() {
+        response.i32(); // node_id
+        response.string(); // host
+        response.i32(); // port
+        response.nullable_string(); // rack
+        response.tags();
+    }
+    response.nullable_string(); // cluster_id
+    response.i32(); // controller_id
+    let mut results = Vec::new();
+    for _ in 0..response.array() {
+        let error_code = response.i16();
+        let name = response.nullable_string().unwrap_or_default();
+        response.skip(17); // topic_id, is_internal
+        assert!(response.array() <= 0, "no partitions");
+        response.i32(); // topic_authorized_operations
+        response.tags();
+        results.push((name, error_code));
+    }
+    response.tags();
+    response.finish();
+    (results, throttle_time_ms)
+}
+
+pub struct SaslAuthenticateResponse {
+    pub error_code: i16,
+    pub error_message: Option<String>,
+    pub auth_bytes: Vec<u8>,
+    pub session_lifetime_ms: i64,
+}
+
+pub fn sasl_handshake(client: &mut Client, mechanism: &str) -> i16 {
+    let mut response = client.send(Request::new(SASL_HANDSHAKE, 1, false).string(mechanism));
+    let error_code = response.i16();
+    for _ in 0..response.array() {
+        response.string();
+    }
+    response.finish();
+    error_code
+}
+
+pub fn sasl_authenticate(client: &mut Client, auth_bytes: &[u8]) -> SaslAuthenticateResponse {
+    let mut response = client.send(Request::new(SASL_AUTHENTICATE, 2, true).bytes(auth_bytes).tags());
+    let result = SaslAuthenticateResponse {
+        error_code: response.i16(),
+        error_message: response.nullable_string(),
+        auth_bytes: response.bytes(),
+        session_lifetime_ms: response.i64(),
+    };
+    response.tags();
+    response.finish();
+    result
+}
+
+pub fn plain_login(client: &mut Client, user: &str, password: &str) -> SaslAuthenticateResponse {
+    assert_eq!(sasl_handshake(client, "PLAIN"), 0);
+    sasl_authenticate(client, format!("\0{}\0{}", user, password).as_bytes())
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum ScramMechanism {
+    Sha256,
+    Sha512,
+}
+
+impl ScramMechanism {
+    pub fn name(self) -> &'static str {
+        match self {
+            ScramMechanism::Sha256 => "SCRAM-SHA-256",
+            ScramMechanism::Sha512 => "SCRAM-SHA-512",
+        }
+    }
+
+    // The mechanism field of AlterUserScramCredentials
+    pub fn type_code(self) -> i8 {
+        match self {
+            ScramMechanism::Sha256 => 1,
+            ScramMechanism::Sha512 => 2,
+        }
+    }
+
+    fn hmac(self, key: &[u8], data: &[u8]) -> Vec<u8> {
+        let algorithm = match self {
+            ScramMechanism::Sha256 => hmac::HMAC_SHA256,
+            ScramMechanism::Sha512 => hmac::HMAC_SHA512,
+        };
+        hmac::sign(&hmac::Key::new(algorithm, key), data).as_ref().to_vec()
+    }
+
+    fn hash(self, data: &[u8]) -> Vec<u8> {
+        let algorithm = match self {
+            ScramMechanism::Sha256 => &digest::SHA256,
+            ScramMechanism::Sha512 => &digest::SHA512,
+        };
+        digest::digest(algorithm, data).as_ref().to_vec()
+    }
+
+    // RFC 5802 Hi(password, salt, iterations)
+    pub fn salted_password(self, password: &str, salt: &[u8], iterations: u32) -> Vec<u8> {
+        let (algorithm, length) = match self {
+            ScramMechanism::Sha256 => (pbkdf2::PBKDF2_HMAC_SHA256, 32),
+            ScramMechanism::Sha512 => (pbkdf2::PBKDF2_HMAC_SHA512, 64),
+        };
+        let mut salted_password = vec![0u8; length];
+        pbkdf2::derive(algorithm, NonZeroU32::new(iterations).unwrap(), salt, password.as_bytes(), &mut salted_password);
+        salted_password
+    }
+}
+
+// The client side of a SCRAM exchange (RFC 5802), with extensions such as ",tokenauth=true" added to
+// the client first message. Returns the first response that failed, or the final one after checking
+// the server signature.
+pub fn scram_login(client: &mut Client, mechanism: ScramMechanism, user: &str, password: &str, extensions: &str) -> SaslAuthenticateResponse {
+    assert_eq!(sasl_handshake(client, mechanism.name()), 0);
+    let mut nonce = [0u8; 18];
+    SystemRandom::new().fill(&mut nonce).unwrap();
+    let client_nonce = base64_encode(&nonce);
+    let client_first_bare = format!("n={},r={}{}", user.replace('=', "=3D").replace(',', "=2C"), client_nonce, extensions);
+
+    let server_first = sasl_authenticate(client, format!("n,,{}", client_first_bare).as_bytes());
+    if server_first.error_code != 0 {
+        return server_first;
+    }
+    let server_first_message = String::from_utf8(server_first.auth_bytes).unwrap();
+    let attribute = |name: &str| {
+        server_first_message.split(',').find_map(|field| field.strip_prefix(name)).unwrap().to_string()
+    };
+    let nonce = attribute("r=");
+    assert!(nonce.starts_with(&client_nonce), "server nonce extends the client nonce");
+    let salt = base64_decode(&attribute("s="));
+    let iterations: u32 = attribute("i=").parse().unwrap();
+
+    let salted_password = mechanism.salted_password(password, &salt, iterations);
+    let client_key = mechanism.hmac(&salted_password, b"Client Key");
+    let stored_key = mechanism.hash(&client_key);
+    let client_final_without_proof = format!("c=biws,r={}", nonce);
+    let auth_message = format!("{},{},{}", client_first_bare, server_first_message, client_final_without_proof);
+    let client_signature = mechanism.hmac(&stored_key, auth_message.as_bytes());
+    let proof: Vec<u8> = client_key.iter().zip(&client_signature).map(|(key, signature)| key ^ signature).collect();
+
+    let server_final = sasl_authenticate(client, format!("{},p={}", client_final_without_proof, base64_encode(&proof)).as_bytes());
+    if server_final.error_code == 0 {
+        let server_key = mechanism.hmac(&salted_password, b"Server Key");
+        let server_signature = mechanism.hmac(&server_key, auth_message.as_bytes());
+        assert_eq!(String::from_utf8(server_final.auth_bytes.clone()).unwrap(), format!("v={}", base64_encode(&server_signature)));
+    }
+    server_final
+}
+
+pub struct CreatedToken {
+    pub error_code: i16,
+    pub owner: String,
+    pub expiry_timestamp_ms: i64,
+    pub token_id: String,
+    pub hmac: Vec<u8>,
+}
+
+// CreateDelegationToken v3 for the connection's own principal, which the response names as the owner
+pub fn create_delegation_token(client: &mut Client, renewers: &[&str]) -> CreatedToken {
+    let mut request = Request::new(CREATE_DELEGATION_TOKEN, 3, true)
+        .nullable_string(None) // owner_principal_type
+        .nullable_string(None) // owner_principal_name
+        .array(renewers.len());
+    for renewer in renewers {
+        request = request.string("User").string(renewer).tags();
+    }
+    let mut response = client.send(request.i64(-1).tags());
+
+    let error_code = response.i16();
+    response.string(); // principal_type
+    let owner = response.string();
+    response.string(); // token_requester_principal_type
+    response.string(); // token_requester_principal_name
+    response.i64(); // issue_timestamp_ms
+    let expiry_timestamp_ms = response.i64();
+    response.i64(); // max_timestamp_ms
+    let token_id = response.string();
+    let hmac = response.bytes();
+    response.i32(); // throttle_time_ms
+    response.tags();
+    response.finish();
+    CreatedToken { error_code, owner, expiry_timestamp_ms, token_id, hmac }
+}
+
+const BASE64_ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+pub fn base64_encode(data: &[u8]) -> String {
+    let mut encoded = String::new();
+    for chunk in data.chunks(3) {
+        let bits = chunk.iter().enumerate().fold(0u32, |bits, (index, byte)| bits | (*byte as u32) << (16 - 8 * index));
+        for index in 0..4 {
+            if index <= chunk.len() {
+                encoded.push(BASE64_ALPHABET[(bits >> (18 - 6 * index) & 0x3f) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+// Unpadded base64url, as JWTs use
+pub fn base64_url_encode(data: &[u8]) -> String {
+    base64_encode(data).trim_end_matches('=').replace('+', "-").replace('/', "_")
+}
+
+pub fn base64_decode(encoded: &str) -> Vec<u8> {
+    let mut decoded = Vec::new();
+    let mut bits = 0u32;
+    let mut bit_count = 0;
+    for c in encoded.bytes().filter(|c| *c != b'=') {
+        let value = BASE64_ALPHABET.iter().position(|letter| *letter == c).expect("base64 character") as u32;
+        bits = bits << 6 | value;
+        bit_count += 6;
+        if bit_count >= 8 {
+            bit_count -= 8;
+            decoded.push((bits >> bit_count) as u8);
+        }
+    }
+    decoded
+}
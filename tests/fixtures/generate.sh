@@ -0,0 +1,50 @@
+#!/bin/sh
+# Regenerates the SSL and OAUTHBEARER test fixtures. Certificates are valid for 100 years.
+set -e
+cd "$(dirname "$0")"
+days=36500
+
+openssl req -x509 -newkey rsa:2048 -nodes -keyout ca.key -out ca.pem -days $days -subj "/CN=Test CA"
+
+# Broker keystores: the certificate followed by its private key. server2 replaces server in the reload test.
+for name in server server2; do
+    openssl req -newkey rsa:2048 -nodes -keyout $name.key -out $name.csr -subj "/O=$name/CN=localhost"
+    printf 'subjectAltName=DNS:localhost,IP:127.0.0.1\n' > $name.ext
+    openssl x509 -req -in $name.csr -CA ca.pem -CAkey ca.key -CAcreateserial -days $days -extfile $name.ext -out $name.crt
+    cat $name.crt $name.key > $name-keystore.pem
+    rm $name.csr $name.ext $name.crt $name.key
+done
+
+# Client certificates
+openssl req -newkey rsa:2048 -nodes -keyout alice.key -out alice.csr -subj "/O=Example/OU=ServiceUsers/CN=alice"
+openssl x509 -req -in alice.csr -CA ca.pem -CAkey ca.key -CAcreateserial -days $days -out alice.pem
+openssl req -newkey rsa:2048 -nodes -keyout bob.key -out bob.csr -subj "/O=Example/OU=Other/CN=bob"
+openssl x509 -req -in bob.csr -CA ca.pem -CAkey ca.key -CAcreateserial -days $days -out bob.pem
+rm alice.csr bob.csr ca.srl
+
+# JWT signing keys (PKCS#8) and the JWKS document with their public halves
+openssl genpkey -algorithm RSA -pkeyopt rsa_keygen_bits:2048 -out jwt-rsa-1.key
+openssl genpkey -algorithm RSA -pkeyopt rsa_keygen_bits:2048 -out jwt-rsa-2.key
+openssl genpkey -algorithm EC -pkeyopt ec_paramgen_curve:P-256 -out jwt-ec-1.key
+python3 - <<'PYTHON'
+import base64, json, subprocess
+
+def b64url(data):
+    return base64.urlsafe_b64encode(data).rstrip(b"=").decode()
+
+def rsa_jwk(kid):
+    modulus = subprocess.check_output(["openssl", "rsa", "-in", f"jwt-{kid}.key", "-noout", "-modulus"]).decode()
+    n = bytes.fromhex(modulus.strip().split("=", 1)[1])
+    return {"kty": "RSA", "use": "sig", "alg": "RS256", "kid": kid, "n": b64url(n), "e": "AQAB"}
+
+def ec_jwk(kid):
+    der = subprocess.check_output(["openssl", "pkey", "-in", f"jwt-{kid}.key", "-pubout", "-outform", "DER"])
+    point = der[-65:]
+    return {"kty": "EC", "use": "sig", "alg": "ES256", "crv": "P-256", "kid": kid,
+            "x": b64url(point[1:33]), "y": b64url(point[33:])}
+
+keys = [rsa_jwk("rsa-1"), rsa_jwk("rsa-2"), ec_jwk("ec-1")]
+with open("jwks.json", "w") as jwks:
+    json.dump({"keys": keys}, jwks, indent=2)
+    jwks.write("\n")
+PYTHON
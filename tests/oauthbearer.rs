@@ -0,0 +1,230 @@
+// OAUTHBEARER authentication: JWT signatures, claim validation and the RFC 7628 error exchange
+mod common;
+
+use std::fs;
+use std::io::BufReader;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use ring::rand::SystemRandom;
+use ring::signature::{EcdsaKeyPair, RsaKeyPair, ECDSA_P256_SHA256_FIXED_SIGNING, RSA_PKCS1_SHA256};
+use serde_json::{json, Value};
+
+use common::{base64_url_encode, fixture, sasl_authenticate, sasl_handshake, Broker, Client, SaslAuthenticateResponse};
+
+const ISSUER: &str = "https://issuer.example.com";
+const AUDIENCE: &str = "kafka";
+
+#[derive(Clone, Copy)]
+enum SigningKey {
+    Rsa(&'static str),
+    Ec(&'static str),
+}
+
+fn pkcs8(kid: &str) -> Vec<u8> {
+    let contents = fs::read(fixture(&format!("jwt-{}.key", kid))).unwrap();
+    let key = rustls_pemfile::private_key(&mut BufReader::new(contents.as_slice())).unwrap().unwrap();
+    key.secret_der().to_vec()
+}
+
+fn now() -> i64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() as i64
+}
+
+// Claims the broker accepts; tests override single claims
+fn valid_claims() -> Value {
+    json!({"iss": ISSUER, "aud": AUDIENCE, "sub": "alice", "iat": now(), "exp": now() + 3600})
+}
+
+fn with_claim(claim: &str, value: Value) -> Value {
+    let mut claims = valid_claims();
+    claims[claim] = value;
+    claims
+}
+
+// A compact JWS signed with the fixture key; the header is filled in with alg and the key's kid
+fn sign(key: SigningKey, claims: &Value) -> String {
+    let (alg, kid) = match key {
+        SigningKey::Rsa(kid) => ("RS256", kid),
+        SigningKey::Ec(kid) => ("ES256", kid),
+    };
+    sign_with_header(key, &json!({"alg": alg, "typ": "JWT", "kid": kid}), claims)
+}
+
+fn sign_with_header(key: SigningKey, header: &Value, claims: &Value) -> String {
+    let signing_input = format!("{}.{}", base64_url_encode(header.to_string().as_bytes()), base64_url_encode(claims.to_string().as_bytes()));
+    let rng = SystemRandom::new();
+    let signature = match key {
+        SigningKey::Rsa(kid) => {
+            let key_pair = RsaKeyPair::from_pkcs8(&pkcs8(kid)).unwrap();
+            let mut signature = vec![0; key_pair.public().modulus_len()];
+            key_pair.sign(&RSA_PKCS1_SHA256, &rng, signing_input.as_bytes(), &mut signature).unwrap();
+            signature
+        }
+        SigningKey::Ec(kid) => {
+            let key_pair = EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, &pkcs8(kid), &rng).unwrap();
+            key_pair.sign(&rng, signing_input.as_bytes()).unwrap().as_ref().to_vec()
+        }
+    };
+    format!("{}.{}", signing_input, base64_url_encode(&signature))
+}
+
+fn oauthbearer_broker(properties: &[(&str, &str)]) -> Broker {
+    let broker = Broker::new(&["SASL_PLAINTEXT"]);
+    let jwks = format!("file://{}", fixture("jwks.json"));
+    let mut properties = properties.to_vec();
+    properties.push(("sasl.enabled.mechanisms", "OAUTHBEARER"));
+    properties.push(("sasl.oauthbearer.jwks.endpoint.url", &jwks));
+    properties.push(("sasl.oauthbearer.expected.issuer", ISSUER));
+    properties.push(("sasl.oauthbearer.expected.audience", AUDIENCE));
+    properties.push(("sasl.oauthbearer.clock.skew.seconds", "30"));
+    broker.start(&properties)
+}
+
+fn oauthbearer_login(client: &mut Client, token: &str) -> SaslAuthenticateResponse {
+    assert_eq!(sasl_handshake(client, "OAUTHBEARER"), 0);
+    sasl_authenticate(client, format!("n,,\x01auth=Bearer {}\x01\x01", token).as_bytes())
+}
+
+// Logs in and returns the principal, failing the test if the token is rejected
+fn principal(broker: &Broker, token: &str) -> String {
+    let mut client = broker.connect("SASL_PLAINTEXT");
+    let login = oauthbearer_login(&mut client, token);
+    assert_eq!(login.error_code, 0, "{:?}", login.error_message);
+    assert!(login.auth_bytes.is_empty(), "token rejected: {}", String::from_utf8_lossy(&login.auth_bytes));
+    // The broker logs the principal of each login before answering it
+    let log = broker.log();
+    let line = log.lines().rev().find(|line| line.contains(" authenticated as User:")).expect("login logged");
+    line.split(" as User:").nth(1).unwrap().trim_end_matches(" via SASL/OAUTHBEARER").to_string()
+}
+
+// Logs in expecting the token to be rejected; returns the failure's error message
+fn rejection(broker: &Broker, token: &str) -> String {
+    let mut client = broker.connect("SASL_PLAINTEXT");
+
+    // RFC 7628: the broker answers an invalid token with an error challenge, the client acknowledges it
+    // with %x01 and only then does authentication fail
+    let login = oauthbearer_login(&mut client, token);
+    assert_eq!(login.error_code, 0, "{:?}", login.error_message);
+    assert_eq!(login.auth_bytes, br#"{"status":"invalid_token"}"#);
+    let failure = sasl_authenticate(&mut client, b"\x01");
+    assert_eq!(failure.error_code, 58); // SASL_AUTHENTICATION_FAILED
+    assert!(client.is_closed());
+    failure.error_message.unwrap()
+}
+
+#[test]
+fn rs256_and_es256_tokens_authenticate_their_subject() {
+    let broker = oauthbearer_broker(&[]);
+
+    assert_eq!(principal(&broker, &sign(SigningKey::Rsa("rsa-1"), &valid_claims())), "alice");
+    assert_eq!(principal(&broker, &sign(SigningKey::Ec("ec-1"), &with_claim("sub", json!("bob")))), "bob");
+    // aud may also be an array
+    assert_eq!(principal(&broker, &sign(SigningKey::Rsa("rsa-1"), &with_claim("aud", json!(["other", AUDIENCE])))), "alice");
+}
+
+#[test]
+fn token_expiry_bounds_the_session_when_reauthentication_is_on() {
+    let broker = oauthbearer_broker(&[("connections.max.reauth.ms", "3600000")]);
+
+    let mut client = broker.connect("SASL_PLAINTEXT");
+    let login = oauthbearer_login(&mut client, &sign(SigningKey::Rsa("rsa-1"), &with_claim("exp", json!(now() + 60))));
+    assert_eq!(login.error_code, 0);
+    assert!((50_000..=60_000).contains(&login.session_lifetime_ms), "session lifetime {} ms", login.session_lifetime_ms);
+
+    let mut client = broker.connect("SASL_PLAINTEXT");
+    let login = oauthbearer_login(&mut client, &sign(SigningKey::Rsa("rsa-1"), &with_claim("exp", json!(now() + 7200))));
+    assert_eq!(login.session_lifetime_ms, 3_600_000);
+}
+
+#[test]
+fn tokens_with_bad_signatures_or_algorithms_are_rejected() {
+    let broker = oauthbearer_broker(&[]);
+    let token = sign(SigningKey::Rsa("rsa-1"), &valid_claims());
+    let (signing_input, _) = token.rsplit_once('.').unwrap();
+
+    // Another valid token's signature
+    let other = sign(SigningKey::Rsa("rsa-1"), &with_claim("sub", json!("mallory")));
+    let (_, other_signature) = other.rsplit_once('.').unwrap();
+    let message = rejection(&broker, &format!("{}.{}", signing_input, other_signature));
+    assert!(message.contains("signature is invalid"), "{}", message);
+
+    // alg none with an empty signature
+    let header = base64_url_encode(br#"{"alg":"none","typ":"JWT"}"#);
+    let claims = base64_url_encode(valid_claims().to_string().as_bytes());
+    let message = rejection(&broker, &format!("{}.{}.", header, claims));
+    assert!(message.contains("algorithm none is not allowed"), "{}", message);
+
+    // HS256 keyed with the public key is the classic algorithm confusion attack
+    let header = json!({"alg": "HS256", "typ": "JWT", "kid": "rsa-1"});
+    let signing_input = format!("{}.{}", base64_url_encode(header.to_string().as_bytes()), claims);
+    let jwks = fs::read(fixture("jwks.json")).unwrap();
+    let tag = ring::hmac::sign(&ring::hmac::Key::new(ring::hmac::HMAC_SHA256, &jwks), signing_input.as_bytes());
+    let message = rejection(&broker, &format!("{}.{}", signing_input, base64_url_encode(tag.as_ref())));
+    assert!(message.contains("algorithm HS256 is not allowed"), "{}", message);
+}
+
+#[test]
+fn time_claims_are_checked_with_clock_skew() {
+    let broker = oauthbearer_broker(&[]);
+    let key = SigningKey::Rsa("rsa-1");
+
+    let message = rejection(&broker, &sign(key, &with_claim("exp", json!(now() - 120))));
+    assert!(message.contains("token expired"), "{}", message);
+    let message = rejection(&broker, &sign(key, &with_claim("iat", json!(now() + 120))));
+    assert!(message.contains("issued in the future"), "{}", message);
+    let message = rejection(&broker, &sign(key, &with_claim("nbf", json!(now() + 120))));
+    assert!(message.contains("not valid before"), "{}", message);
+
+    // Within the 30 second skew all three are tolerated
+    assert_eq!(principal(&broker, &sign(key, &with_claim("nbf", json!(now() + 10)))), "alice");
+    assert_eq!(principal(&broker, &sign(key, &with_claim("iat", json!(now() + 10)))), "alice");
+    assert_eq!(principal(&broker, &sign(key, &with_claim("exp", json!(now() - 10)))), "alice");
+}
+
+#[test]
+fn issuer_and_audience_must_match() {
+    let broker = oauthbearer_broker(&[]);
+    let key = SigningKey::Ec("ec-1");
+
+    let message = rejection(&broker, &sign(key, &with_claim("iss", json!("https://elsewhere.example.com"))));
+    assert!(message.contains("issuer"), "{}", message);
+    let message = rejection(&broker, &sign(key, &with_claim("aud", json!("another-service"))));
+    assert!(message.contains("audience"), "{}", message);
+    let message = rejection(&broker, &sign(key, &with_claim("aud", json!(["another-service", "yet-another"]))));
+    assert!(message.contains("audience"), "{}", message);
+}
+
+#[test]
+fn the_kid_selects_the_verification_key() {
+    let broker = oauthbearer_broker(&[]);
+    let claims = valid_claims();
+
+    assert_eq!(principal(&broker, &sign(SigningKey::Rsa("rsa-2"), &claims)), "alice");
+
+    // Signed with rsa-2 but naming rsa-1
+    let header = json!({"alg": "RS256", "typ": "JWT", "kid": "rsa-1"});
+    let message = rejection(&broker, &sign_with_header(SigningKey::Rsa("rsa-2"), &header, &claims));
+    assert!(message.contains("signature is invalid"), "{}", message);
+
+    let header = json!({"alg": "RS256", "typ": "JWT", "kid": "rsa-3"});
+    let message = rejection(&broker, &sign_with_header(SigningKey::Rsa("rsa-2"), &header, &claims));
+    assert!(message.contains("no RS256 key in the JWKS file matches kid rsa-3"), "{}", message);
+
+    // An ES256 token naming an RSA key
+    let header = json!({"alg": "ES256", "typ": "JWT", "kid": "rsa-1"});
+    assert!(rejection(&broker, &sign_with_header(SigningKey::Ec("ec-1"), &header, &claims)).contains("matches kid rsa-1"));
+
+    // Without a kid every key for the algorithm is tried
+    let header = json!({"alg": "RS256", "typ": "JWT"});
+    assert_eq!(principal(&broker, &sign_with_header(SigningKey::Rsa("rsa-2"), &header, &claims)), "alice");
+}
+
+#[test]
+fn sub_claim_name_picks_the_principal_claim() {
+    let broker = oauthbearer_broker(&[("sasl.oauthbearer.sub.claim.name", "client_id")]);
+    let key = SigningKey::Rsa("rsa-1");
+
+    assert_eq!(principal(&broker, &sign(key, &with_claim("client_id", json!("billing-service")))), "billing-service");
+    let message = rejection(&broker, &sign(key, &valid_claims()));
+    assert!(message.contains("no client_id claim"), "{}", message);
+}
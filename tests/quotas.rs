@@ -0,0 +1,85 @@
+// request_percentage client quotas: throttle times in responses and muted connections
+mod common;
+
+use std::time::{Duration, Instant};
+
+use common::{metadata, Broker, Client, Request};
+
+const ALTER_CLIENT_QUOTAS: i16 = 49;
+
+// Sets a quota for one client-id; returns the entry's error code
+fn set_client_id_quota(client: &mut Client, client_id: &str, key: &str, value: f64) -> i16 {
+    let request = Request::new(ALTER_CLIENT_QUOTAS, 1, true)
+        .array(1)
+        .array(1)
+        .string("client-id")
+        .string(client_id)
+        .tags()
+        .array(1)
+        .string(key)
+        .f64(value)
+        .bool(false) // remove
+        .tags()
+        .tags()
+        .bool(false) // validate_only
+        .tags();
+    let mut response = client.send(request);
+
+    response.i32(); // throttle_time_ms
+    assert_eq!(response.array(), 1);
+    let error_code = response.i16();
+    response.nullable_string(); // error_message
+    for _ in 0..response.array() {
+        response.string(); // entity_type
+        response.nullable_string(); // entity_name
+        response.tags();
+    }
+    response.tags();
+    response.tags();
+    response.finish();
+    error_code
+}
+
+#[test]
+fn clients_over_their_request_quota_are_throttled_and_muted() {
+    // A two second window keeps throttle times, which are capped at the window, short
+    let broker = Broker::new(&["PLAINTEXT"]).start(&[("quota.window.num", "2"), ("quota.window.size.seconds", "1")]);
+    let mut admin = broker.connect("PLAINTEXT");
+    // A millionth of a thread: any request at all exceeds it
+    assert_eq!(set_client_id_quota(&mut admin, "throttled", "request_percentage", 0.0001), 0);
+
+    let mut client = broker.connect("PLAINTEXT");
+    let mut throttle_time_ms = 0;
+    for _ in 0..20 {
+        throttle_time_ms = metadata(&mut client, "throttled", &["orders"]).1;
+        if throttle_time_ms > 0 {
+            break;
+        }
+    }
+    assert!(throttle_time_ms > 0, "request_percentage quota never throttled");
+    assert!(throttle_time_ms <= 2000, "throttle time {} ms exceeds the quota window", throttle_time_ms);
+
+    // The throttled response came straight back; the request after it waits out the mute. Part of
+    // the mute may already have passed by the time the request is sent, hence the wide margin.
+    let started = Instant::now();
+    metadata(&mut client, "throttled", &["orders"]);
+    let waited = started.elapsed();
+    assert!(waited >= Duration::from_millis(throttle_time_ms as u64 / 2),
+            "next request answered after {:?}, throttle time was {} ms", waited, throttle_time_ms);
+
+    // Quotas are per client-id: others are neither throttled nor muted
+    let mut other = broker.connect("PLAINTEXT");
+    for _ in 0..5 {
+        assert_eq!(metadata(&mut other, "unthrottled", &["orders"]).1, 0);
+    }
+}
+
+#[test]
+fn quota_values_are_validated() {
+    let broker = Broker::new(&["PLAINTEXT"]).start(&[]);
+    let mut client = broker.connect("PLAINTEXT");
+
+    assert_eq!(set_client_id_quota(&mut client, "app", "request_percentage", -1.0), 42); // INVALID_REQUEST
+    assert_eq!(set_client_id_quota(&mut client, "app", "no_such_quota", 1.0), 42);
+    assert_eq!(set_client_id_quota(&mut client, "app", "request_percentage", 50.0), 0);
+}
@@ -0,0 +1,238 @@
+// SASL_PLAINTEXT authentication: PLAIN, SCRAM, session lifetimes and delegation tokens
+mod common;
+
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use common::{
+    base64_encode, create_delegation_token, metadata, plain_login, sasl_handshake, scram_login, Broker, Client, Request,
+    ScramMechanism,
+};
+
+const ALTER_USER_SCRAM_CREDENTIALS: i16 = 51;
+const RENEW_DELEGATION_TOKEN: i16 = 39;
+const EXPIRE_DELEGATION_TOKEN: i16 = 40;
+
+const USERS: &str = "alice=alice-secret\nbob=bob-secret\n";
+
+fn sasl_broker(mechanisms: &str, properties: &[(&str, &str)]) -> Broker {
+    let broker = Broker::new(&["PLAINTEXT", "SASL_PLAINTEXT"]);
+    let users = broker.write_file("users.properties", USERS);
+    let mut properties = properties.to_vec();
+    properties.push(("sasl.enabled.mechanisms", mechanisms));
+    properties.push(("sasl.plain.credentials.file", &users));
+    broker.start(&properties)
+}
+
+fn now_ms() -> i64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis() as i64
+}
+
+// Upserts one SCRAM credential per (user, mechanism, password); returns each user's error code
+fn upsert_scram_credentials(client: &mut Client, credentials: &[(&str, ScramMechanism, &str)]) -> Vec<(String, i16)> {
+    let mut request = Request::new(ALTER_USER_SCRAM_CREDENTIALS, 0, true).array(0).array(credentials.len());
+    for (user, mechanism, password) in credentials {
+        let salt = format!("{}-salt", user);
+        request = request
+            .string(user)
+            .i8(mechanism.type_code())
+            .i32(4096)
+            .bytes(salt.as_bytes())
+            .bytes(&mechanism.salted_password(password, salt.as_bytes(), 4096))
+            .tags();
+    }
+    let mut response = client.send(request.tags());
+
+    response.i32(); // throttle_time_ms
+    let mut results = Vec::new();
+    for _ in 0..response.array() {
+        let user = response.string();
+        let error_code = response.i16();
+        response.nullable_string(); // error_message
+        response.tags();
+        results.push((user, error_code));
+    }
+    response.tags();
+    response.finish();
+    results
+}
+
+// RenewDelegationToken and ExpireDelegationToken share their layout; returns (error_code, expiry_timestamp_ms)
+fn token_hmac_request(client: &mut Client, api_key: i16, hmac: &[u8], period_ms: i64) -> (i16, i64) {
+    let mut response = client.send(Request::new(api_key, 2, true).bytes(hmac).i64(period_ms).tags());
+    let result = (response.i16(), response.i64());
+    response.i32(); // throttle_time_ms
+    response.tags();
+    response.finish();
+    result
+}
+
+#[test]
+fn plain_login_with_valid_credentials_allows_requests() {
+    let broker = sasl_broker("PLAIN", &[]);
+
+    let mut client = broker.connect("SASL_PLAINTEXT");
+    let login = plain_login(&mut client, "alice", "alice-secret");
+    assert_eq!(login.error_code, 0, "{:?}", login.error_message);
+    assert_eq!(login.session_lifetime_ms, 0, "sessions don't expire without connections.max.reauth.ms");
+    assert_eq!(metadata(&mut client, "sasl", &["orders"]).0, vec![("orders".to_string(), 3)]);
+}
+
+#[test]
+fn plain_login_with_wrong_password_fails_and_closes_the_connection() {
+    let broker = sasl_broker("PLAIN", &[]);
+
+    let mut client = broker.connect("SASL_PLAINTEXT");
+    let login = plain_login(&mut client, "alice", "bob-secret");
+    assert_eq!(login.error_code, 58); // SASL_AUTHENTICATION_FAILED
+    assert!(login.error_message.is_some());
+    assert!(client.is_closed());
+
+    let mut client = broker.connect("SASL_PLAINTEXT");
+    assert_eq!(plain_login(&mut client, "mallory", "alice-secret").error_code, 58);
+    assert!(client.is_closed());
+}
+
+#[test]
+fn requests_before_authentication_close_the_connection() {
+    let broker = sasl_broker("PLAIN", &[]);
+
+    let mut client = broker.connect("SASL_PLAINTEXT");
+    let request = Request::new(common::METADATA, 12, true).null_array().bool(false).bool(false).tags();
+    assert!(client.try_send(request).is_err());
+
+    let mut client = broker.connect("SASL_PLAINTEXT");
+    assert_eq!(sasl_handshake(&mut client, "GSSAPI"), 33); // UNSUPPORTED_SASL_MECHANISM
+    assert!(client.is_closed());
+}
+
+#[test]
+fn scram_logins_use_credentials_from_alter_user_scram_credentials() {
+    let broker = sasl_broker("SCRAM-SHA-256,SCRAM-SHA-512", &[]);
+
+    // Without an authorizer anyone may alter credentials, including over PLAINTEXT
+    let mut admin = broker.connect("PLAINTEXT");
+    let results = upsert_scram_credentials(&mut admin, &[
+        ("alice", ScramMechanism::Sha256, "alice-scram"),
+        ("bob", ScramMechanism::Sha512, "bob-scram"),
+    ]);
+    assert_eq!(results, vec![("alice".to_string(), 0), ("bob".to_string(), 0)]);
+
+    for (user, mechanism, password) in [("alice", ScramMechanism::Sha256, "alice-scram"), ("bob", ScramMechanism::Sha512, "bob-scram")] {
+        let mut client = broker.connect("SASL_PLAINTEXT");
+        let login = scram_login(&mut client, mechanism, user, password, "");
+        assert_eq!(login.error_code, 0, "{} with {}: {:?}", user, mechanism.name(), login.error_message);
+        assert_eq!(metadata(&mut client, "scram", &["orders"]).0, vec![("orders".to_string(), 3)]);
+    }
+
+    // A wrong password, a mechanism the user has no credential for, and an unknown user
+    for (user, mechanism, password) in [
+        ("alice", ScramMechanism::Sha256, "wrong"),
+        ("alice", ScramMechanism::Sha512, "alice-scram"),
+        ("carol", ScramMechanism::Sha256, "carol-scram"),
+    ] {
+        let mut client = broker.connect("SASL_PLAINTEXT");
+        let login = scram_login(&mut client, mechanism, user, password, "");
+        assert_eq!(login.error_code, 58, "{} with {}", user, mechanism.name());
+        assert!(client.is_closed());
+    }
+}
+
+#[test]
+fn malformed_scram_credentials_are_unacceptable() {
+    let broker = sasl_broker("SCRAM-SHA-256,SCRAM-SHA-512", &[]);
+
+    let salted_password = ScramMechanism::Sha256.salted_password("secret", b"salt", 4096);
+    let upsertions: [(&str, ScramMechanism, &[u8], &[u8]); 4] = [
+        ("empty-salt", ScramMechanism::Sha256, b"", &salted_password),
+        ("empty-salted-password", ScramMechanism::Sha256, b"salt", b""),
+        ("short-salted-password", ScramMechanism::Sha256, b"salt", &salted_password[..31]),
+        ("sha-256-length-for-sha-512", ScramMechanism::Sha512, b"salt", &salted_password),
+    ];
+    let mut request = Request::new(ALTER_USER_SCRAM_CREDENTIALS, 0, true).array(0).array(upsertions.len());
+    for (user, mechanism, salt, salted_password) in upsertions {
+        request = request.string(user).i8(mechanism.type_code()).i32(4096).bytes(salt).bytes(salted_password).tags();
+    }
+    let mut response = broker.connect("PLAINTEXT").send(request.tags());
+
+    response.i32(); // throttle_time_ms
+    assert_eq!(response.array(), 4);
+    for (user, ..) in upsertions {
+        assert_eq!(response.string(), user);
+        assert_eq!(response.i16(), 93, "{}", user); // UNACCEPTABLE_CREDENTIAL
+        response.nullable_string(); // error_message
+        response.tags();
+    }
+}
+
+#[test]
+fn sessions_expire_unless_reauthenticated() {
+    let broker = sasl_broker("PLAIN", &[("connections.max.reauth.ms", "1500")]);
+
+    let mut client = broker.connect("SASL_PLAINTEXT");
+    let login = plain_login(&mut client, "alice", "alice-secret");
+    assert_eq!(login.error_code, 0);
+    assert_eq!(login.session_lifetime_ms, 1500);
+
+    thread::sleep(Duration::from_millis(1000));
+    let reauthentication = plain_login(&mut client, "alice", "alice-secret");
+    assert_eq!(reauthentication.error_code, 0, "{:?}", reauthentication.error_message);
+    assert_eq!(reauthentication.session_lifetime_ms, 1500);
+
+    // Past the first session's end, the re-authenticated one is still valid
+    thread::sleep(Duration::from_millis(1000));
+    assert_eq!(metadata(&mut client, "sasl", &["orders"]).0.len(), 1);
+
+    thread::sleep(Duration::from_millis(1000));
+    let request = Request::new(common::METADATA, 12, true).null_array().bool(false).bool(false).tags();
+    assert!(client.try_send(request).is_err(), "requests after the session expired close the connection");
+}
+
+#[test]
+fn reauthentication_cannot_change_the_principal() {
+    let broker = sasl_broker("PLAIN", &[("connections.max.reauth.ms", "60000")]);
+
+    let mut client = broker.connect("SASL_PLAINTEXT");
+    assert_eq!(plain_login(&mut client, "alice", "alice-secret").error_code, 0);
+    let reauthentication = plain_login(&mut client, "bob", "bob-secret");
+    assert_eq!(reauthentication.error_code, 58);
+    assert!(reauthentication.error_message.unwrap().contains("Cannot change principals"));
+    assert!(client.is_closed());
+}
+
+#[test]
+fn delegation_tokens_authenticate_until_expired() {
+    let broker = sasl_broker("PLAIN,SCRAM-SHA-256,SCRAM-SHA-512", &[("delegation.token.secret.key", "integration-secret")]);
+
+    let mut alice = broker.connect("SASL_PLAINTEXT");
+    assert_eq!(plain_login(&mut alice, "alice", "alice-secret").error_code, 0);
+    let token = create_delegation_token(&mut alice, &[]);
+    assert_eq!(token.error_code, 0);
+    assert_eq!(token.owner, "alice");
+    let password = base64_encode(&token.hmac);
+
+    // The token authenticates as its owner with either SCRAM mechanism, but not without tokenauth
+    for mechanism in [ScramMechanism::Sha256, ScramMechanism::Sha512] {
+        let mut client = broker.connect("SASL_PLAINTEXT");
+        let login = scram_login(&mut client, mechanism, &token.token_id, &password, ",tokenauth=true");
+        assert_eq!(login.error_code, 0, "{}: {:?}", mechanism.name(), login.error_message);
+        // Token-authenticated connections may not create further tokens
+        assert_eq!(create_delegation_token(&mut client, &[]).error_code, 64); // DELEGATION_TOKEN_REQUEST_NOT_ALLOWED
+    }
+    let mut client = broker.connect("SASL_PLAINTEXT");
+    assert_eq!(scram_login(&mut client, ScramMechanism::Sha256, &token.token_id, &password, "").error_code, 58);
+
+    let (error_code, expiry_timestamp_ms) = token_hmac_request(&mut alice, RENEW_DELEGATION_TOKEN, &token.hmac, 3_600_000);
+    assert_eq!(error_code, 0);
+    assert!(expiry_timestamp_ms < token.expiry_timestamp_ms, "renewed to an hour from now, before the default expiry");
+    assert!((expiry_timestamp_ms - (now_ms() + 3_600_000)).abs() < 10_000);
+    let mut client = broker.connect("SASL_PLAINTEXT");
+    assert_eq!(scram_login(&mut client, ScramMechanism::Sha256, &token.token_id, &password, ",tokenauth=true").error_code, 0);
+
+    let (error_code, expiry_timestamp_ms) = token_hmac_request(&mut alice, EXPIRE_DELEGATION_TOKEN, &token.hmac, -1);
+    assert_eq!(error_code, 0);
+    assert!(expiry_timestamp_ms <= now_ms());
+    let mut client = broker.connect("SASL_PLAINTEXT");
+    assert_eq!(scram_login(&mut client, ScramMechanism::Sha256, &token.token_id, &password, ",tokenauth=true").error_code, 58);
+    assert_eq!(token_hmac_request(&mut alice, RENEW_DELEGATION_TOKEN, &token.hmac, 60_000).0, 62); // DELEGATION_TOKEN_NOT_FOUND
+}
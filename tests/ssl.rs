@@ -0,0 +1,161 @@
+// SSL listeners: the handshake, client authentication, principal mapping and certificate reloading
+mod common;
+
+use std::fs;
+use std::io::{self, BufReader};
+use std::net::TcpStream;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use tokio_rustls::rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName};
+use tokio_rustls::rustls::{crypto, ClientConfig, ClientConnection, RootCertStore, StreamOwned};
+
+use common::{fixture, Broker, Client, Request, API_VERSIONS};
+
+const PRINCIPAL_MAPPING_RULES: &str = "RULE:^CN=(.*?),OU=ServiceUsers.*$/$1/U, DEFAULT";
+
+fn certificates(path: &str) -> Vec<CertificateDer<'static>> {
+    let contents = fs::read(path).unwrap();
+    rustls_pemfile::certs(&mut BufReader::new(contents.as_slice())).collect::<Result<_, _>>().unwrap()
+}
+
+fn private_key(path: &str) -> PrivateKeyDer<'static> {
+    let contents = fs::read(path).unwrap();
+    rustls_pemfile::private_key(&mut BufReader::new(contents.as_slice())).unwrap().unwrap()
+}
+
+// Connects to the SSL listener, trusting the test CA and presenting the named client certificate, if
+// any. Returns the client and the certificate the broker presented.
+fn tls_connect(broker: &Broker, client_certificate: Option<&str>) -> io::Result<(Client, CertificateDer<'static>)> {
+    let mut roots = RootCertStore::empty();
+    for certificate in certificates(&fixture("ca.pem")) {
+        roots.add(certificate).unwrap();
+    }
+    let builder = ClientConfig::builder_with_provider(Arc::new(crypto::ring::default_provider()))
+        .with_safe_default_protocol_versions()
+        .unwrap()
+        .with_root_certificates(roots);
+    let config = match client_certificate {
+        Some(name) => builder
+            .with_client_auth_cert(certificates(&fixture(&format!("{}.pem", name))), private_key(&fixture(&format!("{}.key", name))))
+            .unwrap(),
+        None => builder.with_no_client_auth(),
+    };
+
+    let mut connection = ClientConnection::new(Arc::new(config), ServerName::try_from("localhost").unwrap()).unwrap();
+    let mut stream = TcpStream::connect(("127.0.0.1", broker.port("SSL")))?;
+    stream.set_read_timeout(Some(Duration::from_secs(10)))?;
+    while connection.is_handshaking() {
+        connection.complete_io(&mut stream)?;
+    }
+    let server_certificate = connection.peer_certificates().unwrap()[0].clone().into_owned();
+    Ok((Client::over(StreamOwned::new(connection, stream)), server_certificate))
+}
+
+fn api_versions(client: &mut Client) -> io::Result<i16> {
+    let request = Request::new(API_VERSIONS, 3, true).string("integration-test").string("1.0").tags();
+    Ok(client.try_send(request)?.i16())
+}
+
+fn ssl_broker(properties: &[(&str, &str)]) -> Broker {
+    let broker = Broker::new(&["SSL"]);
+    let keystore = broker.write_file("keystore.pem", fs::read(fixture("server-keystore.pem")).unwrap());
+    let truststore = fixture("ca.pem");
+    let mut properties = properties.to_vec();
+    properties.push(("ssl.keystore.location", &keystore));
+    properties.push(("ssl.truststore.location", &truststore));
+    broker.start(&properties)
+}
+
+#[test]
+fn ssl_listeners_complete_the_handshake_and_serve_requests() {
+    let broker = ssl_broker(&[]);
+
+    let (mut client, server_certificate) = tls_connect(&broker, None).unwrap();
+    assert_eq!(server_certificate, certificates(&fixture("server-keystore.pem"))[0]);
+    assert_eq!(api_versions(&mut client).unwrap(), 0);
+    assert_eq!(api_versions(&mut client).unwrap(), 0);
+}
+
+#[test]
+fn required_client_auth_rejects_clients_without_a_certificate() {
+    let broker = ssl_broker(&[("ssl.client.auth", "required")]);
+
+    // With TLS 1.3 the client only learns of the rejection once it reads
+    let rejected = tls_connect(&broker, None).and_then(|(mut client, _)| api_versions(&mut client));
+    assert!(rejected.is_err(), "a client without a certificate was served");
+    assert!(broker.wait_for_log("SSL authentication failed"), "{}", broker.log());
+
+    let (mut client, _) = tls_connect(&broker, Some("alice")).unwrap();
+    assert_eq!(api_versions(&mut client).unwrap(), 0);
+}
+
+// The broker logs the principal of each connection it accepts
+fn principal_logged(broker: &Broker, principal: &str) -> bool {
+    broker.log().lines().any(|line| line.starts_with("accepted new connection") && line.ends_with(&format!(" on SSL as User:{}", principal)))
+}
+
+#[test]
+fn principal_mapping_rules_name_ssl_principals() {
+    let broker = ssl_broker(&[
+        ("ssl.client.auth", "requested"),
+        ("ssl.principal.mapping.rules", PRINCIPAL_MAPPING_RULES),
+    ]);
+
+    let (mut alice, _) = tls_connect(&broker, Some("alice")).unwrap();
+    assert_eq!(api_versions(&mut alice).unwrap(), 0);
+    assert!(principal_logged(&broker, "ALICE"), "{}", broker.log());
+
+    // bob isn't in OU=ServiceUsers, so DEFAULT keeps the whole distinguished name
+    let (mut bob, _) = tls_connect(&broker, Some("bob")).unwrap();
+    assert_eq!(api_versions(&mut bob).unwrap(), 0);
+    assert!(principal_logged(&broker, "CN=bob,OU=Other,O=Example"), "{}", broker.log());
+
+    // Without a certificate the principal is ANONYMOUS
+    let (mut anonymous, _) = tls_connect(&broker, None).unwrap();
+    assert_eq!(api_versions(&mut anonymous).unwrap(), 0);
+    assert!(principal_logged(&broker, "ANONYMOUS"), "{}", broker.log());
+}
+
+#[test]
+fn certificates_matching_no_mapping_rule_are_rejected() {
+    let broker = ssl_broker(&[
+        ("ssl.client.auth", "required"),
+        ("ssl.principal.mapping.rules", "RULE:^CN=(.*?),OU=ServiceUsers.*$/$1/L"),
+    ]);
+
+    let (mut alice, _) = tls_connect(&broker, Some("alice")).unwrap();
+    assert_eq!(api_versions(&mut alice).unwrap(), 0);
+    assert!(principal_logged(&broker, "alice"), "{}", broker.log());
+
+    let rejected = tls_connect(&broker, Some("bob")).and_then(|(mut client, _)| api_versions(&mut client));
+    assert!(rejected.is_err(), "a certificate no rule maps was served");
+    assert!(broker.wait_for_log("no ssl.principal.mapping.rules entry matches CN=bob,OU=Other,O=Example"), "{}", broker.log());
+}
+
+#[test]
+fn rewritten_keystores_are_reloaded() {
+    let broker = ssl_broker(&[]);
+    let original = certificates(&fixture("server-keystore.pem"))[0].clone();
+    let replacement = certificates(&fixture("server2-keystore.pem"))[0].clone();
+
+    let (mut client, server_certificate) = tls_connect(&broker, None).unwrap();
+    assert_eq!(server_certificate, original);
+
+    broker.write_file("keystore.pem", fs::read(fixture("server2-keystore.pem")).unwrap());
+    // The files are checked every few seconds
+    let started = Instant::now();
+    loop {
+        let (_, server_certificate) = tls_connect(&broker, None).unwrap();
+        if server_certificate == replacement {
+            break;
+        }
+        assert!(started.elapsed() < Duration::from_secs(20), "the rewritten keystore was not picked up");
+        thread::sleep(Duration::from_millis(250));
+    }
+    assert!(broker.log().contains("reloaded SSL certificates"));
+
+    // Connections made before the reload keep working
+    assert_eq!(api_versions(&mut client).unwrap(), 0);
+}